@@ -0,0 +1,97 @@
+use libp2p::{PeerId, identity::Keypair};
+use std::{
+  fs,
+  io::{self, Write},
+  path::{Path, PathBuf},
+};
+
+/// keypair of the peer, `PeerId` is derived from it <br>
+/// peer with a key file keeps its `PeerId` across restarts, so other nodes see it as the same node
+pub fn load_or_generate(path: &Path) -> io::Result<Keypair> {
+  match read(path) {
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      let keypair = Keypair::generate_ed25519();
+      write(path, &keypair)?;
+      Ok(keypair)
+    }
+    res => res,
+  }
+}
+
+/// keypair from the key file if it's set, otherwise a new one that lives until the process exits
+pub fn keypair(path: Option<&Path>) -> io::Result<Keypair> {
+  match path {
+    Some(path) => load_or_generate(path),
+    None => Ok(Keypair::generate_ed25519()),
+  }
+}
+
+pub fn read(path: &Path) -> io::Result<Keypair> {
+  let bytes = fs::read(path)?;
+  Keypair::from_protobuf_encoding(&bytes)
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("key file {}: {e}", path.display())))
+}
+
+/// fails if the file already exists, so an identity isn't overwritten by accident
+pub fn write(
+  path: &Path,
+  keypair: &Keypair,
+) -> io::Result<PeerId> {
+  if path.exists() {
+    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("key file {} already exists", path.display())));
+  }
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  let bytes = keypair.to_protobuf_encoding().map_err(io::Error::other)?;
+
+  // write and rename, so a crash in the middle doesn't leave a broken identity
+  // the key is readable only by the owner from the start
+  let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+  // leftover of a write that has crashed before the rename
+  if let Err(e) = fs::remove_file(&tmp)
+    && e.kind() != io::ErrorKind::NotFound
+  {
+    return Err(e);
+  }
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create_new(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+  let mut file = options.open(&tmp)?;
+  file.write_all(&bytes)?;
+  file.sync_all()?;
+  fs::rename(&tmp, path)?;
+
+  Ok(keypair.public().to_peer_id())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn key_file_keeps_peer_id() {
+    let dir = std::env::temp_dir().join(format!("maroon_identity_{}", PeerId::random()));
+    let path = dir.join("node.key");
+
+    let first = load_or_generate(&path).unwrap();
+    let second = load_or_generate(&path).unwrap();
+    assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+    assert_eq!(io::ErrorKind::AlreadyExists, write(&path, &Keypair::generate_ed25519()).unwrap_err().kind());
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    }
+
+    fs::write(&path, b"not a key").unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, read(&path).unwrap_err().kind());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -1,4 +1,5 @@
 pub mod duplex_channel;
+pub mod identity;
 pub mod invoker_handler;
 pub mod logical_clock;
 pub mod retrier;
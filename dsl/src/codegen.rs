@@ -45,7 +45,8 @@ fn rust_type(t: &Type) -> String {
     Type::Void => "()".into(),
     Type::MaxQueue(inner) => format!("std::collections::BinaryHeap<{}>", rust_type(inner)),
     Type::MinQueue(inner) => format!("std::collections::BinaryHeap<std::cmp::Reverse<{}>>", rust_type(inner)),
-    Type::Map(k, v) => format!("std::collections::HashMap<{}, {}>", rust_type(k), rust_type(v)),
+    // ordered map, so heaps are serialized the same way on every node
+    Type::Map(k, v) => format!("std::collections::BTreeMap<{}, {}>", rust_type(k), rust_type(v)),
     Type::Array(t) => format!("Vec<{}>", rust_type(t)),
     Type::Struct(name, _, _) => pascal_case(name),
     Type::Option(t) => format!("Option<{}>", rust_type(t)),
@@ -193,6 +194,27 @@ pub fn generate_rust_types(ir: &IR) -> String {
     }
   }
 
+  // 1.2) Emit built-in system events, runtime delivers them via reserved `mrn.system.*` queues
+  out.push_str(
+    r#"#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemFiberEvent {
+  FiberFailed { fiber_id: String, fiber_type: String, kind: String, detail: String },
+}
+
+impl Default for SystemFiberEvent {
+  fn default() -> Self {
+    SystemFiberEvent::FiberFailed {
+      fiber_id: String::new(),
+      fiber_type: String::new(),
+      kind: String::new(),
+      detail: String::new(),
+    }
+  }
+}
+
+"#,
+  );
+
   // 1.5) Emit wrapper structs for all Future<T> types used anywhere in IR
   use std::collections::BTreeSet as __BTS_FUTS;
   let mut future_wrappers: __BTS_FUTS<String> = __BTS_FUTS::new();
@@ -283,7 +305,7 @@ pub fn generate_rust_types(ir: &IR) -> String {
   let mut heap_structs: Vec<(String, String)> = Vec::new();
   for (fiber_name, fiber) in fibers_sorted.iter() {
     let heap_struct = variant_name(&[fiber_name.0.as_str(), "Heap"]);
-    out.push_str(&format!("#[derive(Clone, Debug, Default)]\npub struct {} {{\n", heap_struct));
+    out.push_str(&format!("#[derive(Clone, Debug, Default, Serialize, Deserialize)]\npub struct {} {{\n", heap_struct));
     let mut heap_fields: Vec<(&String, &Type)> = fiber.heap.iter().collect();
     heap_fields.sort_by(|a, b| a.0.cmp(b.0));
     for (name, ty) in heap_fields {
@@ -296,7 +318,10 @@ pub fn generate_rust_types(ir: &IR) -> String {
     out.push_str("}\n\n");
     if !fiber.init_vars.is_empty() {
       let invars_struct = variant_name(&[fiber_name.0.as_str(), "InVars"]);
-      out.push_str(&format!("#[derive(Clone, Debug, Default)]\npub struct {} {{\n", invars_struct));
+      out.push_str(&format!(
+        "#[derive(Clone, Debug, Default, Serialize, Deserialize)]\npub struct {} {{\n",
+        invars_struct
+      ));
       let mut init_vars_sorted = fiber.init_vars.clone();
       init_vars_sorted.sort_by(|a, b| a.0.cmp(&b.0));
       for InVar(name, ty) in init_vars_sorted {
@@ -307,7 +332,7 @@ pub fn generate_rust_types(ir: &IR) -> String {
     heap_structs.push((camel_ident(&fiber_name.0), heap_struct));
   }
   // Unified Heap as a struct with all fiber heaps accessible at once
-  out.push_str("#[derive(Clone, Debug, Default)]\npub struct Heap {\n");
+  out.push_str("#[derive(Clone, Debug, Default, Serialize, Deserialize)]\npub struct Heap {\n");
   heap_structs.sort_by(|a, b| a.0.cmp(&b.0));
   for (field_name, struct_name) in heap_structs {
     out.push_str(&format!("  pub {}: {},\n", field_name, struct_name));
@@ -315,7 +340,7 @@ pub fn generate_rust_types(ir: &IR) -> String {
   out.push_str("}\n\n");
 
   // 3) Emit State enum variants for all steps of all funcs (always include entry).
-  out.push_str("#[derive(Clone, Debug, PartialEq, Eq)]\npub enum State {\n");
+  out.push_str("#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]\npub enum State {\n");
   // Always include `Completed` and `Idle` as catch-alls to mirror runtime expectations.
   out.push_str("  Completed,\n  Idle,\n");
   for (fiber_name, fiber) in fibers_sorted.iter() {
@@ -407,6 +432,8 @@ pub fn generate_rust_types(ir: &IR) -> String {
       used_types.entry(pub_name.clone()).or_insert_with(|| Type::Struct(pub_name.clone(), Vec::new(), String::new()));
     }
   }
+  // runtime sends system events even if no fiber reads them
+  used_types.insert("SystemFiberEvent".to_string(), Type::Custom("SystemFiberEvent".to_string()));
 
   out.push_str("#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]\npub enum Value {\n");
   for (vname, ty) in used_types.iter() {
@@ -414,6 +441,9 @@ pub fn generate_rust_types(ir: &IR) -> String {
   }
   out.push_str("}\n\n");
 
+  // Helpers to find futures in values and heaps, runtime uses them to track future owners
+  out.push_str(&generate_future_ids_helpers(ir, &used_types));
+
   // Converters for PubQueueMessage values
   // Convert public -> private with a provided future id
   out.push_str("pub fn pub_to_private(val: Value, future_id: String) -> Value {\n  match val {\n");
@@ -473,10 +503,19 @@ pub fn generate_rust_types(ir: &IR) -> String {
   }
   out.push_str("    _ => panic!(\"private_to_pub is only for PubQueueMessage values\"),\n  }\n}\n\n");
 
+  // Name of the PubQueueMessage type for public values, so runtime can check what gateways send
+  out.push_str("pub fn pub_queue_message_type(val: &Value) -> Option<&'static str> {\n  match val {\n");
+  for t in &ir.types {
+    if let Type::PubQueueMessage { name, .. } = t {
+      out.push_str(&format!("    Value::{}Pub(_) => Some({:?}),\n", pascal_case(name), name));
+    }
+  }
+  out.push_str("    _ => None,\n  }\n}\n\n");
+
   // 5) Emit runtime-aligned scaffolding types and global_step
   // StackEntry
   out.push_str(
-    r"#[derive(Clone, Debug, PartialEq, Eq)]
+    r"#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StackEntry {
   State(State),
   // Option<usize> - local index offset back on stack
@@ -490,7 +529,7 @@ pub enum StackEntry {
   );
 
   // FutureKind enum (dynamic variants)
-  out.push_str("#[derive(Clone, Debug, PartialEq, Eq)]\npub enum FutureKind {\n");
+  out.push_str("#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]\npub enum FutureKind {\n");
   for w in future_wrappers.iter() {
     out.push_str(&format!("  {},\n", w));
   }
@@ -503,19 +542,22 @@ pub enum StackEntry {
   out.push_str("  }\n}\n\n");
   // SuccessBindKind and the rest
   out.push_str(
-    r"#[derive(Clone, Debug, PartialEq, Eq)]
+    r"#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SuccessBindKind { String, Future(FutureKind) }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SelectArm {
   FutureVar { future_id: String, bind: Option<String>, next: State },
   Queue { queue_name: String, bind: String, next: State },
+  // Resolves after `ms` milliseconds, runtime turns it into a timer future arm.
+  Timeout { ms: u64, next: State },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CreatePrimitiveValue {
   Future,
-  Queue { name: String, public: bool },
+  // `message` - name of the PubQueueMessage type gateways can send to the public queue
+  Queue { name: String, public: bool, message: Option<String> },
   // Create a scheduled timer future that resolves after `ms` milliseconds.
   // The created future is a Void future (Unit), i.e., it signals completion with no value.
   Schedule { ms: u64 },
@@ -571,9 +613,19 @@ pub enum StepResult {
   // Emit helpers to initialize Heap from fiber init_vars
   out.push_str(&generate_heap_init_helpers(ir));
 
+  // Fingerprint of everything above, peers compare it before exchanging values
+  let hash = schema_hash(&out);
+  out.push_str("// nodes and gateways with different hashes can't exchange values or execute the same epochs\n");
+  out.push_str(&format!("pub const IR_SCHEMA_HASH: u64 = 0x{:016x};\n", hash));
+
   out
 }
 
+/// FNV-1a, it's stable between builds and platforms unlike `DefaultHasher`
+fn schema_hash(code: &str) -> u64 {
+  code.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
 fn generate_prepare_and_result_helpers(ir: &IR) -> String {
   let mut out = String::new();
 
@@ -706,6 +758,131 @@ fn generate_prepare_and_result_helpers(ir: &IR) -> String {
   out
 }
 
+/// fields of a struct-like type, types in `Value` may refer to IR.types only by name
+fn struct_fields(
+  ir: &IR,
+  ty: &Type,
+) -> Option<Vec<StructField>> {
+  let name = match ty {
+    Type::Struct(name, _, _) | Type::PubQueueMessage { name, .. } | Type::Custom(name) => name,
+    _ => return None,
+  };
+  let declared = ir.types.iter().find_map(|t| match t {
+    Type::Struct(n, fields, _) if n == name => Some(fields.clone()),
+    Type::PubQueueMessage { name: n, fields, .. } if n == name => Some(fields.clone()),
+    _ => None,
+  });
+  declared.or_else(|| match ty {
+    Type::Struct(_, fields, _) | Type::PubQueueMessage { fields, .. } => Some(fields.clone()),
+    _ => None,
+  })
+}
+
+/// statements that push ids of all futures `expr` holds into `ids`, `None` if the type can't hold futures
+fn future_ids_code(
+  ir: &IR,
+  ty: &Type,
+  expr: &str,
+  depth: usize,
+) -> Option<String> {
+  // types can be recursive, futures deeper than that aren't tracked
+  if depth > 8 {
+    return None;
+  }
+  let x = format!("x{}", depth);
+  match ty {
+    Type::Future(_) => Some(format!("ids.push({}.0.clone());", expr)),
+    Type::Option(inner) => {
+      future_ids_code(ir, inner, &x, depth + 1).map(|code| format!("if let Some({}) = &{} {{ {} }}", x, expr, code))
+    }
+    Type::Array(inner) | Type::MaxQueue(inner) => {
+      future_ids_code(ir, inner, &x, depth + 1).map(|code| format!("for {} in {}.iter() {{ {} }}", x, expr, code))
+    }
+    Type::MinQueue(inner) => future_ids_code(ir, inner, &format!("{}.0", x), depth + 1)
+      .map(|code| format!("for {} in {}.iter() {{ {} }}", x, expr, code)),
+    Type::Map(k, v) => {
+      let (xk, xv) = (format!("{}k", x), format!("{}v", x));
+      let code: Vec<String> = [future_ids_code(ir, k, &xk, depth + 1), future_ids_code(ir, v, &xv, depth + 1)]
+        .into_iter()
+        .flatten()
+        .collect();
+      if code.is_empty() {
+        return None;
+      }
+      Some(format!("for ({}, {}) in {}.iter() {{ {} }}", xk, xv, expr, code.join(" ")))
+    }
+    Type::Struct(..) | Type::PubQueueMessage { .. } | Type::Custom(_) => {
+      let mut code: Vec<String> = Vec::new();
+      for f in struct_fields(ir, ty)? {
+        let field = format!("{}.{}", expr, camel_ident(&f.name));
+        // public future id is always a future, even if it's declared as a String
+        if f.name == "public_future_id" && f.ty == Type::String {
+          code.push(format!("ids.push({}.clone());", field));
+        } else if let Some(c) = future_ids_code(ir, &f.ty, &field, depth + 1) {
+          code.push(c);
+        }
+      }
+      if code.is_empty() { None } else { Some(code.join(" ")) }
+    }
+    Type::UInt64 | Type::String | Type::Bool | Type::Void => None,
+  }
+}
+
+fn generate_future_ids_helpers(
+  ir: &IR,
+  used_types: &std::collections::BTreeMap<String, Type>,
+) -> String {
+  let mut out = String::new();
+
+  out.push_str(
+    "// ids of all futures the value holds, including nested ones
+",
+  );
+  out.push_str(
+    "pub fn value_future_ids(val: &Value, ids: &mut Vec<String>) {
+  match val {
+",
+  );
+  for (vname, ty) in used_types.iter() {
+    if let Some(code) = future_ids_code(ir, ty, "v", 0) {
+      out.push_str(&format!("    Value::{}(v) => {{ {} }}\n", vname, code));
+    }
+  }
+  out.push_str("    _ => {}\n  }\n}\n\n");
+
+  let mut fibers_sorted: Vec<(&FiberType, &Fiber)> = ir.fibers.iter().collect();
+  fibers_sorted.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+
+  out.push_str(
+    "// ids of all futures in heaps of the fiber, including init vars
+",
+  );
+  out.push_str(
+    "pub fn heap_future_ids(heap: &Heap, ids: &mut Vec<String>) {
+",
+  );
+  for (fiber_name, fiber) in fibers_sorted {
+    let fiber_heap = format!("heap.{}", camel_ident(&fiber_name.0));
+    let mut heap_fields: Vec<(&String, &Type)> = fiber.heap.iter().collect();
+    heap_fields.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, ty) in heap_fields {
+      if let Some(code) = future_ids_code(ir, ty, &format!("{}.{}", fiber_heap, camel_ident(name)), 0) {
+        out.push_str(&format!("  {}\n", code));
+      }
+    }
+    let mut init_vars_sorted = fiber.init_vars.clone();
+    init_vars_sorted.sort_by(|a, b| a.0.cmp(b.0));
+    for InVar(name, ty) in init_vars_sorted {
+      if let Some(code) = future_ids_code(ir, &ty, &format!("{}.in_vars.{}", fiber_heap, camel_ident(name)), 0) {
+        out.push_str(&format!("  {}\n", code));
+      }
+    }
+  }
+  out.push_str("}\n\n");
+
+  out
+}
+
 fn generate_heap_init_helpers(ir: &IR) -> String {
   let mut out = String::new();
 
@@ -1158,6 +1335,7 @@ fn generate_global_step(ir: &IR) -> String {
                   AwaitSpec::Future { future_id, bind: _, ret_to: _ } => {
                     referenced.insert(future_id.0.to_string());
                   }
+                  AwaitSpec::Timeout { .. } => {}
                 }
               }
             }
@@ -1271,7 +1449,7 @@ fn generate_global_step(ir: &IR) -> String {
                   crate::ir::RuntimePrimitive::Future => {
                     parts.push("CreatePrimitiveValue::Future".to_string());
                   }
-                  crate::ir::RuntimePrimitive::Queue { name, public } => {
+                  crate::ir::RuntimePrimitive::Queue { name, public, message } => {
                     let idx_expr = if let Some(pi) = func.in_vars.iter().position(|p| p.0 == name.0) {
                       format!("{}", pi)
                     } else if let Some(li) = func.locals.iter().position(|l| l.0 == name.0) {
@@ -1283,7 +1461,14 @@ fn generate_global_step(ir: &IR) -> String {
                       "if let StackEntry::Value(_, Value::String(x)) = &vars[{}] {{ x.clone() }} else {{ unreachable!() }}",
                       idx_expr
                     );
-                    parts.push(format!("CreatePrimitiveValue::Queue {{ name: {}, public: {} }}", q_expr, public));
+                    let message_expr = match message {
+                      Some(m) => format!("Some({:?}.to_string())", m),
+                      None => "None".to_string(),
+                    };
+                    parts.push(format!(
+                      "CreatePrimitiveValue::Queue {{ name: {}, public: {}, message: {} }}",
+                      q_expr, public, message_expr
+                    ));
                   }
                   crate::ir::RuntimePrimitive::Schedule { ms_var } => {
                     // Extract ms value (u64) from current frame
@@ -1430,6 +1615,10 @@ fn generate_global_step(ir: &IR) -> String {
                       )),
                     }
                   }
+                  AwaitSpec::Timeout { ms, next } => {
+                    let next_v = variant_name(&[fiber_name.0.as_str(), func_name, &next.0]);
+                    arm_parts.push(format!("SelectArm::Timeout {{ ms: {}, next: State::{} }}", ms, next_v));
+                  }
                 }
               }
               out.push_str("      StepResult::Select(vec![");
@@ -1614,6 +1803,7 @@ fn generate_global_step(ir: &IR) -> String {
                 AwaitSpec::Future { future_id, .. } => {
                   referenced.insert(future_id.0.to_string());
                 }
+                AwaitSpec::Timeout { .. } => {}
               }
             }
           }
@@ -1725,7 +1915,7 @@ fn generate_global_step(ir: &IR) -> String {
             for p in primitives {
               match p {
                 crate::ir::RuntimePrimitive::Future => parts.push("CreatePrimitiveValue::Future".to_string()),
-                crate::ir::RuntimePrimitive::Queue { name, public } => {
+                crate::ir::RuntimePrimitive::Queue { name, public, message } => {
                   let idx_expr = if let Some(pi) = func.in_vars.iter().position(|p| p.0 == name.0) {
                     format!("{}", pi)
                   } else if let Some(li) = func.locals.iter().position(|l| l.0 == name.0) {
@@ -1737,7 +1927,14 @@ fn generate_global_step(ir: &IR) -> String {
                     "if let StackEntry::Value(_, Value::String(x)) = &vars[{}] {{ x.clone() }} else {{ unreachable!() }}",
                     idx_expr
                   );
-                  parts.push(format!("CreatePrimitiveValue::Queue {{ name: {}, public: {} }}", q_expr, public));
+                  let message_expr = match message {
+                    Some(m) => format!("Some({:?}.to_string())", m),
+                    None => "None".to_string(),
+                  };
+                  parts.push(format!(
+                    "CreatePrimitiveValue::Queue {{ name: {}, public: {}, message: {} }}",
+                    q_expr, public, message_expr
+                  ));
                 }
                 crate::ir::RuntimePrimitive::Schedule { ms_var } => {
                   let idx_expr = if let Some(pi) = func.in_vars.iter().position(|p| p.0 == ms_var.0) {
@@ -1874,6 +2071,10 @@ fn generate_global_step(ir: &IR) -> String {
                     )),
                   }
                 }
+                AwaitSpec::Timeout { ms, next } => {
+                  let next_v = variant_name(&[fiber_name.0.as_str(), func_name, &next.0]);
+                  arm_parts.push(format!("SelectArm::Timeout {{ ms: {}, next: State::{} }}", ms, next_v));
+                }
               }
             }
             out.push_str("      StepResult::Select(vec![");
@@ -2040,5 +2241,38 @@ mod tests {
     // With compact-by-type Value enum, we expect used types only
     assert!(code.contains("String(String)"));
     assert!(code.contains("OptionUser(Option<User>)"));
+    // system events are always there
+    assert!(code.contains("pub enum SystemFiberEvent"));
+    assert!(code.contains("SystemFiberEvent(SystemFiberEvent)"));
+    // schema hash is stable for the same IR
+    assert!(code.contains("pub const IR_SCHEMA_HASH: u64 = 0x"));
+    assert_eq!(code, generate_rust_types(&ir));
+  }
+
+  #[test]
+  fn finds_nested_futures() {
+    let ir = IR {
+      types: vec![Type::Struct(
+        "Job".into(),
+        vec![
+          StructField { name: "name".into(), ty: Type::String },
+          StructField { name: "result".into(), ty: Type::Future(Box::new(Type::UInt64)) },
+        ],
+        String::new(),
+      )],
+      fibers: HashMap::from([(
+        FiberType::new("worker"),
+        Fiber {
+          heap: HashMap::from([("jobs".into(), Type::Array(Box::new(Type::Custom("Job".into()))))]),
+          init_vars: vec![InVar("first", Type::Option(Box::new(Type::Custom("Job".into()))))],
+          funcs: HashMap::new(),
+        },
+      )]),
+    };
+
+    let code = generate_rust_types(&ir);
+    assert!(code.contains("Value::OptionJob(v) => { if let Some(x0) = &v { ids.push(x0.result.0.clone()); } }"));
+    assert!(code.contains("for x0 in heap.worker.jobs.iter() { ids.push(x0.result.0.clone()); }"));
+    assert!(code.contains("if let Some(x0) = &heap.worker.in_vars.first { ids.push(x0.result.0.clone()); }"));
   }
 }
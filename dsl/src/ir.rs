@@ -186,10 +186,11 @@ pub enum RuntimePrimitive {
   Future,
   /// `name` should be unique and should reference LocalVar typed as String
   /// if `public` == true - new messages can come not from other fibers but from gateways as well
-  /// TODO: provide message types here as well so I can do some transpile and compile-time checks on types
+  /// `message` - name of the `PubQueueMessage` type gateways can send to the queue, required for public queues
   Queue {
     name: LocalVarRef,
     public: bool,
+    message: Option<String>,
   },
   /// creates a future that will be resolved after provided amount of milliseconds
   Schedule {
@@ -238,6 +239,8 @@ pub enum AwaitSpec {
     /// next step after await is resolved in this arm
     next: StepId,
   },
+  /// resolves after `ms` milliseconds if no other arm has been resolved before
+  Timeout { ms: u64, next: StepId },
 }
 
 #[derive(Debug, Clone)]
@@ -579,6 +582,7 @@ fn uses_correct_variables(
                 explanation.push_str(&format!("{:?} references {} that is not defined\n", id, message_var.0));
               }
             }
+            AwaitSpec::Timeout { .. } => {}
           }
         }
       }
@@ -693,7 +697,7 @@ fn uses_correct_variables(
           // primitive-specific checks
           match p {
             RuntimePrimitive::Future => {}
-            RuntimePrimitive::Queue { name: qname, public: _ } => {
+            RuntimePrimitive::Queue { name: qname, public, message } => {
               if let Some(t) = vars_map.get(qname.0) {
                 if *t != Type::String {
                   explanation
@@ -702,6 +706,18 @@ fn uses_correct_variables(
               } else {
                 explanation.push_str(&format!("{:?} references {} that is not defined\n", id, qname.0));
               }
+              let is_pub_message = |name: &String| {
+                ir.types.iter().any(|t| matches!(t, Type::PubQueueMessage { name: n, .. } if n == name))
+              };
+              match message {
+                Some(m) if !is_pub_message(m) => explanation
+                  .push_str(&format!("{:?} Create: queue message type '{}' must be PubQueueMessage\n", id, m)),
+                None if *public => explanation.push_str(&format!(
+                  "{:?} Create: public queue '{}' must declare its PubQueueMessage type\n",
+                  id, qname.0
+                )),
+                _ => {}
+              }
             }
             RuntimePrimitive::Schedule { ms_var } => {
               if let Some(t) = vars_map.get(ms_var.0) {
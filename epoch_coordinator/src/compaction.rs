@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+/// Describes how old epochs are folded into `EpochCheckpoint` and removed from the history
+#[derive(Clone, Copy, Debug)]
+pub struct CompactionParams {
+  /// how many epochs before the slowest live node are kept in the history
+  /// a node that is a bit behind the others or reconnects can still read them without falling back to the checkpoint
+  pub retain_epochs: u64,
+
+  /// how often compaction is attempted and node reports that it is alive
+  pub period: Duration,
+}
+
+impl Default for CompactionParams {
+  fn default() -> CompactionParams {
+    CompactionParams { retain_epochs: 200, period: Duration::from_secs(10) }
+  }
+}
+
+impl CompactionParams {
+  pub fn set_retain_epochs(
+    mut self,
+    retain_epochs: u64,
+  ) -> CompactionParams {
+    self.retain_epochs = retain_epochs;
+    self
+  }
+
+  pub fn set_period(
+    mut self,
+    period: Duration,
+  ) -> CompactionParams {
+    self.period = period;
+    self
+  }
+}
+
+/// sequence number up to which(inclusive) the history can be folded into a new checkpoint
+/// `applied` - last applied sequence numbers of all the live nodes
+/// returns None if there is nothing to compact
+pub(crate) fn compaction_target(
+  applied: impl IntoIterator<Item = u64>,
+  checkpoint_sn: Option<u64>,
+  retain_epochs: u64,
+) -> Option<u64> {
+  let slowest = applied.into_iter().min()?;
+  let target = slowest.checked_sub(retain_epochs)?;
+
+  match checkpoint_sn {
+    Some(sn) if target <= sn => None,
+    _ => Some(target),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_compaction_target() {
+    struct Case<'a> {
+      label: &'a str,
+      applied: Vec<u64>,
+      checkpoint_sn: Option<u64>,
+      retain_epochs: u64,
+      expected: Option<u64>,
+    }
+
+    let cases = vec![
+      Case { label: "no live nodes", applied: vec![], checkpoint_sn: None, retain_epochs: 0, expected: None },
+      Case { label: "short history", applied: vec![10, 20], checkpoint_sn: None, retain_epochs: 20, expected: None },
+      Case {
+        label: "slowest node",
+        applied: vec![50, 30, 40],
+        checkpoint_sn: None,
+        retain_epochs: 10,
+        expected: Some(20),
+      },
+      Case { label: "nothing new", applied: vec![30], checkpoint_sn: Some(20), retain_epochs: 10, expected: None },
+      Case {
+        label: "checkpoint moves",
+        applied: vec![30],
+        checkpoint_sn: Some(15),
+        retain_epochs: 10,
+        expected: Some(20),
+      },
+      Case { label: "no retention", applied: vec![7], checkpoint_sn: None, retain_epochs: 0, expected: Some(7) },
+    ];
+
+    for case in cases {
+      assert_eq!(
+        case.expected,
+        compaction_target(case.applied, case.checkpoint_sn, case.retain_epochs),
+        "{}",
+        case.label
+      );
+    }
+  }
+}
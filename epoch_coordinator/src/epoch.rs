@@ -1,8 +1,13 @@
-use common::{logical_time::LogicalTimeAbsoluteMs, range_key::U64BlobIdClosedInterval};
+use common::{
+  logical_time::LogicalTimeAbsoluteMs,
+  range_key::{KeyOffset, KeyRange, U64BlobIdClosedInterval, range_offset_from_unique_blob_id},
+};
 use derive_more::Display;
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::num::NonZeroUsize;
 
 #[derive(Debug, Clone, Display, Serialize, Deserialize, PartialEq, Eq)]
 #[display("Epoch {{ sn: {:?} increments: {:?}, hash: 0x{:X} }}", sequence_number, increments, hash.iter().fold(0u128, |acc, &x| (acc << 8) | x as u128))]
@@ -17,6 +22,14 @@ pub struct Epoch {
 
   pub creation_time: LogicalTimeAbsoluteMs,
 
+  /// gateways that get a new key range once the epoch is applied, see `KeyRangeLeases::apply`
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub leases: Vec<LeaseRequest>,
+
+  /// changes of the nodes that publish epochs and make consensus, see `Membership::apply`
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub membership: Vec<MembershipChange>,
+
   hash: [u8; 32],
 }
 
@@ -26,14 +39,123 @@ impl Epoch {
     increments: Vec<U64BlobIdClosedInterval>,
     prev_epoch: Option<&Epoch>,
     time_tick: LogicalTimeAbsoluteMs,
+  ) -> Epoch {
+    Epoch::build(creator, increments, prev_epoch.map(|e| (e.sequence_number, &e.hash)), time_tick)
+  }
+
+  /// the same as `next` but for the case when previous epochs have been already folded into a checkpoint
+  pub fn next_after_checkpoint(
+    creator: PeerId,
+    increments: Vec<U64BlobIdClosedInterval>,
+    checkpoint: &EpochCheckpoint,
+    time_tick: LogicalTimeAbsoluteMs,
+  ) -> Epoch {
+    Epoch::build(creator, increments, Some((checkpoint.sequence_number, &checkpoint.hash)), time_tick)
+  }
+
+  /// adds key range lease requests to the epoch, should be called once right after `next`/`next_after_checkpoint`
+  /// epochs without leases have the same hash as before leases existed
+  pub fn with_leases(
+    mut self,
+    leases: Vec<LeaseRequest>,
+  ) -> Epoch {
+    if leases.is_empty() {
+      return self;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(self.hash);
+    for lease in &leases {
+      hasher.update(lease.gateway.to_bytes());
+      match lease.replaces {
+        Some(range) => {
+          hasher.update([1]);
+          hasher.update(range.0.to_le_bytes());
+        }
+        None => hasher.update([0]),
+      }
+    }
+
+    self.hash = hasher.finalize().into();
+    self.leases = leases;
+    self
+  }
+
+  /// adds membership changes to the epoch, should be called once right after `with_leases`
+  /// epochs without membership changes have the same hash as before membership existed
+  pub fn with_membership(
+    mut self,
+    changes: Vec<MembershipChange>,
+  ) -> Epoch {
+    if changes.is_empty() {
+      return self;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(self.hash);
+    for change in &changes {
+      hasher.update(change.node.to_bytes());
+      hasher.update(match change.action {
+        MembershipAction::Add => [0],
+        MembershipAction::Remove => [1],
+      });
+      hasher.update(change.effective_from.to_le_bytes());
+    }
+
+    self.hash = hasher.finalize().into();
+    self.membership = changes;
+    self
+  }
+
+  pub fn hash(&self) -> &[u8; 32] {
+    &self.hash
+  }
+
+  /// checks that the epoch goes right after `prev_epoch` and its hash is built on top of it
+  pub fn verify_after(
+    &self,
+    prev_epoch: Option<&Epoch>,
+  ) -> Result<(), ChainError> {
+    self.verify(prev_epoch.map(|e| (e.sequence_number, &e.hash)))
+  }
+
+  /// the same as `verify_after` but for the epoch that goes right after the checkpoint
+  pub fn verify_after_checkpoint(
+    &self,
+    checkpoint: &EpochCheckpoint,
+  ) -> Result<(), ChainError> {
+    self.verify(Some((checkpoint.sequence_number, &checkpoint.hash)))
+  }
+
+  fn verify(
+    &self,
+    prev: Option<(u64, &[u8; 32])>,
+  ) -> Result<(), ChainError> {
+    let expected = Epoch::build(self.creator, self.increments.clone(), prev, self.creation_time)
+      .with_leases(self.leases.clone())
+      .with_membership(self.membership.clone());
+    if expected.sequence_number != self.sequence_number {
+      return Err(ChainError::SequenceNumber { expected: expected.sequence_number, got: self.sequence_number });
+    }
+    if expected.hash != self.hash {
+      return Err(ChainError::Hash);
+    }
+    Ok(())
+  }
+
+  fn build(
+    creator: PeerId,
+    increments: Vec<U64BlobIdClosedInterval>,
+    prev: Option<(u64, &[u8; 32])>,
+    time_tick: LogicalTimeAbsoluteMs,
   ) -> Epoch {
     let mut hasher = Sha256::new();
 
     let mut sequence_number = 0;
     // Include previous hash if it exists
-    if let Some(prev) = prev_epoch {
-      hasher.update(prev.hash);
-      sequence_number = prev.sequence_number + 1;
+    if let Some((prev_sequence_number, prev_hash)) = prev {
+      hasher.update(prev_hash);
+      sequence_number = prev_sequence_number + 1;
     }
 
     // Include current epoch data
@@ -43,9 +165,405 @@ impl Epoch {
     }
 
     hasher.update(creator.to_bytes());
+    // nodes execute the epoch at this time, so it can't be changed after the epoch is published
+    hasher.update(time_tick.0.to_le_bytes());
 
     let hash = hasher.finalize().into();
 
-    Epoch { creator, sequence_number, increments, hash, creation_time: time_tick }
+    Epoch { creator, sequence_number, increments, hash, creation_time: time_tick, leases: vec![], membership: vec![] }
+  }
+}
+
+/// gateway asks for a new key range instead of the one it has
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LeaseRequest {
+  pub gateway: PeerId,
+  /// the range the gateway has now, `None` if it doesn't have any yet
+  pub replaces: Option<KeyRange>,
+}
+
+/// Key ranges that have been leased to gateways
+///
+/// it's built only from epochs, so every node has the same leases after the same epoch
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyRangeLeases {
+  /// the latest range of every gateway
+  pub ranges: HashMap<PeerId, KeyRange>,
+  /// ranges before it have been already leased
+  pub next_free: KeyRange,
+}
+
+impl KeyRangeLeases {
+  /// grants the requests in order and returns what each gateway has got
+  /// a request is granted only if the gateway still has `replaces`, so repeated requests don't take extra ranges
+  /// ranges that already have committed transactions(ex: from gateways with a static range) are skipped
+  pub fn apply(
+    &mut self,
+    requests: &[LeaseRequest],
+    commited_offsets: &HashMap<KeyRange, KeyOffset>,
+  ) -> Vec<(PeerId, KeyRange)> {
+    let mut granted = vec![];
+    for request in requests {
+      if self.ranges.get(&request.gateway).copied() != request.replaces {
+        continue;
+      }
+
+      while commited_offsets.contains_key(&self.next_free) {
+        self.next_free.0 += 1;
+      }
+      let range = self.next_free;
+      self.next_free.0 += 1;
+
+      self.ranges.insert(request.gateway, range);
+      granted.push((request.gateway, range));
+    }
+    granted
+  }
+}
+
+#[derive(Debug, Clone, Copy, Display, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MembershipAction {
+  Add,
+  Remove,
+}
+
+/// asks to add a node to the membership or remove it from there
+#[derive(Debug, Clone, Copy, Display, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[display("{action} {node} from epoch {effective_from}")]
+pub struct MembershipChange {
+  pub node: PeerId,
+  pub action: MembershipAction,
+  /// the first epoch that is published and confirmed by the changed membership <br>
+  /// change can't affect the epoch it's committed in, so it takes effect not earlier than the next one
+  pub effective_from: u64,
+}
+
+/// Nodes that publish epochs and make consensus
+///
+/// it's built only from epochs, so every node has the same membership after the same epoch <br>
+/// empty membership means that it hasn't been set up yet and nodes that are connected now do everything
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Membership {
+  pub nodes: BTreeSet<PeerId>,
+  /// committed changes that haven't taken effect yet, by the epoch they take effect at
+  pub scheduled: BTreeMap<u64, Vec<MembershipChange>>,
+}
+
+impl Membership {
+  /// schedules changes committed in the epoch `sequence_number`
+  pub fn apply(
+    &mut self,
+    sequence_number: u64,
+    changes: &[MembershipChange],
+  ) {
+    for change in changes {
+      let effective_from = change.effective_from.max(sequence_number + 1);
+      self.scheduled.entry(effective_from).or_default().push(*change);
+    }
+  }
+
+  /// makes the changes that take effect at `sequence_number` or earlier, returns true if members have changed
+  pub fn advance_to(
+    &mut self,
+    sequence_number: u64,
+  ) -> bool {
+    let mut changed = false;
+    while let Some(entry) = self.scheduled.first_entry()
+      && *entry.key() <= sequence_number
+    {
+      for change in entry.remove() {
+        changed |= match change.action {
+          MembershipAction::Add => self.nodes.insert(change.node),
+          MembershipAction::Remove => self.nodes.remove(&change.node),
+        };
+      }
+    }
+    changed
+  }
+
+  /// true if the change adds or removes something after the committed changes take effect
+  pub fn changes_anything(
+    &self,
+    change: &MembershipChange,
+  ) -> bool {
+    let mut will_be_member = self.nodes.contains(&change.node);
+    for scheduled in self.scheduled.values().flatten().filter(|c| c.node == change.node) {
+      will_be_member = scheduled.action == MembershipAction::Add;
+    }
+    will_be_member != (change.action == MembershipAction::Add)
+  }
+
+  pub fn is_member(
+    &self,
+    node: &PeerId,
+  ) -> bool {
+    self.nodes.contains(node)
+  }
+
+  /// how many members should have a transaction to confirm it: majority of the members
+  /// `None` if membership hasn't been set up yet
+  pub fn quorum(&self) -> Option<NonZeroUsize> {
+    NonZeroUsize::new(self.nodes.len()).map(|n| n.get() / 2 + 1).and_then(NonZeroUsize::new)
+  }
+}
+
+/// Why an epoch doesn't belong to the chain
+#[derive(Debug, Clone, Display, PartialEq, Eq)]
+pub enum ChainError {
+  #[display("expected sequence number {expected}, got {got}")]
+  SequenceNumber { expected: u64, got: u64 },
+  #[display("hash doesn't match the predecessor")]
+  Hash,
+}
+
+/// All the epochs up to `sequence_number`(inclusive) folded into one record
+/// so the history before it can be removed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EpochCheckpoint {
+  /// sequence number of the last folded epoch
+  pub sequence_number: u64,
+
+  /// hash of the last folded epoch, the next epoch is chained to it
+  pub hash: [u8; 32],
+
+  /// offsets that have been committed up to `sequence_number`(inclusive)
+  pub commited_offsets: HashMap<KeyRange, KeyOffset>,
+
+  /// key ranges leased up to `sequence_number`(inclusive)
+  #[serde(default)]
+  pub leases: KeyRangeLeases,
+
+  /// membership of the epoch right after `sequence_number`
+  #[serde(default)]
+  pub membership: Membership,
+}
+
+impl EpochCheckpoint {
+  /// folds `epochs` into the checkpoint. `epochs` should go one by one right after the checkpoint(or from 0 if there is no checkpoint)
+  /// returns None if there is a gap or `epochs` is empty
+  pub fn fold<'a>(
+    prev: Option<&EpochCheckpoint>,
+    epochs: impl IntoIterator<Item = &'a Epoch>,
+  ) -> Option<EpochCheckpoint> {
+    let mut next_sn = prev.map_or(0, |c| c.sequence_number + 1);
+    let mut commited_offsets = prev.map(|c| c.commited_offsets.clone()).unwrap_or_default();
+    let mut leases = prev.map(|c| c.leases.clone()).unwrap_or_default();
+    let mut membership = prev.map(|c| c.membership.clone()).unwrap_or_default();
+    let mut last_hash = None;
+
+    for epoch in epochs {
+      if epoch.sequence_number != next_sn {
+        return None;
+      }
+
+      for interval in &epoch.increments {
+        let (range, offset) = range_offset_from_unique_blob_id(interval.end());
+        commited_offsets.entry(range).and_modify(|o| *o = (*o).max(offset)).or_insert(offset);
+      }
+      leases.apply(&epoch.leases, &commited_offsets);
+      membership.apply(epoch.sequence_number, &epoch.membership);
+      membership.advance_to(epoch.sequence_number + 1);
+
+      next_sn += 1;
+      last_hash = Some(epoch.hash);
+    }
+
+    last_hash.map(|hash| EpochCheckpoint { sequence_number: next_sn - 1, hash, commited_offsets, leases, membership })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fold_checkpoint() {
+    let peer_id = PeerId::random();
+    let e0 = Epoch::next(
+      peer_id,
+      vec![
+        U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(0), KeyOffset(3)),
+        U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(1), KeyOffset(0), KeyOffset(1)),
+      ],
+      None,
+      LogicalTimeAbsoluteMs(10),
+    );
+    let e1 = Epoch::next(
+      peer_id,
+      vec![U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(4), KeyOffset(6))],
+      Some(&e0),
+      LogicalTimeAbsoluteMs(20),
+    );
+    let e2 = Epoch::next(
+      peer_id,
+      vec![U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(2), KeyOffset(0), KeyOffset(0))],
+      Some(&e1),
+      LogicalTimeAbsoluteMs(30),
+    );
+
+    let cp0 = EpochCheckpoint::fold(None, [&e0]).unwrap();
+    let cp2 = EpochCheckpoint::fold(Some(&cp0), [&e1, &e2]).unwrap();
+
+    struct Case<'a> {
+      label: &'a str,
+      got: Option<EpochCheckpoint>,
+      expected: Option<EpochCheckpoint>,
+    }
+
+    let cases = vec![
+      Case { label: "empty", got: EpochCheckpoint::fold(None, []), expected: None },
+      Case { label: "gap at the start", got: EpochCheckpoint::fold(None, [&e1]), expected: None },
+      Case { label: "gap in the middle", got: EpochCheckpoint::fold(None, [&e0, &e2]), expected: None },
+      Case {
+        label: "from scratch",
+        got: EpochCheckpoint::fold(None, [&e0, &e1]),
+        expected: Some(EpochCheckpoint {
+          sequence_number: 1,
+          hash: e1.hash,
+          commited_offsets: HashMap::from([(KeyRange(0), KeyOffset(6)), (KeyRange(1), KeyOffset(1))]),
+          leases: KeyRangeLeases::default(),
+          membership: Membership::default(),
+        }),
+      },
+      Case {
+        label: "on top of a checkpoint",
+        got: Some(cp2.clone()),
+        expected: Some(EpochCheckpoint {
+          sequence_number: 2,
+          hash: e2.hash,
+          commited_offsets: HashMap::from([
+            (KeyRange(0), KeyOffset(6)),
+            (KeyRange(1), KeyOffset(1)),
+            (KeyRange(2), KeyOffset(0)),
+          ]),
+          leases: KeyRangeLeases::default(),
+          membership: Membership::default(),
+        }),
+      },
+    ];
+
+    for case in cases {
+      assert_eq!(case.expected, case.got, "{}", case.label);
+    }
+
+    // chain continues from the checkpoint in the same way as from the epoch itself
+    let after_epoch = Epoch::next(peer_id, vec![], Some(&e2), LogicalTimeAbsoluteMs(40));
+    let after_checkpoint = Epoch::next_after_checkpoint(peer_id, vec![], &cp2, LogicalTimeAbsoluteMs(40));
+    assert_eq!(after_epoch, after_checkpoint);
+
+    assert_eq!(Ok(()), e0.verify_after(None));
+    assert_eq!(Ok(()), e2.verify_after(Some(&e1)));
+    assert_eq!(Ok(()), after_checkpoint.verify_after_checkpoint(&cp2));
+    assert_eq!(Err(ChainError::SequenceNumber { expected: 1, got: 2 }), e2.verify_after(Some(&e0)));
+    assert_eq!(Err(ChainError::SequenceNumber { expected: 0, got: 1 }), e1.verify_after(None));
+
+    let mut forged = e1.clone();
+    forged.increments =
+      vec![U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(4), KeyOffset(9))];
+    assert_eq!(Err(ChainError::Hash), forged.verify_after(Some(&e0)));
+    let mut forged = e1.clone();
+    forged.creation_time = LogicalTimeAbsoluteMs(25);
+    assert_eq!(Err(ChainError::Hash), forged.verify_after(Some(&e0)));
+    assert_ne!(
+      after_epoch.hash,
+      Epoch::next(peer_id, vec![], Some(&e2), LogicalTimeAbsoluteMs(41)).hash,
+      "creation time is a part of the hash"
+    );
+
+    // checkpoint is stored as json
+    let restored: EpochCheckpoint = serde_json::from_slice(&serde_json::to_vec(&cp2).unwrap()).unwrap();
+    assert_eq!(cp2, restored);
+  }
+
+  #[test]
+  fn test_key_range_leases() {
+    let node = PeerId::random();
+    let (gw1, gw2) = (PeerId::random(), PeerId::random());
+    let lease = |gateway: PeerId, replaces: Option<u64>| LeaseRequest { gateway, replaces: replaces.map(KeyRange) };
+
+    // range 0 is already used by a gateway with a static range
+    let e0 = Epoch::next(
+      node,
+      vec![U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(0), KeyOffset(3))],
+      None,
+      LogicalTimeAbsoluteMs(10),
+    )
+    .with_leases(vec![lease(gw1, None), lease(gw2, None)]);
+    // the second request from gw1 for the first range is a duplicate
+    let e1 = Epoch::next(node, vec![], Some(&e0), LogicalTimeAbsoluteMs(20)).with_leases(vec![
+      lease(gw1, Some(1)),
+      lease(gw1, Some(1)),
+      lease(gw2, None),
+    ]);
+
+    let mut leases = KeyRangeLeases::default();
+    let offsets = HashMap::from([(KeyRange(0), KeyOffset(3))]);
+    assert_eq!(vec![(gw1, KeyRange(1)), (gw2, KeyRange(2))], leases.apply(&e0.leases, &offsets));
+    assert_eq!(vec![(gw1, KeyRange(3))], leases.apply(&e1.leases, &offsets));
+
+    let checkpoint = EpochCheckpoint::fold(None, [&e0, &e1]).unwrap();
+    assert_eq!(leases, checkpoint.leases);
+    assert_eq!(KeyRange(4), checkpoint.leases.next_free);
+
+    // leases are a part of the hash
+    assert_eq!(Ok(()), e1.verify_after(Some(&e0)));
+    let mut forged = e1.clone();
+    forged.leases = vec![lease(gw2, Some(2))];
+    assert_eq!(Err(ChainError::Hash), forged.verify_after(Some(&e0)));
+
+    // epochs without leases don't have them in json
+    let no_leases = Epoch::next(node, vec![], Some(&e1), LogicalTimeAbsoluteMs(30)).with_leases(vec![]);
+    assert!(!String::from_utf8(serde_json::to_vec(&no_leases).unwrap()).unwrap().contains("leases"));
+  }
+
+  #[test]
+  fn test_membership() {
+    let node = PeerId::random();
+    let (n1, n2, n3) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let change =
+      |node: PeerId, action: MembershipAction, effective_from: u64| MembershipChange { node, action, effective_from };
+
+    // changes can't take effect earlier than the next epoch
+    let e0 = Epoch::next(node, vec![], None, LogicalTimeAbsoluteMs(10)).with_membership(vec![
+      change(n1, MembershipAction::Add, 0),
+      change(n2, MembershipAction::Add, 0),
+      change(n3, MembershipAction::Add, 3),
+    ]);
+    let e1 = Epoch::next(n1, vec![], Some(&e0), LogicalTimeAbsoluteMs(20)).with_membership(vec![change(
+      n2,
+      MembershipAction::Remove,
+      2,
+    )]);
+
+    let mut membership = Membership::default();
+    assert_eq!(None, membership.quorum());
+    membership.apply(0, &e0.membership);
+    assert!(!membership.advance_to(0));
+    assert!(membership.advance_to(1));
+    assert_eq!(BTreeSet::from([n1, n2]), membership.nodes);
+    assert_eq!(NonZeroUsize::new(2), membership.quorum());
+
+    membership.apply(1, &e1.membership);
+    assert!(!membership.changes_anything(&change(n2, MembershipAction::Remove, 5)));
+    assert!(membership.changes_anything(&change(n2, MembershipAction::Add, 5)));
+    assert!(!membership.changes_anything(&change(n3, MembershipAction::Add, 5)));
+    assert!(membership.advance_to(3));
+    assert_eq!(BTreeSet::from([n1, n3]), membership.nodes);
+    assert!(membership.scheduled.is_empty());
+
+    // checkpoint keeps the membership of the epoch after it, with the changes that haven't taken effect yet
+    let checkpoint = EpochCheckpoint::fold(None, [&e0, &e1]).unwrap();
+    assert_eq!(BTreeSet::from([n1]), checkpoint.membership.nodes);
+    assert_eq!(BTreeMap::from([(3, vec![change(n3, MembershipAction::Add, 3)])]), checkpoint.membership.scheduled);
+    let restored: EpochCheckpoint = serde_json::from_slice(&serde_json::to_vec(&checkpoint).unwrap()).unwrap();
+    assert_eq!(checkpoint, restored);
+
+    // membership changes are a part of the hash
+    assert_eq!(Ok(()), e1.verify_after(Some(&e0)));
+    let mut forged = e1.clone();
+    forged.membership = vec![change(n1, MembershipAction::Remove, 2)];
+    assert_eq!(Err(ChainError::Hash), forged.verify_after(Some(&e0)));
+    let no_changes = Epoch::next(node, vec![], Some(&e1), LogicalTimeAbsoluteMs(30)).with_membership(vec![]);
+    assert!(!String::from_utf8(serde_json::to_vec(&no_changes).unwrap()).unwrap().contains("membership"));
   }
 }
@@ -1,7 +1,14 @@
-use super::epoch::Epoch;
-use crate::interface::{EpochRequest, EpochUpdates, Interface};
+use super::epoch::{Epoch, EpochCheckpoint};
+use crate::compaction::{CompactionParams, compaction_target};
+use crate::interface::{
+  EpochCoordinator, EpochRequest, EpochUpdates, HISTORY_RESCAN_PERIOD, Interface, OrderedUpdatesSender, StartError,
+};
 use derive_more::Display;
-use etcd_client::{Client, Compare, CompareOp, Error, Txn, TxnOp, WatchOptions, WatchResponse};
+use etcd_client::{
+  Client, Compare, CompareOp, Error, GetOptions, LeaseKeepAliveStream, LeaseKeeper, PutOptions, Txn, TxnOp,
+  WatchOptions, WatchResponse,
+};
+use futures::future::{BoxFuture, FutureExt};
 use log::{error, info, warn};
 use opentelemetry::{
   KeyValue, global,
@@ -10,7 +17,7 @@ use opentelemetry::{
 use serde::{Deserialize, Serialize};
 use std::{sync::OnceLock, time::Duration};
 use tokio::sync::{mpsc::UnboundedSender, watch::Receiver};
-use tokio::time::Instant;
+use tokio::time::{Instant, interval};
 
 fn epoch_coordinator_requests_to_etcd_counter() -> &'static Counter<u64> {
   static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
@@ -42,6 +49,8 @@ fn epoch_coordinator_maroon_commited_transactions_counter() -> &'static Counter<
 pub const MAROON_PREFIX: &str = "/maroon";
 const MAROON_LATEST: &str = "/maroon/latest";
 const MAROON_HISTORY: &str = "/maroon/history";
+const MAROON_CHECKPOINT: &str = "/maroon/checkpoint";
+const MAROON_APPLIED: &str = "/maroon/applied";
 
 /// implementation uses etcd as a backend for EpochCoordinator
 ///
@@ -58,13 +67,21 @@ const MAROON_HISTORY: &str = "/maroon/history";
 ///       epoch_obj_100500
 ///   /latest
 ///     epoch_obj_100500
+///   /checkpoint
+///     checkpoint_obj (all the epochs before /history are folded here)
+///   /applied
+///     /<lease_id>
+///       last applied sequence number of a live node
 ///
-///
-/// TODO: introduce some compaction or GC for older objects in history and keep the size relatively small (200-300 objects in history)
+/// Every node periodically folds the history that all the live nodes have applied(minus `CompactionParams::retain_epochs`)
+/// into the checkpoint and removes it from `/history`. A node is live while its `/applied` key's lease is alive
 pub struct EtcdEpochCoordinator {
   etcd_endpoints: Vec<String>,
   new_epoch_receiver: Receiver<Option<EpochRequest>>,
   epoch_updates_sender: UnboundedSender<EpochUpdates>,
+  applied_receiver: Receiver<Option<u64>>,
+  last_applied_sn: Option<u64>,
+  compaction: CompactionParams,
 }
 
 impl EtcdEpochCoordinator {
@@ -76,31 +93,50 @@ impl EtcdEpochCoordinator {
       etcd_endpoints: etcd_endpoints.clone(),
       new_epoch_receiver: interface.receiver,
       epoch_updates_sender: interface.sender,
+      applied_receiver: interface.applied_receiver,
+      last_applied_sn: None,
+      compaction: CompactionParams::default(),
     }
   }
 
+  pub fn set_compaction_params(
+    mut self,
+    params: CompactionParams,
+  ) -> EtcdEpochCoordinator {
+    self.compaction = params;
+    self
+  }
+
+  /// epochs up to `sn`(inclusive) are already applied by the node and won't be delivered
+  pub fn set_last_applied_sequence_number(
+    mut self,
+    sn: Option<u64>,
+  ) -> EtcdEpochCoordinator {
+    self.last_applied_sn = sn;
+    self
+  }
+
   /// starts infinite loop. After this all the communications with corrdinator only through `EpochCoordinatorInterface`
   pub async fn start(self) -> Result<(), Error> {
     info!("start epoch coordinator");
 
     let mut client = Client::connect(self.etcd_endpoints, None).await?;
-    let mut last_rev: Option<i64> = None;
     let mut last_committed_sn: Option<u64> = None;
 
-    let mut sender = self.epoch_updates_sender;
+    let mut updates = OrderedUpdatesSender::new(self.epoch_updates_sender, self.last_applied_sn);
     let mut receiver = self.new_epoch_receiver;
+    let mut applied_receiver = self.applied_receiver;
+
+    let mut liveness = Liveness::new(self.compaction.period);
+    let mut compaction_ticker = interval(self.compaction.period);
+    let mut rescan_ticker = interval(HISTORY_RESCAN_PERIOD);
 
     let mut watcher_creation_timeout = Duration::from_millis(50);
 
     loop {
-      let options = {
-        let mut w = WatchOptions::new().with_prefix();
-        if let Some(r) = last_rev {
-          w = w.with_start_revision(r);
-        }
-        w
-      };
-      let watch_result = client.watch(MAROON_LATEST, Some(options)).await;
+      // watch is created before reading the history so nothing committed in between is lost
+      // everything that was missed while reconnecting is backfilled from history, duplicates are skipped by `updates`
+      let watch_result = client.watch(MAROON_LATEST, Some(WatchOptions::new().with_prefix())).await;
 
       let (watcher, mut watch_stream) = match watch_result {
         Ok(res) => {
@@ -122,9 +158,24 @@ impl EtcdEpochCoordinator {
       // Keep watcher alive within the task scope
       let _watcher = watcher;
 
+      if let Err(e) = backfill_history(&mut client, &mut updates).await {
+        error!("load epoch history err: {e}; reconnecting...");
+        tokio::time::sleep(watcher_creation_timeout).await;
+        continue;
+      }
+
       loop {
         tokio::select! {
-          _ = receiver.changed() => {
+          changed = receiver.changed() => {
+              if changed.is_err() {
+                info!("epoch requests channel closed, stop epoch coordinator");
+                // other nodes don't wait for this one to compact the history
+                if let Err(e) = liveness.revoke(&mut client).await {
+                  error!("revoke node liveness err: {e}");
+                }
+                return Ok(());
+              }
+
               let next = receiver.borrow_and_update().clone();
               if let Some(payload) = next {
                   let sn = payload.epoch.sequence_number;
@@ -138,12 +189,33 @@ impl EtcdEpochCoordinator {
                   }
               }
           },
+          Ok(()) = applied_receiver.changed() => {
+            liveness.applied_sn = *applied_receiver.borrow_and_update();
+          },
+          _ = rescan_ticker.tick(), if updates.has_history_gap() => {
+            if let Err(e) = backfill_history(&mut client, &mut updates).await {
+              error!("load epoch history err: {e}; reconnecting...");
+              break; // breaks inner loop - reconnect
+            }
+          },
+          _ = compaction_ticker.tick() => {
+            if let Err(e) = liveness.refresh(&mut client).await {
+              error!("refresh node liveness err: {e}");
+            }
+            match compact_history(&mut client, self.compaction.retain_epochs).await {
+              Ok(Some(checkpoint)) => info!("epoch history is compacted up to {}", checkpoint.sequence_number),
+              Ok(None) => {},
+              Err(e) => error!("compact epoch history err: {e}"),
+            }
+          },
           watch_result = watch_stream.message() => match watch_result{
             Ok(Some(message)) => {
-              if let Some(h) = message.header() {
-                last_rev = Some(h.revision());
+              if !handle_watch_message(&mut updates, message) {
+                if let Err(e) = backfill_history(&mut client, &mut updates).await {
+                  error!("load epoch history err: {e}; reconnecting...");
+                  break; // breaks inner loop - reconnect
+                }
               }
-              handle_watch_message(&mut sender, message);
             }
             Ok(None) => {
               // Server cleanly closed the watch (EOF)
@@ -166,25 +238,240 @@ impl EtcdEpochCoordinator {
   }
 }
 
+impl EpochCoordinator for EtcdEpochCoordinator {
+  fn start(self: Box<Self>) -> BoxFuture<'static, Result<(), StartError>> {
+    async move { EtcdEpochCoordinator::start(*self).await.map_err(StartError::from) }.boxed()
+  }
+
+  fn set_last_applied_sequence_number(
+    self: Box<Self>,
+    sn: Option<u64>,
+  ) -> Box<dyn EpochCoordinator> {
+    Box::new(EtcdEpochCoordinator::set_last_applied_sequence_number(*self, sn))
+  }
+}
+
 #[derive(Deserialize, Serialize, Debug, Display)]
 struct EpochObject {
   epoch: Epoch,
 }
 
+/// returns false if some epochs were skipped by the watch and history backfill is needed
 fn handle_watch_message(
-  sender: &mut UnboundedSender<EpochUpdates>,
+  updates: &mut OrderedUpdatesSender,
   message: WatchResponse,
-) {
+) -> bool {
   for event in message.events() {
     if let Some(kv) = event.kv() {
       if let Ok(epoch_obj) = serde_json::from_slice::<EpochObject>(kv.value()) {
         info!("etcd watch got {} epoch", epoch_obj.epoch.sequence_number);
-        if let Err(e) = sender.send(EpochUpdates::New(epoch_obj.epoch)) {
-          error!("failed to send epoch update: {}", e);
+        if !updates.send(epoch_obj.epoch) {
+          return false;
         }
       }
     }
   }
+  true
+}
+
+/// reads `/maroon/checkpoint` with `/maroon/history` and delivers all the epochs that haven't been delivered yet
+async fn backfill_history(
+  client: &mut Client,
+  updates: &mut OrderedUpdatesSender,
+) -> Result<(), Error> {
+  // history is read before the checkpoint: compaction puts a new checkpoint first and only then removes the history
+  // so if some epochs are already removed - they are in the checkpoint
+  let history = load_history(client).await?;
+  let checkpoint = load_checkpoint(client).await?;
+
+  if let Some((checkpoint, _)) = checkpoint {
+    updates.send_checkpoint(checkpoint);
+  }
+
+  let from = updates.next_sn();
+  let history = history.into_iter().filter(|e| e.sequence_number >= from).collect::<Vec<Epoch>>();
+
+  info!("backfill {} epochs from etcd history starting from {}", history.len(), from);
+  updates.send_history(history);
+  Ok(())
+}
+
+fn record_request<T>(resp: &Result<T, Error>) {
+  let label = match resp {
+    Ok(_) => KeyValue::new("success", true),
+    Err(_) => KeyValue::new("success", "error"),
+  };
+  epoch_coordinator_requests_to_etcd_counter().add(1, &[label]);
+}
+
+async fn load_history(client: &mut Client) -> Result<Vec<Epoch>, Error> {
+  let resp = client.get(format!("{}/", MAROON_HISTORY), Some(GetOptions::new().with_prefix())).await;
+  record_request(&resp);
+
+  Ok(
+    resp?
+      .kvs()
+      .iter()
+      .filter_map(|kv| serde_json::from_slice::<EpochObject>(kv.value()).ok())
+      .map(|obj| obj.epoch)
+      .collect(),
+  )
+}
+
+/// returns the checkpoint with its version, version is used for CAS during compaction
+async fn load_checkpoint(client: &mut Client) -> Result<Option<(EpochCheckpoint, i64)>, Error> {
+  let resp = client.get(MAROON_CHECKPOINT, None).await;
+  record_request(&resp);
+
+  let resp = resp?;
+  let Some(kv) = resp.kvs().first() else {
+    return Ok(None);
+  };
+
+  match serde_json::from_slice::<EpochCheckpoint>(kv.value()) {
+    Ok(checkpoint) => Ok(Some((checkpoint, kv.version()))),
+    Err(e) => {
+      error!("corrupted epoch checkpoint: {e}");
+      Ok(None)
+    }
+  }
+}
+
+/// folds the history that all the live nodes have applied into the checkpoint and removes it from `/maroon/history`
+/// returns a new checkpoint if this node has moved it
+async fn compact_history(
+  client: &mut Client,
+  retain_epochs: u64,
+) -> Result<Option<EpochCheckpoint>, Error> {
+  let resp = client.get(format!("{}/", MAROON_APPLIED), Some(GetOptions::new().with_prefix())).await;
+  record_request(&resp);
+  let applied =
+    resp?.kvs().iter().filter_map(|kv| kv.value_str().ok().and_then(|v| v.parse::<u64>().ok())).collect::<Vec<u64>>();
+
+  let mut history = load_history(client).await?;
+  history.sort_by_key(|e| e.sequence_number);
+  let loaded = load_checkpoint(client).await?;
+  let (mut checkpoint, version) = match loaded {
+    Some((checkpoint, version)) => (Some(checkpoint), version),
+    None => (None, 0),
+  };
+
+  let mut new_checkpoint = None;
+  let checkpoint_sn = checkpoint.as_ref().map(|c| c.sequence_number);
+  if let Some(target) = compaction_target(applied, checkpoint_sn, retain_epochs) {
+    let folded =
+      history.iter().filter(|e| checkpoint_sn.is_none_or(|sn| e.sequence_number > sn) && e.sequence_number <= target);
+    if let Some(folded) = EpochCheckpoint::fold(checkpoint.as_ref(), folded) {
+      let resp = client
+        .txn(
+          Txn::new()
+            .when(vec![Compare::version(MAROON_CHECKPOINT, CompareOp::Equal, version)])
+            .and_then(vec![TxnOp::put(MAROON_CHECKPOINT, serde_json::to_vec(&folded).unwrap(), None)]),
+        )
+        .await;
+      record_request(&resp);
+
+      // somebody else has moved the checkpoint, will try next time
+      if !resp?.succeeded() {
+        return Ok(None);
+      }
+
+      checkpoint = Some(folded.clone());
+      new_checkpoint = Some(folded);
+    }
+  }
+
+  // removes everything that is in the checkpoint, including leftovers of previous attempts
+  if let Some(checkpoint) = &checkpoint {
+    for epoch in history.iter().filter(|e| e.sequence_number <= checkpoint.sequence_number) {
+      let resp = client.delete(format!("{}/{}", MAROON_HISTORY, epoch.sequence_number), None).await;
+      record_request(&resp);
+      resp?;
+    }
+  }
+
+  Ok(new_checkpoint)
+}
+
+/// keeps `/maroon/applied/<lease_id>` with the last applied sequence number while the node is running
+/// the key is attached to a lease, so etcd removes it when the node is gone and compaction doesn't wait for it
+struct Liveness {
+  ttl: i64,
+  lease: Option<Lease>,
+  applied_sn: Option<u64>,
+}
+
+struct Lease {
+  id: i64,
+  keeper: LeaseKeeper,
+  stream: LeaseKeepAliveStream,
+}
+
+impl Liveness {
+  fn new(refresh_period: Duration) -> Liveness {
+    // a node survives a couple of missed refreshes
+    Liveness { ttl: (refresh_period.as_secs() as i64 * 3).max(1), lease: None, applied_sn: None }
+  }
+
+  async fn refresh(
+    &mut self,
+    client: &mut Client,
+  ) -> Result<(), Error> {
+    let Some(applied_sn) = self.applied_sn else {
+      return Ok(());
+    };
+
+    let result = self.refresh_lease(client).await;
+    if result.is_err() {
+      // the lease might be gone, the next attempt starts a new one
+      self.lease = None;
+    }
+    let lease_id = result?;
+
+    let resp = client
+      .put(
+        format!("{}/{:x}", MAROON_APPLIED, lease_id),
+        applied_sn.to_string(),
+        Some(PutOptions::new().with_lease(lease_id)),
+      )
+      .await;
+    record_request(&resp);
+    resp?;
+    Ok(())
+  }
+
+  /// node is gone, it doesn't hold the history compaction back anymore
+  async fn revoke(
+    &mut self,
+    client: &mut Client,
+  ) -> Result<(), Error> {
+    let Some(lease) = self.lease.take() else {
+      return Ok(());
+    };
+    client.lease_revoke(lease.id).await?;
+    Ok(())
+  }
+
+  /// prolongs the current lease or grants a new one if it's expired
+  async fn refresh_lease(
+    &mut self,
+    client: &mut Client,
+  ) -> Result<i64, Error> {
+    if let Some(lease) = &mut self.lease {
+      lease.keeper.keep_alive().await?;
+      if let Some(resp) = lease.stream.message().await? {
+        if resp.ttl() > 0 {
+          return Ok(lease.id);
+        }
+      }
+      warn!("lease {:x} is expired", lease.id);
+    }
+
+    let id = client.lease_grant(self.ttl, None).await?.id();
+    let (keeper, stream) = client.lease_keep_alive(id).await?;
+    self.lease = Some(Lease { id, keeper, stream });
+    Ok(id)
+  }
 }
 
 // TODO: return here an error and write a dockerized-test to set/update latest
@@ -203,7 +490,12 @@ async fn handle_commit_new_epoch(
   let resp = client
     .txn(
       Txn::new()
-        .when(vec![Compare::version(format!("{}/{}", MAROON_HISTORY, seq_number), CompareOp::Equal, 0)])
+        // every commit puts `/maroon/latest` once, so its version is the sequence number of the next epoch
+        // the history key itself might be already removed by compaction
+        .when(vec![
+          Compare::version(format!("{}/{}", MAROON_HISTORY, seq_number), CompareOp::Equal, 0),
+          Compare::version(MAROON_LATEST, CompareOp::Equal, seq_number as i64),
+        ])
         .and_then(vec![
           TxnOp::put(MAROON_LATEST, serde_json::to_vec(&new_epoch).unwrap(), None),
           TxnOp::put(format!("{}/{}", MAROON_HISTORY, seq_number), serde_json::to_vec(&new_epoch).unwrap(), None),
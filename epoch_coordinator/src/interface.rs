@@ -1,32 +1,60 @@
-use super::epoch::Epoch;
-use std::fmt;
+use super::epoch::{Epoch, EpochCheckpoint};
+use futures::future::BoxFuture;
+use log::{debug, error};
+use std::{fmt, time::Duration};
 use tokio::sync::{
   mpsc::{UnboundedReceiver, UnboundedSender},
   watch::{Receiver, Sender},
 };
 
-// TODO: should I download epoch history through this coordinator?
-// For example node was offline or
-// if yes - add an interface
-// if no - where to download it? p2p? s3?
-
 // a pair interface to ControllerInterface. Is used in epoch coordinator itself
 pub struct Interface {
   pub receiver: Receiver<Option<EpochRequest>>,
   pub sender: UnboundedSender<EpochUpdates>,
+  /// last sequence number that has been applied by the node
+  pub applied_receiver: Receiver<Option<u64>>,
 }
 
 // a pair interface to Interface. Is used by some other components that want to communicate with epoch coordinator
 pub struct ControllerInterface {
   pub receiver: UnboundedReceiver<EpochUpdates>,
   pub sender: Sender<Option<EpochRequest>>,
+  /// reports the last applied sequence number, history is compacted only when all live nodes are past it
+  pub applied_sender: Sender<Option<u64>>,
 }
 
 pub fn create_interface_pair() -> (Interface, ControllerInterface) {
   let (ec_tx, ec_rx) = tokio::sync::watch::channel::<Option<EpochRequest>>(None);
   let (ec_tx_u, ec_rx_u) = tokio::sync::mpsc::unbounded_channel::<EpochUpdates>();
+  let (applied_tx, applied_rx) = tokio::sync::watch::channel::<Option<u64>>(None);
+
+  (
+    Interface { receiver: ec_rx, sender: ec_tx_u, applied_receiver: applied_rx },
+    ControllerInterface { receiver: ec_rx_u, sender: ec_tx, applied_sender: applied_tx },
+  )
+}
+
+pub type StartError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Backend that stores committed epochs
+///
+/// Every implementation should provide the same semantics:
+/// - an epoch is committed only if there is no epoch with the same `sequence_number` yet (CAS)
+/// - every committed epoch (by any node) is delivered back through `EpochUpdates::New`
+/// - epochs are delivered exactly once and in order of `sequence_number`. On start and after every reconnect
+///   the history is scanned from the last delivered(or last applied by the node) sequence number
+/// - history is folded into `EpochCheckpoint` once all the live nodes applied it. If a node needs epochs
+///   that are already folded - it gets `EpochUpdates::Checkpoint` first
+pub trait EpochCoordinator: Send {
+  /// starts infinite loop. After this all the communications with coordinator only through `Interface`
+  fn start(self: Box<Self>) -> BoxFuture<'static, Result<(), StartError>>;
 
-  (Interface { receiver: ec_rx, sender: ec_tx_u }, ControllerInterface { receiver: ec_rx_u, sender: ec_tx })
+  /// epochs up to `sn`(inclusive) are already applied by the node and won't be delivered,
+  /// ex: the node has restored them from a snapshot
+  fn set_last_applied_sequence_number(
+    self: Box<Self>,
+    sn: Option<u64>,
+  ) -> Box<dyn EpochCoordinator>;
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +66,93 @@ pub struct EpochRequest {
 pub enum EpochUpdates {
   /// when a new epoch detected
   New(Epoch),
+  /// epochs the node hasn't seen yet are folded into the checkpoint, next epochs go after it
+  Checkpoint(EpochCheckpoint),
+}
+
+/// how often coordinators read the history again while it has a gap
+pub(crate) const HISTORY_RESCAN_PERIOD: Duration = Duration::from_secs(1);
+
+/// Sends `EpochUpdates::New` to the controller side and keeps track of what has been already delivered
+/// so coordinators can mix history backfill with live updates without duplicates and gaps
+pub(crate) struct OrderedUpdatesSender {
+  sender: UnboundedSender<EpochUpdates>,
+  next_sn: u64,
+  /// the last history has had a gap, the epochs after it haven't been delivered
+  history_gap: bool,
+}
+
+impl OrderedUpdatesSender {
+  pub(crate) fn new(
+    sender: UnboundedSender<EpochUpdates>,
+    last_applied_sn: Option<u64>,
+  ) -> OrderedUpdatesSender {
+    OrderedUpdatesSender { sender, next_sn: last_applied_sn.map_or(0, |sn| sn + 1), history_gap: false }
+  }
+
+  /// sequence number of the epoch that is expected to be delivered next
+  pub(crate) fn next_sn(&self) -> u64 {
+    self.next_sn
+  }
+
+  /// returns false if the epoch is from the future and there is a gap before it - history backfill is needed
+  /// already delivered epochs are skipped
+  pub(crate) fn send(
+    &mut self,
+    epoch: Epoch,
+  ) -> bool {
+    if epoch.sequence_number < self.next_sn {
+      debug!("epoch {} has been already delivered, skip", epoch.sequence_number);
+      return true;
+    }
+    if epoch.sequence_number > self.next_sn {
+      return false;
+    }
+
+    self.next_sn += 1;
+    if let Err(e) = self.sender.send(EpochUpdates::New(epoch)) {
+      error!("failed to send epoch update: {}", e);
+    }
+    true
+  }
+
+  /// sends the checkpoint if the node hasn't got all the epochs that are folded into it
+  pub(crate) fn send_checkpoint(
+    &mut self,
+    checkpoint: EpochCheckpoint,
+  ) {
+    if checkpoint.sequence_number < self.next_sn {
+      return;
+    }
+
+    self.next_sn = checkpoint.sequence_number + 1;
+    if let Err(e) = self.sender.send(EpochUpdates::Checkpoint(checkpoint)) {
+      error!("failed to send epoch update: {}", e);
+    }
+  }
+
+  /// sends epochs from history in order, stops at the first gap
+  /// history with a gap should be read again from `next_sn`, see `has_history_gap`
+  pub(crate) fn send_history(
+    &mut self,
+    mut history: Vec<Epoch>,
+  ) {
+    history.sort_by_key(|e| e.sequence_number);
+    self.history_gap = false;
+    for epoch in history {
+      let sn = epoch.sequence_number;
+      if !self.send(epoch) {
+        error!("epoch history has a gap: expected {}, got {}, it's read again", self.next_sn, sn);
+        self.history_gap = true;
+        return;
+      }
+    }
+  }
+
+  /// the node waits for the epochs after the gap, ex: history was read in the middle of compaction
+  pub(crate) fn has_history_gap(&self) -> bool {
+    self.history_gap
+  }
 }
 
 #[derive(Debug)]
@@ -55,3 +170,38 @@ impl fmt::Display for CommitError {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use common::logical_time::LogicalTimeAbsoluteMs;
+  use libp2p::PeerId;
+  use tokio::sync::mpsc::unbounded_channel;
+
+  #[test]
+  fn history_with_gap_is_read_again() {
+    let peer_id = PeerId::random();
+    let e0 = Epoch::next(peer_id, vec![], None, LogicalTimeAbsoluteMs(10));
+    let e1 = Epoch::next(peer_id, vec![], Some(&e0), LogicalTimeAbsoluteMs(20));
+    let e2 = Epoch::next(peer_id, vec![], Some(&e1), LogicalTimeAbsoluteMs(30));
+
+    let (sender, mut receiver) = unbounded_channel();
+    let mut updates = OrderedUpdatesSender::new(sender, None);
+    let mut delivered = || {
+      let mut sns = Vec::new();
+      while let Ok(EpochUpdates::New(epoch)) = receiver.try_recv() {
+        sns.push(epoch.sequence_number);
+      }
+      sns
+    };
+
+    // epoch 1 isn't in the history yet
+    updates.send_history(vec![e2.clone(), e0.clone()]);
+    assert_eq!(vec![0], delivered());
+    assert!(updates.has_history_gap());
+
+    updates.send_history(vec![e1, e2]);
+    assert_eq!(vec![1, 2], delivered());
+    assert!(!updates.has_history_gap());
+  }
+}
@@ -1,3 +1,5 @@
+pub mod compaction;
 pub mod epoch;
 pub mod etcd;
 pub mod interface;
+pub mod memory;
@@ -0,0 +1,302 @@
+use super::epoch::{Epoch, EpochCheckpoint};
+use crate::compaction::{CompactionParams, compaction_target};
+use crate::interface::{
+  EpochCoordinator, EpochRequest, EpochUpdates, HISTORY_RESCAN_PERIOD, Interface, OrderedUpdatesSender, StartError,
+};
+use futures::future::{BoxFuture, FutureExt};
+use log::info;
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::{Arc, Mutex},
+};
+use tokio::{
+  sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch::Receiver,
+  },
+  time::interval,
+};
+
+/// In-process storage that imitates what `EtcdEpochCoordinator` keeps in etcd
+///
+/// - `history` - the same as `/maroon/history/<seq>`, commit is a CAS on the sequence number
+/// - `watchers` - the same as a watch on `/maroon/latest`, every commit is delivered to every watcher
+/// - `checkpoint` - the same as `/maroon/checkpoint`, history before it is removed
+/// - `applied` - the same as `/maroon/applied/<lease>`, last applied sequence number of every running coordinator
+///
+/// Clones share the same storage, so several nodes in one process(single-node run, integration tests)
+/// can use one store as a cluster-wide coordinator without etcd
+#[derive(Clone, Default)]
+pub struct InMemoryEpochStore {
+  inner: Arc<Mutex<StoreState>>,
+}
+
+#[derive(Default)]
+struct StoreState {
+  history: BTreeMap<u64, Epoch>,
+  watchers: Vec<UnboundedSender<Epoch>>,
+  checkpoint: Option<EpochCheckpoint>,
+  applied: HashMap<u64, u64>,
+  next_node_id: u64,
+}
+
+impl StoreState {
+  fn next_sn(&self) -> u64 {
+    match (self.history.last_key_value(), &self.checkpoint) {
+      (Some((sn, _)), _) => sn + 1,
+      (None, Some(checkpoint)) => checkpoint.sequence_number + 1,
+      (None, None) => 0,
+    }
+  }
+}
+
+impl InMemoryEpochStore {
+  pub fn new() -> InMemoryEpochStore {
+    InMemoryEpochStore::default()
+  }
+
+  /// commits the epoch if it's exactly the next one: there is no epoch with the same sequence number yet
+  /// and it's not folded into the checkpoint
+  /// returns true if the epoch was successfully committed
+  pub fn try_commit(
+    &self,
+    epoch: Epoch,
+  ) -> bool {
+    let mut state = self.inner.lock().expect("not poisoned");
+    if epoch.sequence_number != state.next_sn() {
+      return false;
+    }
+
+    state.history.insert(epoch.sequence_number, epoch.clone());
+    state.watchers.retain(|w| w.send(epoch.clone()).is_ok());
+    true
+  }
+
+  /// subscribes to all epochs that will be committed after this call
+  pub fn watch(&self) -> UnboundedReceiver<Epoch> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    self.inner.lock().expect("not poisoned").watchers.push(sender);
+    receiver
+  }
+
+  /// current checkpoint and all committed epochs after it starting from `sequence_number`(inclusive) in order
+  pub fn history_from(
+    &self,
+    sequence_number: u64,
+  ) -> (Option<EpochCheckpoint>, Vec<Epoch>) {
+    let state = self.inner.lock().expect("not poisoned");
+    (state.checkpoint.clone(), state.history.range(sequence_number..).map(|(_, e)| e.clone()).collect())
+  }
+
+  pub fn checkpoint(&self) -> Option<EpochCheckpoint> {
+    self.inner.lock().expect("not poisoned").checkpoint.clone()
+  }
+
+  /// registers a new live node, its applied sequence number is taken into account during compaction
+  pub fn register_node(&self) -> u64 {
+    let mut state = self.inner.lock().expect("not poisoned");
+    state.next_node_id += 1;
+    state.next_node_id
+  }
+
+  pub fn unregister_node(
+    &self,
+    node_id: u64,
+  ) {
+    self.inner.lock().expect("not poisoned").applied.remove(&node_id);
+  }
+
+  pub fn report_applied(
+    &self,
+    node_id: u64,
+    sequence_number: u64,
+  ) {
+    self.inner.lock().expect("not poisoned").applied.insert(node_id, sequence_number);
+  }
+
+  /// folds epochs that all the live nodes have applied into the checkpoint and removes them from the history
+  /// returns a new checkpoint if there was something to compact
+  pub fn compact(
+    &self,
+    retain_epochs: u64,
+  ) -> Option<EpochCheckpoint> {
+    let mut state = self.inner.lock().expect("not poisoned");
+
+    let checkpoint_sn = state.checkpoint.as_ref().map(|c| c.sequence_number);
+    let target = compaction_target(state.applied.values().copied(), checkpoint_sn, retain_epochs)?;
+    let checkpoint = EpochCheckpoint::fold(state.checkpoint.as_ref(), state.history.range(..=target).map(|(_, e)| e))?;
+
+    state.history = state.history.split_off(&(target + 1));
+    state.checkpoint = Some(checkpoint.clone());
+    Some(checkpoint)
+  }
+
+  pub fn latest(&self) -> Option<Epoch> {
+    self.inner.lock().expect("not poisoned").history.last_key_value().map(|(_, e)| e.clone())
+  }
+}
+
+/// implementation uses `InMemoryEpochStore` as a backend for EpochCoordinator
+pub struct InMemoryEpochCoordinator {
+  store: InMemoryEpochStore,
+  new_epoch_receiver: Receiver<Option<EpochRequest>>,
+  epoch_updates_sender: UnboundedSender<EpochUpdates>,
+  applied_receiver: Receiver<Option<u64>>,
+  last_applied_sn: Option<u64>,
+  compaction: CompactionParams,
+}
+
+impl InMemoryEpochCoordinator {
+  pub fn new(
+    store: InMemoryEpochStore,
+    interface: Interface,
+  ) -> InMemoryEpochCoordinator {
+    InMemoryEpochCoordinator {
+      store,
+      new_epoch_receiver: interface.receiver,
+      epoch_updates_sender: interface.sender,
+      applied_receiver: interface.applied_receiver,
+      last_applied_sn: None,
+      compaction: CompactionParams::default(),
+    }
+  }
+
+  pub fn set_compaction_params(
+    mut self,
+    params: CompactionParams,
+  ) -> InMemoryEpochCoordinator {
+    self.compaction = params;
+    self
+  }
+
+  /// epochs up to `sn`(inclusive) are already applied by the node and won't be delivered
+  pub fn set_last_applied_sequence_number(
+    mut self,
+    sn: Option<u64>,
+  ) -> InMemoryEpochCoordinator {
+    self.last_applied_sn = sn;
+    self
+  }
+
+  /// starts infinite loop. After this all the communications with corrdinator only through `EpochCoordinatorInterface`
+  /// finishes when the controller side of the interface is dropped
+  pub async fn start(self) {
+    info!("start in-memory epoch coordinator");
+
+    // subscribe before reading the history so nothing committed in between is lost, duplicates are skipped by `updates`
+    let mut watcher = self.store.watch();
+    let mut receiver = self.new_epoch_receiver;
+    let mut applied_receiver = self.applied_receiver;
+    let mut updates = OrderedUpdatesSender::new(self.epoch_updates_sender, self.last_applied_sn);
+    let mut last_committed_sn: Option<u64> = None;
+
+    // node is considered alive while the coordinator is running
+    let registration = NodeRegistration::new(self.store.clone());
+    let mut compaction_ticker = interval(self.compaction.period);
+    let mut rescan_ticker = interval(HISTORY_RESCAN_PERIOD);
+
+    backfill_history(&self.store, &mut updates);
+
+    loop {
+      tokio::select! {
+        changed = receiver.changed() => {
+          if changed.is_err() {
+            info!("epoch requests channel closed, stop in-memory epoch coordinator");
+            return;
+          }
+
+          let next = receiver.borrow_and_update().clone();
+          if let Some(payload) = next {
+            let sn = payload.epoch.sequence_number;
+            if Some(sn) == last_committed_sn {
+              continue;
+            }
+
+            let success = self.store.try_commit(payload.epoch);
+            info!("commit {} epoch success: {}", sn, success);
+            if success {
+              last_committed_sn = Some(sn);
+            }
+          }
+        },
+        Some(epoch) = watcher.recv() => {
+          info!("in-memory watch got {} epoch", epoch.sequence_number);
+          if !updates.send(epoch) {
+            backfill_history(&self.store, &mut updates);
+          }
+        },
+        changed = applied_receiver.changed() => {
+          if changed.is_err() {
+            info!("applied channel closed, stop in-memory epoch coordinator");
+            return;
+          }
+
+          let applied = *applied_receiver.borrow_and_update();
+          if let Some(sn) = applied {
+            self.store.report_applied(registration.node_id, sn);
+          }
+        },
+        _ = rescan_ticker.tick(), if updates.has_history_gap() => {
+          backfill_history(&self.store, &mut updates);
+        },
+        _ = compaction_ticker.tick() => {
+          if let Some(checkpoint) = self.store.compact(self.compaction.retain_epochs) {
+            info!("epoch history is compacted up to {}", checkpoint.sequence_number);
+          }
+        },
+      }
+    }
+  }
+
+  /// same as `start` but spawns a background tokio thread
+  pub fn start_on_background(self) {
+    tokio::spawn(self.start());
+  }
+}
+
+fn backfill_history(
+  store: &InMemoryEpochStore,
+  updates: &mut OrderedUpdatesSender,
+) {
+  let (checkpoint, history) = store.history_from(updates.next_sn());
+  if let Some(checkpoint) = checkpoint {
+    updates.send_checkpoint(checkpoint);
+  }
+  updates.send_history(history);
+}
+
+/// unregisters the node from the store when the coordinator stops
+struct NodeRegistration {
+  store: InMemoryEpochStore,
+  node_id: u64,
+}
+
+impl NodeRegistration {
+  fn new(store: InMemoryEpochStore) -> NodeRegistration {
+    let node_id = store.register_node();
+    NodeRegistration { store, node_id }
+  }
+}
+
+impl Drop for NodeRegistration {
+  fn drop(&mut self) {
+    self.store.unregister_node(self.node_id);
+  }
+}
+
+impl EpochCoordinator for InMemoryEpochCoordinator {
+  fn start(self: Box<Self>) -> BoxFuture<'static, Result<(), StartError>> {
+    async move {
+      InMemoryEpochCoordinator::start(*self).await;
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn set_last_applied_sequence_number(
+    self: Box<Self>,
+    sn: Option<u64>,
+  ) -> Box<dyn EpochCoordinator> {
+    Box::new(InMemoryEpochCoordinator::set_last_applied_sequence_number(*self, sn))
+  }
+}
@@ -0,0 +1,130 @@
+use common::logical_time::LogicalTimeAbsoluteMs;
+use common::range_key::{KeyOffset, KeyRange, U64BlobIdClosedInterval};
+use epoch_coordinator::{
+  compaction::CompactionParams,
+  epoch::Epoch,
+  interface::{EpochRequest, EpochUpdates, create_interface_pair},
+  memory::{InMemoryEpochCoordinator, InMemoryEpochStore},
+};
+use libp2p::PeerId;
+use std::{collections::HashMap, time::Duration};
+
+/// Two coordinators share one store: each of them gets every committed epoch through "watch"
+/// and an epoch with already used sequence number is rejected
+#[tokio::test(flavor = "multi_thread")]
+async fn in_memory_epoch_coordinator() {
+  _ = env_logger::try_init();
+
+  let store = InMemoryEpochStore::new();
+
+  let (iface_1, mut controller_1) = create_interface_pair();
+  let (iface_2, mut controller_2) = create_interface_pair();
+  InMemoryEpochCoordinator::new(store.clone(), iface_1).start_on_background();
+  InMemoryEpochCoordinator::new(store.clone(), iface_2).start_on_background();
+
+  // give coordinators time to subscribe to the store
+  tokio::time::sleep(Duration::from_millis(50)).await;
+
+  let peer_id_1 = PeerId::random();
+  let peer_id_2 = PeerId::random();
+
+  let epoch = Epoch::next(peer_id_1, vec![U64BlobIdClosedInterval::new(0, 13)], None, LogicalTimeAbsoluteMs(100));
+  let conflicting = Epoch::next(peer_id_2, vec![U64BlobIdClosedInterval::new(0, 3)], None, LogicalTimeAbsoluteMs(100));
+  let epoch2 =
+    Epoch::next(peer_id_2, vec![U64BlobIdClosedInterval::new(14, 16)], Some(&epoch), LogicalTimeAbsoluteMs(200));
+
+  _ = controller_1.sender.send(Some(EpochRequest { epoch: epoch.clone() }));
+  assert_eq!(EpochUpdates::New(epoch.clone()), controller_1.receiver.recv().await.expect("can it be None?"));
+  assert_eq!(EpochUpdates::New(epoch.clone()), controller_2.receiver.recv().await.expect("can it be None?"));
+
+  // the same sequence number - CAS fails, nobody gets an update
+  _ = controller_2.sender.send(Some(EpochRequest { epoch: conflicting }));
+
+  _ = controller_2.sender.send(Some(EpochRequest { epoch: epoch2.clone() }));
+  assert_eq!(EpochUpdates::New(epoch2.clone()), controller_1.receiver.recv().await.expect("can it be None?"));
+  assert_eq!(EpochUpdates::New(epoch2.clone()), controller_2.receiver.recv().await.expect("can it be None?"));
+
+  assert_eq!(Some(epoch2), store.latest());
+}
+
+/// Coordinator that starts after some epochs have been committed gets the history first and only then live updates
+#[tokio::test(flavor = "multi_thread")]
+async fn in_memory_epoch_coordinator_backfills_history() {
+  _ = env_logger::try_init();
+
+  let store = InMemoryEpochStore::new();
+  let peer_id = PeerId::random();
+
+  let epoch0 = Epoch::next(peer_id, vec![U64BlobIdClosedInterval::new(0, 3)], None, LogicalTimeAbsoluteMs(100));
+  let epoch1 =
+    Epoch::next(peer_id, vec![U64BlobIdClosedInterval::new(4, 5)], Some(&epoch0), LogicalTimeAbsoluteMs(200));
+  let epoch2 =
+    Epoch::next(peer_id, vec![U64BlobIdClosedInterval::new(6, 9)], Some(&epoch1), LogicalTimeAbsoluteMs(300));
+  assert!(store.try_commit(epoch0.clone()));
+  assert!(store.try_commit(epoch1.clone()));
+
+  let (iface_late, mut controller_late) = create_interface_pair();
+  InMemoryEpochCoordinator::new(store.clone(), iface_late).start_on_background();
+
+  // node has already applied epoch0 by itself, so it needs only the rest
+  let (iface_restarted, mut controller_restarted) = create_interface_pair();
+  InMemoryEpochCoordinator::new(store.clone(), iface_restarted)
+    .set_last_applied_sequence_number(Some(0))
+    .start_on_background();
+
+  assert_eq!(EpochUpdates::New(epoch0), controller_late.receiver.recv().await.expect("can it be None?"));
+  assert_eq!(EpochUpdates::New(epoch1.clone()), controller_late.receiver.recv().await.expect("can it be None?"));
+  assert_eq!(EpochUpdates::New(epoch1), controller_restarted.receiver.recv().await.expect("can it be None?"));
+
+  _ = controller_late.sender.send(Some(EpochRequest { epoch: epoch2.clone() }));
+  assert_eq!(EpochUpdates::New(epoch2.clone()), controller_late.receiver.recv().await.expect("can it be None?"));
+  assert_eq!(EpochUpdates::New(epoch2), controller_restarted.receiver.recv().await.expect("can it be None?"));
+}
+
+/// History that all the live nodes have applied is folded into the checkpoint,
+/// a node that starts later begins from the checkpoint
+#[tokio::test(flavor = "multi_thread")]
+async fn in_memory_epoch_coordinator_compacts_history() {
+  _ = env_logger::try_init();
+
+  let store = InMemoryEpochStore::new();
+  let compaction = CompactionParams::default().set_retain_epochs(1).set_period(Duration::from_millis(20));
+
+  let (iface, mut controller) = create_interface_pair();
+  InMemoryEpochCoordinator::new(store.clone(), iface).set_compaction_params(compaction).start_on_background();
+
+  let peer_id = PeerId::random();
+  let mut epochs: Vec<Epoch> = vec![];
+  for i in 0..4u64 {
+    let epoch = Epoch::next(
+      peer_id,
+      vec![U64BlobIdClosedInterval::new(i * 10, i * 10 + 9)],
+      epochs.last(),
+      LogicalTimeAbsoluteMs(i * 100),
+    );
+    assert!(store.try_commit(epoch.clone()));
+    assert_eq!(EpochUpdates::New(epoch.clone()), controller.receiver.recv().await.expect("can it be None?"));
+    epochs.push(epoch);
+  }
+
+  // nothing is compacted until the node reports what it has applied
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert_eq!(None, store.checkpoint());
+
+  _ = controller.applied_sender.send(Some(3));
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  let checkpoint = store.checkpoint().expect("history should be compacted");
+  assert_eq!(2, checkpoint.sequence_number);
+  assert_eq!(HashMap::from([(KeyRange(0), KeyOffset(29))]), checkpoint.commited_offsets);
+  assert_eq!((Some(checkpoint.clone()), vec![epochs[3].clone()]), store.history_from(0));
+
+  // folded sequence numbers can't be reused
+  assert!(!store.try_commit(Epoch::next(peer_id, vec![], Some(&epochs[0]), LogicalTimeAbsoluteMs(500))));
+
+  let (iface_late, mut controller_late) = create_interface_pair();
+  InMemoryEpochCoordinator::new(store.clone(), iface_late).start_on_background();
+
+  assert_eq!(EpochUpdates::Checkpoint(checkpoint), controller_late.receiver.recv().await.expect("can it be None?"));
+  assert_eq!(EpochUpdates::New(epochs[3].clone()), controller_late.receiver.recv().await.expect("can it be None?"));
+}
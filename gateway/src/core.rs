@@ -2,18 +2,24 @@ use crate::network_interface::{Inbox, Outbox};
 use crate::p2p::P2P;
 use axum::extract::ws::{Message, WebSocket};
 use common::duplex_channel::create_a_b_duplex_pair;
+use common::identity;
 use generated::maroon_assembler::Value;
-use log::error;
+use log::{error, info, warn};
 use protocol::node2gw::{Meta, Transaction, TxStatus};
 use protocol::transaction::TaskBlueprint;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
 use tokio::sync::{
   broadcast,
   mpsc::{self, UnboundedReceiver, UnboundedSender},
+  watch,
 };
 use types::range_key::{KeyRange, UniqueU64BlobId, full_interval_for_range};
 
+const LEASE_RETRY_PERIOD: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone, Serialize)]
 pub enum MonitorEvent {
   NewRequest { id: UniqueU64BlobId, queue: String, value: Value },
@@ -36,6 +42,13 @@ pub struct Gateway {
   new_request_sender: UnboundedSender<NewRequest>,
   new_request_receiver: Option<UnboundedReceiver<NewRequest>>,
 
+  /// used for asking nodes for a key range without going through the background loop
+  lease_sender: UnboundedSender<Outbox>,
+  leased_range_sender: Option<watch::Sender<Option<KeyRange>>>,
+  leased_range_receiver: watch::Receiver<Option<KeyRange>>,
+
+  /// key range that is leased to this gateway, all the ids it creates come from it
+  range: Option<KeyRange>,
   interval_left: UniqueU64BlobId,
   interval_right: UniqueU64BlobId,
 
@@ -43,29 +56,33 @@ pub struct Gateway {
 }
 
 impl Gateway {
+  /// `key_file` keeps the identity of the gateway across restarts, see `common::identity`
   pub fn new(
-    range: KeyRange,
     node_urls: Vec<String>,
+    key_file: Option<&Path>,
   ) -> Result<Gateway, Box<dyn std::error::Error>> {
     let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Outbox, Inbox>();
     let (new_request_sender, new_request_receiver) = mpsc::unbounded_channel::<NewRequest>();
     let (monitor_tx, _monitor_rx) = broadcast::channel::<MonitorEvent>(1024);
+    let (leased_range_sender, leased_range_receiver) = watch::channel::<Option<KeyRange>>(None);
 
-    let mut p2p = P2P::new(node_urls, b2a_endpoint)?;
+    let mut p2p = P2P::new(node_urls, identity::keypair(key_file)?, b2a_endpoint)?;
     // TODO: prepare works in background and you can't start sending requests immediately when you created Gateway
     // I need to create some sort of state/flags or block the thread that can prevent sending requests before initialization even happened
     p2p.prepare().map_err(|e| format!("prepare: {}", e))?;
 
-    let interval = full_interval_for_range(range);
-
     Ok(Gateway {
+      lease_sender: a2b_endpoint.sender.clone(),
       p2p_sender: Some(a2b_endpoint.sender),
       p2p_receiver: Some(a2b_endpoint.receiver),
       p2p: Some(p2p),
       new_request_sender,
       new_request_receiver: Some(new_request_receiver),
-      interval_left: interval.start(),
-      interval_right: interval.end(),
+      leased_range_sender: Some(leased_range_sender),
+      leased_range_receiver,
+      range: None,
+      interval_left: UniqueU64BlobId(0),
+      interval_right: UniqueU64BlobId(0),
       monitor_tx,
     })
   }
@@ -81,6 +98,7 @@ impl Gateway {
     let p2p_sender = self.p2p_sender.take().expect("cant take twice");
     let mut new_request_receiver = self.new_request_receiver.take().expect("cant take twice");
     let monitor_tx = self.monitor_tx.clone();
+    let leased_range_sender = self.leased_range_sender.take().expect("cant take twice");
 
     tokio::spawn(async move {
       p2p.start_event_loop().await;
@@ -92,7 +110,7 @@ impl Gateway {
       loop {
         tokio::select! {
           Some(inbox) = p2p_receiver.recv() => {
-            handle_inbox(inbox, &mut ws_registry, &monitor_tx).await;
+            handle_inbox(inbox, &p2p_sender, &mut ws_registry, &monitor_tx, &leased_range_sender).await;
           }
           Some(req) = new_request_receiver.recv() => {
             handle_send_new_request(&p2p_sender, req, &mut ws_registry, &monitor_tx);
@@ -100,6 +118,34 @@ impl Gateway {
         }
       }
     });
+
+    self.renew_key_range().await;
+  }
+
+  /// leases a new key range from the nodes, replacing the current one
+  /// waits until one of the nodes answers, which happens only after the lease is committed in an epoch
+  async fn renew_key_range(&mut self) {
+    let replaces = self.range;
+    loop {
+      if let Err(e) = self.lease_sender.send(Outbox::LeaseKeyRange(replaces)) {
+        error!("gateway lease key range: {e}");
+      }
+
+      // request is lost if there is no connection to nodes yet, so it's repeated until someone answers
+      match tokio::time::timeout(LEASE_RETRY_PERIOD, self.leased_range_receiver.wait_for(|r| *r > replaces)).await {
+        Ok(Ok(leased)) => {
+          let range = leased.expect("greater than replaced range");
+          let interval = full_interval_for_range(range);
+          self.range = Some(range);
+          self.interval_left = interval.start();
+          self.interval_right = interval.end();
+          info!("leased key range {range}");
+          return;
+        }
+        Ok(Err(e)) => panic!("leased range sender dropped: {e}"),
+        Err(_) => warn!("no key range leased in {LEASE_RETRY_PERIOD:?}, retrying"),
+      }
+    }
   }
 
   pub async fn send_request(
@@ -107,8 +153,8 @@ impl Gateway {
     blueprint: TaskBlueprint,
     response_socket: Option<WebSocket>,
   ) {
-    if self.interval_left >= self.interval_right {
-      todo!("request new key range");
+    if self.range.is_none() || self.interval_left >= self.interval_right {
+      self.renew_key_range().await;
     }
 
     let id = self.interval_left;
@@ -140,10 +186,30 @@ fn handle_send_new_request(
 
 async fn handle_inbox(
   inbox: Inbox,
+  sender: &UnboundedSender<Outbox>,
   ws_registry: &mut HashMap<UniqueU64BlobId, WebSocket>,
   monitor_tx: &broadcast::Sender<MonitorEvent>,
+  leased_range_sender: &watch::Sender<Option<KeyRange>>,
 ) {
   match inbox {
+    Inbox::NodeConnected => {
+      // requests that are still waiting for the result might have finished while the node was unreachable
+      let waiting: Vec<UniqueU64BlobId> = ws_registry.keys().copied().collect();
+      if !waiting.is_empty() {
+        let _ = sender.send(Outbox::GetTxStatus(waiting));
+      }
+    }
+    Inbox::KeyRangeLeased(range) => {
+      // every node answers the same lease and ranges are only growing, so late answers are ignored
+      leased_range_sender.send_if_modified(|current| {
+        if *current < Some(range) {
+          *current = Some(range);
+          true
+        } else {
+          false
+        }
+      });
+    }
     Inbox::TxUpdates(tx_updates) => {
       for update in tx_updates {
         let socket = ws_registry.get_mut(&update.meta.id);
@@ -152,7 +218,7 @@ async fn handle_inbox(
           if let Err(e) = socket.send(Message::Text(payload.into())).await {
             error!("send ws response: {e}");
           };
-          if update.meta.status == TxStatus::Finished {
+          if matches!(update.meta.status, TxStatus::Finished | TxStatus::Rejected(_)) {
             ws_registry.remove(&update.meta.id);
           }
         }
@@ -12,9 +12,8 @@ use axum::{
 use gateway::core::{Gateway, MonitorEvent};
 use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
 use protocol::transaction::TaskBlueprint;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 use tokio::net::TcpListener;
-use types::range_key::KeyRange;
 
 async fn multiply_handler(
   State(gw): State<Arc<tokio::sync::Mutex<Gateway>>>,
@@ -124,9 +123,10 @@ async fn main() {
     .collect();
 
   let server_port = std::env::var("PORT").unwrap_or("5000".to_string()).parse::<u16>().unwrap();
-  let key_range = KeyRange(std::env::var("KEY_RANGE").unwrap_or("0".to_string()).parse::<u64>().unwrap());
 
-  let mut gateway_app = Gateway::new(key_range, node_urls).expect("should be ok");
+  let key_file = std::env::var("KEY_FILE").ok().map(PathBuf::from);
+
+  let mut gateway_app = Gateway::new(node_urls, key_file.as_deref()).expect("should be ok");
   gateway_app.start_in_background().await;
 
   // server
@@ -1,13 +1,20 @@
 use protocol::node2gw::{Transaction, TxUpdate};
+use types::range_key::{KeyRange, UniqueU64BlobId};
 
 /// Input for p2p layer from higher modules perspective
 #[derive(Debug, Clone)]
 pub enum Outbox {
   NewTransaction(Transaction),
+  /// ask nodes for a new key range. `Some` is the range that is exhausted and should be replaced
+  LeaseKeyRange(Option<KeyRange>),
+  GetTxStatus(Vec<UniqueU64BlobId>),
 }
 
 /// Input for the layer that lives on top of p2p layer. Output for p2p Layer
 #[derive(Debug, Clone)]
 pub enum Inbox {
   TxUpdates(Vec<TxUpdate>),
+  KeyRangeLeased(KeyRange),
+  /// connection to the node is (re)established, tx updates sent while it was down could be missed
+  NodeConnected,
 }
@@ -17,12 +17,13 @@ use libp2p::{
   yamux::Config as YamuxConfig,
 };
 use libp2p_request_response::{Message as RequestResponseMessage, ProtocolSupport};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use protocol::gm_request_response::{self, Behaviour as GMBehaviour, Event as GMEvent};
 use protocol::meta_exchange::{
-  self, Behaviour as MetaExchangeBehaviour, Event as MEEvent, Response as MEResponse, Role,
+  self, Behaviour as MetaExchangeBehaviour, Capabilities, Compatibility, Event as MEEvent, Response as MEResponse, Role,
 };
 use protocol::node2gw::{GossipMessage as N2GWGossipMessage, GossipPayload as N2GWGossipPayload, node2gw_topic};
+use protocol::wire;
 use schema::mn_events::{CommandBody, Eid, LogEvent, LogEventBody, now_microsec};
 use std::{collections::HashSet, time::Duration};
 use tokio::sync::mpsc::UnboundedSender;
@@ -60,9 +61,9 @@ pub struct P2P {
 impl P2P {
   pub fn new(
     node_urls: Vec<String>,
+    kp: identity::Keypair,
     interface_endpoint: Endpoint<Inbox, Outbox>,
   ) -> Result<P2P, Box<dyn std::error::Error>> {
-    let kp = identity::Keypair::generate_ed25519();
     let peer_id = PeerId::from(kp.public());
     info!("Local peer id: {:?}", peer_id);
 
@@ -134,6 +135,8 @@ impl P2P {
                   // Map Outbox -> gm_request_response::Request
                   let gm_req = match request.clone() {
                     Outbox::NewTransaction(tx) => gm_request_response::Request::NewTransaction(tx),
+                    Outbox::LeaseKeyRange(replaces) => gm_request_response::Request::LeaseKeyRange(replaces),
+                    Outbox::GetTxStatus(ids) => gm_request_response::Request::GetTxStatus(ids),
                   };
                   let _request_id = swarm.behaviour_mut().request_response.send_request(peer_id, gm_req);
                   state_log::log(LogEvent {
@@ -173,6 +176,15 @@ fn handle_swarm_event(
         GMEvent::Message { message, .. } => match message {
           RequestResponseMessage::Response { request_id, response } => {
             debug!("Response: {:?}, {:?}", request_id, response);
+            match response {
+              gm_request_response::Response::KeyRangeLeased(range) => {
+                _ = sender.send(Inbox::KeyRangeLeased(range));
+              }
+              gm_request_response::Response::TxStatuses(tx_updates) => {
+                _ = sender.send(Inbox::TxUpdates(tx_updates));
+              }
+              _ => {}
+            }
           }
           _ => {}
         },
@@ -182,13 +194,21 @@ fn handle_swarm_event(
     SwarmEvent::Behaviour(GatewayEvent::MetaExchange(meta_exchange)) => {
       debug!("MetaExchange: {:?}", meta_exchange);
       match meta_exchange {
-        MEEvent::Message { message, .. } => match message {
+        MEEvent::Message { message, peer, .. } => match message {
           RequestResponseMessage::Response { request_id, response } => {
             debug!("MetaExchangeResponse: {:?} {:?}", request_id, response);
           }
-          RequestResponseMessage::Request { channel, .. } => {
-            let res = swarm.behaviour_mut().meta_exchange.send_response(channel, MEResponse { role: Role::Gateway });
+          RequestResponseMessage::Request { channel, request, .. } => {
+            let response = MEResponse { role: Role::Gateway, capabilities: Capabilities::current() };
+            let res = swarm.behaviour_mut().meta_exchange.send_response(channel, response);
             debug!("MetaExchangeRequestRes: {:?}", res);
+
+            // gateway doesn't send requests to the nodes it can't talk to
+            if let Compatibility::Incompatible(reason) = Capabilities::current().check(&request.capabilities) {
+              warn!("refuse node {peer}: {reason}");
+              maroon_peer_ids.remove(&peer);
+              _ = swarm.disconnect_peer_id(peer);
+            }
           }
         },
         _ => {}
@@ -200,7 +220,7 @@ fn handle_swarm_event(
     SwarmEvent::Behaviour(GatewayEvent::Gossipsub(gs_e)) => {
       match gs_e {
         GossipsubEvent::Message { propagation_source: _, message_id: _, message } => {
-          match serde_json::from_slice::<N2GWGossipMessage>(&message.data) {
+          match wire::decode_gossip::<N2GWGossipMessage>(&message.data) {
             Ok(p2p_message) => match p2p_message.payload {
               N2GWGossipPayload::Node2GWTxUpdate(tx_updates) => {
                 _ = sender.send(Inbox::TxUpdates(tx_updates));
@@ -218,6 +238,7 @@ fn handle_swarm_event(
     SwarmEvent::ConnectionEstablished { peer_id, .. } => {
       maroon_peer_ids.insert(peer_id);
       debug!("connected to {}", peer_id);
+      _ = sender.send(Inbox::NodeConnected);
     }
     SwarmEvent::ConnectionClosed { peer_id, .. } => {
       maroon_peer_ids.remove(&peer_id);
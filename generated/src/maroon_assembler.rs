@@ -44,6 +44,22 @@ pub struct TestInfiniteSummatorQueueMessage {
   pub publicFutureId: FutureU64,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemFiberEvent {
+  FiberFailed { fiber_id: String, fiber_type: String, kind: String, detail: String },
+}
+
+impl Default for SystemFiberEvent {
+  fn default() -> Self {
+    SystemFiberEvent::FiberFailed {
+      fiber_id: String::new(),
+      fiber_type: String::new(),
+      kind: String::new(),
+      detail: String::new(),
+    }
+  }
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FutureTestIncrementTask(pub String);
 
@@ -53,29 +69,45 @@ pub struct FutureU64(pub String);
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FutureUnit(pub String);
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct RootHeap {}
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestAbandonedFuturesHeap {}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestCalculatorHeap {
   pub in_vars: TestCalculatorInVars,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestCalculatorInVars {
   pub calculationRequestsQueueName: String,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestCreateQueueHeap {}
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestDroppedRequestHeap {}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestFailingSenderHeap {
+  pub in_vars: TestFailingSenderInVars,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestFailingSenderInVars {
+  pub inQueuename: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestFunctionsCallHeap {
   pub binarySearchValues: Vec<u64>,
   pub in_vars: TestFunctionsCallInVars,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestFunctionsCallInVars {
   pub binarySearchArray: Vec<u64>,
   pub binarySearchTarget: u64,
@@ -84,47 +116,67 @@ pub struct TestFunctionsCallInVars {
   pub multb: u64,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestInfiniteSummatorHeap {}
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestRootFiberHeap {}
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestRootFiberSleepTestHeap {}
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestSelectQueueHeap {}
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestSelectTickHeap {}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestSelectTimeoutHeap {}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TestSupervisorHeap {}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestTaskExecutorIncrementerHeap {
   pub in_vars: TestTaskExecutorIncrementerInVars,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TestTaskExecutorIncrementerInVars {
   pub inTaskqueuename: String,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Heap {
   pub root: RootHeap,
+  pub testAbandonedFutures: TestAbandonedFuturesHeap,
   pub testCalculator: TestCalculatorHeap,
   pub testCreateQueue: TestCreateQueueHeap,
+  pub testDroppedRequest: TestDroppedRequestHeap,
+  pub testFailingSender: TestFailingSenderHeap,
   pub testFunctionsCall: TestFunctionsCallHeap,
   pub testInfiniteSummator: TestInfiniteSummatorHeap,
   pub testRootFiber: TestRootFiberHeap,
   pub testRootFiberSleepTest: TestRootFiberSleepTestHeap,
   pub testSelectQueue: TestSelectQueueHeap,
+  pub testSelectTick: TestSelectTickHeap,
+  pub testSelectTimeout: TestSelectTimeoutHeap,
+  pub testSupervisor: TestSupervisorHeap,
   pub testTaskExecutorIncrementer: TestTaskExecutorIncrementerHeap,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum State {
   Completed,
   Idle,
   RootMainEntry,
   RootMainReturn,
+  TestAbandonedFuturesMainCreateFutures,
+  TestAbandonedFuturesMainEntry,
+  TestAbandonedFuturesMainReturn,
+  TestAbandonedFuturesMainSleep,
+  TestAbandonedFuturesMainWokeUp,
   TestCalculatorMainCalculate,
   TestCalculatorMainDebugGottenTask,
   TestCalculatorMainDebugVars,
@@ -143,6 +195,13 @@ pub enum State {
   TestCreateQueueMainExtractFutAndInc,
   TestCreateQueueMainReturn,
   TestCreateQueueMainWrongQueueCreation,
+  TestDroppedRequestMainAwaitRequest,
+  TestDroppedRequestMainCreateQueue,
+  TestDroppedRequestMainEntry,
+  TestDroppedRequestMainReturn,
+  TestFailingSenderMainEntry,
+  TestFailingSenderMainReturn,
+  TestFailingSenderMainSend,
   TestFunctionsCallBinarySearchCalculateDiv,
   TestFunctionsCallBinarySearchCmpLess,
   TestFunctionsCallBinarySearchEntry,
@@ -200,6 +259,29 @@ pub enum State {
   TestSelectQueueMainReturn,
   TestSelectQueueMainSelectCounter,
   TestSelectQueueMainStartWork,
+  TestSelectTickMainAwaitTick,
+  TestSelectTickMainCreateTimer,
+  TestSelectTickMainEntry,
+  TestSelectTickMainReturn,
+  TestSelectTickMainTickFired,
+  TestSelectTickMainTickPending,
+  TestSelectTimeoutMainAwaitFast,
+  TestSelectTimeoutMainAwaitSlow,
+  TestSelectTimeoutMainCreateTimers,
+  TestSelectTimeoutMainEntry,
+  TestSelectTimeoutMainFastFired,
+  TestSelectTimeoutMainFastTimedOut,
+  TestSelectTimeoutMainInitFastMs,
+  TestSelectTimeoutMainReturn,
+  TestSelectTimeoutMainSlowFired,
+  TestSelectTimeoutMainSlowTimedOut,
+  TestSupervisorMainAwaitFailure,
+  TestSupervisorMainCreateFibers,
+  TestSupervisorMainDebugVars,
+  TestSupervisorMainEntry,
+  TestSupervisorMainInitMissingQueueName,
+  TestSupervisorMainReportFailure,
+  TestSupervisorMainUnpackFailure,
   TestTaskExecutorIncrementerMainAwait,
   TestTaskExecutorIncrementerMainDebug2,
   TestTaskExecutorIncrementerMainDebugVars,
@@ -221,6 +303,7 @@ pub enum Value {
   OptionString(Option<String>),
   OptionU64(Option<u64>),
   String(String),
+  SystemFiberEvent(SystemFiberEvent),
   TestCalculatorTask(TestCalculatorTask),
   TestCreateQueueMessage(TestCreateQueueMessage),
   TestCreateQueueMessagePub(TestCreateQueueMessagePub),
@@ -231,6 +314,41 @@ pub enum Value {
   Unit(()),
 }
 
+// ids of all futures the value holds, including nested ones
+pub fn value_future_ids(
+  val: &Value,
+  ids: &mut Vec<String>,
+) {
+  match val {
+    Value::FutureTestIncrementTask(v) => {
+      ids.push(v.0.clone());
+    }
+    Value::FutureU64(v) => {
+      ids.push(v.0.clone());
+    }
+    Value::FutureUnit(v) => {
+      ids.push(v.0.clone());
+    }
+    Value::TestCalculatorTask(v) => {
+      ids.push(v.responseFutureId.0.clone());
+    }
+    Value::TestCreateQueueMessage(v) => {
+      ids.push(v.publicFutureId.0.clone());
+    }
+    Value::TestInfiniteSummatorQueueMessage(v) => {
+      ids.push(v.publicFutureId.0.clone());
+    }
+    _ => {}
+  }
+}
+
+// ids of all futures in heaps of the fiber, including init vars
+pub fn heap_future_ids(
+  heap: &Heap,
+  ids: &mut Vec<String>,
+) {
+}
+
 pub fn pub_to_private(
   val: Value,
   future_id: String,
@@ -260,7 +378,15 @@ pub fn private_to_pub(val: Value) -> Value {
   }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+pub fn pub_queue_message_type(val: &Value) -> Option<&'static str> {
+  match val {
+    Value::TestCreateQueueMessagePub(_) => Some("TestCreateQueueMessage"),
+    Value::TestInfiniteSummatorQueueMessagePub(_) => Some("TestInfiniteSummatorQueueMessage"),
+    _ => None,
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StackEntry {
   State(State),
   // Option<usize> - local index offset back on stack
@@ -270,7 +396,7 @@ pub enum StackEntry {
   // In-place updates to the current frame (offset -> new Value)
   FrameAssign(Vec<(usize, Value)>),
 }
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FutureKind {
   FutureTestIncrementTask,
   FutureU64,
@@ -288,22 +414,25 @@ pub fn wrap_future_id(
   }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SuccessBindKind {
   String,
   Future(FutureKind),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SelectArm {
   FutureVar { future_id: String, bind: Option<String>, next: State },
   Queue { queue_name: String, bind: String, next: State },
+  // Resolves after `ms` milliseconds, runtime turns it into a timer future arm.
+  Timeout { ms: u64, next: State },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CreatePrimitiveValue {
   Future,
-  Queue { name: String, public: bool },
+  // `message` - name of the PubQueueMessage type gateways can send to the public queue
+  Queue { name: String, public: bool, message: Option<String> },
   // Create a scheduled timer future that resolves after `ms` milliseconds.
   // The created future is a Void future (Unit), i.e., it signals completion with no value.
   Schedule { ms: u64 },
@@ -356,6 +485,11 @@ pub fn func_args_count(e: &State) -> usize {
   match e {
     State::RootMainEntry => 0,
     State::RootMainReturn => 0,
+    State::TestAbandonedFuturesMainEntry => 4,
+    State::TestAbandonedFuturesMainCreateFutures => 4,
+    State::TestAbandonedFuturesMainReturn => 4,
+    State::TestAbandonedFuturesMainSleep => 4,
+    State::TestAbandonedFuturesMainWokeUp => 4,
     State::TestCalculatorMainEntry => 3,
     State::TestCalculatorMainCalculate => 3,
     State::TestCalculatorMainDebugGottenTask => 3,
@@ -374,6 +508,13 @@ pub fn func_args_count(e: &State) -> usize {
     State::TestCreateQueueMainExtractFutAndInc => 6,
     State::TestCreateQueueMainReturn => 6,
     State::TestCreateQueueMainWrongQueueCreation => 6,
+    State::TestDroppedRequestMainEntry => 4,
+    State::TestDroppedRequestMainAwaitRequest => 4,
+    State::TestDroppedRequestMainCreateQueue => 4,
+    State::TestDroppedRequestMainReturn => 4,
+    State::TestFailingSenderMainEntry => 2,
+    State::TestFailingSenderMainReturn => 2,
+    State::TestFailingSenderMainSend => 2,
     State::TestFunctionsCallBinarySearchEntry => 6,
     State::TestFunctionsCallBinarySearchCalculateDiv => 6,
     State::TestFunctionsCallBinarySearchCmpLess => 6,
@@ -431,6 +572,29 @@ pub fn func_args_count(e: &State) -> usize {
     State::TestSelectQueueMainReturn => 5,
     State::TestSelectQueueMainSelectCounter => 5,
     State::TestSelectQueueMainStartWork => 5,
+    State::TestSelectTickMainEntry => 3,
+    State::TestSelectTickMainAwaitTick => 3,
+    State::TestSelectTickMainCreateTimer => 3,
+    State::TestSelectTickMainReturn => 3,
+    State::TestSelectTickMainTickFired => 3,
+    State::TestSelectTickMainTickPending => 3,
+    State::TestSelectTimeoutMainEntry => 5,
+    State::TestSelectTimeoutMainAwaitFast => 5,
+    State::TestSelectTimeoutMainAwaitSlow => 5,
+    State::TestSelectTimeoutMainCreateTimers => 5,
+    State::TestSelectTimeoutMainFastFired => 5,
+    State::TestSelectTimeoutMainFastTimedOut => 5,
+    State::TestSelectTimeoutMainInitFastMs => 5,
+    State::TestSelectTimeoutMainReturn => 5,
+    State::TestSelectTimeoutMainSlowFired => 5,
+    State::TestSelectTimeoutMainSlowTimedOut => 5,
+    State::TestSupervisorMainEntry => 5,
+    State::TestSupervisorMainAwaitFailure => 5,
+    State::TestSupervisorMainCreateFibers => 5,
+    State::TestSupervisorMainDebugVars => 5,
+    State::TestSupervisorMainInitMissingQueueName => 5,
+    State::TestSupervisorMainReportFailure => 5,
+    State::TestSupervisorMainUnpackFailure => 5,
     State::TestTaskExecutorIncrementerMainEntry => 4,
     State::TestTaskExecutorIncrementerMainAwait => 4,
     State::TestTaskExecutorIncrementerMainDebug2 => 4,
@@ -457,6 +621,31 @@ pub fn global_step(
       next: State::RootMainReturn,
     },
     State::RootMainReturn => StepResult::ReturnVoid,
+    State::TestAbandonedFuturesMainEntry => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(0, Value::U64(1000u64))]),
+      StackEntry::State(State::TestAbandonedFuturesMainCreateFutures),
+    ]),
+    State::TestAbandonedFuturesMainCreateFutures => StepResult::Create {
+      primitives: vec![
+        CreatePrimitiveValue::Future,
+        CreatePrimitiveValue::Schedule {
+          ms: if let StackEntry::Value(_, Value::U64(x)) = &vars[0] { x.clone() } else { unreachable!() },
+        },
+      ],
+      success_next: State::TestAbandonedFuturesMainSleep,
+      success_binds: vec!["abandoned".to_string(), "abandonedTimer".to_string()],
+      success_kinds: vec![
+        SuccessBindKind::Future(FutureKind::FutureU64),
+        SuccessBindKind::Future(FutureKind::FutureUnit),
+      ],
+      fail_next: State::TestAbandonedFuturesMainReturn,
+      fail_binds: vec!["createError".to_string(), "createError".to_string()],
+    },
+    State::TestAbandonedFuturesMainReturn => StepResult::ReturnVoid,
+    State::TestAbandonedFuturesMainSleep => {
+      StepResult::Select(vec![SelectArm::Timeout { ms: 10, next: State::TestAbandonedFuturesMainWokeUp }])
+    }
+    State::TestAbandonedFuturesMainWokeUp => StepResult::Debug("woke up", State::TestAbandonedFuturesMainReturn),
     State::TestCalculatorMainEntry => StepResult::DebugPrintVars(State::TestCalculatorMainSelectQueue),
     State::TestCalculatorMainCalculate => {
       let calculationRequestsQueueName: String = heap.testCalculator.in_vars.calculationRequestsQueueName.clone();
@@ -546,6 +735,7 @@ pub fn global_step(
         primitives: vec![CreatePrimitiveValue::Queue {
           name: if let StackEntry::Value(_, Value::String(x)) = &vars[1] { x.clone() } else { unreachable!() },
           public: true,
+          message: Some("TestCreateQueueMessage".to_string()),
         }],
         success_next: State::TestCreateQueueMainDebugVars2,
         success_binds: vec!["created_queue_name".to_string()],
@@ -587,10 +777,12 @@ pub fn global_step(
           CreatePrimitiveValue::Queue {
             name: if let StackEntry::Value(_, Value::String(x)) = &vars[1] { x.clone() } else { unreachable!() },
             public: true,
+            message: Some("TestCreateQueueMessage".to_string()),
           },
           CreatePrimitiveValue::Queue {
             name: if let StackEntry::Value(_, Value::String(x)) = &vars[1] { x.clone() } else { unreachable!() },
             public: true,
+            message: Some("TestCreateQueueMessage".to_string()),
           },
         ],
         success_next: State::TestCreateQueueMainReturn,
@@ -600,6 +792,62 @@ pub fn global_step(
         fail_binds: vec!["f_queueCreationError".to_string(), "f_queueCreationError".to_string()],
       }
     }
+    State::TestDroppedRequestMainEntry => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(0, Value::String("droppedRequests".to_string()))]),
+      StackEntry::State(State::TestDroppedRequestMainCreateQueue),
+    ]),
+    State::TestDroppedRequestMainAwaitRequest => {
+      let createdQueueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[1] { x.clone() } else { unreachable!() };
+      StepResult::Select(vec![SelectArm::Queue {
+        queue_name: createdQueueName.clone(),
+        bind: "request".to_string(),
+        next: State::TestDroppedRequestMainReturn,
+      }])
+    }
+    State::TestDroppedRequestMainCreateQueue => {
+      let queueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() };
+      StepResult::Create {
+        primitives: vec![CreatePrimitiveValue::Queue {
+          name: if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() },
+          public: true,
+          message: Some("TestCreateQueueMessage".to_string()),
+        }],
+        success_next: State::TestDroppedRequestMainAwaitRequest,
+        success_binds: vec!["createdQueueName".to_string()],
+        success_kinds: vec![SuccessBindKind::String],
+        fail_next: State::TestDroppedRequestMainReturn,
+        fail_binds: vec!["createError".to_string()],
+      }
+    }
+    State::TestDroppedRequestMainReturn => StepResult::ReturnVoid,
+    State::TestFailingSenderMainEntry => {
+      let inQueuename: String = heap.testFailingSender.in_vars.inQueuename.clone();
+      let message: u64 = if let StackEntry::Value(_, Value::U64(x)) = &vars[1] { x.clone() } else { unreachable!() };
+      let queueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() };
+      {
+        let out = { heap.testFailingSender.in_vars.inQueuename.clone() };
+        StepResult::Next(vec![
+          StackEntry::FrameAssign(vec![(0, Value::String(out))]),
+          StackEntry::State(State::TestFailingSenderMainSend),
+        ])
+      }
+    }
+    State::TestFailingSenderMainReturn => StepResult::ReturnVoid,
+    State::TestFailingSenderMainSend => {
+      let message: u64 = if let StackEntry::Value(_, Value::U64(x)) = &vars[1] { x.clone() } else { unreachable!() };
+      let queueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() };
+      StepResult::SetValues {
+        values: vec![SetPrimitiveValue::QueueMessage {
+          queue_name: queueName.clone(),
+          value: Value::U64(message.clone()),
+        }],
+        next: State::TestFailingSenderMainReturn,
+      }
+    }
     State::TestFunctionsCallBinarySearchEntry => {
       let e: u64 = if let StackEntry::Value(_, Value::U64(x)) = &vars[0] { x.clone() } else { unreachable!() };
       let left: u64 = if let StackEntry::Value(_, Value::U64(x)) = &vars[1] { x.clone() } else { unreachable!() };
@@ -896,6 +1144,7 @@ pub fn global_step(
         primitives: vec![CreatePrimitiveValue::Queue {
           name: if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() },
           public: true,
+          message: Some("TestInfiniteSummatorQueueMessage".to_string()),
         }],
         success_next: State::TestInfiniteSummatorMainSelectQueue,
         success_binds: vec!["infiniteCalculatorQueue".to_string()],
@@ -987,7 +1236,8 @@ pub fn global_step(
       StepResult::Create {
         primitives: vec![CreatePrimitiveValue::Queue {
           name: if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() },
-          public: true,
+          public: false,
+          message: None,
         }],
         success_next: State::TestRootFiberMainCreateFiber,
         success_binds: vec!["rootQueueName".to_string()],
@@ -1155,6 +1405,161 @@ pub fn global_step(
         ])
       }
     }
+    State::TestSelectTickMainEntry => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(0, Value::U64(50u64))]),
+      StackEntry::State(State::TestSelectTickMainCreateTimer),
+    ]),
+    State::TestSelectTickMainAwaitTick => {
+      let tickTimer: FutureUnit =
+        if let StackEntry::Value(_, Value::FutureUnit(x)) = &vars[1] { x.clone() } else { unreachable!() };
+      StepResult::Select(vec![
+        SelectArm::FutureVar { future_id: tickTimer.0.clone(), bind: None, next: State::TestSelectTickMainTickFired },
+        SelectArm::Timeout { ms: 30, next: State::TestSelectTickMainTickPending },
+      ])
+    }
+    State::TestSelectTickMainCreateTimer => StepResult::Create {
+      primitives: vec![CreatePrimitiveValue::Schedule {
+        ms: if let StackEntry::Value(_, Value::U64(x)) = &vars[0] { x.clone() } else { unreachable!() },
+      }],
+      success_next: State::TestSelectTickMainAwaitTick,
+      success_binds: vec!["tickTimer".to_string()],
+      success_kinds: vec![SuccessBindKind::Future(FutureKind::FutureUnit)],
+      fail_next: State::TestSelectTickMainReturn,
+      fail_binds: vec!["createError".to_string()],
+    },
+    State::TestSelectTickMainReturn => StepResult::ReturnVoid,
+    State::TestSelectTickMainTickFired => StepResult::Debug("tick timer fired", State::TestSelectTickMainReturn),
+    State::TestSelectTickMainTickPending => {
+      StepResult::Debug("tick timer is pending", State::TestSelectTickMainAwaitTick)
+    }
+    State::TestSelectTimeoutMainEntry => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(0, Value::U64(100u64))]),
+      StackEntry::State(State::TestSelectTimeoutMainInitFastMs),
+    ]),
+    State::TestSelectTimeoutMainAwaitFast => {
+      let fastTimer: FutureUnit =
+        if let StackEntry::Value(_, Value::FutureUnit(x)) = &vars[3] { x.clone() } else { unreachable!() };
+      StepResult::Select(vec![
+        SelectArm::FutureVar {
+          future_id: fastTimer.0.clone(),
+          bind: None,
+          next: State::TestSelectTimeoutMainFastFired,
+        },
+        SelectArm::Timeout { ms: 30, next: State::TestSelectTimeoutMainFastTimedOut },
+      ])
+    }
+    State::TestSelectTimeoutMainAwaitSlow => {
+      let slowTimer: FutureUnit =
+        if let StackEntry::Value(_, Value::FutureUnit(x)) = &vars[2] { x.clone() } else { unreachable!() };
+      StepResult::Select(vec![
+        SelectArm::FutureVar {
+          future_id: slowTimer.0.clone(),
+          bind: None,
+          next: State::TestSelectTimeoutMainSlowFired,
+        },
+        SelectArm::Timeout { ms: 30, next: State::TestSelectTimeoutMainSlowTimedOut },
+      ])
+    }
+    State::TestSelectTimeoutMainCreateTimers => StepResult::Create {
+      primitives: vec![
+        CreatePrimitiveValue::Schedule {
+          ms: if let StackEntry::Value(_, Value::U64(x)) = &vars[0] { x.clone() } else { unreachable!() },
+        },
+        CreatePrimitiveValue::Schedule {
+          ms: if let StackEntry::Value(_, Value::U64(x)) = &vars[1] { x.clone() } else { unreachable!() },
+        },
+      ],
+      success_next: State::TestSelectTimeoutMainAwaitSlow,
+      success_binds: vec!["slowTimer".to_string(), "fastTimer".to_string()],
+      success_kinds: vec![
+        SuccessBindKind::Future(FutureKind::FutureUnit),
+        SuccessBindKind::Future(FutureKind::FutureUnit),
+      ],
+      fail_next: State::TestSelectTimeoutMainReturn,
+      fail_binds: vec!["createError".to_string(), "createError".to_string()],
+    },
+    State::TestSelectTimeoutMainFastFired => StepResult::Debug("fast timer fired", State::TestSelectTimeoutMainReturn),
+    State::TestSelectTimeoutMainFastTimedOut => {
+      StepResult::Debug("fast timer timed out", State::TestSelectTimeoutMainReturn)
+    }
+    State::TestSelectTimeoutMainInitFastMs => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(1, Value::U64(10u64))]),
+      StackEntry::State(State::TestSelectTimeoutMainCreateTimers),
+    ]),
+    State::TestSelectTimeoutMainReturn => StepResult::ReturnVoid,
+    State::TestSelectTimeoutMainSlowFired => StepResult::Debug("slow timer fired", State::TestSelectTimeoutMainReturn),
+    State::TestSelectTimeoutMainSlowTimedOut => {
+      StepResult::Debug("slow timer timed out", State::TestSelectTimeoutMainAwaitFast)
+    }
+    State::TestSupervisorMainEntry => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(0, Value::String("mrn.system.fibers".to_string()))]),
+      StackEntry::State(State::TestSupervisorMainInitMissingQueueName),
+    ]),
+    State::TestSupervisorMainAwaitFailure => {
+      let systemQueueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() };
+      StepResult::Select(vec![SelectArm::Queue {
+        queue_name: systemQueueName.clone(),
+        bind: "event".to_string(),
+        next: State::TestSupervisorMainUnpackFailure,
+      }])
+    }
+    State::TestSupervisorMainCreateFibers => {
+      let missingQueueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[1] { x.clone() } else { unreachable!() };
+      let systemQueueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() };
+      StepResult::CreateFibers {
+        details: vec![
+          (
+            FiberType::new("testFailingSender"),
+            vec![Value::String(if let StackEntry::Value(_, Value::String(x)) = &vars[1] {
+              x.clone()
+            } else {
+              unreachable!()
+            })],
+          ),
+          (
+            FiberType::new("testFailingSender"),
+            vec![Value::String(if let StackEntry::Value(_, Value::String(x)) = &vars[0] {
+              x.clone()
+            } else {
+              unreachable!()
+            })],
+          ),
+        ],
+        next: State::TestSupervisorMainAwaitFailure,
+      }
+    }
+    State::TestSupervisorMainDebugVars => StepResult::DebugPrintVars(State::TestSupervisorMainAwaitFailure),
+    State::TestSupervisorMainInitMissingQueueName => StepResult::Next(vec![
+      StackEntry::FrameAssign(vec![(1, Value::String("missingQueue".to_string()))]),
+      StackEntry::State(State::TestSupervisorMainCreateFibers),
+    ]),
+    State::TestSupervisorMainReportFailure => StepResult::Debug("fiber failed", State::TestSupervisorMainDebugVars),
+    State::TestSupervisorMainUnpackFailure => {
+      let event: SystemFiberEvent =
+        if let StackEntry::Value(_, Value::SystemFiberEvent(x)) = &vars[2] { x.clone() } else { unreachable!() };
+      let failedFiber: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[3] { x.clone() } else { unreachable!() };
+      let failureKind: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[4] { x.clone() } else { unreachable!() };
+      let missingQueueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[1] { x.clone() } else { unreachable!() };
+      let systemQueueName: String =
+        if let StackEntry::Value(_, Value::String(x)) = &vars[0] { x.clone() } else { unreachable!() };
+      {
+        let out = {
+          let SystemFiberEvent::FiberFailed { fiber_id, fiber_type, kind, .. } = event;
+          (format!("{fiber_type}:{fiber_id}"), kind)
+        };
+        let (o0, o1) = out;
+        StepResult::Next(vec![
+          StackEntry::FrameAssign(vec![(3, Value::String(o0)), (4, Value::String(o1))]),
+          StackEntry::State(State::TestSupervisorMainReportFailure),
+        ])
+      }
+    }
     State::TestTaskExecutorIncrementerMainEntry => {
       StepResult::Debug("start function", State::TestTaskExecutorIncrementerMainInitQueueName)
     }
@@ -1270,6 +1675,32 @@ fn root_result_main_value(stack: &[StackEntry]) -> Value {
   Value::Unit(root_result_main(stack))
 }
 
+pub fn testAbandonedFutures_prepare_main() -> (Vec<StackEntry>, Heap) {
+  let mut stack: Vec<StackEntry> = Vec::new();
+  stack.push(StackEntry::Retrn(Some(1)));
+  stack.push(StackEntry::Value("timerMs".to_string(), Value::U64(0u64)));
+  stack.push(StackEntry::Value("abandoned".to_string(), Value::FutureU64(FutureU64::default())));
+  stack.push(StackEntry::Value("abandonedTimer".to_string(), Value::FutureUnit(FutureUnit::default())));
+  stack.push(StackEntry::Value("createError".to_string(), Value::OptionString(None)));
+  stack.push(StackEntry::State(State::TestAbandonedFuturesMainEntry));
+  let heap = Heap::default();
+  (stack, heap)
+}
+
+pub fn testAbandonedFutures_result_main(stack: &[StackEntry]) -> () {
+  let _ = stack;
+  ()
+}
+
+fn testAbandonedFutures_prepare_main_from_values(args: Vec<Value>) -> Vec<StackEntry> {
+  let (stack, _heap) = testAbandonedFutures_prepare_main();
+  stack
+}
+
+fn testAbandonedFutures_result_main_value(stack: &[StackEntry]) -> Value {
+  Value::Unit(testAbandonedFutures_result_main(stack))
+}
+
 pub fn testCalculator_prepare_main() -> (Vec<StackEntry>, Heap) {
   let mut stack: Vec<StackEntry> = Vec::new();
   stack.push(StackEntry::Retrn(Some(1)));
@@ -1323,6 +1754,57 @@ fn testCreateQueue_result_main_value(stack: &[StackEntry]) -> Value {
   Value::Unit(testCreateQueue_result_main(stack))
 }
 
+pub fn testDroppedRequest_prepare_main() -> (Vec<StackEntry>, Heap) {
+  let mut stack: Vec<StackEntry> = Vec::new();
+  stack.push(StackEntry::Retrn(Some(1)));
+  stack.push(StackEntry::Value("queueName".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::Value("createdQueueName".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::Value("createError".to_string(), Value::OptionString(None)));
+  stack
+    .push(StackEntry::Value("request".to_string(), Value::TestCreateQueueMessage(TestCreateQueueMessage::default())));
+  stack.push(StackEntry::State(State::TestDroppedRequestMainEntry));
+  let heap = Heap::default();
+  (stack, heap)
+}
+
+pub fn testDroppedRequest_result_main(stack: &[StackEntry]) -> () {
+  let _ = stack;
+  ()
+}
+
+fn testDroppedRequest_prepare_main_from_values(args: Vec<Value>) -> Vec<StackEntry> {
+  let (stack, _heap) = testDroppedRequest_prepare_main();
+  stack
+}
+
+fn testDroppedRequest_result_main_value(stack: &[StackEntry]) -> Value {
+  Value::Unit(testDroppedRequest_result_main(stack))
+}
+
+pub fn testFailingSender_prepare_main() -> (Vec<StackEntry>, Heap) {
+  let mut stack: Vec<StackEntry> = Vec::new();
+  stack.push(StackEntry::Retrn(Some(1)));
+  stack.push(StackEntry::Value("queueName".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::Value("message".to_string(), Value::U64(0u64)));
+  stack.push(StackEntry::State(State::TestFailingSenderMainEntry));
+  let heap = Heap::default();
+  (stack, heap)
+}
+
+pub fn testFailingSender_result_main(stack: &[StackEntry]) -> () {
+  let _ = stack;
+  ()
+}
+
+fn testFailingSender_prepare_main_from_values(args: Vec<Value>) -> Vec<StackEntry> {
+  let (stack, _heap) = testFailingSender_prepare_main();
+  stack
+}
+
+fn testFailingSender_result_main_value(stack: &[StackEntry]) -> Value {
+  Value::Unit(testFailingSender_result_main(stack))
+}
+
 pub fn testFunctionsCall_prepare_binarySearch(
   e: u64,
   left: u64,
@@ -1616,6 +2098,85 @@ fn testSelectQueue_result_main_value(stack: &[StackEntry]) -> Value {
   Value::Unit(testSelectQueue_result_main(stack))
 }
 
+pub fn testSelectTick_prepare_main() -> (Vec<StackEntry>, Heap) {
+  let mut stack: Vec<StackEntry> = Vec::new();
+  stack.push(StackEntry::Retrn(Some(1)));
+  stack.push(StackEntry::Value("tickMs".to_string(), Value::U64(0u64)));
+  stack.push(StackEntry::Value("tickTimer".to_string(), Value::FutureUnit(FutureUnit::default())));
+  stack.push(StackEntry::Value("createError".to_string(), Value::OptionString(None)));
+  stack.push(StackEntry::State(State::TestSelectTickMainEntry));
+  let heap = Heap::default();
+  (stack, heap)
+}
+
+pub fn testSelectTick_result_main(stack: &[StackEntry]) -> () {
+  let _ = stack;
+  ()
+}
+
+fn testSelectTick_prepare_main_from_values(args: Vec<Value>) -> Vec<StackEntry> {
+  let (stack, _heap) = testSelectTick_prepare_main();
+  stack
+}
+
+fn testSelectTick_result_main_value(stack: &[StackEntry]) -> Value {
+  Value::Unit(testSelectTick_result_main(stack))
+}
+
+pub fn testSelectTimeout_prepare_main() -> (Vec<StackEntry>, Heap) {
+  let mut stack: Vec<StackEntry> = Vec::new();
+  stack.push(StackEntry::Retrn(Some(1)));
+  stack.push(StackEntry::Value("slowMs".to_string(), Value::U64(0u64)));
+  stack.push(StackEntry::Value("fastMs".to_string(), Value::U64(0u64)));
+  stack.push(StackEntry::Value("slowTimer".to_string(), Value::FutureUnit(FutureUnit::default())));
+  stack.push(StackEntry::Value("fastTimer".to_string(), Value::FutureUnit(FutureUnit::default())));
+  stack.push(StackEntry::Value("createError".to_string(), Value::OptionString(None)));
+  stack.push(StackEntry::State(State::TestSelectTimeoutMainEntry));
+  let heap = Heap::default();
+  (stack, heap)
+}
+
+pub fn testSelectTimeout_result_main(stack: &[StackEntry]) -> () {
+  let _ = stack;
+  ()
+}
+
+fn testSelectTimeout_prepare_main_from_values(args: Vec<Value>) -> Vec<StackEntry> {
+  let (stack, _heap) = testSelectTimeout_prepare_main();
+  stack
+}
+
+fn testSelectTimeout_result_main_value(stack: &[StackEntry]) -> Value {
+  Value::Unit(testSelectTimeout_result_main(stack))
+}
+
+pub fn testSupervisor_prepare_main() -> (Vec<StackEntry>, Heap) {
+  let mut stack: Vec<StackEntry> = Vec::new();
+  stack.push(StackEntry::Retrn(Some(1)));
+  stack.push(StackEntry::Value("systemQueueName".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::Value("missingQueueName".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::Value("event".to_string(), Value::SystemFiberEvent(SystemFiberEvent::default())));
+  stack.push(StackEntry::Value("failedFiber".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::Value("failureKind".to_string(), Value::String(String::new())));
+  stack.push(StackEntry::State(State::TestSupervisorMainEntry));
+  let heap = Heap::default();
+  (stack, heap)
+}
+
+pub fn testSupervisor_result_main(stack: &[StackEntry]) -> () {
+  let _ = stack;
+  ()
+}
+
+fn testSupervisor_prepare_main_from_values(args: Vec<Value>) -> Vec<StackEntry> {
+  let (stack, _heap) = testSupervisor_prepare_main();
+  stack
+}
+
+fn testSupervisor_result_main_value(stack: &[StackEntry]) -> Value {
+  Value::Unit(testSupervisor_result_main(stack))
+}
+
 pub fn testTaskExecutorIncrementer_prepare_main() -> (Vec<StackEntry>, Heap) {
   let mut stack: Vec<StackEntry> = Vec::new();
   stack.push(StackEntry::Retrn(Some(1)));
@@ -1648,8 +2209,11 @@ fn testTaskExecutorIncrementer_result_main_value(stack: &[StackEntry]) -> Value
 pub fn get_prepare_fn(key: &str) -> PrepareFn {
   match key {
     "root.main" => root_prepare_main_from_values,
+    "testAbandonedFutures.main" => testAbandonedFutures_prepare_main_from_values,
     "testCalculator.main" => testCalculator_prepare_main_from_values,
     "testCreateQueue.main" => testCreateQueue_prepare_main_from_values,
+    "testDroppedRequest.main" => testDroppedRequest_prepare_main_from_values,
+    "testFailingSender.main" => testFailingSender_prepare_main_from_values,
     "testFunctionsCall.binary_search" => testFunctionsCall_prepare_binarySearch_from_values,
     "testFunctionsCall.factorial" => testFunctionsCall_prepare_factorial_from_values,
     "testFunctionsCall.main" => testFunctionsCall_prepare_main_from_values,
@@ -1659,6 +2223,9 @@ pub fn get_prepare_fn(key: &str) -> PrepareFn {
     "testRootFiber.main" => testRootFiber_prepare_main_from_values,
     "testRootFiberSleepTest.main" => testRootFiberSleepTest_prepare_main_from_values,
     "testSelectQueue.main" => testSelectQueue_prepare_main_from_values,
+    "testSelectTick.main" => testSelectTick_prepare_main_from_values,
+    "testSelectTimeout.main" => testSelectTimeout_prepare_main_from_values,
+    "testSupervisor.main" => testSupervisor_prepare_main_from_values,
     "testTaskExecutorIncrementer.main" => testTaskExecutorIncrementer_prepare_main_from_values,
     _ => panic!("shouldnt be here"),
   }
@@ -1675,6 +2242,15 @@ fn root_prepare_heap_from_values(args: Vec<Value>) -> Heap {
   root_prepare_heap()
 }
 
+pub fn testAbandonedFutures_prepare_heap() -> Heap {
+  let mut heap = Heap::default();
+  heap
+}
+
+fn testAbandonedFutures_prepare_heap_from_values(args: Vec<Value>) -> Heap {
+  testAbandonedFutures_prepare_heap()
+}
+
 pub fn testCalculator_prepare_heap(calculationRequestsQueueName: String) -> Heap {
   let mut heap = Heap::default();
   heap.testCalculator.in_vars.calculationRequestsQueueName = calculationRequestsQueueName;
@@ -1696,6 +2272,26 @@ fn testCreateQueue_prepare_heap_from_values(args: Vec<Value>) -> Heap {
   testCreateQueue_prepare_heap()
 }
 
+pub fn testDroppedRequest_prepare_heap() -> Heap {
+  let mut heap = Heap::default();
+  heap
+}
+
+fn testDroppedRequest_prepare_heap_from_values(args: Vec<Value>) -> Heap {
+  testDroppedRequest_prepare_heap()
+}
+
+pub fn testFailingSender_prepare_heap(inQueuename: String) -> Heap {
+  let mut heap = Heap::default();
+  heap.testFailingSender.in_vars.inQueuename = inQueuename;
+  heap
+}
+
+fn testFailingSender_prepare_heap_from_values(args: Vec<Value>) -> Heap {
+  let inQueuename: String = if let Some(Value::String(x)) = args.get(0) { x.clone() } else { String::new() };
+  testFailingSender_prepare_heap(inQueuename)
+}
+
 pub fn testFunctionsCall_prepare_heap(
   multa: u64,
   multb: u64,
@@ -1758,6 +2354,33 @@ fn testSelectQueue_prepare_heap_from_values(args: Vec<Value>) -> Heap {
   testSelectQueue_prepare_heap()
 }
 
+pub fn testSelectTick_prepare_heap() -> Heap {
+  let mut heap = Heap::default();
+  heap
+}
+
+fn testSelectTick_prepare_heap_from_values(args: Vec<Value>) -> Heap {
+  testSelectTick_prepare_heap()
+}
+
+pub fn testSelectTimeout_prepare_heap() -> Heap {
+  let mut heap = Heap::default();
+  heap
+}
+
+fn testSelectTimeout_prepare_heap_from_values(args: Vec<Value>) -> Heap {
+  testSelectTimeout_prepare_heap()
+}
+
+pub fn testSupervisor_prepare_heap() -> Heap {
+  let mut heap = Heap::default();
+  heap
+}
+
+fn testSupervisor_prepare_heap_from_values(args: Vec<Value>) -> Heap {
+  testSupervisor_prepare_heap()
+}
+
 pub fn testTaskExecutorIncrementer_prepare_heap(inTaskqueuename: String) -> Heap {
   let mut heap = Heap::default();
   heap.testTaskExecutorIncrementer.in_vars.inTaskqueuename = inTaskqueuename;
@@ -1772,14 +2395,23 @@ fn testTaskExecutorIncrementer_prepare_heap_from_values(args: Vec<Value>) -> Hea
 pub fn get_heap_init_fn(fiber: &FiberType) -> HeapInitFn {
   match fiber.0.as_str() {
     "root" => root_prepare_heap_from_values,
+    "testAbandonedFutures" => testAbandonedFutures_prepare_heap_from_values,
     "testCalculator" => testCalculator_prepare_heap_from_values,
     "testCreateQueue" => testCreateQueue_prepare_heap_from_values,
+    "testDroppedRequest" => testDroppedRequest_prepare_heap_from_values,
+    "testFailingSender" => testFailingSender_prepare_heap_from_values,
     "testFunctionsCall" => testFunctionsCall_prepare_heap_from_values,
     "testInfiniteSummator" => testInfiniteSummator_prepare_heap_from_values,
     "testRootFiber" => testRootFiber_prepare_heap_from_values,
     "testRootFiberSleepTest" => testRootFiberSleepTest_prepare_heap_from_values,
     "testSelectQueue" => testSelectQueue_prepare_heap_from_values,
+    "testSelectTick" => testSelectTick_prepare_heap_from_values,
+    "testSelectTimeout" => testSelectTimeout_prepare_heap_from_values,
+    "testSupervisor" => testSupervisor_prepare_heap_from_values,
     "testTaskExecutorIncrementer" => testTaskExecutorIncrementer_prepare_heap_from_values,
     _ => |_| Heap::default(),
   }
 }
+
+// nodes and gateways with different hashes can't exchange values or execute the same epochs
+pub const IR_SCHEMA_HASH: u64 = 0xc0b1c9883d89077b;
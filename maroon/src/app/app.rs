@@ -1,17 +1,21 @@
 use super::{
-  interface::{CurrentOffsets, Request, Response},
-  params::Params,
+  interface::{CurrentOffsets, Divergence, EpochState, ReplicaDivergence, Request, Response},
+  params::{EpochLimits, Params},
+  tx_results::TxResults,
 };
 use crate::{
   app::app_metrics,
   epoch_decision_engine::{EpochDecisionEngine, new_decider},
   linearizer::{Linearizer, LogLineriazer},
-  network::{Inbox, NodeState, Outbox},
+  network::{EpochDigest, Inbox, NodeState, Outbox, PeerCompatibility},
+  snapshot::{self, Snapshot},
+  wal::{Wal, WalRecord, WalSync, WalTruncation},
 };
 use common::{
   duplex_channel::Endpoint,
-  invoker_handler::{HandlerInterface, RequestWrapper},
+  invoker_handler::{HandlerInterface, InvokerInterface, RequestWrapper},
   logical_clock::{MonotonicTimer, Timer},
+  logical_time::LogicalTimeAbsoluteMs,
   range_key::{
     self, KeyOffset, KeyRange, U64BlobIdClosedInterval, UniqueU64BlobId, range_offset_from_unique_blob_id,
     unique_blob_id_from_range_and_offset,
@@ -19,27 +23,34 @@ use common::{
 };
 use epoch_coordinator::{
   self,
-  epoch::Epoch,
+  epoch::{Epoch, EpochCheckpoint, KeyRangeLeases, LeaseRequest, Membership, MembershipChange},
   interface::{ControllerInterface as EpochCoordinatorControllerInterface, EpochRequest, EpochUpdates},
 };
+use generated::maroon_assembler::Value;
 use libp2p::PeerId;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use opentelemetry::KeyValue;
 use protocol::{
+  m2m_request_response::{ResumeToken, TxPage, TxPageRequest},
   node2gw::TxUpdate,
-  transaction::{Transaction, TxStatus},
+  transaction::{Meta, Transaction, TxStatus},
 };
-use runtime::runtime::TaskBlueprint;
-use runtime::runtime::{Input as RuntimeInput, Output as RuntimeOutput};
+use runtime::runtime::{Input as RuntimeInput, Output as RuntimeOutput, Rejection, check_public_message};
+use runtime::runtime::{StateDigest, TaskBlueprint};
+use runtime::snapshot::{RuntimeSnapshot, SnapshotRequest};
+use schema::mn_events::{LogEvent, LogEventBody, now_microsec};
 use std::{
-  collections::{HashMap, HashSet},
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
   num::NonZeroUsize,
   time::Duration,
   vec,
 };
 use tokio::{
-  sync::oneshot,
-  time::{MissedTickBehavior, interval},
+  sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    oneshot,
+  },
+  time::{Instant, MissedTickBehavior, interval},
 };
 use types::range_key::range_from_unique_blob_id;
 
@@ -57,9 +68,13 @@ pub struct App<L: Linearizer> {
 
   /// offsets for all the nodes this one knows about(+ itself)
   offsets: HashMap<KeyRange, HashMap<PeerId, KeyOffset>>,
+  /// when other nodes have advertised their offsets the last time
+  /// offsets of the nodes that have disconnected or have been silent for `Params::offsets_ttl` are dropped
+  offsets_advertised_at: HashMap<PeerId, Instant>,
 
   /// consensus offset that is collected from currently running nodes
   /// it's not what is stored on s3 or etcd!!!
+  /// it's recalculated from scratch, so ranges without quorum among the alive nodes don't have it
   ///
   /// what to do if some nodes are gone and new nodes don't have all the offsets yet? - download from s3
   consensus_offset: HashMap<KeyRange, KeyOffset>,
@@ -73,18 +88,98 @@ pub struct App<L: Linearizer> {
   /// it's what will be stored on etcd & s3
   epochs: Vec<Epoch>,
 
+  /// epochs that came before their predecessors, they're applied as soon as the gap is filled
+  out_of_order_epochs: BTreeMap<u64, Epoch>,
+
+  /// all the epochs before `epochs` folded into one record
+  /// is set when the node starts after the history has been compacted
+  checkpoint: Option<EpochCheckpoint>,
+
+  /// transactions from applied epochs that haven't been sent to runtime yet
+  /// execution halts at the first transaction that the node doesn't have
+  pending_execution: VecDeque<PendingEpoch>,
+  execution_blocked_since: Option<Instant>,
+
+  /// key ranges leased to gateways by the applied epochs
+  leases: KeyRangeLeases,
+  /// lease requests of the gateways connected to this node, they go to the next epoch this node commits
+  /// and stay here until they're granted by any epoch
+  lease_requests: HashMap<PeerId, LeaseRequest>,
+
+  /// nodes that publish epochs and make consensus, it's built from the applied epochs
+  membership: Membership,
+  /// membership changes that haven't been committed yet, they go to the next epoch this node commits
+  /// they're gossiped on every advertisement, so the members get the requests that have come to other nodes
+  membership_requests: BTreeSet<MembershipChange>,
+  /// nodes this node is connected to, they publish epochs while membership is empty
+  connected_nodes: HashSet<PeerId>,
+  /// the latest meta exchange with every peer since the start, including the refused ones
+  peers_compatibility: BTreeMap<PeerId, PeerCompatibility>,
+
+  /// is set when an incoming epoch doesn't match the local history
+  /// node stops applying and committing epochs, so it doesn't execute a forked history
+  divergence: Option<Divergence>,
+
   // TODO: right now there are many assumptions made with the thought that elements won't disappear here(keep that in mind when it changes)
   transactions: HashMap<UniqueU64BlobId, Transaction>,
+  /// final statuses and results of the recent transactions, they're kept after gateways are notified
+  /// so gateways that missed the notification can ask for them
+  results: TxResults,
+  /// page requests to other nodes that haven't been answered yet, at most one per peer
+  tx_pages_in_flight: HashMap<PeerId, (TxPageRequest, Instant)>,
+  /// id of the next page request
+  next_tx_page_request: u64,
+  /// pages served to each peer in the current second: when the second has started and how many
+  tx_pages_served: HashMap<PeerId, (Instant, u32)>,
+
+  /// durable log of received transactions and results, `None` if `Params::wal_dir` is not set
+  wal: Option<Wal>,
 
   linearizer: L,
   epoch_coordinator: EpochCoordinatorControllerInterface,
 
+  /// asks runtime for its state when it's time to upload a snapshot
+  runtime_snapshots: Option<InvokerInterface<SnapshotRequest, RuntimeSnapshot>>,
+  /// how many inputs have been sent to runtime, runtime snapshot should include exactly them
+  runtime_inputs_sent: u64,
+  /// sequence number of the epoch the last snapshot has been made or restored at
+  last_snapshot_sn: Option<u64>,
+  /// checkpoints of the uploaded snapshots, WAL records before them aren't needed anymore
+  uploaded_snapshots: (UnboundedSender<EpochCheckpoint>, UnboundedReceiver<EpochCheckpoint>),
+  /// WAL truncations that have finished in the background, records that have come meanwhile are appended after them
+  truncated_wal: (UnboundedSender<WalTruncation>, UnboundedReceiver<WalTruncation>),
+
+  /// runtime state digests, one per epoch in the order epochs are sent, if not set - replicas aren't compared
+  runtime_digests: Option<UnboundedReceiver<StateDigest>>,
+  /// sequence numbers of the epochs that have been sent to runtime and whose digests haven't come back yet
+  undigested_epochs: VecDeque<u64>,
+  /// own digests that are gossiped with the next offsets advertisement
+  ungossiped_digests: Vec<EpochDigest>,
+  /// own digests of the recent `DIGESTS_WINDOW` epochs
+  own_digests: BTreeMap<u64, StateDigest>,
+  /// digests other nodes have gossiped for the epochs this node hasn't executed yet
+  peer_digests: BTreeMap<u64, HashMap<PeerId, StateDigest>>,
+  /// the first epoch after which runtime state of this node doesn't match another node
+  replica_divergence: Option<ReplicaDivergence>,
+
   /// keeps logic that calculates if it's time to send a new epoch or not
   send_decider: EpochDecisionEngine<MonotonicTimer>,
 
   timer: MonotonicTimer,
 }
 
+/// how many own digests are kept to compare with the ones of slower nodes
+const DIGESTS_WINDOW: usize = 1024;
+
+/// transactions of an applied epoch that are waiting to be sent to runtime
+struct PendingEpoch {
+  sequence_number: u64,
+  time: LogicalTimeAbsoluteMs,
+  ids: Vec<UniqueU64BlobId>,
+  /// `ids[..available]` are known to be on the node
+  available: usize,
+}
+
 impl<L: Linearizer> App<L> {
   pub fn new(
     peer_id: PeerId,
@@ -95,23 +190,231 @@ impl<L: Linearizer> App<L> {
     params: Params,
   ) -> Result<App<LogLineriazer>, Box<dyn std::error::Error>> {
     let epoch_period = params.epoch_period;
-    Ok(App {
+    let tx_results_limit = params.tx_results_limit;
+    let (wal, wal_records) = match &params.wal_dir {
+      Some(dir) => {
+        let (wal, records) = Wal::open(dir)?;
+        (Some(wal), records)
+      }
+      None => (None, Vec::new()),
+    };
+
+    let mut app = App {
       params,
       peer_id,
       p2p_interface,
       state_interface,
       runtime_interface,
       offsets: HashMap::new(),
+      offsets_advertised_at: HashMap::new(),
       self_offsets: HashMap::new(),
       consensus_offset: HashMap::new(),
       commited_offsets: HashMap::new(),
       epochs: Vec::new(),
+      out_of_order_epochs: BTreeMap::new(),
+      checkpoint: None,
+      pending_execution: VecDeque::new(),
+      execution_blocked_since: None,
+      leases: KeyRangeLeases::default(),
+      lease_requests: HashMap::new(),
+      membership: Membership::default(),
+      membership_requests: BTreeSet::new(),
+      connected_nodes: HashSet::new(),
+      peers_compatibility: BTreeMap::new(),
+      divergence: None,
       transactions: HashMap::new(),
+      results: TxResults::new(tx_results_limit),
+      tx_pages_in_flight: HashMap::new(),
+      next_tx_page_request: 0,
+      tx_pages_served: HashMap::new(),
+      wal,
       linearizer: LogLineriazer::new(),
       epoch_coordinator,
+      runtime_snapshots: None,
+      runtime_inputs_sent: 0,
+      last_snapshot_sn: None,
+      uploaded_snapshots: unbounded_channel(),
+      truncated_wal: unbounded_channel(),
+      runtime_digests: None,
+      undigested_epochs: VecDeque::new(),
+      ungossiped_digests: Vec::new(),
+      own_digests: BTreeMap::new(),
+      peer_digests: BTreeMap::new(),
+      replica_divergence: None,
       send_decider: new_decider(peer_id, epoch_period),
       timer: MonotonicTimer::new(),
-    })
+    };
+    app.restore_from_wal(wal_records);
+    Ok(app)
+  }
+
+  /// without it node doesn't make snapshots even if `Params::snapshots` is set
+  pub fn set_runtime_snapshot_invoker(
+    mut self,
+    invoker: InvokerInterface<SnapshotRequest, RuntimeSnapshot>,
+  ) -> App<L> {
+    self.runtime_snapshots = Some(invoker);
+    self
+  }
+
+  /// without it node doesn't compare its runtime state with other nodes
+  /// runtime should send one digest per input, see `Runtime::set_digests_sender`
+  pub fn set_runtime_digests_receiver(
+    mut self,
+    receiver: UnboundedReceiver<StateDigest>,
+  ) -> App<L> {
+    self.runtime_digests = Some(receiver);
+    self
+  }
+
+  /// continues from the snapshot's checkpoint, runtime should be restored from the same snapshot
+  /// should be called before `loop_until_shutdown`
+  pub fn restore_from_snapshot(
+    &mut self,
+    checkpoint: EpochCheckpoint,
+    runtime: &RuntimeSnapshot,
+  ) {
+    info!("restore from snapshot at epoch {}", checkpoint.sequence_number);
+    // the last epochs of the snapshot might be not digested yet, runtime sends their digests after restore
+    let first_undigested = (checkpoint.sequence_number + 1).saturating_sub(runtime.undigested_inputs());
+    self.undigested_epochs = (first_undigested..=checkpoint.sequence_number).collect();
+    self.last_snapshot_sn = Some(checkpoint.sequence_number);
+
+    // the WAL is truncated up to the committed offsets, so ranges don't start from offset 0 after restart anymore
+    let mut truncated_ranges = HashMap::new();
+    for (range, offset) in &checkpoint.commited_offsets {
+      let self_offset = self.self_offsets.entry(*range).or_insert(*offset);
+      *self_offset = (*self_offset).max(*offset);
+      truncated_ranges.insert(*range, Vec::new());
+    }
+    for (new_range, new_offset) in update_self_offsets(&mut self.self_offsets, &mut self.transactions, truncated_ranges)
+    {
+      move_offset_pointer(&mut self.offsets, self.peer_id, new_range, new_offset);
+    }
+
+    self.apply_checkpoint(checkpoint);
+  }
+
+  /// rebuilds known transactions, self offsets and results from what has been written to the WAL before restart
+  fn restore_from_wal(
+    &mut self,
+    records: Vec<WalRecord>,
+  ) {
+    if records.is_empty() {
+      return;
+    }
+
+    let mut txs = Vec::new();
+    let mut results = Vec::new();
+    for record in records {
+      match record {
+        WalRecord::Transaction(tx) => txs.push(tx),
+        WalRecord::Result(update) => results.push(update),
+      }
+    }
+
+    let private = private_txs(&txs);
+    for (new_range, new_offset) in
+      update_self_offsets(&mut self.self_offsets, &mut self.transactions, txs_to_range_tx_map(txs))
+    {
+      move_offset_pointer(&mut self.offsets, self.peer_id, new_range, new_offset);
+    }
+
+    for update in results {
+      if let Some(tx) = self.transactions.get_mut(&update.meta.id) {
+        tx.meta.status = update.meta.status.clone();
+      }
+      self.results.insert(update);
+    }
+    // the node might have stopped before it has written the rejection
+    self.reject_private(private);
+
+    info!("restored from wal: {} transactions, {} results", self.transactions.len(), self.results.len());
+  }
+
+  /// writes transactions that the node doesn't know yet to the WAL
+  fn persist_new_transactions<'a>(
+    &mut self,
+    txs: impl IntoIterator<Item = &'a Transaction>,
+  ) {
+    let Some(wal) = &mut self.wal else {
+      return;
+    };
+
+    for tx in txs {
+      if self.transactions.contains_key(&tx.meta.id) {
+        continue;
+      }
+      if let Err(e) = wal.append(&WalRecord::Transaction(tx.clone())) {
+        error!("couldnt write tx {} to wal: {e}", tx.meta.id);
+      }
+    }
+  }
+
+  /// syncs the records written while handling the last event in the background, so the event loop doesn't wait for the disk
+  fn sync_wal(&mut self) {
+    let Some(wal) = &mut self.wal else {
+      return;
+    };
+    match wal.take_unsynced() {
+      Ok(Some(pending)) => {
+        tokio::task::spawn_blocking(move || {
+          if let Err(e) = pending.sync() {
+            error!("couldnt sync wal: {e}");
+          }
+        });
+      }
+      Ok(None) => {}
+      Err(e) => error!("couldnt sync wal: {e}"),
+    }
+  }
+
+  /// the node restored from the snapshot doesn't need transactions it has executed,
+  /// results gateways can still ask for are kept
+  fn truncate_wal(
+    &mut self,
+    checkpoint: &EpochCheckpoint,
+  ) {
+    let Some(wal) = &mut self.wal else {
+      return;
+    };
+    let Some(truncation) = wal.start_truncation(&checkpoint.commited_offsets, self.results.ids().copied().collect())
+    else {
+      return;
+    };
+    let truncated = self.truncated_wal.0.clone();
+    let sequence_number = checkpoint.sequence_number;
+    tokio::task::spawn_blocking(move || {
+      if let Err(e) = truncation.run() {
+        error!("couldnt truncate wal up to epoch {sequence_number}: {e}");
+      }
+      _ = truncated.send(truncation);
+    });
+  }
+
+  fn finish_wal_truncation(
+    &mut self,
+    truncation: WalTruncation,
+  ) {
+    if let Some(wal) = &mut self.wal
+      && let Err(e) = wal.finish_truncation(truncation)
+    {
+      error!("couldnt finish wal truncation: {e}");
+    }
+  }
+
+  /// the node is about to stop, so records are synced right away and the truncation in progress is waited for
+  async fn flush_wal(&mut self) {
+    while self.wal.as_ref().is_some_and(Wal::is_truncating)
+      && let Some(truncation) = self.truncated_wal.1.recv().await
+    {
+      self.finish_wal_truncation(truncation);
+    }
+    if let Some(wal) = &mut self.wal
+      && let Err(e) = wal.take_unsynced().and_then(|pending| pending.map_or(Ok(()), WalSync::sync))
+    {
+      error!("shutdown: couldnt sync wal: {e}");
+    }
   }
 
   /// starts a loop that processes events and executes logic
@@ -145,37 +448,139 @@ impl<L: Linearizer> App<L> {
           Some(updates)= self.epoch_coordinator.receiver.recv() => {
             self.handle_epoch_coordinator_updates(updates);
           },
+          Some(digest) = recv_if_set(&mut self.runtime_digests) => {
+            self.handle_runtime_digest(digest);
+          },
+          Some(checkpoint) = self.uploaded_snapshots.1.recv() => {
+            self.truncate_wal(&checkpoint);
+          },
+          Some(truncation) = self.truncated_wal.1.recv() => {
+            self.finish_wal_truncation(truncation);
+          },
           got_results_count = self.runtime_interface.receiver.recv_many(&mut runtime_result_buf, runtime_result_limit) => {
             if got_results_count == 0 {
               continue;
             }
-
-            let mut for_notification = Vec::<TxUpdate>::with_capacity(got_results_count);
-            for r in runtime_result_buf.drain(..) {
-              let tx = self.transactions.get_mut(&r.0).expect("not possible to get result without existing transaction");
-              tx.meta.status = TxStatus::Finished;
-
-              // TODO: save results on node itself
-              for_notification.push(TxUpdate { meta: tx.meta.clone(), result: Some(r.1) });
-
-              app_metrics::finished_txs().add(1, &[KeyValue::new("range", range_from_unique_blob_id(tx.meta.id).0 as i64)]);
-            }
-
-            self.p2p_interface.send(Outbox::NotifyGWs(for_notification));
+            self.notify_gateways(runtime_result_buf.drain(..));
           },
           _ = &mut shutdown =>{
-            info!("TODO: shutdown the app");
+            self.shutdown().await;
             break;
           }
       }
+      self.sync_wal();
+    }
+  }
+
+  /// finishes what the node has started: runtime executes the epochs it has got, gateways get their results
+  /// and the last snapshot is uploaded. Messages from the network are not handled anymore, so no new transactions
+  /// other components stop after the app is gone, p2p layer sends everything app has sent before that
+  async fn shutdown(&mut self) {
+    info!("shutdown: stop taking new transactions");
+
+    let timeout = tokio::time::sleep(self.params.shutdown_timeout);
+    tokio::pin!(timeout);
+    let mut outputs = Vec::<RuntimeOutput>::new();
+    // runtime sends a digest once it has executed an epoch, without digests there is no way to know it
+    while self.runtime_digests.is_some() && !self.undigested_epochs.is_empty() {
+      tokio::select! {
+        Some(digest) = recv_if_set(&mut self.runtime_digests) => {
+          self.handle_runtime_digest(digest);
+        },
+        Some(output) = self.runtime_interface.receiver.recv() => {
+          outputs.push(output);
+        },
+        _ = &mut timeout => {
+          warn!("shutdown: runtime hasn't executed {} epochs in time", self.undigested_epochs.len());
+          break;
+        },
+        else => break,
+      }
+    }
+
+    // outputs of the executed epochs are sent before their digests
+    while let Ok(output) = self.runtime_interface.receiver.try_recv() {
+      outputs.push(output);
+    }
+    if !outputs.is_empty() {
+      self.notify_gateways(outputs.into_iter());
+    }
+
+    self.flush_wal().await;
+    if let Some(upload) = self.prepare_snapshot(1)
+      && let Some(checkpoint) = upload.await
+    {
+      self.truncate_wal(&checkpoint);
+      self.flush_wal().await;
     }
+    info!("shutdown: app is done");
+  }
+
+  /// finishes transactions with runtime outputs and lets gateways know
+  fn notify_gateways(
+    &mut self,
+    outputs: impl Iterator<Item = RuntimeOutput>,
+  ) {
+    let mut for_notification = Vec::<TxUpdate>::new();
+    for (id, output) in outputs {
+      let (status, result) = match output {
+        Ok(value) => (TxStatus::Finished, Some(value)),
+        Err(rejection) => (TxStatus::Rejected(rejection.reason().to_string()), None),
+      };
+      for_notification.push(self.finish_tx(id, status, result));
+    }
+
+    self.p2p_interface.send(Outbox::NotifyGWs(for_notification));
+  }
+
+  /// sets the final status of the transaction, writes it to the WAL and keeps it for `GetTxStatus`
+  fn finish_tx(
+    &mut self,
+    id: UniqueU64BlobId,
+    status: TxStatus,
+    result: Option<Value>,
+  ) -> TxUpdate {
+    let meta = match self.transactions.get_mut(&id) {
+      Some(tx) => {
+        tx.meta.status = status;
+        tx.meta.clone()
+      }
+      // runtime restored from a snapshot finishes transactions that have been sent to it before the snapshot
+      None => Meta { id, status },
+    };
+
+    let update = TxUpdate { meta, result };
+    if let Some(wal) = &mut self.wal
+      && let Err(e) = wal.append(&WalRecord::Result(update.clone()))
+    {
+      error!("couldnt write result of tx {id} to wal: {e}");
+    }
+    self.results.insert(update.clone());
+
+    let range = KeyValue::new("range", range_from_unique_blob_id(id).0 as i64);
+    match update.meta.status {
+      TxStatus::Rejected(_) => app_metrics::rejected_txs().add(1, &[range]),
+      _ => app_metrics::finished_txs().add(1, &[range]),
+    }
+    update
   }
 
   fn recalculate_consensus_offsets(&mut self) {
-    // TODO: Should I be worried that I might have some stale values in consensus_offset?
+    self.drop_stale_offsets();
+
+    // once membership is set up only members confirm transactions
+    let quorum = self.membership.quorum().unwrap_or(self.params.consensus_nodes);
+    self.consensus_offset.clear();
     for (k, v) in &self.offsets {
-      if let Some(max) = consensus_maximum(&v, self.params.consensus_nodes) {
-        self.consensus_offset.insert(*k, *max);
+      let max = if self.membership.nodes.is_empty() {
+        consensus_maximum(v, quorum).copied()
+      } else {
+        let members: HashMap<PeerId, KeyOffset> =
+          v.iter().filter(|(peer, _)| self.membership.is_member(peer)).map(|(peer, o)| (*peer, *o)).collect();
+        consensus_maximum(&members, quorum).copied()
+      };
+      if let Some(max) = max {
+        self.consensus_offset.insert(*k, max);
       }
     }
 
@@ -187,60 +592,518 @@ impl<L: Linearizer> App<L> {
     info!("consensus_offset:{}", str);
   }
 
+  /// drops offsets of the nodes that haven't advertised them for `Params::offsets_ttl`
+  fn drop_stale_offsets(&mut self) {
+    let now = Instant::now();
+    let stale: Vec<PeerId> = self
+      .offsets_advertised_at
+      .iter()
+      .filter(|(_, advertised_at)| now.duration_since(**advertised_at) > self.params.offsets_ttl)
+      .map(|(peer, _)| *peer)
+      .collect();
+    self.drop_offsets(&stale, "ttl");
+
+    app_metrics::set_offsets_staleness_ms(
+      self.offsets_advertised_at.iter().map(|(peer, at)| (*peer, now.duration_since(*at).as_millis() as u64)).collect(),
+    );
+  }
+
+  /// the nodes don't count in consensus until they advertise their offsets again
+  fn drop_offsets(
+    &mut self,
+    peers: &[PeerId],
+    reason: &'static str,
+  ) {
+    for peer in peers {
+      self.offsets_advertised_at.remove(peer);
+      for nodes in self.offsets.values_mut() {
+        nodes.remove(peer);
+      }
+      info!("offsets of {peer} are dropped: {reason}");
+      app_metrics::dropped_offsets().add(1, &[KeyValue::new("reason", reason)]);
+    }
+    self.offsets.retain(|_, nodes| !nodes.is_empty());
+  }
+
   fn handle_epoch_coordinator_updates(
     &mut self,
     updates: EpochUpdates,
   ) {
+    if let Some(divergence) = &self.divergence {
+      debug!("node has diverged({divergence}), skip epoch updates");
+      return;
+    }
+
     match updates {
-      EpochUpdates::New(mut new_epoch) => {
+      EpochUpdates::New(new_epoch) => {
         debug!("got epoch updates seq_n: {}", new_epoch.sequence_number);
-        app_metrics::set_latest_epoch_seq_number(new_epoch.sequence_number);
 
-        new_epoch.increments.sort();
-        self.linearizer.new_epoch(new_epoch.clone());
+        let next_sn = self.next_epoch_sequence_number();
+        if new_epoch.sequence_number < next_sn {
+          debug!("epoch {} has been already applied, skip", new_epoch.sequence_number);
+          return;
+        }
+        if new_epoch.sequence_number > next_sn {
+          warn!("epoch {} came before {}, postpone it", new_epoch.sequence_number, next_sn);
+          self.out_of_order_epochs.insert(new_epoch.sequence_number, new_epoch);
+          return;
+        }
 
-        {
-          // update commited offsets on self state so we know where to start next epoch
-          let new_epoch = new_epoch.clone();
-          for interval in &new_epoch.increments {
-            let (range, new_offset) = range_offset_from_unique_blob_id(interval.end());
-            self.commited_offsets.insert(range, new_offset);
-          }
+        self.verify_and_apply_epoch(new_epoch);
+      }
+      EpochUpdates::Checkpoint(checkpoint) => {
+        debug!("got epoch checkpoint seq_n: {}", checkpoint.sequence_number);
 
-          self.send_decider.update_latest_epoch(new_epoch.creator, new_epoch.creation_time);
-          self.epochs.push(new_epoch);
+        if checkpoint.sequence_number < self.next_epoch_sequence_number() {
+          debug!("checkpoint {} has been already applied, skip", checkpoint.sequence_number);
+          return;
         }
 
-        {
-          // send to runtime
-          let time = new_epoch.creation_time.clone();
-          let mut blueprints = vec![];
-
-          for interval in new_epoch.increments {
-            for i in interval.iter() {
-              // TODO: if some transactions are not here yet but the node got a new epoch with them. Should I pause execution at all?
-              // or should I execute until I reach missing transactions? and continue only when I'll download the missing ones?
-              let tx= self.transactions.get_mut(&i).expect("TODO: make sure all txs are here, epochs might contain bigger offsets that current node sees right now");
-
-              // TODO: notify gateway nodes here about status changing?
-              // here I'm chaning local status of transactions but not advertising it anywhere
-              // so nobody knows about the progress, only if explicitly requests the status
-              tx.meta.status = TxStatus::Pending;
-
-              blueprints.push(TaskBlueprint {
-                global_id: tx.meta.id,
-                q_name: tx.blueprint.queue_name.clone(),
-                value: tx.blueprint.param.clone(),
-              });
-            }
-          }
+        self.apply_checkpoint(checkpoint);
+      }
+    }
 
-          if blueprints.len() > 0 {
-            self.runtime_interface.send((time, blueprints));
-          }
+    while self.divergence.is_none() {
+      let Some(next) = self.out_of_order_epochs.remove(&self.next_epoch_sequence_number()) else {
+        break;
+      };
+      self.verify_and_apply_epoch(next);
+    }
+
+    let applied = self.next_epoch_sequence_number().checked_sub(1);
+    self.epoch_coordinator.applied_sender.send_if_modified(|sn| {
+      let modified = *sn != applied;
+      *sn = applied;
+      modified
+    });
+  }
+
+  fn next_epoch_sequence_number(&self) -> u64 {
+    match (self.epochs.last(), &self.checkpoint) {
+      (Some(epoch), _) => epoch.sequence_number + 1,
+      (None, Some(checkpoint)) => checkpoint.sequence_number + 1,
+      (None, None) => 0,
+    }
+  }
+
+  /// starts from the checkpoint instead of replaying all the epochs that are folded into it
+  fn apply_checkpoint(
+    &mut self,
+    checkpoint: EpochCheckpoint,
+  ) {
+    // transactions from the folded epochs are executed only by a runtime restored from a snapshot that has them
+    if self.last_snapshot_sn.is_none_or(|sn| sn < checkpoint.sequence_number) {
+      let divergence = Divergence::NoSnapshot { sequence_number: checkpoint.sequence_number };
+      error!("node has diverged: {divergence}");
+      app_metrics::set_diverged(true);
+      self.divergence = Some(divergence);
+      return;
+    }
+
+    info!("start from epoch checkpoint {}", checkpoint.sequence_number);
+    app_metrics::set_latest_epoch_seq_number(checkpoint.sequence_number);
+
+    for (range, offset) in &checkpoint.commited_offsets {
+      let commited = self.commited_offsets.entry(*range).or_insert(*offset);
+      *commited = (*commited).max(*offset);
+    }
+
+    self.leases = checkpoint.leases.clone();
+    self.answer_granted_leases(checkpoint.leases.ranges.iter().map(|(gateway, range)| (*gateway, *range)).collect());
+
+    self.membership = checkpoint.membership.clone();
+    self.membership_requests.retain(|change| checkpoint.membership.changes_anything(change));
+    self.update_publishers();
+
+    self.epochs.clear();
+    self.out_of_order_epochs.retain(|sn, _| *sn > checkpoint.sequence_number);
+    self.checkpoint = Some(checkpoint);
+  }
+
+  fn verify_and_apply_epoch(
+    &mut self,
+    epoch: Epoch,
+  ) {
+    if let Err(divergence) = verify_epoch(&epoch, self.epochs.last(), self.checkpoint.as_ref(), &self.commited_offsets)
+    {
+      error!("node has diverged: {divergence}");
+      app_metrics::set_diverged(true);
+      self.divergence = Some(divergence);
+      return;
+    }
+    if !self.membership.nodes.is_empty() && !self.membership.is_member(&epoch.creator) {
+      let divergence = Divergence::NotMember { sequence_number: epoch.sequence_number, creator: epoch.creator };
+      error!("node has diverged: {divergence}");
+      app_metrics::set_diverged(true);
+      self.divergence = Some(divergence);
+      return;
+    }
+
+    self.apply_epoch(epoch);
+  }
+
+  /// applies the epoch that is exactly the next after `self.epochs.last()`
+  fn apply_epoch(
+    &mut self,
+    mut new_epoch: Epoch,
+  ) {
+    app_metrics::set_latest_epoch_seq_number(new_epoch.sequence_number);
+
+    new_epoch.increments.sort();
+    self.linearizer.new_epoch(new_epoch.clone());
+
+    {
+      // update commited offsets on self state so we know where to start next epoch
+      let new_epoch = new_epoch.clone();
+      for interval in &new_epoch.increments {
+        let (range, new_offset) = range_offset_from_unique_blob_id(interval.end());
+        self.commited_offsets.insert(range, new_offset);
+      }
+
+      self.send_decider.update_latest_epoch(new_epoch.creator, new_epoch.creation_time);
+      self.epochs.push(new_epoch);
+    }
+
+    let granted = self.leases.apply(&new_epoch.leases, &self.commited_offsets);
+    self.answer_granted_leases(granted);
+
+    self.membership.apply(new_epoch.sequence_number, &new_epoch.membership);
+    for change in &new_epoch.membership {
+      self.membership_requests.remove(change);
+    }
+    if self.membership.advance_to(new_epoch.sequence_number + 1) {
+      info!("membership from epoch {}: {:?}", new_epoch.sequence_number + 1, self.membership.nodes);
+      self.update_publishers();
+    }
+
+    // send to runtime
+    // epochs without transactions are sent as well, they move runtime time forward
+    let ids = new_epoch.increments.iter().flat_map(|interval| interval.iter()).collect::<Vec<UniqueU64BlobId>>();
+    self.pending_execution.push_back(PendingEpoch {
+      sequence_number: new_epoch.sequence_number,
+      time: new_epoch.creation_time,
+      ids,
+      available: 0,
+    });
+    self.execute_pending();
+  }
+
+  /// answers the gateways that wait for these leases on this node
+  fn answer_granted_leases(
+    &mut self,
+    granted: Vec<(PeerId, KeyRange)>,
+  ) {
+    for (gateway, range) in granted {
+      if self.lease_requests.get(&gateway).is_none_or(|request| request.replaces == Some(range)) {
+        continue;
+      }
+      self.lease_requests.remove(&gateway);
+      info!("key range {range} is leased to gateway {gateway}");
+      self.p2p_interface.send(Outbox::KeyRangeLeased((gateway, range)));
+    }
+  }
+
+  /// sends the applied epochs to runtime in order, each of them as a whole
+  /// stops at the first epoch with a transaction that hasn't reached the node yet and requests the missing ones
+  fn execute_pending(&mut self) {
+    while let Some(pending) = self.pending_execution.front_mut() {
+      while let Some(id) = pending.ids.get(pending.available)
+        && self.transactions.contains_key(id)
+      {
+        pending.available += 1;
+      }
+
+      if pending.available < pending.ids.len() {
+        if self.execution_blocked_since.is_none() {
+          info!("execution is blocked by missing tx: {:?}", pending.ids[pending.available]);
+          self.execution_blocked_since = Some(Instant::now());
+          self.request_missing_for_execution();
+        }
+        return;
+      }
+
+      // epoch goes to runtime as a single input, so every node digests runtime state at the same points
+      let pending = self.pending_execution.pop_front().expect("checked above");
+      let mut blueprints = Vec::with_capacity(pending.ids.len());
+      for id in &pending.ids {
+        let tx = self.transactions.get_mut(id).expect("checked above");
+        // it's rejected as soon as it's received, see `reject_private`, every node skips it the same way
+        if check_public_message(&tx.blueprint.param).is_err() {
+          continue;
         }
+
+        // TODO: notify gateway nodes here about status changing?
+        // here I'm chaning local status of transactions but not advertising it anywhere
+        // so nobody knows about the progress, only if explicitly requests the status
+        tx.meta.status = TxStatus::Pending;
+
+        blueprints.push(TaskBlueprint {
+          global_id: tx.meta.id,
+          q_name: tx.blueprint.queue_name.clone(),
+          value: tx.blueprint.param.clone(),
+        });
+      }
+
+      self.runtime_interface.send((pending.time, blueprints));
+      self.runtime_inputs_sent += 1;
+      if self.runtime_digests.is_some() {
+        self.undigested_epochs.push_back(pending.sequence_number);
       }
     }
+
+    if let Some(since) = self.execution_blocked_since.take() {
+      let blocked = since.elapsed().as_millis() as u64;
+      info!("execution is unblocked after {}ms", blocked);
+      app_metrics::execution_blocked_ms().record(blocked, &[]);
+      app_metrics::set_execution_blocked_ms(0);
+    }
+
+    self.snapshot_if_needed();
+  }
+
+  /// uploads a snapshot once `every_epochs` epochs have been executed since the previous one
+  fn snapshot_if_needed(&mut self) {
+    let Some(every_epochs) = self.params.snapshots.as_ref().map(|params| params.every_epochs) else {
+      return;
+    };
+    if let Some(upload) = self.prepare_snapshot(every_epochs) {
+      let uploaded = self.uploaded_snapshots.0.clone();
+      tokio::spawn(async move {
+        if let Some(checkpoint) = upload.await {
+          _ = uploaded.send(checkpoint);
+        }
+      });
+    }
+  }
+
+  /// returns the upload of a snapshot if at least `min_epochs` epochs have been executed since the previous one
+  /// it's done only when all the applied epochs are sent to runtime, so runtime state matches the checkpoint exactly
+  /// upload returns the checkpoint of the snapshot if it's uploaded
+  fn prepare_snapshot(
+    &mut self,
+    min_epochs: u64,
+  ) -> Option<impl Future<Output = Option<EpochCheckpoint>> + Send + 'static> {
+    let (Some(params), Some(invoker)) = (&self.params.snapshots, &self.runtime_snapshots) else {
+      return None;
+    };
+    if !self.pending_execution.is_empty() {
+      return None;
+    }
+
+    let applied = self.next_epoch_sequence_number().checked_sub(1)?;
+    let executed_since_last = match self.last_snapshot_sn {
+      Some(last) => applied.saturating_sub(last),
+      None => applied + 1,
+    };
+    if executed_since_last < min_epochs.max(1) {
+      return None;
+    }
+
+    let checkpoint = EpochCheckpoint::fold(self.checkpoint.as_ref(), &self.epochs)?;
+    self.last_snapshot_sn = Some(checkpoint.sequence_number);
+
+    let runtime_snapshot = invoker.request(SnapshotRequest { inputs_count: self.runtime_inputs_sent });
+    let store = params.store.clone();
+    Some(async move {
+      let snapshot = Snapshot { checkpoint, runtime: runtime_snapshot.await };
+      match snapshot::upload(store.as_ref(), &snapshot).await {
+        Ok(()) => {
+          info!("snapshot at epoch {} is uploaded", snapshot.checkpoint.sequence_number);
+          Some(snapshot.checkpoint)
+        }
+        Err(e) => {
+          error!("couldnt upload snapshot at epoch {}: {e}", snapshot.checkpoint.sequence_number);
+          None
+        }
+      }
+    })
+  }
+
+  /// requests transactions that block execution from the nodes that have advertised them
+  fn request_missing_for_execution(&mut self) {
+    let missing = self.missing_for_execution();
+    self.request_tx_pages(missing);
+  }
+
+  /// transactions that block execution by the nodes that have advertised them
+  fn missing_for_execution(&self) -> HashMap<PeerId, Vec<U64BlobIdClosedInterval>> {
+    let Some(since) = self.execution_blocked_since else {
+      return HashMap::new();
+    };
+    app_metrics::set_execution_blocked_ms(since.elapsed().as_millis() as u64);
+
+    let missing =
+      missing_intervals(self.pending_execution.iter().flat_map(|p| p.ids[p.available..].iter()), &self.transactions);
+    assign_missing_intervals(missing, &self.offsets, self.peer_id)
+  }
+
+  /// starts paging through the intervals of every peer that isn't paging already <br>
+  /// the next page is asked once the previous one arrives, so one peer serves one page at a time
+  fn request_tx_pages(
+    &mut self,
+    intervals: HashMap<PeerId, Vec<U64BlobIdClosedInterval>>,
+  ) {
+    for (peer_id, intervals) in intervals {
+      if let Some((_, sent_at)) = self.tx_pages_in_flight.get(&peer_id)
+        && sent_at.elapsed() < self.params.tx_sync_limits.page_timeout
+      {
+        continue;
+      }
+      debug!("request txs from peerID:[{}]: {:?}", peer_id, intervals);
+      self.send_tx_page_request(peer_id, intervals, None);
+    }
+  }
+
+  fn send_tx_page_request(
+    &mut self,
+    peer_id: PeerId,
+    intervals: Vec<U64BlobIdClosedInterval>,
+    resume: Option<ResumeToken>,
+  ) {
+    let id = self.next_tx_page_request;
+    self.next_tx_page_request += 1;
+    let request = TxPageRequest { id, intervals, resume, limit: self.params.tx_sync_limits.page_txs };
+    self.tx_pages_in_flight.insert(peer_id, (request.clone(), Instant::now()));
+    self.p2p_interface.send(Outbox::RequestTxPage((peer_id, request)));
+  }
+
+  fn is_tx_page_in_flight(
+    &self,
+    peer_id: PeerId,
+    request_id: u64,
+  ) -> bool {
+    self.tx_pages_in_flight.get(&peer_id).is_some_and(|(request, _)| request.id == request_id)
+  }
+
+  /// adds transactions that have come from other nodes
+  fn add_missing_txs(
+    &mut self,
+    txs: Vec<Transaction>,
+  ) {
+    self.persist_new_transactions(&txs);
+    let private = private_txs(&txs);
+    for (new_range, new_offset) in
+      update_self_offsets(&mut self.self_offsets, &mut self.transactions, txs_to_range_tx_map(txs))
+    {
+      move_offset_pointer(&mut self.offsets, self.peer_id, new_range, new_offset);
+    }
+    self.reject_private(private);
+    self.execute_pending();
+  }
+
+  /// every node rejects transactions to private queues as soon as it gets them, no matter from where,
+  /// so `GetTxStatus` is answered the same way by all of them <br>
+  /// rejected transaction still goes through epochs, so offsets of its range keep moving
+  fn reject_private(
+    &mut self,
+    private: Vec<(UniqueU64BlobId, Rejection)>,
+  ) {
+    let mut updates = Vec::new();
+    for (id, rejection) in private {
+      // already finished, ex: its rejection is restored from the WAL
+      if !self
+        .transactions
+        .get(&id)
+        .is_some_and(|tx| !matches!(tx.meta.status, TxStatus::Finished | TxStatus::Rejected(_)))
+      {
+        continue;
+      }
+      info!("tx {id} is rejected: {rejection}");
+      updates.push(self.finish_tx(id, TxStatus::Rejected(rejection.reason().to_string()), None));
+    }
+    if !updates.is_empty() {
+      self.p2p_interface.send(Outbox::NotifyGWs(updates));
+    }
+  }
+
+  /// `false` if the peer has got all the pages it can get in this second
+  fn take_tx_page_budget(
+    &mut self,
+    peer_id: PeerId,
+  ) -> bool {
+    let now = Instant::now();
+    let (since, served) = self.tx_pages_served.entry(peer_id).or_insert((now, 0));
+    if now.duration_since(*since) >= Duration::from_secs(1) {
+      (*since, *served) = (now, 0);
+    }
+    if *served >= self.params.tx_sync_limits.pages_per_peer_per_sec {
+      return false;
+    }
+    *served += 1;
+    true
+  }
+
+  /// runtime has executed the next sent epoch
+  fn handle_runtime_digest(
+    &mut self,
+    digest: StateDigest,
+  ) {
+    let Some(sequence_number) = self.undigested_epochs.pop_front() else {
+      warn!("got runtime digest without an executed epoch");
+      return;
+    };
+
+    self.ungossiped_digests.push(EpochDigest { sequence_number, digest });
+
+    self.own_digests.insert(sequence_number, digest);
+    while self.own_digests.len() > DIGESTS_WINDOW {
+      self.own_digests.pop_first();
+    }
+
+    // digests of the epochs before this one can't be compared anymore
+    self.peer_digests = self.peer_digests.split_off(&sequence_number);
+    if let Some(peers) = self.peer_digests.remove(&sequence_number) {
+      for (peer, peer_digest) in peers {
+        self.compare_digests(sequence_number, peer, peer_digest);
+      }
+    }
+  }
+
+  fn handle_peer_digest(
+    &mut self,
+    peer: PeerId,
+    peer_digest: EpochDigest,
+  ) {
+    let EpochDigest { sequence_number, digest } = peer_digest;
+    if self.own_digests.contains_key(&sequence_number) {
+      self.compare_digests(sequence_number, peer, digest);
+      return;
+    }
+
+    let executed = self.own_digests.last_key_value().is_some_and(|(last, _)| *last >= sequence_number);
+    if executed {
+      // either too old or the node has started after this epoch
+      return;
+    }
+
+    self.peer_digests.entry(sequence_number).or_default().insert(peer, digest);
+    while self.peer_digests.len() > DIGESTS_WINDOW {
+      self.peer_digests.pop_last();
+    }
+  }
+
+  fn compare_digests(
+    &mut self,
+    sequence_number: u64,
+    peer: PeerId,
+    peer_digest: StateDigest,
+  ) {
+    if self.own_digests.get(&sequence_number) == Some(&peer_digest) {
+      return;
+    }
+    if self.replica_divergence.as_ref().is_some_and(|d| d.sequence_number <= sequence_number) {
+      return;
+    }
+
+    let divergence = ReplicaDivergence { sequence_number, peer };
+    error!("replica has diverged: {divergence}");
+    app_metrics::set_replica_diverged(true);
+    state_log::log(LogEvent {
+      timestamp_micros: now_microsec(),
+      emitter: self.peer_id,
+      body: LogEventBody::ReplicaDiverged { sequence_number, peer },
+    });
+    self.replica_divergence = Some(divergence);
   }
 
   fn handle_inbox_message(
@@ -249,6 +1112,7 @@ impl<L: Linearizer> App<L> {
   ) {
     match msg {
       Inbox::State((peer_id, state)) => {
+        self.offsets_advertised_at.insert(peer_id, Instant::now());
         for (k, v) in state.offsets {
           if let Some(in_map) = self.offsets.get_mut(&k) {
             in_map.insert(peer_id, v);
@@ -258,43 +1122,93 @@ impl<L: Linearizer> App<L> {
         }
       }
       Inbox::Nodes(nodes) => {
-        self.recalculate_order(&nodes);
+        let disconnected: Vec<PeerId> =
+          self.offsets_advertised_at.keys().filter(|peer| !nodes.contains(peer)).copied().collect();
+        self.drop_offsets(&disconnected, "disconnected");
+        self.connected_nodes = nodes;
+        self.update_publishers();
       }
       Inbox::NewTransaction(tx) => {
         debug!("got new tx: {tx:?}");
+        self.persist_new_transactions([&tx]);
+        let private = private_txs([&tx]);
         if let Some((new_range, new_offset)) = update_self_offset(&mut self.self_offsets, &mut self.transactions, tx) {
           move_offset_pointer(&mut self.offsets, self.peer_id, new_range, new_offset);
         }
+        self.reject_private(private);
+        self.execute_pending();
       }
       Inbox::MissingTx(txs) => {
-        for (new_range, new_offset) in
-          update_self_offsets(&mut self.self_offsets, &mut self.transactions, txs_to_range_tx_map(txs))
-        {
-          move_offset_pointer(&mut self.offsets, self.peer_id, new_range, new_offset);
-        }
+        self.add_missing_txs(txs);
       }
       Inbox::RequestMissingTxs((peer_id, intervals)) => {
-        let mut capacity: usize = 0;
-        for interval in &intervals {
-          capacity += interval.ids_count();
-        }
-
-        let mut response: Vec<Transaction> = Vec::with_capacity(capacity);
-
-        for interval in intervals {
-          let mut pointer = interval.start();
-          let end = interval.end();
-          while pointer <= end {
-            let Some(tx) = self.transactions.get(&pointer) else {
-              continue;
-            };
-            response.push(tx.clone());
-            pointer += UniqueU64BlobId(1);
-          }
+        if !self.take_tx_page_budget(peer_id) {
+          return;
         }
+        // the peer asks for the rest again when it sees it's still behind
+        let request = TxPageRequest { id: 0, intervals, resume: None, limit: self.params.tx_sync_limits.page_txs };
+        let page = tx_page(&self.transactions, &request, request.limit);
 
         debug!("send_back_missing_txs to peerID:[{}]", peer_id);
-        self.p2p_interface.sender.send(Outbox::RequestedTxsForPeer((peer_id, response))).expect("shouldnt drop sender");
+        self.p2p_interface.send(Outbox::RequestedTxsForPeer((peer_id, page.txs)));
+      }
+      Inbox::GetTxPage((query_id, peer_id, request)) => {
+        if !self.take_tx_page_budget(peer_id) {
+          debug!("peerID:[{}] is throttled", peer_id);
+          self.p2p_interface.send(Outbox::TxPage((query_id, request.id, None)));
+          return;
+        }
+        let limit = request.limit.min(self.params.tx_sync_limits.page_txs);
+        let page = tx_page(&self.transactions, &request, limit);
+        self.p2p_interface.send(Outbox::TxPage((query_id, request.id, Some(page))));
+      }
+      Inbox::TxPage((peer_id, page)) => {
+        // a late answer on a request that has been replaced after `page_timeout`,
+        // its resume token points into other intervals
+        if !self.is_tx_page_in_flight(peer_id, page.request_id) {
+          debug!("drop stale tx page {} from peerID:[{}]", page.request_id, peer_id);
+          return;
+        }
+        let request = self.tx_pages_in_flight.remove(&peer_id).map(|(request, _)| request);
+        self.add_missing_txs(page.txs);
+        if let (Some(request), Some(next)) = (request, page.next) {
+          self.send_tx_page_request(peer_id, request.intervals, Some(next));
+        }
+      }
+      Inbox::TxPageThrottled((peer_id, request_id)) => {
+        if !self.is_tx_page_in_flight(peer_id, request_id) {
+          return;
+        }
+        // asked again on one of the next advertisements
+        debug!("peerID:[{}] throttles tx pages", peer_id);
+        self.tx_pages_in_flight.remove(&peer_id);
+      }
+      Inbox::LeaseKeyRange(request) => {
+        // the gateway has already got a new range, but hasn't received the answer
+        if let Some(range) = self.leases.ranges.get(&request.gateway).copied()
+          && Some(range) != request.replaces
+        {
+          self.p2p_interface.send(Outbox::KeyRangeLeased((request.gateway, range)));
+          return;
+        }
+        debug!("gateway {} asks for a key range instead of {:?}", request.gateway, request.replaces);
+        self.lease_requests.insert(request.gateway, request);
+      }
+      Inbox::GetTxStatus((query_id, ids)) => {
+        self.p2p_interface.send(Outbox::TxStatuses((query_id, self.tx_statuses(&ids))));
+      }
+      Inbox::Digests((peer_id, digests)) => {
+        for digest in digests {
+          self.handle_peer_digest(peer_id, digest);
+        }
+      }
+      Inbox::MembershipChanges(changes) => {
+        for change in changes {
+          self.request_membership_change(change);
+        }
+      }
+      Inbox::PeerCompatibility((peer_id, compatibility)) => {
+        self.peers_compatibility.insert(peer_id, compatibility);
       }
     }
   }
@@ -303,21 +1217,30 @@ impl<L: Linearizer> App<L> {
     self.recalculate_consensus_offsets();
     debug!("broadcast_self_state: {:?}", self.self_offsets);
     self.p2p_interface.send(Outbox::State(NodeState { offsets: self.self_offsets.clone() }));
-
-    let delays = self_delays(&self.transactions, &self.self_offsets, &self.offsets);
-    if delays.len() == 0 {
-      return;
+    if !self.ungossiped_digests.is_empty() {
+      self.p2p_interface.send(Outbox::Digests(std::mem::take(&mut self.ungossiped_digests)));
+    }
+    if !self.membership_requests.is_empty() {
+      self.p2p_interface.send(Outbox::MembershipChanges(self.membership_requests.iter().copied().collect()));
     }
 
-    info!("delay detected: {:?}", delays);
+    // requests might be lost or the nodes that have the transactions weren't known yet, so retry on every tick
+    // transactions that block execution go first
+    let mut missing = self.missing_for_execution();
 
+    let delays = self_delays(&self.transactions, &self.self_offsets, &self.offsets);
+    if !delays.is_empty() {
+      debug!("delay detected: {:?}", delays);
+    }
     for (peer_id, intervals) in delays {
-      self.p2p_interface.sender.send(Outbox::RequestMissingTxs((peer_id, intervals))).expect("dont drop channel");
+      missing.entry(peer_id).or_insert(intervals);
     }
+
+    self.request_tx_pages(missing);
   }
 
   fn handle_request(
-    &self,
+    &mut self,
     wrapper: RequestWrapper<Request, Response>,
   ) {
     match wrapper.request {
@@ -329,36 +1252,162 @@ impl<L: Linearizer> App<L> {
           error!("couldnt send response: {unsent_response}");
         }
       }
+      Request::GetEpochState => {
+        if let Err(unsent_response) = wrapper.response.send(Response::EpochState(EpochState {
+          applied_sequence_number: self.next_epoch_sequence_number().checked_sub(1),
+          divergence: self.divergence.clone(),
+          replica_divergence: self.replica_divergence.clone(),
+        })) {
+          error!("couldnt send response: {unsent_response}");
+        }
+      }
+      Request::GetTxStatus(ids) => {
+        if let Err(unsent_response) = wrapper.response.send(Response::TxStatuses(self.tx_statuses(&ids))) {
+          error!("couldnt send response: {unsent_response}");
+        }
+      }
+      Request::GetMembership => {
+        if let Err(unsent_response) = wrapper.response.send(Response::Membership(self.membership.clone())) {
+          error!("couldnt send response: {unsent_response}");
+        }
+      }
+      Request::ChangeMembership(change) => {
+        self.request_membership_change(change);
+        if let Err(unsent_response) = wrapper.response.send(Response::Membership(self.membership.clone())) {
+          error!("couldnt send response: {unsent_response}");
+        }
+      }
+      Request::GetPeersCompatibility => {
+        let response = Response::PeersCompatibility(self.peers_compatibility.clone());
+        if let Err(unsent_response) = wrapper.response.send(response) {
+          error!("couldnt send response: {unsent_response}");
+        }
+      }
     }
   }
 
+  /// the latest known status of each transaction, with the result if it's still kept
+  fn tx_statuses(
+    &self,
+    ids: &[UniqueU64BlobId],
+  ) -> Vec<TxUpdate> {
+    ids
+      .iter()
+      .filter_map(|id| {
+        self
+          .results
+          .get(id)
+          .cloned()
+          .or_else(|| self.transactions.get(id).map(|tx| TxUpdate { meta: tx.meta.clone(), result: None }))
+      })
+      .collect()
+  }
+
+  /// keeps the change until it's committed, changes that don't change anything are skipped
+  fn request_membership_change(
+    &mut self,
+    change: MembershipChange,
+  ) {
+    if !self.membership.changes_anything(&change) || self.membership_requests.contains(&change) {
+      return;
+    }
+    info!("membership change is requested: {change}");
+    self.membership_requests.insert(change);
+  }
+
   fn commit_epoch_if_needed(&mut self) {
-    if !self.send_decider.should_send() {
+    if self.divergence.is_some() || !self.send_decider.should_send() {
+      return;
+    }
+    if !self.membership.nodes.is_empty() && !self.membership.is_member(&self.peer_id) {
       return;
     }
 
-    let increments = calculate_epoch_increments(&self.consensus_offset, &self.commited_offsets);
+    let limits = self.params.epoch_limits;
+    let increments = calculate_epoch_increments(
+      &self.consensus_offset,
+      &self.commited_offsets,
+      &limits,
+      self.next_epoch_sequence_number(),
+    );
+
+    let mut leases: Vec<LeaseRequest> = self.lease_requests.values().cloned().collect();
+    leases.sort_by_key(|l| l.gateway);
+
+    let now = self.timer.from_start();
+    let new_epoch = fit_epoch(increments, limits.max_bytes, |increments| {
+      match (self.epochs.last(), &self.checkpoint) {
+        (None, Some(checkpoint)) => Epoch::next_after_checkpoint(self.peer_id, increments, checkpoint, now),
+        (prev_epoch, _) => Epoch::next(self.peer_id, increments, prev_epoch, now),
+      }
+      .with_leases(leases.clone())
+      .with_membership(self.membership_requests.iter().copied().collect())
+    });
 
-    let prev_epoch = self.epochs.last().map(|e| e);
-    let new_epoch = Epoch::next(self.peer_id, increments, prev_epoch, self.timer.from_start());
+    let included: u64 = new_epoch.increments.iter().map(|interval| interval.ids_count() as u64).sum();
+    app_metrics::set_epoch_backlog_txs(uncommited_txs_count(&self.consensus_offset, &self.commited_offsets) - included);
 
     info!("attempt to commit new_epoch: {}", &new_epoch);
     let _ = self.epoch_coordinator.sender.send(Some(EpochRequest { epoch: new_epoch }));
   }
 
-  fn recalculate_order(
-    &mut self,
-    ids: &HashSet<PeerId>,
-  ) {
-    self.send_decider.update_node_ids(ids);
+  /// members publish epochs in turns, until membership is set up - all the connected nodes
+  fn update_publishers(&mut self) {
+    if self.membership.nodes.is_empty() {
+      self.send_decider.update_node_ids(&self.connected_nodes);
+    } else {
+      self.send_decider.update_node_ids(&self.membership.nodes.iter().copied().collect());
+    }
   }
 }
 
-fn calculate_epoch_increments(
+/// checks that the epoch continues the history the node has: it's chained to the predecessor
+/// and every range continues right after what has been already committed, without overlaps, gaps or going backwards
+fn verify_epoch(
+  epoch: &Epoch,
+  prev_epoch: Option<&Epoch>,
+  checkpoint: Option<&EpochCheckpoint>,
+  commited_offsets: &HashMap<KeyRange, KeyOffset>,
+) -> Result<(), Divergence> {
+  let chain = match (prev_epoch, checkpoint) {
+    (None, Some(checkpoint)) => epoch.verify_after_checkpoint(checkpoint),
+    (prev_epoch, _) => epoch.verify_after(prev_epoch),
+  };
+  chain.map_err(|error| Divergence::Chain { sequence_number: epoch.sequence_number, error })?;
+
+  // where the next increment for a range should start
+  let mut expected_starts = HashMap::<KeyRange, KeyOffset>::new();
+  for interval in &epoch.increments {
+    let (range, start) = range_offset_from_unique_blob_id(interval.start());
+    let (end_range, end) = range_offset_from_unique_blob_id(interval.end());
+    if range != end_range || start > end {
+      return Err(Divergence::MalformedIncrement {
+        sequence_number: epoch.sequence_number,
+        interval: interval.clone(),
+      });
+    }
+
+    let expected = expected_starts
+      .get(&range)
+      .copied()
+      .or_else(|| commited_offsets.get(&range).map(|o| *o + KeyOffset(1)))
+      .unwrap_or(KeyOffset(0));
+    if start != expected {
+      return Err(Divergence::Increment { sequence_number: epoch.sequence_number, range, expected, got: start });
+    }
+
+    expected_starts.insert(range, end + KeyOffset(1));
+  }
+
+  Ok(())
+}
+
+/// confirmed transactions that haven't been committed yet: where the next increment of each range starts and how many ids it has
+fn uncommited_txs(
   consensus_offset: &HashMap<KeyRange, KeyOffset>,
   commited_offsets: &HashMap<KeyRange, KeyOffset>,
-) -> Vec<U64BlobIdClosedInterval> {
-  let mut increments = Vec::new();
+) -> Vec<(KeyRange, KeyOffset, u64)> {
+  let mut uncommited = Vec::new();
   for (range, offset) in consensus_offset {
     let mut start = KeyOffset(0);
     if let Some(prev) = commited_offsets.get(&range) {
@@ -369,10 +1418,76 @@ fn calculate_epoch_increments(
       continue;
     }
 
-    increments.push(U64BlobIdClosedInterval::new_from_range_and_offsets(*range, start, *offset));
+    uncommited.push((*range, start, offset.0 - start.0 + 1));
+  }
+  uncommited
+}
+
+fn uncommited_txs_count(
+  consensus_offset: &HashMap<KeyRange, KeyOffset>,
+  commited_offsets: &HashMap<KeyRange, KeyOffset>,
+) -> u64 {
+  uncommited_txs(consensus_offset, commited_offsets).iter().map(|(_, _, count)| count).sum()
+}
+
+/// takes confirmed transactions within the limits, what doesn't fit goes to the next epochs <br>
+/// `max_txs` is shared equally between the ranges, the share a range doesn't need goes to the others <br>
+/// ranges take turns by `sequence_number` of the epoch, so if there are more ranges than transactions the epoch can take
+/// every range gets its turn. Increments are in the order of the turn
+fn calculate_epoch_increments(
+  consensus_offset: &HashMap<KeyRange, KeyOffset>,
+  commited_offsets: &HashMap<KeyRange, KeyOffset>,
+  limits: &EpochLimits,
+  sequence_number: u64,
+) -> Vec<U64BlobIdClosedInterval> {
+  let mut uncommited = uncommited_txs(consensus_offset, commited_offsets);
+  if uncommited.is_empty() {
+    return vec![];
+  }
+  uncommited.sort();
+  let turn = (sequence_number % uncommited.len() as u64) as usize;
+  uncommited.rotate_left(turn);
+
+  let wanted: Vec<u64> = uncommited.iter().map(|(_, _, count)| (*count).min(limits.max_txs_per_range)).collect();
+  let mut granted = vec![0u64; uncommited.len()];
+  let mut budget = limits.max_txs;
+  loop {
+    let hungry: Vec<usize> = (0..wanted.len()).filter(|i| granted[*i] < wanted[*i]).collect();
+    if hungry.is_empty() || budget == 0 {
+      break;
+    }
+    let share = (budget / hungry.len() as u64).max(1);
+    for i in hungry {
+      let add = share.min(wanted[i] - granted[i]).min(budget);
+      granted[i] += add;
+      budget -= add;
+    }
   }
 
-  increments
+  uncommited
+    .into_iter()
+    .zip(granted)
+    .filter(|(_, granted)| *granted > 0)
+    .map(|((range, start, _), granted)| {
+      U64BlobIdClosedInterval::new_from_range_and_offsets(range, start, KeyOffset(start.0 + granted - 1))
+    })
+    .collect()
+}
+
+/// builds the epoch and drops increments from the end until it takes no more than `max_bytes` in the epoch coordinator
+/// the last increments are of the ranges whose turn is the latest in this epoch, see `calculate_epoch_increments`
+fn fit_epoch(
+  mut increments: Vec<U64BlobIdClosedInterval>,
+  max_bytes: usize,
+  build: impl Fn(Vec<U64BlobIdClosedInterval>) -> Epoch,
+) -> Epoch {
+  let serialized_len = |epoch: &Epoch| serde_json::to_vec(epoch).map(|bytes| bytes.len()).unwrap_or(usize::MAX);
+  let mut epoch = build(increments.clone());
+  while !increments.is_empty() && serialized_len(&epoch) > max_bytes {
+    increments.pop();
+    epoch = build(increments.clone());
+  }
+  epoch
 }
 
 /// moves offset pointer for a particular peerID(node)
@@ -390,6 +1505,14 @@ fn move_offset_pointer(
   mut_range.insert(peer_id, new_offset);
 }
 
+/// transactions that can't be sent by gateways with the reason
+fn private_txs<'a>(txs: impl IntoIterator<Item = &'a Transaction>) -> Vec<(UniqueU64BlobId, Rejection)> {
+  txs
+    .into_iter()
+    .filter_map(|tx| check_public_message(&tx.blueprint.param).err().map(|rejection| (tx.meta.id, rejection)))
+    .collect()
+}
+
 /// wrapper around `update_self_offsets`
 fn update_self_offset(
   self_offsets: &mut HashMap<KeyRange, KeyOffset>,
@@ -513,6 +1636,103 @@ fn self_delays(
   result
 }
 
+/// never resolves if there is no receiver, so it can be a `select!` branch
+async fn recv_if_set<T>(receiver: &mut Option<UnboundedReceiver<T>>) -> Option<T> {
+  match receiver {
+    Some(receiver) => receiver.recv().await,
+    None => std::future::pending().await,
+  }
+}
+
+/// covers at most `limit` ids of the requested intervals starting from `resume`, ids without transactions are skipped
+fn tx_page(
+  transactions: &HashMap<UniqueU64BlobId, Transaction>,
+  request: &TxPageRequest,
+  limit: u32,
+) -> TxPage {
+  let (intervals, resume, request_id) = (&request.intervals, request.resume, request.id);
+  let mut txs = Vec::new();
+  let mut covered = 0;
+  let first = resume.map_or(0, |token| token.interval as usize);
+
+  for (index, interval) in intervals.iter().enumerate().skip(first) {
+    let start = match resume {
+      Some(token) if index == first => token.next_id.max(interval.start()),
+      _ => interval.start(),
+    };
+    for id in (start.0..=interval.end().0).map(UniqueU64BlobId) {
+      // at least one id per page, so paging always moves forward
+      if covered >= limit.max(1) {
+        return TxPage { request_id, txs, next: Some(ResumeToken { interval: index as u32, next_id: id }) };
+      }
+      covered += 1;
+      if let Some(tx) = transactions.get(&id) {
+        txs.push(tx.clone());
+      }
+    }
+  }
+
+  TxPage { request_id, txs, next: None }
+}
+
+/// groups ids that are not in `transactions` into intervals
+fn missing_intervals<'a>(
+  ids: impl Iterator<Item = &'a UniqueU64BlobId>,
+  transactions: &HashMap<UniqueU64BlobId, Transaction>,
+) -> Vec<U64BlobIdClosedInterval> {
+  let mut result = Vec::new();
+  let mut current: Option<(UniqueU64BlobId, UniqueU64BlobId)> = None;
+
+  for id in ids.filter(|id| !transactions.contains_key(id)) {
+    current = match current {
+      Some((start, end))
+        if end + UniqueU64BlobId(1) == *id && range_from_unique_blob_id(start) == range_from_unique_blob_id(*id) =>
+      {
+        Some((start, *id))
+      }
+      Some((start, end)) => {
+        result.push(U64BlobIdClosedInterval::new(start, end));
+        Some((*id, *id))
+      }
+      None => Some((*id, *id)),
+    };
+  }
+
+  if let Some((start, end)) = current {
+    result.push(U64BlobIdClosedInterval::new(start, end));
+  }
+
+  result
+}
+
+/// picks a node for every interval that has advertised an offset covering it
+/// intervals that nobody has advertised yet are skipped
+fn assign_missing_intervals(
+  missing: Vec<U64BlobIdClosedInterval>,
+  offsets: &HashMap<KeyRange, HashMap<PeerId, KeyOffset>>,
+  self_peer_id: PeerId,
+) -> HashMap<PeerId, Vec<U64BlobIdClosedInterval>> {
+  let mut result = HashMap::<PeerId, Vec<U64BlobIdClosedInterval>>::new();
+
+  for interval in missing {
+    let (range, end) = range_offset_from_unique_blob_id(interval.end());
+    let Some(nodes) = offsets.get(&range) else {
+      continue;
+    };
+    let Some((peer_id, _)) = nodes
+      .iter()
+      .filter(|(peer, offset)| **peer != self_peer_id && **offset >= end)
+      .max_by_key(|(_, offset)| **offset)
+    else {
+      continue;
+    };
+
+    result.entry(*peer_id).or_default().push(interval);
+  }
+
+  result
+}
+
 /// returns maximum offset among peers keeping in mind the `n_consensus`
 /// if `n_consensus` is 2 - it will find the maximum number that is present in at least 2 peers
 fn consensus_maximum(
@@ -792,9 +2012,294 @@ mod tests {
         expected_increments: vec![U64BlobIdClosedInterval::new(7, 10)],
       },
     ] {
-      let mut increments = calculate_epoch_increments(&case.consensus_offset, &case.commited_offsets);
+      let mut increments =
+        calculate_epoch_increments(&case.consensus_offset, &case.commited_offsets, &EpochLimits::default(), 0);
       increments.sort();
       assert_eq!(case.expected_increments, increments, "{}", case.label);
     }
   }
+
+  #[test]
+  fn test_epoch_increments_limits() {
+    let interval = |range: u64, start: u64, end: u64| {
+      U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(range), KeyOffset(start), KeyOffset(end))
+    };
+    let limits = |max_txs: u64, max_txs_per_range: u64| EpochLimits { max_txs, max_txs_per_range, max_bytes: 0 };
+
+    // range 1 needs less than its share, the rest is split between the others
+    let consensus =
+      HashMap::from([(KeyRange(0), KeyOffset(99)), (KeyRange(1), KeyOffset(2)), (KeyRange(2), KeyOffset(49))]);
+    assert_eq!(
+      vec![interval(0, 0, 28), interval(1, 0, 2), interval(2, 0, 27)],
+      calculate_epoch_increments(&consensus, &HashMap::new(), &limits(60, 40), 0)
+    );
+    // per range limit
+    assert_eq!(
+      vec![interval(0, 0, 9), interval(1, 0, 2), interval(2, 0, 9)],
+      calculate_epoch_increments(&consensus, &HashMap::new(), &limits(100, 10), 0)
+    );
+
+    // when there are more ranges than transactions the epoch takes, ranges take turns
+    let consensus: HashMap<KeyRange, KeyOffset> = (0..4).map(|range| (KeyRange(range), KeyOffset(4))).collect();
+    let commited = HashMap::from([(KeyRange(3), KeyOffset(1))]);
+    assert_eq!(
+      vec![interval(0, 0, 0), interval(1, 0, 0)],
+      calculate_epoch_increments(&consensus, &commited, &limits(2, 10), 0)
+    );
+    assert_eq!(
+      vec![interval(1, 0, 0), interval(2, 0, 0)],
+      calculate_epoch_increments(&consensus, &commited, &limits(2, 10), 1)
+    );
+    assert_eq!(
+      vec![interval(3, 2, 2), interval(0, 0, 0)],
+      calculate_epoch_increments(&consensus, &commited, &limits(2, 10), 7)
+    );
+
+    // increments that don't fit into the epoch coordinator wait for the next epochs
+    let peer_id = PeerId::random();
+    let build = |increments| Epoch::next(peer_id, increments, None, LogicalTimeAbsoluteMs(10));
+    let len = |increments| serde_json::to_vec(&build(increments)).unwrap().len();
+    let (one, two) = (len(vec![interval(1, 0, 5)]), len(vec![interval(1, 0, 5), interval(0, 0, 5)]));
+    assert_eq!(
+      vec![interval(1, 0, 5)],
+      fit_epoch(vec![interval(1, 0, 5), interval(0, 0, 5)], two - 1, build).increments
+    );
+    assert_eq!(2, fit_epoch(vec![interval(1, 0, 5), interval(0, 0, 5)], two, build).increments.len());
+    assert!(fit_epoch(vec![interval(1, 0, 5)], one - 1, build).increments.is_empty());
+  }
+
+  #[test]
+  fn test_verify_epoch() {
+    use epoch_coordinator::epoch::ChainError;
+
+    let peer_id = PeerId::random();
+    let interval = |range: u64, start: u64, end: u64| {
+      U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(range), KeyOffset(start), KeyOffset(end))
+    };
+    let e0 = Epoch::next(peer_id, vec![interval(0, 0, 3)], None, LogicalTimeAbsoluteMs(10));
+    let checkpoint = EpochCheckpoint {
+      sequence_number: 0,
+      hash: *e0.hash(),
+      commited_offsets: HashMap::from([(KeyRange(0), KeyOffset(3))]),
+      leases: KeyRangeLeases::default(),
+      membership: Membership::default(),
+    };
+    let next =
+      |increments: Vec<U64BlobIdClosedInterval>| Epoch::next(peer_id, increments, Some(&e0), LogicalTimeAbsoluteMs(20));
+    let commited = HashMap::from([(KeyRange(0), KeyOffset(3))]);
+
+    struct Case<'a> {
+      label: &'a str,
+      epoch: Epoch,
+      prev_epoch: Option<&'a Epoch>,
+      checkpoint: Option<&'a EpochCheckpoint>,
+      expected: Result<(), Divergence>,
+    }
+
+    let cases = vec![
+      Case { label: "first epoch", epoch: e0.clone(), prev_epoch: None, checkpoint: None, expected: Ok(()) },
+      Case {
+        label: "continues ranges",
+        epoch: next(vec![interval(0, 4, 5), interval(1, 0, 2)]),
+        prev_epoch: Some(&e0),
+        checkpoint: None,
+        expected: Ok(()),
+      },
+      Case {
+        label: "several increments for the same range",
+        epoch: next(vec![interval(0, 4, 5), interval(0, 6, 6)]),
+        prev_epoch: Some(&e0),
+        checkpoint: None,
+        expected: Ok(()),
+      },
+      Case {
+        label: "after checkpoint",
+        epoch: next(vec![interval(0, 4, 4)]),
+        prev_epoch: None,
+        checkpoint: Some(&checkpoint),
+        expected: Ok(()),
+      },
+      Case {
+        label: "forked chain",
+        epoch: next(vec![]),
+        prev_epoch: None,
+        checkpoint: None,
+        expected: Err(Divergence::Chain {
+          sequence_number: 1,
+          error: ChainError::SequenceNumber { expected: 0, got: 1 },
+        }),
+      },
+      Case {
+        label: "overlapping increment",
+        epoch: next(vec![interval(0, 3, 5)]),
+        prev_epoch: Some(&e0),
+        checkpoint: None,
+        expected: Err(Divergence::Increment {
+          sequence_number: 1,
+          range: KeyRange(0),
+          expected: KeyOffset(4),
+          got: KeyOffset(3),
+        }),
+      },
+      Case {
+        label: "overlapping increments in one epoch",
+        epoch: next(vec![interval(0, 4, 6), interval(0, 5, 7)]),
+        prev_epoch: Some(&e0),
+        checkpoint: None,
+        expected: Err(Divergence::Increment {
+          sequence_number: 1,
+          range: KeyRange(0),
+          expected: KeyOffset(7),
+          got: KeyOffset(5),
+        }),
+      },
+      Case {
+        label: "gap",
+        epoch: next(vec![interval(1, 1, 1)]),
+        prev_epoch: Some(&e0),
+        checkpoint: None,
+        expected: Err(Divergence::Increment {
+          sequence_number: 1,
+          range: KeyRange(1),
+          expected: KeyOffset(0),
+          got: KeyOffset(1),
+        }),
+      },
+      Case {
+        label: "interval across ranges",
+        epoch: next(vec![U64BlobIdClosedInterval::new(
+          unique_blob_id_from_range_and_offset(KeyRange(0), KeyOffset(4)),
+          unique_blob_id_from_range_and_offset(KeyRange(1), KeyOffset(0)),
+        )]),
+        prev_epoch: Some(&e0),
+        checkpoint: None,
+        expected: Err(Divergence::MalformedIncrement {
+          sequence_number: 1,
+          interval: U64BlobIdClosedInterval::new(
+            unique_blob_id_from_range_and_offset(KeyRange(0), KeyOffset(4)),
+            unique_blob_id_from_range_and_offset(KeyRange(1), KeyOffset(0)),
+          ),
+        }),
+      },
+    ];
+
+    for case in cases {
+      let commited =
+        if case.prev_epoch.is_some() || case.checkpoint.is_some() { commited.clone() } else { HashMap::new() };
+      assert_eq!(
+        case.expected,
+        verify_epoch(&case.epoch, case.prev_epoch, case.checkpoint, &commited),
+        "{}",
+        case.label
+      );
+    }
+  }
+
+  #[test]
+  fn test_missing_intervals() {
+    struct Case<'a> {
+      label: &'a str,
+      ids: Vec<UniqueU64BlobId>,
+      present: Vec<u64>,
+      expected: Vec<U64BlobIdClosedInterval>,
+    }
+
+    let id = |range: u64, offset: u64| unique_blob_id_from_range_and_offset(KeyRange(range), KeyOffset(offset));
+    let tx = |range: u64, offset: u64| test_tx(id(range, offset).0);
+
+    let cases = vec![
+      Case { label: "nothing is missing", ids: vec![id(0, 0), id(0, 1)], present: vec![0, 1], expected: vec![] },
+      Case {
+        label: "gaps",
+        ids: vec![id(0, 0), id(0, 1), id(0, 2), id(0, 3), id(0, 4), id(0, 5)],
+        present: vec![0, 3],
+        expected: vec![
+          U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(1), KeyOffset(2)),
+          U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(4), KeyOffset(5)),
+        ],
+      },
+      Case {
+        label: "several ranges",
+        ids: vec![id(0, 0), id(1, 0), id(1, 1)],
+        present: vec![],
+        expected: vec![
+          U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(0), KeyOffset(0), KeyOffset(0)),
+          U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(1), KeyOffset(0), KeyOffset(1)),
+        ],
+      },
+    ];
+
+    for case in cases {
+      let transactions: HashMap<UniqueU64BlobId, Transaction> = case
+        .present
+        .iter()
+        .map(|o| {
+          let tx = tx(0, *o);
+          (tx.meta.id, tx)
+        })
+        .collect();
+      assert_eq!(case.expected, missing_intervals(case.ids.iter(), &transactions), "{}", case.label);
+    }
+  }
+
+  #[test]
+  fn test_tx_page() {
+    // 3 is missing
+    let transactions: HashMap<UniqueU64BlobId, Transaction> =
+      [0, 1, 2, 4, 5, 10, 11].into_iter().map(test_tx).map(|tx| (tx.meta.id, tx)).collect();
+    let intervals = vec![U64BlobIdClosedInterval::new(1, 5), U64BlobIdClosedInterval::new(10, 11)];
+    let token = |interval: u32, next_id: u64| Some(ResumeToken { interval, next_id: UniqueU64BlobId(next_id) });
+    let request = |intervals: &[U64BlobIdClosedInterval], resume: Option<ResumeToken>| TxPageRequest {
+      id: 7,
+      intervals: intervals.to_vec(),
+      resume,
+      limit: 3,
+    };
+
+    let first = tx_page(&transactions, &request(&intervals, None), 3);
+    assert_eq!(TxPage { request_id: 7, txs: vec![test_tx(1), test_tx(2)], next: token(0, 4) }, first);
+
+    let second = tx_page(&transactions, &request(&intervals, first.next), 3);
+    assert_eq!(TxPage { request_id: 7, txs: vec![test_tx(4), test_tx(5), test_tx(10)], next: token(1, 11) }, second);
+
+    let last = tx_page(&transactions, &request(&intervals, second.next), 3);
+    assert_eq!(TxPage { request_id: 7, txs: vec![test_tx(11)], next: None }, last);
+
+    // a gap doesn't stop the page and zero limit still moves forward
+    assert_eq!(
+      vec![test_tx(1), test_tx(2), test_tx(4), test_tx(5)],
+      tx_page(&transactions, &request(&intervals[..1], None), 10).txs
+    );
+    assert_eq!(
+      TxPage { request_id: 7, txs: vec![test_tx(1)], next: token(0, 2) },
+      tx_page(&transactions, &request(&intervals, None), 0)
+    );
+  }
+
+  #[test]
+  fn test_assign_missing_intervals() {
+    let self_id = PeerId::random();
+    let p1 = PeerId::random();
+    let p2 = PeerId::random();
+
+    let offsets = HashMap::from([
+      (KeyRange(0), HashMap::from([(self_id, KeyOffset(10)), (p1, KeyOffset(5)), (p2, KeyOffset(3))])),
+      (KeyRange(1), HashMap::from([(p2, KeyOffset(2))])),
+    ]);
+    let interval = |range: u64, start: u64, end: u64| {
+      U64BlobIdClosedInterval::new_from_range_and_offsets(KeyRange(range), KeyOffset(start), KeyOffset(end))
+    };
+
+    let assigned = assign_missing_intervals(
+      vec![interval(0, 1, 2), interval(0, 4, 5), interval(0, 7, 8), interval(1, 0, 2), interval(2, 0, 0)],
+      &offsets,
+      self_id,
+    );
+
+    // the node with the biggest offset is asked, nobody has advertised 0:7-8(except self) and range 2
+    assert_eq!(
+      HashMap::from([(p1, vec![interval(0, 1, 2), interval(0, 4, 5)]), (p2, vec![interval(1, 0, 2)])]),
+      assigned
+    );
+  }
 }
@@ -1,11 +1,21 @@
-use opentelemetry::{global, metrics::Counter};
-use std::sync::OnceLock;
+use libp2p::PeerId;
+use opentelemetry::KeyValue;
+use opentelemetry::{
+  global,
+  metrics::{Counter, Histogram, ObservableGauge},
+};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
 
 static LATEST_EPOCH: AtomicU64 = AtomicU64::new(0);
+static EPOCH_DIVERGED: AtomicU64 = AtomicU64::new(0);
+static EXECUTION_BLOCKED_MS: AtomicU64 = AtomicU64::new(0);
+static REPLICA_DIVERGED: AtomicU64 = AtomicU64::new(0);
+static EPOCH_BACKLOG_TXS: AtomicU64 = AtomicU64::new(0);
+static OFFSETS_STALENESS_MS: Mutex<Vec<(PeerId, u64)>> = Mutex::new(Vec::new());
 
 pub fn register_gauges() {
-  static INIT: OnceLock<()> = OnceLock::new();
+  static INIT: OnceLock<Vec<ObservableGauge<u64>>> = OnceLock::new();
   INIT.get_or_init(|| {
     let meter = global::meter("maroon_app");
 
@@ -18,8 +28,55 @@ pub fn register_gauges() {
       })
       .build();
 
-    // Leak the registration so it's never dropped during process lifetime.
-    std::mem::forget(latest_epoch);
+    let epoch_diverged = meter
+      .u64_observable_gauge("maroon_epoch_diverged")
+      .with_description("1 if the node got an epoch that doesn't match its history and stopped applying epochs")
+      .with_callback(|observer| {
+        let v = EPOCH_DIVERGED.load(Ordering::Relaxed);
+        observer.observe(v, &[]);
+      })
+      .build();
+
+    let execution_blocked = meter
+      .u64_observable_gauge("maroon_execution_blocked_ms")
+      .with_description("How long execution of committed epochs has been waiting for missing transactions right now")
+      .with_callback(|observer| {
+        let v = EXECUTION_BLOCKED_MS.load(Ordering::Relaxed);
+        observer.observe(v, &[]);
+      })
+      .build();
+
+    let replica_diverged = meter
+      .u64_observable_gauge("maroon_replica_diverged")
+      .with_description("1 if runtime state of the node doesn't match the state another node has after the same epoch")
+      .with_callback(|observer| {
+        let v = REPLICA_DIVERGED.load(Ordering::Relaxed);
+        observer.observe(v, &[]);
+      })
+      .build();
+
+    let epoch_backlog = meter
+      .u64_observable_gauge("maroon_epoch_backlog_txs")
+      .with_description("Confirmed transactions that haven't fit into the last epoch this node has proposed")
+      .with_callback(|observer| {
+        let v = EPOCH_BACKLOG_TXS.load(Ordering::Relaxed);
+        observer.observe(v, &[]);
+      })
+      .build();
+
+    let offsets_staleness = meter
+      .u64_observable_gauge("maroon_peer_offsets_staleness_ms")
+      .with_description("How long ago each node has advertised its offsets, dropped nodes aren't reported")
+      .with_callback(|observer| {
+        let staleness = OFFSETS_STALENESS_MS.lock().unwrap_or_else(|e| e.into_inner());
+        for (peer, ms) in staleness.iter() {
+          observer.observe(*ms, &[KeyValue::new("peer", peer.to_string())]);
+        }
+      })
+      .build();
+
+    // Keep the registrations so they're never dropped during process lifetime.
+    vec![latest_epoch, epoch_diverged, execution_blocked, replica_diverged, epoch_backlog, offsets_staleness]
   });
 }
 
@@ -27,6 +84,38 @@ pub fn set_latest_epoch_seq_number(v: u64) {
   LATEST_EPOCH.store(v, Ordering::Relaxed);
 }
 
+pub fn set_diverged(diverged: bool) {
+  EPOCH_DIVERGED.store(diverged as u64, Ordering::Relaxed);
+}
+
+pub fn set_replica_diverged(diverged: bool) {
+  REPLICA_DIVERGED.store(diverged as u64, Ordering::Relaxed);
+}
+
+pub fn set_execution_blocked_ms(v: u64) {
+  EXECUTION_BLOCKED_MS.store(v, Ordering::Relaxed);
+}
+
+pub fn set_epoch_backlog_txs(v: u64) {
+  EPOCH_BACKLOG_TXS.store(v, Ordering::Relaxed);
+}
+
+pub fn set_offsets_staleness_ms(staleness: Vec<(PeerId, u64)>) {
+  *OFFSETS_STALENESS_MS.lock().unwrap_or_else(|e| e.into_inner()) = staleness;
+}
+
+// how long execution was waiting for missing transactions, recorded when it's unblocked
+pub fn execution_blocked_ms() -> &'static Histogram<u64> {
+  static HISTOGRAM: OnceLock<Histogram<u64>> = OnceLock::new();
+  HISTOGRAM.get_or_init(|| {
+    global::meter("maroon_app")
+      .u64_histogram("maroon_execution_blocked_duration_ms")
+      .with_description("How long execution of committed epochs was waiting for missing transactions")
+      .with_boundaries(vec![10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0])
+      .build()
+  })
+}
+
 pub fn know_txs() -> &'static Counter<u64> {
   static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
   COUNTER.get_or_init(|| {
@@ -47,3 +136,25 @@ pub fn finished_txs() -> &'static Counter<u64> {
       .build()
   })
 }
+
+// how many transactions on this node are in rejected state
+pub fn rejected_txs() -> &'static Counter<u64> {
+  static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
+  COUNTER.get_or_init(|| {
+    global::meter("maroon_app")
+      .u64_counter("maroon_tx_rejected")
+      .with_description("How many transactions rejected by this node")
+      .build()
+  })
+}
+
+// offsets of the nodes that don't count in consensus anymore, by reason: disconnected or ttl
+pub fn dropped_offsets() -> &'static Counter<u64> {
+  static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
+  COUNTER.get_or_init(|| {
+    global::meter("maroon_app")
+      .u64_counter("maroon_peer_offsets_dropped")
+      .with_description("How many times offsets of other nodes have been dropped from consensus")
+      .build()
+  })
+}
@@ -1,14 +1,35 @@
-use common::range_key::{KeyOffset, KeyRange};
+use crate::network::PeerCompatibility;
+use common::range_key::{KeyOffset, KeyRange, U64BlobIdClosedInterval, UniqueU64BlobId};
 use derive_more::Display;
-use std::collections::HashMap;
+use epoch_coordinator::epoch::{ChainError, Membership, MembershipChange};
+use libp2p::PeerId;
+use protocol::node2gw::TxUpdate;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Display)]
 pub enum Request {
   GetState,
+  GetEpochState,
+  /// statuses of the given transactions, the ones node doesn't know about are skipped
+  #[display("GetTxStatus({_0:?})")]
+  GetTxStatus(Vec<UniqueU64BlobId>),
+  /// membership after the latest applied epoch
+  GetMembership,
+  /// the change goes to one of the next epochs, answers with the current membership
+  ChangeMembership(MembershipChange),
+  /// what every peer seen since the start understands and how the node talks to it
+  GetPeersCompatibility,
 }
 #[derive(Debug, PartialEq, Eq, Display)]
 pub enum Response {
   State(CurrentOffsets),
+  EpochState(EpochState),
+  #[display("TxStatuses({_0:?})")]
+  TxStatuses(Vec<TxUpdate>),
+  #[display("Membership({_0:?})")]
+  Membership(Membership),
+  #[display("PeersCompatibility({_0:?})")]
+  PeersCompatibility(BTreeMap<PeerId, PeerCompatibility>),
 }
 
 #[derive(Debug, PartialEq, Eq, Display)]
@@ -17,3 +38,41 @@ pub struct CurrentOffsets {
   pub self_offsets: HashMap<KeyRange, KeyOffset>,
   pub consensus_offset: HashMap<KeyRange, KeyOffset>,
 }
+
+#[derive(Debug, PartialEq, Eq, Display)]
+#[display(
+  "EpochState(applied_sequence_number: {applied_sequence_number:?}, divergence: {divergence:?}, replica_divergence: {replica_divergence:?})"
+)]
+pub struct EpochState {
+  /// sequence number of the last epoch that has been applied by the node
+  pub applied_sequence_number: Option<u64>,
+  /// is set when the node got an epoch that doesn't match its own history
+  /// after that the node doesn't apply or commit any epochs
+  pub divergence: Option<Divergence>,
+  /// is set when runtime state of the node after some epoch doesn't match the state of another node
+  /// the node keeps working, it's only reported
+  pub replica_divergence: Option<ReplicaDivergence>,
+}
+
+/// Why an incoming epoch doesn't match the history the node has already applied
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum Divergence {
+  #[display("epoch {sequence_number} doesn't belong to the chain: {error}")]
+  Chain { sequence_number: u64, error: ChainError },
+  #[display("epoch {sequence_number} has increment for {range} starting at {got} while {expected} is expected")]
+  Increment { sequence_number: u64, range: KeyRange, expected: KeyOffset, got: KeyOffset },
+  #[display("epoch {sequence_number} has malformed increment {interval:?}")]
+  MalformedIncrement { sequence_number: u64, interval: U64BlobIdClosedInterval },
+  #[display("epoch {sequence_number} is published by {creator} that isn't a member")]
+  NotMember { sequence_number: u64, creator: PeerId },
+  #[display("epochs up to {sequence_number} are folded into a checkpoint and there is no snapshot after them")]
+  NoSnapshot { sequence_number: u64 },
+}
+
+/// The first epoch after which runtime state of the node differs from the state of another node
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display("runtime state after epoch {sequence_number} doesn't match the state of {peer}")]
+pub struct ReplicaDivergence {
+  pub sequence_number: u64,
+  pub peer: PeerId,
+}
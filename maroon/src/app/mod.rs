@@ -6,7 +6,8 @@ pub mod params;
 mod tests_single; // test app as a black box
 
 pub use app::App;
-pub use interface::{CurrentOffsets, Request, Response};
-pub use params::Params;
+pub use interface::{CurrentOffsets, Divergence, EpochState, ReplicaDivergence, Request, Response};
+pub use params::{EpochLimits, Params, TxSyncLimits};
 
 mod app_metrics;
+mod tx_results;
@@ -1,8 +1,11 @@
-use std::{num::NonZeroUsize, time::Duration};
+use std::{num::NonZeroUsize, path::PathBuf, sync::Arc, time::Duration};
 
+use crate::network::Allowlist;
+use crate::object_store::ObjectStore;
 use common::logical_time::LogicalTimeAbsoluteMs;
+use protocol::wire::Encoding;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct Params {
   /// how often node will send state info to other nodes
   /// consensus offset is recalculated on this tick
@@ -10,11 +13,92 @@ pub struct Params {
   /// minimum amount of nodes that should have the same transactions(+ current one) in order to confirm them
   /// TODO: separate pub struct ConsensusAlgoParams in a separate lib/consensus crate with its own test suite?
   pub consensus_nodes: NonZeroUsize,
+  /// offsets of a node that hasn't advertised them for this long don't count in consensus
+  pub offsets_ttl: Duration,
 
   /// periods between epochs <br>
   /// this parameter only says **when** you should start a new epoch <br>
   /// however due to multiple reasons a new epoch might not start after this period
   pub epoch_period: LogicalTimeAbsoluteMs,
+
+  /// how much of the confirmed transactions goes to one epoch, the rest waits for the next ones
+  pub epoch_limits: EpochLimits,
+
+  /// how the node gets missing transactions from other nodes and serves them
+  pub tx_sync_limits: TxSyncLimits,
+
+  /// directory of the write-ahead log with received transactions and their results <br>
+  /// node restores its state from it after restart. If not set - everything is kept only in memory
+  pub wal_dir: Option<PathBuf>,
+
+  /// how many statuses and results of finished transactions node keeps for `GetTxStatus` requests
+  pub tx_results_limit: usize,
+
+  /// where node uploads runtime snapshots and from where it bootstraps. If not set - node starts from scratch
+  pub snapshots: Option<SnapshotParams>,
+
+  /// how long node waits for runtime to execute the epochs it has got before it stops
+  pub shutdown_timeout: Duration,
+
+  /// how node encodes gossip. Every node decodes both encodings,
+  /// `Json` is for clusters where some nodes or gateways haven't been upgraded yet
+  pub gossip_encoding: Encoding,
+
+  /// file with the keypair of the node, it's created if it doesn't exist <br>
+  /// node keeps its `PeerId` across restarts only with it, otherwise a new identity is generated on every start
+  pub key_file: Option<PathBuf>,
+
+  /// nodes and gateways that can connect to the node, offsets and transactions of other peers are rejected
+  pub allowlist: Allowlist,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EpochLimits {
+  /// transactions in the epoch, they're split fairly between the ranges
+  pub max_txs: u64,
+  /// transactions of one `KeyRange` in the epoch
+  pub max_txs_per_range: u64,
+  /// serialized epoch, it's stored as one value in the epoch coordinator
+  pub max_bytes: usize,
+}
+
+impl Default for EpochLimits {
+  fn default() -> EpochLimits {
+    // etcd doesn't take requests bigger than 1.5MiB by default, epoch is written twice in one request
+    EpochLimits { max_txs: 100_000, max_txs_per_range: 10_000, max_bytes: 512 * 1024 }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxSyncLimits {
+  /// ids one page covers, both asked and served
+  pub page_txs: u32,
+  /// pages the node serves to one peer per second, the rest are throttled
+  pub pages_per_peer_per_sec: u32,
+  /// page that hasn't been answered for this long is asked again
+  pub page_timeout: Duration,
+}
+
+impl Default for TxSyncLimits {
+  fn default() -> TxSyncLimits {
+    TxSyncLimits { page_txs: 1_000, pages_per_peer_per_sec: 50, page_timeout: Duration::from_secs(5) }
+  }
+}
+
+#[derive(Clone)]
+pub struct SnapshotParams {
+  pub store: Arc<dyn ObjectStore>,
+  /// node makes a snapshot after executing this amount of epochs since the previous one
+  pub every_epochs: u64,
+}
+
+impl std::fmt::Debug for SnapshotParams {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    f.debug_struct("SnapshotParams").field("every_epochs", &self.every_epochs).finish_non_exhaustive()
+  }
 }
 
 impl Params {
@@ -22,7 +106,17 @@ impl Params {
     Params {
       advertise_period: Duration::from_millis(50), // 20Hz :)
       consensus_nodes: NonZeroUsize::new(2).unwrap(),
+      offsets_ttl: Duration::from_secs(5),
       epoch_period: LogicalTimeAbsoluteMs::from_millis(60),
+      epoch_limits: EpochLimits::default(),
+      tx_sync_limits: TxSyncLimits::default(),
+      wal_dir: None,
+      tx_results_limit: 100_000,
+      snapshots: None,
+      shutdown_timeout: Duration::from_secs(10),
+      gossip_encoding: Encoding::Binary,
+      key_file: None,
+      allowlist: Allowlist::Open,
     }
   }
 
@@ -42,6 +136,14 @@ impl Params {
     self
   }
 
+  pub fn set_offsets_ttl(
+    mut self,
+    ttl: Duration,
+  ) -> Params {
+    self.offsets_ttl = ttl;
+    self
+  }
+
   pub fn set_epoch_period(
     mut self,
     new_period: LogicalTimeAbsoluteMs,
@@ -49,4 +151,77 @@ impl Params {
     self.epoch_period = new_period;
     self
   }
+
+  pub fn set_epoch_limits(
+    mut self,
+    limits: EpochLimits,
+  ) -> Params {
+    self.epoch_limits = limits;
+    self
+  }
+
+  pub fn set_tx_sync_limits(
+    mut self,
+    limits: TxSyncLimits,
+  ) -> Params {
+    self.tx_sync_limits = limits;
+    self
+  }
+
+  pub fn set_wal_dir(
+    mut self,
+    dir: impl Into<PathBuf>,
+  ) -> Params {
+    self.wal_dir = Some(dir.into());
+    self
+  }
+
+  pub fn set_tx_results_limit(
+    mut self,
+    limit: usize,
+  ) -> Params {
+    self.tx_results_limit = limit;
+    self
+  }
+
+  pub fn set_snapshots(
+    mut self,
+    store: Arc<dyn ObjectStore>,
+    every_epochs: u64,
+  ) -> Params {
+    self.snapshots = Some(SnapshotParams { store, every_epochs });
+    self
+  }
+
+  pub fn set_shutdown_timeout(
+    mut self,
+    timeout: Duration,
+  ) -> Params {
+    self.shutdown_timeout = timeout;
+    self
+  }
+
+  pub fn set_gossip_encoding(
+    mut self,
+    encoding: Encoding,
+  ) -> Params {
+    self.gossip_encoding = encoding;
+    self
+  }
+
+  pub fn set_key_file(
+    mut self,
+    path: impl Into<PathBuf>,
+  ) -> Params {
+    self.key_file = Some(path.into());
+    self
+  }
+
+  pub fn set_allowlist(
+    mut self,
+    allowlist: Allowlist,
+  ) -> Params {
+    self.allowlist = allowlist;
+    self
+  }
 }
@@ -1,19 +1,27 @@
-use crate::app::Params;
-use crate::app::interface::CurrentOffsets;
+use crate::app::interface::{CurrentOffsets, Divergence, EpochState, ReplicaDivergence};
+use crate::app::{Params, Request as AppRequest, Response as AppResponse, TxSyncLimits};
 use crate::network::*;
+use crate::object_store::{LocalDirStore, ObjectStore};
+use crate::snapshot;
 use crate::test_helpers::{new_test_instance, new_test_instance_with_params, reaches_state, test_tx};
+use crate::wal::WalRecord;
 use common::duplex_channel::create_a_b_duplex_pair;
 use common::invoker_handler::create_invoker_handler_pair;
+use common::logical_clock::MonotonicTimer;
 use common::logical_time::LogicalTimeAbsoluteMs;
 use common::range_key::{KeyOffset, KeyRange, U64BlobIdClosedInterval, UniqueU64BlobId};
-use epoch_coordinator::epoch::Epoch;
+use epoch_coordinator::epoch::{
+  ChainError, Epoch, EpochCheckpoint, KeyRangeLeases, LeaseRequest, Membership, MembershipAction, MembershipChange,
+};
 use epoch_coordinator::interface::{EpochUpdates, create_interface_pair as create_epoch_coordinator_interface_pair};
 use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
 use libp2p::PeerId;
+use protocol::m2m_request_response::{ResumeToken, TxPage};
+use protocol::meta_exchange::{Capabilities, Role};
 use protocol::node2gw::TxUpdate;
 use protocol::transaction::{Meta, TxStatus};
-use runtime::runtime::{Input as RuntimeInput, Output as RuntimeOutput, TaskBlueprint};
-use std::collections::HashMap;
+use runtime::runtime::{Input as RuntimeInput, Output as RuntimeOutput, Rejection, Runtime, TaskBlueprint};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
@@ -197,11 +205,12 @@ async fn app_detects_that_its_behind_and_makes_request() {
     .expect("dont drop");
 
   while let Some(outbox) = a2b_endpoint.receiver.recv().await {
-    let Outbox::RequestMissingTxs((peer, requested_intervals)) = outbox else {
+    let Outbox::RequestTxPage((peer, request)) = outbox else {
       continue;
     };
     assert_eq!(rnd_peer, peer);
-    assert_eq!(requested_intervals, vec![U64BlobIdClosedInterval::new(1, 3), U64BlobIdClosedInterval::new(5, 8),]);
+    assert_eq!(request.intervals, vec![U64BlobIdClosedInterval::new(1, 3), U64BlobIdClosedInterval::new(5, 8),]);
+    assert_eq!(None, request.resume);
 
     break;
   }
@@ -340,8 +349,8 @@ async fn app_executes_after_epoch_confirmed() {
   );
 
   // imitate result from runtime
-  b2a_runtime.send((UniqueU64BlobId(0), Value::U64(2)));
-  b2a_runtime.send((UniqueU64BlobId(1), Value::U64(2)));
+  b2a_runtime.send((UniqueU64BlobId(0), Ok(Value::U64(2))));
+  b2a_runtime.send((UniqueU64BlobId(1), Ok(Value::U64(2))));
 
   let mut transmitted = Vec::<TxUpdate>::with_capacity(2);
 
@@ -364,3 +373,1187 @@ async fn app_executes_after_epoch_confirmed() {
     transmitted,
   );
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_applies_epochs_in_sequence_order() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(1))).unwrap();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  let rnd_peer = PeerId::random();
+  let epoch0 = Epoch::next(rnd_peer, vec![U64BlobIdClosedInterval::new(0, 0)], None, LogicalTimeAbsoluteMs(0));
+  let epoch1 =
+    Epoch::next(rnd_peer, vec![U64BlobIdClosedInterval::new(1, 1)], Some(&epoch0), LogicalTimeAbsoluteMs(10));
+
+  // epoch from the future is postponed until its predecessor comes, duplicate is ignored
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch1));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch0.clone()));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch0));
+
+  let blueprint = |id: u64| TaskBlueprint {
+    global_id: UniqueU64BlobId(id),
+    q_name: "testInfiniteCalculatorQueue".to_string(),
+    value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: 5, b: 7 }),
+  };
+
+  assert_eq!(Some((LogicalTimeAbsoluteMs(0), vec![blueprint(0)])), b2a_runtime.receiver.recv().await);
+  assert_eq!(Some((LogicalTimeAbsoluteMs(10), vec![blueprint(1)])), b2a_runtime.receiver.recv().await);
+
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert!(b2a_runtime.receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_starts_from_epoch_checkpoint() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (mut epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  // epochs 0..=4 are already folded and tx 0 is in them
+  let checkpoint = EpochCheckpoint {
+    sequence_number: 4,
+    hash: [7; 32],
+    commited_offsets: HashMap::from([(KeyRange(0), KeyOffset(0))]),
+    leases: KeyRangeLeases::default(),
+    membership: Membership::default(),
+  };
+  let epoch5 = Epoch::next_after_checkpoint(
+    PeerId::random(),
+    vec![U64BlobIdClosedInterval::new(1, 1)],
+    &checkpoint,
+    LogicalTimeAbsoluteMs(10),
+  );
+
+  // runtime state after the folded epochs comes from a snapshot
+  let (_, snapshot_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  app.restore_from_snapshot(checkpoint.clone(), &Runtime::new(MonotonicTimer::new(), snapshot_runtime).snapshot(0));
+
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(1))).unwrap();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch5.clone()));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::Checkpoint(checkpoint));
+
+  assert_eq!(
+    Some((
+      LogicalTimeAbsoluteMs(10),
+      vec![TaskBlueprint {
+        global_id: UniqueU64BlobId(1),
+        q_name: "testInfiniteCalculatorQueue".to_string(),
+        value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: 5, b: 7 }),
+      }]
+    )),
+    b2a_runtime.receiver.recv().await
+  );
+
+  // app reports what it has applied so coordinator can compact the history
+  epoch_coordinator_interface.applied_receiver.wait_for(|sn| *sn == Some(5)).await.expect("app is running");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_refuses_epoch_checkpoint_without_snapshot() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(1))).unwrap();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  let checkpoint = EpochCheckpoint {
+    sequence_number: 4,
+    hash: [7; 32],
+    commited_offsets: HashMap::from([(KeyRange(0), KeyOffset(0))]),
+    leases: KeyRangeLeases::default(),
+    membership: Membership::default(),
+  };
+  let epoch5 = Epoch::next_after_checkpoint(
+    PeerId::random(),
+    vec![U64BlobIdClosedInterval::new(1, 1)],
+    &checkpoint,
+    LogicalTimeAbsoluteMs(10),
+  );
+
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::Checkpoint(checkpoint));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch5));
+
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  let AppResponse::EpochState(epoch_state) = state_invoker.request(AppRequest::GetEpochState).await else {
+    unreachable!("GetEpochState is answered with EpochState");
+  };
+  assert_eq!(
+    EpochState {
+      applied_sequence_number: None,
+      divergence: Some(Divergence::NoSnapshot { sequence_number: 4 }),
+      replica_divergence: None,
+    },
+    epoch_state
+  );
+
+  // epochs after the checkpoint aren't executed without the state of the folded ones
+  assert!(b2a_runtime.receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_stops_on_forked_epoch() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(1))).unwrap();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  let rnd_peer = PeerId::random();
+  let epoch0 = Epoch::next(rnd_peer, vec![U64BlobIdClosedInterval::new(0, 0)], None, LogicalTimeAbsoluteMs(0));
+  // chained to some other epoch 0 that this node has never seen
+  let other_epoch0 = Epoch::next(rnd_peer, vec![], None, LogicalTimeAbsoluteMs(0));
+  let forked_epoch1 =
+    Epoch::next(rnd_peer, vec![U64BlobIdClosedInterval::new(1, 1)], Some(&other_epoch0), LogicalTimeAbsoluteMs(10));
+
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch0));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(forked_epoch1));
+
+  assert_eq!(Some(LogicalTimeAbsoluteMs(0)), b2a_runtime.receiver.recv().await.map(|(time, _)| time));
+
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  let AppResponse::EpochState(epoch_state) = state_invoker.request(AppRequest::GetEpochState).await else {
+    unreachable!("GetEpochState is answered with EpochState");
+  };
+  assert_eq!(
+    EpochState {
+      applied_sequence_number: Some(0),
+      divergence: Some(Divergence::Chain { sequence_number: 1, error: ChainError::Hash }),
+      replica_divergence: None,
+    },
+    epoch_state
+  );
+
+  // forked epoch isn't executed
+  assert!(b2a_runtime.receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_reports_first_diverging_replica_epoch() {
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let (digests_sender, digests_receiver) = tokio::sync::mpsc::unbounded_channel();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime)
+    .set_runtime_digests_receiver(digests_receiver);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  let same_peer = PeerId::random();
+  let ahead_peer = PeerId::random();
+  let late_peer = PeerId::random();
+
+  // a faster node has already executed epoch 1 and got a different state
+  a2b_endpoint.send(Inbox::Digests((ahead_peer, vec![EpochDigest { sequence_number: 1, digest: [2; 32] }])));
+
+  let rnd_peer = PeerId::random();
+  let epoch0 = Epoch::next(rnd_peer, vec![], None, LogicalTimeAbsoluteMs(0));
+  let epoch1 = Epoch::next(rnd_peer, vec![], Some(&epoch0), LogicalTimeAbsoluteMs(10));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch0));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(epoch1));
+
+  // runtime digests epochs in the order they've been sent
+  for digest in [[0; 32], [1; 32]] {
+    b2a_runtime.receiver.recv().await.expect("app is running");
+    digests_sender.send(digest).unwrap();
+  }
+
+  // own digests are gossiped with the offsets
+  let mut gossiped = vec![];
+  while gossiped.len() < 2 {
+    if let Some(Outbox::Digests(digests)) = a2b_endpoint.receiver.recv().await {
+      gossiped.extend(digests);
+    }
+  }
+  assert_eq!(
+    vec![EpochDigest { sequence_number: 0, digest: [0; 32] }, EpochDigest { sequence_number: 1, digest: [1; 32] }],
+    gossiped
+  );
+  a2b_endpoint.send(Inbox::Digests((same_peer, vec![EpochDigest { sequence_number: 0, digest: [0; 32] }])));
+
+  let epoch_state = || async {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let AppResponse::EpochState(epoch_state) = state_invoker.request(AppRequest::GetEpochState).await else {
+      unreachable!("GetEpochState is answered with EpochState");
+    };
+    epoch_state
+  };
+  assert_eq!(
+    EpochState {
+      applied_sequence_number: Some(1),
+      divergence: None,
+      replica_divergence: Some(ReplicaDivergence { sequence_number: 1, peer: ahead_peer }),
+    },
+    epoch_state().await
+  );
+
+  // the first diverging epoch is reported, even if it's found later
+  a2b_endpoint.send(Inbox::Digests((late_peer, vec![EpochDigest { sequence_number: 0, digest: [3; 32] }])));
+  assert_eq!(Some(ReplicaDivergence { sequence_number: 0, peer: late_peer }), epoch_state().await.replica_divergence);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_waits_for_missing_transactions_before_execution() {
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default()
+      .set_advertise_period(Duration::from_millis(100))
+      .set_tx_sync_limits(TxSyncLimits { page_timeout: Duration::from_millis(100), ..TxSyncLimits::default() }),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  let peer_with_txs = PeerId::random();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(3))).unwrap();
+  a2b_endpoint
+    .sender
+    .send(Inbox::State((peer_with_txs, NodeState { offsets: HashMap::from([(KeyRange(0), KeyOffset(3))]) })))
+    .unwrap();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  // epoch covers transactions this node hasn't got yet
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(Epoch::next(
+    PeerId::random(),
+    vec![U64BlobIdClosedInterval::new(0, 3)],
+    None,
+    LogicalTimeAbsoluteMs(0),
+  )));
+
+  let blueprint = |id: u64| TaskBlueprint {
+    global_id: UniqueU64BlobId(id),
+    q_name: "testInfiniteCalculatorQueue".to_string(),
+    value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: 5, b: 7 }),
+  };
+
+  // exactly the gap is requested from the node that has it
+  loop {
+    let msg = a2b_endpoint.receiver.recv().await.expect("app is running");
+    if let Outbox::RequestTxPage((peer_id, request)) = msg {
+      if peer_id == peer_with_txs && request.intervals == vec![U64BlobIdClosedInterval::new(1, 2)] {
+        break;
+      }
+    }
+  }
+  assert!(b2a_runtime.receiver.try_recv().is_err());
+
+  a2b_endpoint.send(Inbox::MissingTx(vec![test_tx(2), test_tx(1)]));
+
+  // the whole epoch goes to runtime once they arrive
+  assert_eq!(
+    Some((LogicalTimeAbsoluteMs(0), vec![blueprint(0), blueprint(1), blueprint(2), blueprint(3)])),
+    b2a_runtime.receiver.recv().await
+  );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_leases_key_ranges_through_epochs() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default()
+      .set_consensus_nodes(NonZeroUsize::new(1).unwrap())
+      .set_epoch_period(LogicalTimeAbsoluteMs::from_millis(200)),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // accept every proposed epoch
+  let mut rx = epoch_coordinator_interface.receiver;
+  let tx = epoch_coordinator_interface.sender;
+  tokio::spawn(async move {
+    while rx.changed().await.is_ok() {
+      let next = rx.borrow_and_update().clone();
+      if let Some(v) = next {
+        let _ = tx.send(EpochUpdates::New(v.epoch));
+      }
+    }
+  });
+
+  let sender = a2b_endpoint.sender;
+  let mut receiver = a2b_endpoint.receiver;
+  let gw1 = PeerId::random();
+  let gw2 = PeerId::random();
+  sender.send(Inbox::LeaseKeyRange(LeaseRequest { gateway: gw1, replaces: None })).unwrap();
+
+  let mut next_leased = async || loop {
+    let msg = receiver.recv().await.expect("app is running");
+    if let Outbox::KeyRangeLeased(leased) = msg {
+      return leased;
+    }
+  };
+
+  assert_eq!((gw1, KeyRange(0)), next_leased().await);
+
+  // a repeated request for the range that is already granted is answered right away
+  sender.send(Inbox::LeaseKeyRange(LeaseRequest { gateway: gw1, replaces: None })).unwrap();
+  assert_eq!((gw1, KeyRange(0)), next_leased().await);
+
+  sender.send(Inbox::LeaseKeyRange(LeaseRequest { gateway: gw2, replaces: None })).unwrap();
+  assert_eq!((gw2, KeyRange(1)), next_leased().await);
+
+  sender.send(Inbox::LeaseKeyRange(LeaseRequest { gateway: gw1, replaces: Some(KeyRange(0)) })).unwrap();
+  assert_eq!((gw1, KeyRange(2)), next_leased().await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_answers_tx_statuses() {
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default().set_tx_results_limit(1),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  for id in 0..3 {
+    a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(id))).unwrap();
+  }
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  let result = |n: u64| Value::U64(n);
+  b2a_runtime.sender.send((UniqueU64BlobId(0), Ok(result(0)))).unwrap();
+  b2a_runtime.sender.send((UniqueU64BlobId(1), Ok(result(1)))).unwrap();
+  tokio::time::sleep(Duration::from_millis(100)).await;
+
+  let update = |id: u64, status: TxStatus, result: Option<Value>| TxUpdate {
+    meta: Meta { id: UniqueU64BlobId(id), status },
+    result,
+  };
+  // result of tx 0 is evicted by the newer one, unknown tx 9 is skipped
+  let expected = vec![
+    update(0, TxStatus::Finished, None),
+    update(1, TxStatus::Finished, Some(result(1))),
+    update(2, TxStatus::Pending, None),
+  ];
+  let ids: Vec<UniqueU64BlobId> = [0, 1, 2, 9].into_iter().map(UniqueU64BlobId).collect();
+
+  assert_eq!(
+    AppResponse::TxStatuses(expected.clone()),
+    state_invoker.request(AppRequest::GetTxStatus(ids.clone())).await
+  );
+
+  a2b_endpoint.sender.send(Inbox::GetTxStatus((7, ids))).unwrap();
+  loop {
+    let msg = a2b_endpoint.receiver.recv().await.expect("app is running");
+    if let Outbox::TxStatuses(statuses) = msg {
+      assert_eq!((7, expected), statuses);
+      break;
+    }
+  }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_rejects_malformed_transactions() {
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  let mut rejected_tx = test_tx(0);
+  rejected_tx.blueprint.param = Value::U64(5);
+  a2b_endpoint.sender.send(Inbox::NewTransaction(rejected_tx)).unwrap();
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(1))).unwrap();
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  b2a_runtime.sender.send((UniqueU64BlobId(1), Err(Rejection::UnknownQueue))).unwrap();
+
+  let rejected = |id: u64, reason: &str| TxUpdate {
+    meta: Meta { id: UniqueU64BlobId(id), status: TxStatus::Rejected(reason.to_string()) },
+    result: None,
+  };
+  let mut notified = Vec::new();
+  while notified.len() < 2 {
+    if let Outbox::NotifyGWs(updates) = a2b_endpoint.receiver.recv().await.expect("app is running") {
+      notified.extend(updates);
+    }
+  }
+  assert_eq!(vec![rejected(0, "not_public_message"), rejected(1, "unknown_queue")], notified);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_rejects_private_transactions_from_other_nodes() {
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // the node that has got it from a gateway has rejected it already
+  let mut private_tx = test_tx(0);
+  private_tx.blueprint.param = Value::U64(5);
+  a2b_endpoint.send(Inbox::MissingTx(vec![private_tx, test_tx(1)]));
+
+  let rejected = TxUpdate {
+    meta: Meta { id: UniqueU64BlobId(0), status: TxStatus::Rejected("not_public_message".to_string()) },
+    result: None,
+  };
+  loop {
+    if let Outbox::NotifyGWs(updates) = a2b_endpoint.receiver.recv().await.expect("app is running") {
+      assert_eq!(vec![rejected.clone()], updates);
+      break;
+    }
+  }
+  assert_eq!(
+    AppResponse::TxStatuses(vec![
+      rejected,
+      TxUpdate { meta: Meta { id: UniqueU64BlobId(1), status: TxStatus::Pending }, result: None }
+    ]),
+    state_invoker.request(AppRequest::GetTxStatus(vec![UniqueU64BlobId(0), UniqueU64BlobId(1)])).await
+  );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_restores_transactions_from_wal() {
+  let wal_dir = tempfile::tempdir().unwrap();
+  let expected_state =
+    || CurrentOffsets { self_offsets: HashMap::from([(KeyRange(0), KeyOffset(2))]), consensus_offset: HashMap::new() };
+
+  {
+    let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+    let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+      create_epoch_coordinator_interface_pair();
+    let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+    let (state_invoker, handler) = create_invoker_handler_pair();
+    let mut app = new_test_instance_with_params(
+      b2a_endpoint,
+      handler,
+      epoch_coordinator_controller_interface,
+      a2b_runtime,
+      Params::default().set_wal_dir(wal_dir.path()),
+    );
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let app_handle = tokio::spawn(async move {
+      app.loop_until_shutdown(shutdown_rx).await;
+    });
+
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(0)));
+    a2b_endpoint.send(Inbox::MissingTx(vec![test_tx(2), test_tx(1)]));
+    // duplicate isn't written twice
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(1)));
+
+    assert!(reaches_state(5, Duration::from_millis(10), &state_invoker, expected_state()).await);
+
+    shutdown_tx.send(()).unwrap();
+    app_handle.await.unwrap();
+  }
+
+  // node restarts with the same wal and knows everything it has received before
+  let (_a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default().set_wal_dir(wal_dir.path()),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  assert!(reaches_state(1, Duration::from_millis(10), &state_invoker, expected_state()).await);
+
+  let (_, records) = crate::wal::Wal::open(wal_dir.path()).unwrap();
+  assert_eq!(3, records.len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_restores_self_offsets_after_wal_truncation() {
+  let wal_dir = tempfile::tempdir().unwrap();
+  let store_dir = tempfile::tempdir().unwrap();
+  let store: Arc<dyn ObjectStore> = Arc::new(LocalDirStore::new(store_dir.path()));
+
+  {
+    let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+    let (epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+      create_epoch_coordinator_interface_pair();
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+    let (_state_invoker, handler) = create_invoker_handler_pair();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut app = new_test_instance_with_params(
+      b2a_endpoint,
+      handler,
+      epoch_coordinator_controller_interface,
+      a2b_runtime,
+      Params::default().set_wal_dir(wal_dir.path()).set_snapshots(store.clone(), 1),
+    )
+    .set_runtime_snapshot_invoker(snapshot_invoker);
+    let mut runtime = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_snapshot_handler(snapshot_handler);
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(0)));
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(1)));
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(2)));
+    let app_handle = tokio::spawn(async move {
+      app.loop_until_shutdown(shutdown_rx).await;
+    });
+    tokio::spawn(async move {
+      runtime.run("root".to_string()).await;
+    });
+
+    // let app receive transactions first
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(Epoch::next(
+      PeerId::random(),
+      vec![U64BlobIdClosedInterval::new(0, 1)],
+      None,
+      LogicalTimeAbsoluteMs(0),
+    )));
+
+    // snapshot is uploaded and the committed transactions are dropped from the WAL
+    let mut truncated = false;
+    for _ in 0..50 {
+      let (_, records) = crate::wal::Wal::open(wal_dir.path()).unwrap();
+      let has_first_tx =
+        records.iter().any(|record| matches!(record, WalRecord::Transaction(tx) if tx.meta.id == UniqueU64BlobId(0)));
+      if !has_first_tx {
+        truncated = true;
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(truncated);
+
+    shutdown_tx.send(()).unwrap();
+    app_handle.await.unwrap();
+  }
+
+  // node restarts with the truncated wal and the snapshot, offsets continue from the checkpoint
+  let snapshot = snapshot::load_latest(store.as_ref()).await.unwrap().expect("uploaded before truncation");
+  let (_a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default().set_wal_dir(wal_dir.path()),
+  );
+  app.restore_from_snapshot(snapshot.checkpoint, &snapshot.runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  let AppResponse::State(state) = state_invoker.request(AppRequest::GetState).await else {
+    unreachable!("GetState is answered with State");
+  };
+  assert_eq!(HashMap::from([(KeyRange(0), KeyOffset(2))]), state.self_offsets);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_restores_from_snapshot() {
+  let store_dir = tempfile::tempdir().unwrap();
+  let store: Arc<dyn ObjectStore> = Arc::new(LocalDirStore::new(store_dir.path()));
+  let first_epoch =
+    Epoch::next(PeerId::random(), vec![U64BlobIdClosedInterval::new(0, 1)], None, LogicalTimeAbsoluteMs(0));
+
+  {
+    let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+    let (epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+      create_epoch_coordinator_interface_pair();
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+    let (_state_invoker, handler) = create_invoker_handler_pair();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut app = new_test_instance_with_params(
+      b2a_endpoint,
+      handler,
+      epoch_coordinator_controller_interface,
+      a2b_runtime,
+      Params::default().set_snapshots(store.clone(), 1),
+    )
+    .set_runtime_snapshot_invoker(snapshot_invoker);
+    let mut runtime = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_snapshot_handler(snapshot_handler);
+    let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(0)));
+    a2b_endpoint.send(Inbox::NewTransaction(test_tx(1)));
+    tokio::spawn(async move {
+      app.loop_until_shutdown(shutdown_rx).await;
+    });
+    tokio::spawn(async move {
+      runtime.run("root".to_string()).await;
+    });
+
+    // let app receive transactions first
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(first_epoch.clone()));
+
+    let mut uploaded = false;
+    for _ in 0..50 {
+      if snapshot::load_latest(store.as_ref()).await.unwrap().is_some() {
+        uploaded = true;
+        break;
+      }
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(uploaded);
+  }
+
+  let snapshot = snapshot::load_latest(store.as_ref()).await.unwrap().expect("checked above");
+  assert_eq!(EpochCheckpoint::fold(None, [&first_epoch]), Some(snapshot.checkpoint.clone()));
+
+  // a new node starts from the snapshot and gets only the epochs after it
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default(),
+  );
+  let mut runtime = Runtime::new(MonotonicTimer::new(), b2a_runtime);
+  app.restore_from_snapshot(snapshot.checkpoint, &snapshot.runtime);
+  runtime.restore(snapshot.runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  a2b_endpoint.send(Inbox::NewTransaction(test_tx(2)));
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+  tokio::spawn(async move {
+    runtime.run("root".to_string()).await;
+  });
+
+  // let app receive transactions first
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(first_epoch.clone()));
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(Epoch::next(
+    PeerId::random(),
+    vec![U64BlobIdClosedInterval::new(2, 2)],
+    Some(&first_epoch),
+    LogicalTimeAbsoluteMs(0),
+  )));
+
+  // summator fiber from the snapshot executes the transaction
+  loop {
+    let msg = a2b_endpoint.receiver.recv().await.expect("app is running");
+    if let Outbox::NotifyGWs(updates) = msg
+      && let Some(update) = updates.iter().find(|u| u.meta.id == UniqueU64BlobId(2))
+    {
+      assert_eq!(Some(Value::U64(35)), update.result);
+      break;
+    }
+  }
+
+  let AppResponse::EpochState(epoch_state) = state_invoker.request(AppRequest::GetEpochState).await else {
+    unreachable!("GetEpochState is answered with EpochState")
+  };
+  assert_eq!(Some(1), epoch_state.applied_sequence_number);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_finishes_sent_epochs_on_shutdown() {
+  let (mut a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, mut b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_state_invoker, handler) = create_invoker_handler_pair();
+  let (digests_sender, digests_receiver) = tokio::sync::mpsc::unbounded_channel();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default().set_consensus_nodes(NonZeroUsize::new(1).unwrap()).set_shutdown_timeout(Duration::from_secs(5)),
+  )
+  .set_runtime_digests_receiver(digests_receiver);
+  let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  a2b_endpoint.sender.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  let app = tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(Epoch::next(
+    PeerId::random(),
+    vec![U64BlobIdClosedInterval::new(0, 0)],
+    None,
+    LogicalTimeAbsoluteMs(0),
+  )));
+  b2a_runtime.receiver.recv().await.expect("app sends the epoch to runtime");
+
+  // node is asked to stop while runtime is still executing the epoch
+  shutdown_tx.send(()).unwrap();
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  assert!(!app.is_finished(), "app waits for runtime");
+
+  b2a_runtime.send((UniqueU64BlobId(0), Ok(Value::U64(35))));
+  digests_sender.send([0; 32]).unwrap();
+  tokio::time::timeout(Duration::from_secs(1), app).await.expect("app stops once the epoch is executed").unwrap();
+
+  // gateways get the result before the app drops its side of p2p
+  let mut notified = vec![];
+  while let Some(msg) = a2b_endpoint.receiver.recv().await {
+    if let Outbox::NotifyGWs(updates) = msg {
+      notified.extend(updates);
+    }
+  }
+  assert_eq!(
+    vec![TxUpdate { meta: Meta { id: UniqueU64BlobId(0), status: TxStatus::Finished }, result: Some(Value::U64(35)) }],
+    notified
+  );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_changes_membership_through_epochs() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (epoch_coordinator_interface, epoch_coordinator_controller_interface) = create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default()
+      .set_consensus_nodes(NonZeroUsize::new(1).unwrap())
+      .set_epoch_period(LogicalTimeAbsoluteMs::from_millis(200)),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // accept every proposed epoch
+  let (committed_tx, mut committed) = tokio::sync::mpsc::unbounded_channel();
+  let mut rx = epoch_coordinator_interface.receiver;
+  let tx = epoch_coordinator_interface.sender.clone();
+  tokio::spawn(async move {
+    while rx.changed().await.is_ok() {
+      let next = rx.borrow_and_update().clone();
+      if let Some(v) = next {
+        _ = committed_tx.send(v.epoch.clone());
+        let _ = tx.send(EpochUpdates::New(v.epoch));
+      }
+    }
+  });
+
+  let membership = || async {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let AppResponse::Membership(membership) = state_invoker.request(AppRequest::GetMembership).await else {
+      unreachable!("GetMembership is answered with Membership");
+    };
+    membership
+  };
+
+  // without membership the node publishes on its own
+  let node = committed.recv().await.expect("app publishes epochs").creator;
+  let other = PeerId::random();
+  let add_node = MembershipChange { node, action: MembershipAction::Add, effective_from: 0 };
+  let add_other = MembershipChange { node: other, action: MembershipAction::Add, effective_from: 0 };
+  let AppResponse::Membership(current) = state_invoker.request(AppRequest::ChangeMembership(add_node)).await else {
+    unreachable!("ChangeMembership is answered with Membership");
+  };
+  assert_eq!(Membership::default(), current);
+  // requests that have come to other nodes are gossiped
+  a2b_endpoint.send(Inbox::MembershipChanges(vec![add_other]));
+
+  let mut with_changes = committed.recv().await.expect("app publishes epochs");
+  while with_changes.membership.is_empty() {
+    with_changes = committed.recv().await.expect("app publishes epochs");
+  }
+  let mut expected = vec![add_node, add_other];
+  expected.sort();
+  assert_eq!(expected, with_changes.membership);
+
+  while membership().await.nodes.len() < 2 {}
+  assert_eq!(BTreeSet::from([node, other]), membership().await.nodes);
+
+  // removed node stops publishing once the change takes effect
+  state_invoker
+    .request(AppRequest::ChangeMembership(MembershipChange {
+      node,
+      action: MembershipAction::Remove,
+      effective_from: 0,
+    }))
+    .await;
+  while membership().await.is_member(&node) {}
+  let AppResponse::EpochState(epoch_state) = state_invoker.request(AppRequest::GetEpochState).await else {
+    unreachable!("GetEpochState is answered with EpochState");
+  };
+  let applied = epoch_state.applied_sequence_number.unwrap();
+  tokio::time::sleep(Duration::from_millis(600)).await;
+  // the first accepted epoch with a sequence number is the one that has been applied
+  let mut last = None;
+  while let Ok(epoch) = committed.try_recv() {
+    assert!(epoch.sequence_number <= applied, "{epoch} is published after the node is removed");
+    if epoch.sequence_number == applied && last.is_none() {
+      last = Some(epoch);
+    }
+  }
+
+  // epochs from nodes that aren't members are not applied
+  let stranger = PeerId::random();
+  let last = last.expect("the last applied epoch is published by the node");
+  _ = epoch_coordinator_interface.sender.send(EpochUpdates::New(Epoch::next(
+    stranger,
+    vec![],
+    Some(&last),
+    LogicalTimeAbsoluteMs(0),
+  )));
+  tokio::time::sleep(Duration::from_millis(100)).await;
+  let AppResponse::EpochState(epoch_state) = state_invoker.request(AppRequest::GetEpochState).await else {
+    unreachable!("GetEpochState is answered with EpochState");
+  };
+  assert_eq!(Some(Divergence::NotMember { sequence_number: applied + 1, creator: stranger }), epoch_state.divergence);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_drops_stale_offsets_from_consensus() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default().set_offsets_ttl(Duration::from_millis(500)),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  let (n1, n2) = (PeerId::random(), PeerId::random());
+  let advertise = |peer: PeerId, offset: u64| {
+    a2b_endpoint.send(Inbox::State((peer, NodeState { offsets: HashMap::from([(KeyRange(1), KeyOffset(offset))]) })));
+  };
+  let consensus =
+    |offsets: HashMap<KeyRange, KeyOffset>| CurrentOffsets { self_offsets: HashMap::new(), consensus_offset: offsets };
+
+  advertise(n1, 3);
+  advertise(n2, 2);
+  assert!(
+    reaches_state(
+      5,
+      Duration::from_millis(20),
+      &state_invoker,
+      consensus(HashMap::from([(KeyRange(1), KeyOffset(2))]))
+    )
+    .await
+  );
+
+  // disconnected node doesn't count anymore
+  a2b_endpoint.send(Inbox::Nodes(HashSet::from([n1])));
+  assert!(reaches_state(5, Duration::from_millis(20), &state_invoker, consensus(HashMap::new())).await);
+
+  // it's back, but then n1 goes silent
+  advertise(n2, 3);
+  assert!(
+    reaches_state(
+      5,
+      Duration::from_millis(20),
+      &state_invoker,
+      consensus(HashMap::from([(KeyRange(1), KeyOffset(3))]))
+    )
+    .await
+  );
+  for _ in 0..12 {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    advertise(n2, 3);
+  }
+  assert!(reaches_state(5, Duration::from_millis(20), &state_invoker, consensus(HashMap::new())).await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_exposes_peers_compatibility() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance(b2a_endpoint, handler, epoch_coordinator_controller_interface, a2b_runtime);
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  let (node, gateway) = (PeerId::random(), PeerId::random());
+  let current = Capabilities::current();
+  let node_compatibility =
+    PeerCompatibility { role: Role::Node, capabilities: current.clone(), compatibility: current.check(&current) };
+  let gateway_compatibility = PeerCompatibility {
+    role: Role::Gateway,
+    capabilities: Capabilities::legacy(),
+    compatibility: current.check(&Capabilities::legacy()),
+  };
+  a2b_endpoint.send(Inbox::PeerCompatibility((node, node_compatibility.clone())));
+  a2b_endpoint.send(Inbox::PeerCompatibility((gateway, gateway_compatibility.clone())));
+
+  let expected = BTreeMap::from([(node, node_compatibility), (gateway, gateway_compatibility)]);
+  for _ in 0..5 {
+    let AppResponse::PeersCompatibility(peers) = state_invoker.request(AppRequest::GetPeersCompatibility).await else {
+      unreachable!("GetPeersCompatibility is answered with PeersCompatibility");
+    };
+    if peers == expected {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+  }
+  panic!("peers compatibility isn't exposed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_catches_up_in_pages() {
+  const BEHIND: u64 = 100_000;
+  let limits = TxSyncLimits { page_txs: 2_000, pages_per_peer_per_sec: 10, page_timeout: Duration::from_secs(1) };
+  let params = || Params::default().set_advertise_period(Duration::from_millis(50)).set_tx_sync_limits(limits);
+
+  // node `behind` asks, node `ahead` serves
+  let (mut behind_net, behind_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_behind_coordinator, behind_controller) = create_epoch_coordinator_interface_pair();
+  let (behind_runtime, _behind_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (behind_state, behind_handler) = create_invoker_handler_pair();
+  let mut behind =
+    new_test_instance_with_params(behind_endpoint, behind_handler, behind_controller, behind_runtime, params());
+
+  let (mut ahead_net, ahead_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_ahead_coordinator, ahead_controller) = create_epoch_coordinator_interface_pair();
+  let (ahead_runtime, _ahead_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (_ahead_state, ahead_handler) = create_invoker_handler_pair();
+  let mut ahead =
+    new_test_instance_with_params(ahead_endpoint, ahead_handler, ahead_controller, ahead_runtime, params());
+
+  ahead_net.send(Inbox::MissingTx((0..BEHIND).map(test_tx).collect()));
+
+  let (_behind_shutdown, behind_shutdown_rx) = oneshot::channel();
+  let (_ahead_shutdown, ahead_shutdown_rx) = oneshot::channel();
+  tokio::spawn(async move {
+    behind.loop_until_shutdown(behind_shutdown_rx).await;
+  });
+  tokio::spawn(async move {
+    ahead.loop_until_shutdown(ahead_shutdown_rx).await;
+  });
+
+  // plays the network between the two nodes
+  let (behind_id, ahead_id) = (PeerId::random(), PeerId::random());
+  let throttled = Arc::new(Mutex::new(0));
+  let network_throttled = throttled.clone();
+  tokio::spawn(async move {
+    let mut in_flight = None;
+    for query_id in 0.. {
+      tokio::select! {
+        Some(msg) = behind_net.receiver.recv() => {
+          if let Outbox::RequestTxPage((peer_id, request)) = msg {
+            assert_eq!(ahead_id, peer_id);
+            assert!(request.limit <= limits.page_txs);
+            assert_eq!(None, in_flight.replace(query_id), "only one page is asked at a time");
+            ahead_net.send(Inbox::GetTxPage((query_id, behind_id, request)));
+          }
+        }
+        Some(msg) = ahead_net.receiver.recv() => match msg {
+          Outbox::State(state) => behind_net.send(Inbox::State((ahead_id, state))),
+          Outbox::TxPage((query_id, request_id, page)) => {
+            assert_eq!(Some(query_id), in_flight.take());
+            match page {
+              Some(page) => behind_net.send(Inbox::TxPage((ahead_id, page))),
+              None => {
+                *network_throttled.lock().await += 1;
+                behind_net.send(Inbox::TxPageThrottled((ahead_id, request_id)));
+              }
+            }
+          }
+          _ => {}
+        },
+      }
+    }
+  });
+
+  assert!(
+    reaches_state(
+      100,
+      Duration::from_millis(100),
+      &behind_state,
+      CurrentOffsets {
+        self_offsets: HashMap::from([(KeyRange(0), KeyOffset(BEHIND - 1))]),
+        consensus_offset: HashMap::from([(KeyRange(0), KeyOffset(BEHIND - 1))]),
+      }
+    )
+    .await
+  );
+  // 50 pages at 10 pages per second don't go through without throttling
+  assert!(*throttled.lock().await > 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_drops_tx_pages_of_replaced_requests() {
+  let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
+  let (_epoch_coordinator_interface, epoch_coordinator_controller_interface) =
+    create_epoch_coordinator_interface_pair();
+  let (a2b_runtime, _b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
+  let (state_invoker, handler) = create_invoker_handler_pair();
+  let mut app = new_test_instance_with_params(
+    b2a_endpoint,
+    handler,
+    epoch_coordinator_controller_interface,
+    a2b_runtime,
+    Params::default()
+      .set_advertise_period(Duration::from_millis(20))
+      .set_tx_sync_limits(TxSyncLimits { page_timeout: Duration::from_millis(50), ..TxSyncLimits::default() }),
+  );
+  let (_shutdown_tx, shutdown_rx) = oneshot::channel();
+  tokio::spawn(async move {
+    app.loop_until_shutdown(shutdown_rx).await;
+  });
+
+  // requests are read in the helper, while messages are sent
+  let (to_app, mut from_app) = (a2b_endpoint.sender, a2b_endpoint.receiver);
+  let peer = PeerId::random();
+  to_app.send(Inbox::NewTransaction(test_tx(0))).unwrap();
+  to_app.send(Inbox::State((peer, NodeState { offsets: HashMap::from([(KeyRange(0), KeyOffset(10))]) }))).unwrap();
+
+  let mut next_request = async |intervals: Vec<U64BlobIdClosedInterval>| loop {
+    if let Some(Outbox::RequestTxPage((_, request))) = from_app.recv().await
+      && request.intervals == intervals
+    {
+      return request;
+    }
+  };
+  let replaced = next_request(vec![U64BlobIdClosedInterval::new(1, 10)]).await;
+
+  // the gap changes, so the request that is asked again after the timeout has other intervals
+  to_app.send(Inbox::NewTransaction(test_tx(5))).unwrap();
+  let current = next_request(vec![U64BlobIdClosedInterval::new(1, 4), U64BlobIdClosedInterval::new(6, 10)]).await;
+
+  // late page of the replaced request, its token would skip 6-8 of the current intervals
+  let token = ResumeToken { interval: 1, next_id: UniqueU64BlobId(9) };
+  to_app
+    .send(Inbox::TxPage((peer, TxPage { request_id: replaced.id, txs: vec![test_tx(1)], next: Some(token) })))
+    .unwrap();
+  let current = loop {
+    let request = next_request(current.intervals.clone()).await;
+    assert_eq!(None, request.resume, "paging doesn't continue from a stale page");
+    if request.id != current.id {
+      break request;
+    }
+  };
+  // transactions of the stale page aren't taken either
+  let AppResponse::State(state) = state_invoker.request(AppRequest::GetState).await else {
+    unreachable!("GetState is answered with State");
+  };
+  assert_eq!(HashMap::from([(KeyRange(0), KeyOffset(0))]), state.self_offsets);
+
+  // the page of the current request is taken and paging goes on
+  let token = ResumeToken { interval: 0, next_id: UniqueU64BlobId(2) };
+  to_app
+    .send(Inbox::TxPage((peer, TxPage { request_id: current.id, txs: vec![test_tx(1)], next: Some(token) })))
+    .unwrap();
+  loop {
+    if next_request(current.intervals.clone()).await.resume == Some(token) {
+      break;
+    }
+  }
+}
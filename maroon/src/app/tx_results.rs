@@ -0,0 +1,80 @@
+use common::range_key::UniqueU64BlobId;
+use protocol::node2gw::TxUpdate;
+use std::collections::{HashMap, VecDeque};
+
+/// final statuses and results of the recent transactions, so gateways can ask for them later <br>
+/// keeps at most `limit` of them, the oldest ones are evicted first
+pub(crate) struct TxResults {
+  limit: usize,
+  updates: HashMap<UniqueU64BlobId, TxUpdate>,
+  /// insertion order of `updates` keys
+  order: VecDeque<UniqueU64BlobId>,
+}
+
+impl TxResults {
+  pub(crate) fn new(limit: usize) -> TxResults {
+    TxResults { limit, updates: HashMap::new(), order: VecDeque::new() }
+  }
+
+  pub(crate) fn insert(
+    &mut self,
+    update: TxUpdate,
+  ) {
+    if self.limit == 0 {
+      return;
+    }
+
+    let id = update.meta.id;
+    if self.updates.insert(id, update).is_some() {
+      return;
+    }
+
+    self.order.push_back(id);
+    while self.order.len() > self.limit {
+      let Some(oldest) = self.order.pop_front() else {
+        break;
+      };
+      self.updates.remove(&oldest);
+    }
+  }
+
+  pub(crate) fn get(
+    &self,
+    id: &UniqueU64BlobId,
+  ) -> Option<&TxUpdate> {
+    self.updates.get(id)
+  }
+
+  pub(crate) fn ids(&self) -> impl Iterator<Item = &UniqueU64BlobId> {
+    self.order.iter()
+  }
+
+  pub(crate) fn len(&self) -> usize {
+    self.updates.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use protocol::transaction::{Meta, TxStatus};
+
+  fn finished(id: u64) -> TxUpdate {
+    TxUpdate { meta: Meta { id: UniqueU64BlobId(id), status: TxStatus::Finished }, result: None }
+  }
+
+  #[test]
+  fn evicts_the_oldest_results() {
+    let mut results = TxResults::new(2);
+    results.insert(finished(0));
+    results.insert(finished(1));
+    // updating the result doesn't make it newer
+    results.insert(finished(0));
+    results.insert(finished(2));
+
+    assert_eq!(2, results.len());
+    assert!(results.get(&UniqueU64BlobId(0)).is_none());
+    assert_eq!(Some(&finished(1)), results.get(&UniqueU64BlobId(1)));
+    assert_eq!(Some(&finished(2)), results.get(&UniqueU64BlobId(2)));
+  }
+}
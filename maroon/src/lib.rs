@@ -4,10 +4,14 @@ mod macros;
 pub mod app;
 pub mod linearizer;
 pub mod network;
+pub mod object_store;
+pub mod snapshot;
 pub mod stack;
+pub mod wal;
 
 mod epoch_decision_engine;
 pub mod metrics;
+mod runtime_metrics;
 
 #[cfg(test)]
 mod test_helpers;
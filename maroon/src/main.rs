@@ -1,10 +1,17 @@
-use log::error;
-use maroon::app::Params;
+use epoch_coordinator::epoch::{MembershipAction, MembershipChange};
+use epoch_coordinator::memory::InMemoryEpochStore;
+use log::{error, info};
+use maroon::app::{Params, Request};
 use maroon::metrics;
+use maroon::network::Allowlist;
+use maroon::object_store::LocalDirStore;
+use maroon::stack::EpochCoordinatorBackend;
+use protocol::wire::Encoding;
 use schema::mn_events::{LogEvent, LogEventBody, now_microsec};
-use std::future;
 use std::num::NonZeroUsize;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -13,7 +20,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let node_urls: Vec<String> =
     std::env::var("NODE_URLS").map_err(|e| format!("NODE_URLS not set: {}", e))?.split(',').map(String::from).collect();
   let etcd_urls: Vec<String> =
-    std::env::var("ETCD_URLS").map_err(|e| format!("ETCD_URLS not set: {}", e))?.split(',').map(String::from).collect();
+    std::env::var("ETCD_URLS").unwrap_or_default().split(',').filter(|u| !u.is_empty()).map(String::from).collect();
 
   let self_url: String = std::env::var("SELF_URL").map_err(|e| format!("SELF_URL not set: {}", e))?;
 
@@ -26,20 +33,60 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     .unwrap()
     .unwrap();
 
-  let params = Params::default().set_consensus_nodes(consensus_nodes);
+  let mut params = Params::default().set_consensus_nodes(consensus_nodes);
+  if let Ok(wal_dir) = std::env::var("WAL_DIR") {
+    params = params.set_wal_dir(wal_dir);
+  }
+  if let Ok(snapshot_dir) = std::env::var("SNAPSHOT_DIR") {
+    let every_epochs = std::env::var("SNAPSHOT_EVERY_EPOCHS").unwrap_or("1000".to_string()).parse::<u64>()?;
+    params = params.set_snapshots(Arc::new(LocalDirStore::new(snapshot_dir)), every_epochs);
+  }
+  if let Ok(key_file) = std::env::var("KEY_FILE") {
+    params = params.set_key_file(key_file);
+  }
+  // only these peers can connect once any of the lists is set
+  let allowed_nodes = std::env::var("ALLOWED_NODES");
+  let allowed_gateways = std::env::var("ALLOWED_GATEWAYS");
+  if allowed_nodes.is_ok() || allowed_gateways.is_ok() {
+    params = params
+      .set_allowlist(Allowlist::parse(&allowed_nodes.unwrap_or_default(), &allowed_gateways.unwrap_or_default())?);
+  }
+  // until all the nodes and gateways of the cluster understand binary gossip
+  if std::env::var("GOSSIP_ENCODING").is_ok_and(|encoding| encoding == "json") {
+    params = params.set_gossip_encoding(Encoding::Json);
+  }
+
+  let coordinator_backend = if etcd_urls.is_empty() {
+    // every process would have its own epoch history, so only a single node can run without etcd
+    let single_node = consensus_nodes.get() == 1 || node_urls.iter().all(|url| *url == self_url);
+    if !single_node {
+      return Err("ETCD_URLS is empty, it can be empty only for a single node(CONSENSUS_NODES=1)".into());
+    }
+    info!("ETCD_URLS is empty, epochs will be stored in memory of this process");
+    EpochCoordinatorBackend::InMemory(InMemoryEpochStore::new())
+  } else {
+    EpochCoordinatorBackend::Etcd(etcd_urls)
+  };
 
-  let (maroon_stack, _stack_remote_control) = maroon::stack::MaroonStack::new(node_urls, etcd_urls, self_url, params)?;
+  let (maroon_stack, stack_remote_control) =
+    maroon::stack::MaroonStack::new(node_urls, coordinator_backend, self_url, params)?;
   let meter_provider = metrics::init_meter_provider(maroon_stack.id)?;
 
   let id = maroon_stack.id;
-  let _shutdown = maroon_stack.start();
+  let running_stack = maroon_stack.start();
+
+  // node asks the members to add it, the change is committed by them in one of the next epochs
+  if std::env::var("JOIN_MEMBERSHIP").is_ok_and(|join| join == "true") {
+    let join = MembershipChange { node: id, action: MembershipAction::Add, effective_from: 0 };
+    info!("ask to join the membership: {join}");
+    stack_remote_control.state_invoker.request(Request::ChangeMembership(join)).await;
+  }
 
   state_log::log(LogEvent { timestamp_micros: now_microsec(), emitter: id, body: LogEventBody::MaroonNodeUp });
 
-  // TODO: implement proper shutdown
-  // forever pause current state in order to prevent killing the process
-  // later will be replaced with something else. Don't know with what
-  future::pending::<()>().await;
+  wait_for_stop_signal().await?;
+  info!("got stop signal, shutting down");
+  running_stack.shutdown().await;
 
   if let Err(e) = meter_provider.shutdown() {
     error!("meter provider shutdown: {e}");
@@ -48,3 +95,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   state_log::log(LogEvent { timestamp_micros: now_microsec(), emitter: id, body: LogEventBody::MaroonNodeDown });
   Ok(())
 }
+
+/// SIGTERM from orchestrator or SIGINT from terminal
+async fn wait_for_stop_signal() -> Result<(), Box<dyn std::error::Error>> {
+  let mut sigterm = signal(SignalKind::terminate())?;
+  tokio::select! {
+    _ = sigterm.recv() => {},
+    res = tokio::signal::ctrl_c() => res?,
+  }
+  Ok(())
+}
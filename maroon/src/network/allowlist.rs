@@ -0,0 +1,79 @@
+use libp2p::PeerId;
+use protocol::meta_exchange::Role;
+use std::collections::HashSet;
+
+/// peers the node talks to, by their `PeerId`s <br>
+/// ed25519 `PeerId` contains the public key and noise proves the peer owns it, so a peer can't pretend to be another one
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Allowlist {
+  /// any peer is allowed, for development and tests
+  #[default]
+  Open,
+  Peers {
+    nodes: HashSet<PeerId>,
+    gateways: HashSet<PeerId>,
+  },
+}
+
+impl Allowlist {
+  /// comma separated peer ids of nodes and gateways, ex: from `keygen --peer-id`
+  pub fn parse(
+    nodes: &str,
+    gateways: &str,
+  ) -> Result<Allowlist, Box<dyn std::error::Error>> {
+    Ok(Allowlist::Peers { nodes: parse_peer_ids(nodes)?, gateways: parse_peer_ids(gateways)? })
+  }
+
+  /// the peer can connect in one of the roles
+  pub fn knows(
+    &self,
+    peer: &PeerId,
+  ) -> bool {
+    match self {
+      Allowlist::Open => true,
+      Allowlist::Peers { nodes, gateways } => nodes.contains(peer) || gateways.contains(peer),
+    }
+  }
+
+  pub fn allows(
+    &self,
+    peer: &PeerId,
+    role: &Role,
+  ) -> bool {
+    match (self, role) {
+      (Allowlist::Open, _) => true,
+      (Allowlist::Peers { nodes, .. }, Role::Node) => nodes.contains(peer),
+      (Allowlist::Peers { gateways, .. }, Role::Gateway) => gateways.contains(peer),
+    }
+  }
+}
+
+fn parse_peer_ids(list: &str) -> Result<HashSet<PeerId>, Box<dyn std::error::Error>> {
+  list
+    .split(',')
+    .map(str::trim)
+    .filter(|id| !id.is_empty())
+    .map(|id| id.parse::<PeerId>().map_err(|e| format!("peer id {id}: {e}").into()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn allowlist_checks_roles() {
+    let (node, gateway, stranger) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let allowlist = Allowlist::parse(&format!("{node}, "), &gateway.to_string()).unwrap();
+
+    assert!(allowlist.knows(&node) && allowlist.knows(&gateway));
+    assert!(!allowlist.knows(&stranger));
+    assert!(allowlist.allows(&node, &Role::Node));
+    assert!(!allowlist.allows(&node, &Role::Gateway));
+    assert!(!allowlist.allows(&gateway, &Role::Node));
+    assert!(Allowlist::Open.allows(&stranger, &Role::Node));
+
+    assert!(Allowlist::parse("not a peer id", "").is_err());
+    assert_eq!(Allowlist::Peers { nodes: HashSet::new(), gateways: HashSet::new() }, Allowlist::parse("", "").unwrap());
+  }
+}
@@ -1,6 +1,10 @@
-use common::range_key::{KeyOffset, KeyRange, U64BlobIdClosedInterval};
+use common::range_key::{KeyOffset, KeyRange, U64BlobIdClosedInterval, UniqueU64BlobId};
+use epoch_coordinator::epoch::{LeaseRequest, MembershipChange};
 use libp2p::PeerId;
+use protocol::m2m_request_response::{TxPage, TxPageRequest};
+use protocol::meta_exchange::{Capabilities, Compatibility, Role};
 use protocol::{node2gw::TxUpdate, transaction::Transaction};
+use runtime::runtime::StateDigest;
 use serde::{Deserialize, Serialize};
 use std::{
   collections::{HashMap, HashSet},
@@ -13,12 +17,29 @@ use std::{
 pub enum Outbox {
   State(NodeState),
 
-  RequestMissingTxs((PeerId, Vec<U64BlobIdClosedInterval>)),
+  /// asks the peer for a page of transactions the node doesn't have
+  RequestTxPage((PeerId, TxPageRequest)),
+  /// answer to `Inbox::GetTxPage` with the same query id and the id of the request,
+  /// `None` - the peer has asked for too many pages
+  TxPage((u64, u64, Option<TxPage>)),
+  /// answer to the nodes that don't support pages
   RequestedTxsForPeer((PeerId, Vec<Transaction>)),
 
   // send updates on transactions. any update: status change, got results, etc...
   // my idea right now is that node will send this update once, if gateway was down during this period - it needs to request the status itself
   NotifyGWs(Vec<TxUpdate>),
+
+  // answer to the gateway's lease request once the lease is committed
+  KeyRangeLeased((PeerId, KeyRange)),
+
+  // answer to the gateway's `GetTxStatus` query with the same id
+  TxStatuses((u64, Vec<TxUpdate>)),
+
+  // gossip runtime state digests of the recently executed epochs, so other nodes can check they've got the same state
+  Digests(Vec<EpochDigest>),
+
+  // gossip membership changes that haven't been committed yet, so they get to the nodes that publish epochs
+  MembershipChanges(Vec<MembershipChange>),
 }
 
 /// Input for the layer that lives on top of p2p layer. Output for p2p Layer
@@ -29,8 +50,35 @@ pub enum Inbox {
   Nodes(HashSet<PeerId>),
   NewTransaction(Transaction),
 
+  /// request of the nodes that don't support pages, it's answered with one page
   RequestMissingTxs((PeerId, Vec<U64BlobIdClosedInterval>)),
   MissingTx(Vec<Transaction>),
+
+  /// peer asks for a page of transactions, the answer should go with the same query id
+  GetTxPage((u64, PeerId, TxPageRequest)),
+  /// answer on `Outbox::RequestTxPage`
+  TxPage((PeerId, TxPage)),
+  /// the peer doesn't serve pages to this node for now
+  TxPageThrottled((PeerId, u64)),
+
+  Digests((PeerId, Vec<EpochDigest>)),
+
+  LeaseKeyRange(LeaseRequest),
+  MembershipChanges(Vec<MembershipChange>),
+
+  /// gateway asks for statuses of its transactions, the answer should go with the same query id
+  GetTxStatus((u64, Vec<UniqueU64BlobId>)),
+
+  /// peer has told what it understands in meta exchange
+  PeerCompatibility((PeerId, PeerCompatibility)),
+}
+
+/// what a peer has told about itself in meta exchange and how the node talks to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerCompatibility {
+  pub role: Role,
+  pub capabilities: Capabilities,
+  pub compatibility: Compatibility,
 }
 
 // Node state
@@ -38,3 +86,10 @@ pub enum Inbox {
 pub struct NodeState {
   pub offsets: HashMap<KeyRange, KeyOffset>,
 }
+
+/// runtime state digest of the node after executing the epoch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EpochDigest {
+  pub sequence_number: u64,
+  pub digest: StateDigest,
+}
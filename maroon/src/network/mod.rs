@@ -1,5 +1,7 @@
+pub mod allowlist;
 pub mod interface;
 pub mod p2p;
 
-pub use interface::{Inbox, NodeState, Outbox};
+pub use allowlist::Allowlist;
+pub use interface::{EpochDigest, Inbox, NodeState, Outbox, PeerCompatibility};
 pub use p2p::P2P;
@@ -1,7 +1,9 @@
+use super::allowlist::Allowlist;
 use super::interface::{Inbox, Outbox};
-use crate::network::interface::NodeState;
+use crate::network::interface::{EpochDigest, NodeState, PeerCompatibility};
 use common::duplex_channel::Endpoint;
 use derive_more::From;
+use epoch_coordinator::epoch::{LeaseRequest, MembershipChange};
 use futures::StreamExt;
 use libp2p::dns::Transport as DnsTransport;
 use libp2p::{
@@ -9,7 +11,7 @@ use libp2p::{
   core::{transport::Transport as _, upgrade},
   gossipsub::{
     Behaviour as GossipsubBehaviour, ConfigBuilder as GossipsubConfigBuilder, Event as GossipsubEvent,
-    MessageAuthenticity, Sha256Topic, TopicHash, ValidationMode,
+    MessageAcceptance, MessageAuthenticity, Sha256Topic, TopicHash, ValidationMode,
   },
   identity,
   noise::{Config as NoiseConfig, Error as NoiseError},
@@ -18,7 +20,7 @@ use libp2p::{
   tcp::{Config as TcpConfig, tokio::Transport as TcpTokioTransport},
   yamux::Config as YamuxConfig,
 };
-use libp2p_request_response::{Message as RequestResponseMessage, ProtocolSupport};
+use libp2p_request_response::{Message as RequestResponseMessage, ProtocolSupport, ResponseChannel};
 use log::{debug, error, info, warn};
 use opentelemetry::{KeyValue, global, metrics::Counter};
 use protocol::gm_request_response::{
@@ -28,20 +30,47 @@ use protocol::m2m_request_response::{
   self, Behaviour as M2MBehaviour, Event as M2MEvent, Request as M2MRequest, Response as M2MResponse,
 };
 use protocol::meta_exchange::{
-  self, Behaviour as MetaExchangeBehaviour, Event as MEEvent, Request as MERequest, Response as MEResponse, Role,
+  self, Behaviour as MetaExchangeBehaviour, Capabilities, Compatibility, Event as MEEvent, Request as MERequest,
+  Response as MEResponse, Role, features,
 };
 use protocol::node2gw::{GossipMessage as N2GWGossipMessage, GossipPayload as N2GWGossipPayload, node2gw_topic_hash};
+use protocol::wire::{self, Encoding};
 use schema::mn_events::{LogEvent, LogEventBody, now_microsec};
 use serde::{Deserialize, Serialize};
 use std::sync::OnceLock;
-use std::{collections::HashSet, fmt::Debug, time::Duration};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Debug,
+  time::Duration,
+};
 use tokio::sync::mpsc::UnboundedSender;
 
+/// gateway requests that are answered by the app later
+#[derive(Default)]
+struct PendingResponses {
+  /// gateways wait for the answer on their lease requests until the lease is committed
+  leases: HashMap<PeerId, Vec<ResponseChannel<GMResponse>>>,
+  /// tx status queries by their id
+  tx_statuses: HashMap<u64, ResponseChannel<GMResponse>>,
+  /// pages of transactions other nodes wait for by their query id
+  tx_pages: HashMap<u64, ResponseChannel<M2MResponse>>,
+  next_query_id: u64,
+}
+
+const NODE_P2P_TOPIC: &str = "node-p2p";
+/// how long the swarm keeps running after leaving, so peers get the unsubscription and the last messages
+const LEAVE_LINGER: Duration = Duration::from_millis(500);
+
 fn counter_requests() -> &'static Counter<u64> {
   static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
   COUNTER.get_or_init(|| global::meter("p2p_network").u64_counter("requests").build())
 }
 
+fn counter_refused() -> &'static Counter<u64> {
+  static COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
+  COUNTER.get_or_init(|| global::meter("p2p_network").u64_counter("refused_peers").build())
+}
+
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "MaroonEvent")]
 struct MaroonBehaviour {
@@ -61,6 +90,40 @@ pub enum MaroonEvent {
   M2MReqRes(M2MEvent),
 }
 
+/// peers the node talks to
+#[derive(Default)]
+struct Peers {
+  /// nodes that have passed meta exchange, including this one
+  nodes: HashSet<PeerId>,
+  gateways: HashSet<PeerId>,
+  /// capabilities of the connected peers
+  capabilities: HashMap<PeerId, Capabilities>,
+  allowlist: Allowlist,
+}
+
+impl Peers {
+  /// binary gossip is sent to the topic only if all of its receivers can decode it
+  fn gossip_encoding(
+    &self,
+    receivers: &HashSet<PeerId>,
+    configured: Encoding,
+  ) -> Encoding {
+    // receiver that hasn't finished meta exchange yet might be a legacy one
+    let all_binary =
+      receivers.iter().all(|peer| self.capabilities.get(peer).is_some_and(|c| c.supports(features::BINARY_GOSSIP)));
+    if all_binary { configured } else { Encoding::Json }
+  }
+}
+
+/// where and how the node gossips
+struct Gossip {
+  node_p2p_topic: TopicHash,
+  /// topic for broadcasting node messages to gateways
+  node_2_gw_topic: TopicHash,
+  /// is used unless some of the receivers can't decode it
+  encoding: Encoding,
+}
+
 pub struct P2P {
   pub peer_id: PeerId,
 
@@ -68,10 +131,8 @@ pub struct P2P {
   self_url: String,
 
   swarm: Swarm<MaroonBehaviour>,
-  node_p2p_topic: TopicHash,
-
-  // topic for broadcasting node messages to gateways
-  node_2_gw_topic: TopicHash,
+  gossip: Gossip,
+  allowlist: Allowlist,
 
   interface_endpoint: Endpoint<Inbox, Outbox>,
 }
@@ -80,9 +141,9 @@ impl P2P {
   pub fn new(
     node_urls: Vec<String>,
     self_url: String,
+    kp: identity::Keypair,
     interface_endpoint: Endpoint<Inbox, Outbox>,
   ) -> Result<P2P, Box<dyn std::error::Error>> {
-    let kp = identity::Keypair::generate_ed25519();
     let peer_id = PeerId::from(kp.public());
     info!("Local peer id: {:?}", peer_id);
 
@@ -100,13 +161,15 @@ impl P2P {
         .mesh_outbound_min(1)
         .mesh_n_low(1)
         .mesh_n(2)
-        .validation_mode(ValidationMode::Permissive)
+        // messages are signed by their authors and checked against the allowlist before they're forwarded
+        .validation_mode(ValidationMode::Strict)
+        .validate_messages()
         .build()
         .map_err(|e| format!("gossipsub config builder: {e}"))?,
     )
     .map_err(|e| format!("gossipsub behaviour creation: {e}"))?;
 
-    let node_p2p_topic = Sha256Topic::new("node-p2p");
+    let node_p2p_topic = Sha256Topic::new(NODE_P2P_TOPIC);
     gossipsub.subscribe(&node_p2p_topic)?;
 
     let behaviour = MaroonBehaviour {
@@ -131,12 +194,32 @@ impl P2P {
       self_url,
       peer_id,
       swarm,
-      node_p2p_topic: node_p2p_topic.hash().clone(),
-      node_2_gw_topic: node2gw_topic_hash(),
+      gossip: Gossip {
+        node_p2p_topic: node_p2p_topic.hash().clone(),
+        node_2_gw_topic: node2gw_topic_hash(),
+        encoding: Encoding::default(),
+      },
+      allowlist: Allowlist::default(),
       interface_endpoint,
     })
   }
 
+  pub fn set_gossip_encoding(
+    mut self,
+    encoding: Encoding,
+  ) -> P2P {
+    self.gossip.encoding = encoding;
+    self
+  }
+
+  pub fn set_allowlist(
+    mut self,
+    allowlist: Allowlist,
+  ) -> P2P {
+    self.allowlist = allowlist;
+    self
+  }
+
   /// starts listening and performs all the bindings but doesn't react yeat
   pub fn prepare(&mut self) -> Result<(), Box<dyn std::error::Error>> {
     println!("URL: {}", self.self_url);
@@ -157,12 +240,13 @@ impl P2P {
 
   /// blocking operation, so you might want to spawn it on a separate thread
   /// after calling this - channels at `interface_channels` will start to send messages
-  /// TODO: add stop/finish channel
+  /// returns once the app has dropped its side of the interface and everything it has sent is handled
   pub async fn start_event_loop(self) {
-    let mut alive_peer_ids: HashSet<PeerId> = HashSet::new();
-    let mut alive_gateway_ids: HashSet<PeerId> = HashSet::new();
+    let mut peers = Peers { allowlist: self.allowlist, ..Peers::default() };
+    let mut pending_responses = PendingResponses::default();
 
-    alive_peer_ids.insert(self.peer_id);
+    peers.nodes.insert(self.peer_id);
+    peers.capabilities.insert(self.peer_id, Capabilities::current());
     let mut swarm = self.swarm;
 
     let mut receiver = self.interface_endpoint.receiver;
@@ -170,14 +254,17 @@ impl P2P {
 
     loop {
       tokio::select! {
-          Some(outbox) = receiver.recv() => {
+          outbox = receiver.recv() => {
+              let Some(outbox) = outbox else {
+                break;
+              };
               handle_receiver_outbox(
                   &mut swarm,
                   outbox,
                   self.peer_id,
-                  self.node_p2p_topic.clone(),
-                  self.node_2_gw_topic.clone(),
-                  &alive_gateway_ids,
+                  &self.gossip,
+                  &peers,
+                  &mut pending_responses,
               );
           },
           event = swarm.select_next_some() => {
@@ -185,76 +272,160 @@ impl P2P {
                   &mut swarm,
                   event,
                   &to_app,
-                  &mut alive_peer_ids,
-                  &mut alive_gateway_ids,
+                  &mut peers,
+                  &mut pending_responses,
                   self.peer_id,
               );
           }
       }
     }
+
+    leave(&mut swarm).await;
   }
 }
 
+/// unsubscribes from nodes gossip, so other nodes don't keep this one in their meshes
+async fn leave(swarm: &mut Swarm<MaroonBehaviour>) {
+  info!("leave gossipsub");
+  swarm.behaviour_mut().gossipsub.unsubscribe(&Sha256Topic::new(NODE_P2P_TOPIC));
+  // events aren't handled anymore, swarm only sends what has been queued
+  let linger = async {
+    loop {
+      swarm.select_next_some().await;
+    }
+  };
+  _ = tokio::time::timeout(LEAVE_LINGER, linger).await;
+}
+
 fn handle_receiver_outbox(
   swarm: &mut Swarm<MaroonBehaviour>,
   outbox_message: Outbox,
   peer_id: PeerId,
-  node_p2p_topic: TopicHash,
-  node_2_gw_topic: TopicHash,
-  alive_gateway_ids: &HashSet<PeerId>,
+  gossip: &Gossip,
+  peers: &Peers,
+  pending_responses: &mut PendingResponses,
 ) {
   match outbox_message {
     Outbox::State(state) => {
-      let message = GossipMessage { peer_id: peer_id, payload: GossipPayload::State(state) };
-
-      let bytes = guard_ok!(serde_json::to_vec(&message), e, {
-        error!("serialize message error: {e}");
+      publish_to_nodes(swarm, gossip, peers, GossipMessage { peer_id, payload: GossipPayload::State(state) });
+    }
+    Outbox::Digests(digests) => {
+      publish_to_nodes(swarm, gossip, peers, GossipMessage { peer_id, payload: GossipPayload::Digests(digests) });
+    }
+    Outbox::MembershipChanges(changes) => {
+      publish_to_nodes(
+        swarm,
+        gossip,
+        peers,
+        GossipMessage { peer_id, payload: GossipPayload::MembershipChanges(changes) },
+      );
+    }
+    Outbox::RequestTxPage((peer_id, request)) => {
+      let supports_pages = peers.capabilities.get(&peer_id).is_some_and(|c| c.supports(features::TX_PAGES));
+      let request =
+        if supports_pages { M2MRequest::GetTxPage(request) } else { M2MRequest::GetMissingTx(request.intervals) };
+      swarm.behaviour_mut().m2m_req_res.send_request(&peer_id, request);
+    }
+    Outbox::TxPage((query_id, request_id, page)) => {
+      let Some(channel) = pending_responses.tx_pages.remove(&query_id) else {
         return;
-      });
-
-      if let Err(e) = swarm.behaviour_mut().gossipsub.publish(node_p2p_topic, bytes) {
-        warn!("gossip broadcast error: {}", e);
+      };
+      let response = page.map_or(M2MResponse::Throttled(request_id), M2MResponse::TxPage);
+      if swarm.behaviour_mut().m2m_req_res.send_response(channel, response).is_err() {
+        debug!("node has gone before getting its page");
       }
     }
-    Outbox::RequestMissingTxs((peer_id, ranges)) => {
-      swarm.behaviour_mut().m2m_req_res.send_request(&peer_id, M2MRequest::GetMissingTx(ranges));
-    }
     Outbox::RequestedTxsForPeer((peer_id, missing_txs)) => {
       swarm.behaviour_mut().m2m_req_res.send_request(&peer_id, M2MRequest::MissingTx(missing_txs));
     }
+    Outbox::KeyRangeLeased((gateway, range)) => {
+      for channel in pending_responses.leases.remove(&gateway).unwrap_or_default() {
+        if swarm.behaviour_mut().request_response.send_response(channel, GMResponse::KeyRangeLeased(range)).is_err() {
+          debug!("gateway {gateway} has gone before getting its lease");
+        }
+      }
+    }
+    Outbox::TxStatuses((query_id, updates)) => {
+      let Some(channel) = pending_responses.tx_statuses.remove(&query_id) else {
+        return;
+      };
+      if swarm.behaviour_mut().request_response.send_response(channel, GMResponse::TxStatuses(updates)).is_err() {
+        debug!("gateway has gone before getting tx statuses");
+      }
+    }
     Outbox::NotifyGWs(tx_updates) => {
-      if alive_gateway_ids.len() == 0 {
+      if peers.gateways.is_empty() {
         return;
       }
       let message = N2GWGossipMessage { peer_id: peer_id, payload: N2GWGossipPayload::Node2GWTxUpdate(tx_updates) };
 
-      let bytes = guard_ok!(serde_json::to_vec(&message), e, {
+      let encoding = peers.gossip_encoding(&peers.gateways, gossip.encoding);
+      let bytes = guard_ok!(wire::encode_gossip(encoding, &message), e, {
         error!("serialize message error: {e}");
         return;
       });
-      if let Err(e) = swarm.behaviour_mut().gossipsub.publish(node_2_gw_topic, bytes) {
+      if let Err(e) = swarm.behaviour_mut().gossipsub.publish(gossip.node_2_gw_topic.clone(), bytes) {
         warn!("gossip node2gw broadcast error: {}", e);
       }
     }
   }
 }
 
+fn publish_to_nodes(
+  swarm: &mut Swarm<MaroonBehaviour>,
+  gossip: &Gossip,
+  peers: &Peers,
+  message: GossipMessage,
+) {
+  let encoding = peers.gossip_encoding(&peers.nodes, gossip.encoding);
+  let bytes = guard_ok!(wire::encode_gossip(encoding, &message), e, {
+    error!("serialize message error: {e}");
+    return;
+  });
+
+  if let Err(e) = swarm.behaviour_mut().gossipsub.publish(gossip.node_p2p_topic.clone(), bytes) {
+    warn!("gossip broadcast error: {}", e);
+  }
+}
+
 fn handle_swarm_event(
   swarm: &mut Swarm<MaroonBehaviour>,
   event: SwarmEvent<MaroonEvent>,
   to_app: &UnboundedSender<Inbox>,
-  alive_peer_ids: &mut HashSet<PeerId>,
-  alive_gateway_ids: &mut HashSet<PeerId>,
+  peers: &mut Peers,
+  pending_responses: &mut PendingResponses,
   id: PeerId,
 ) {
   match event {
-    SwarmEvent::Behaviour(MaroonEvent::Gossipsub(GossipsubEvent::Message { message, .. })) => {
+    SwarmEvent::Behaviour(MaroonEvent::Gossipsub(GossipsubEvent::Message {
+      propagation_source,
+      message_id,
+      message,
+    })) => {
       counter_requests().add(1, &[KeyValue::new("type", "gossip")]);
-      match serde_json::from_slice::<GossipMessage>(&message.data) {
+      let decoded = wire::decode_gossip::<GossipMessage>(&message.data);
+      let valid = match &decoded {
+        Ok(p2p_message) => {
+          validate_gossip(&peers.allowlist, message.source, p2p_message).map_err(|r| refused(id, r)).is_ok()
+        }
+        Err(_) => false,
+      };
+      // invalid messages aren't forwarded and their propagation source is penalized
+      let acceptance = if valid { MessageAcceptance::Accept } else { MessageAcceptance::Reject };
+      swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance);
+
+      match decoded {
+        Ok(_) if !valid => {}
         Ok(p2p_message) => match p2p_message.payload {
           GossipPayload::State(state) => {
             _ = to_app.send(Inbox::State((p2p_message.peer_id, state)));
           }
+          GossipPayload::Digests(digests) => {
+            _ = to_app.send(Inbox::Digests((p2p_message.peer_id, digests)));
+          }
+          GossipPayload::MembershipChanges(changes) => {
+            _ = to_app.send(Inbox::MembershipChanges(changes));
+          }
         },
         Err(e) => {
           error!("swarm deserialize: {e}");
@@ -263,32 +434,43 @@ fn handle_swarm_event(
     }
     SwarmEvent::Behaviour(MaroonEvent::MetaExchange(meta_exchange)) => {
       counter_requests().add(1, &[KeyValue::new("type", "meta_exchange")]);
-      handle_meta_exchange(id, swarm, meta_exchange, alive_peer_ids, alive_gateway_ids, to_app);
+      handle_meta_exchange(id, swarm, meta_exchange, peers, to_app);
     }
     SwarmEvent::Behaviour(MaroonEvent::Ping(PingEvent { .. })) => {
       // TODO: have an idea to use result.duration for calculating logical time between nodes. let's see
     }
     SwarmEvent::Behaviour(MaroonEvent::RequestResponse(gm_request_response)) => {
       counter_requests().add(1, &[KeyValue::new("type", "request_response")]);
-      handle_request_response(swarm, &to_app, gm_request_response);
+      handle_request_response(swarm, &to_app, peers, pending_responses, gm_request_response);
     }
     SwarmEvent::Behaviour(MaroonEvent::M2MReqRes(m2m_request_response)) => {
       counter_requests().add(1, &[KeyValue::new("type", "m2m_request_response")]);
-      handle_m2m_req_res(swarm, &to_app, m2m_request_response);
+      handle_m2m_req_res(swarm, &to_app, peers, pending_responses, m2m_request_response);
     }
     SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-      swarm.behaviour_mut().meta_exchange.send_request(&peer_id, MERequest { role: Role::Node });
+      if !peers.allowlist.knows(&peer_id) {
+        refused(id, Refusal::new(peer_id, "unknown_peer", "not in the allowlist".to_string()));
+        _ = swarm.disconnect_peer_id(peer_id);
+        return;
+      }
+      swarm
+        .behaviour_mut()
+        .meta_exchange
+        .send_request(&peer_id, MERequest { role: Role::Node, capabilities: Capabilities::current() });
     }
     SwarmEvent::ConnectionClosed { peer_id, .. } => {
-      if alive_gateway_ids.remove(&peer_id) {
+      peers.capabilities.remove(&peer_id);
+      // gateway is gone, its lease requests can't be answered anymore
+      pending_responses.leases.remove(&peer_id);
+      if peers.gateways.remove(&peer_id) {
         state_log::log(LogEvent {
           timestamp_micros: now_microsec(),
           emitter: id,
           body: LogEventBody::GatewayDisconnected { gid: peer_id },
         });
       }
-      if alive_peer_ids.remove(&peer_id) {
-        _ = to_app.send(Inbox::Nodes(alive_peer_ids.clone()));
+      if peers.nodes.remove(&peer_id) {
+        _ = to_app.send(Inbox::Nodes(peers.nodes.clone()));
       }
     }
     SwarmEvent::OutgoingConnectionError { peer_id, connection_id, error } => {
@@ -301,14 +483,30 @@ fn handle_swarm_event(
 fn handle_m2m_req_res(
   swarm: &mut Swarm<MaroonBehaviour>,
   to_app: &UnboundedSender<Inbox>,
+  peers: &Peers,
+  pending_responses: &mut PendingResponses,
   m2m_request_response: M2MEvent,
 ) {
   let M2MEvent::Message { message, peer, .. } = m2m_request_response else {
     return;
   };
-
-  let RequestResponseMessage::Request { request, channel, .. } = message else {
+  // transactions are taken only from the allowed nodes, the channel is dropped without an answer
+  if !peers.allowlist.allows(&peer, &Role::Node) {
+    counter_refused().add(1, &[KeyValue::new("reason", "m2m_request")]);
+    warn!("ignore m2m message from {peer}, it isn't an allowed node");
     return;
+  }
+
+  let (request, channel) = match message {
+    RequestResponseMessage::Request { request, channel, .. } => (request, channel),
+    RequestResponseMessage::Response { response, .. } => {
+      match response {
+        M2MResponse::Ack => {}
+        M2MResponse::TxPage(page) => _ = to_app.send(Inbox::TxPage((peer, page))),
+        M2MResponse::Throttled(request_id) => _ = to_app.send(Inbox::TxPageThrottled((peer, request_id))),
+      }
+      return;
+    }
   };
 
   match request {
@@ -316,6 +514,14 @@ fn handle_m2m_req_res(
       to_app.send(Inbox::RequestMissingTxs((peer, ranges))).expect("TODO: shouldnt panic?")
     }
     M2MRequest::MissingTx(missing_txs) => to_app.send(Inbox::MissingTx(missing_txs)).expect("TODO: shouldnt panic?"),
+    M2MRequest::GetTxPage(request) => {
+      // answered once the app has built the page
+      let query_id = pending_responses.next_query_id;
+      pending_responses.next_query_id += 1;
+      pending_responses.tx_pages.insert(query_id, channel);
+      _ = to_app.send(Inbox::GetTxPage((query_id, peer, request)));
+      return;
+    }
   }
 
   _ = swarm.behaviour_mut().m2m_req_res.send_response(channel, M2MResponse::Ack);
@@ -324,12 +530,19 @@ fn handle_m2m_req_res(
 fn handle_request_response(
   swarm: &mut Swarm<MaroonBehaviour>,
   to_app: &UnboundedSender<Inbox>,
+  peers: &Peers,
+  pending_responses: &mut PendingResponses,
   gm_request_response: GMEvent,
 ) {
   match gm_request_response {
-    GMEvent::Message { message, .. } => match message {
+    GMEvent::Message { message, peer, .. } => match message {
       RequestResponseMessage::Request { request_id, request, channel } => {
         debug!("Got request: {:?}, {:?}", request_id, request);
+        if !peers.allowlist.allows(&peer, &Role::Gateway) {
+          counter_refused().add(1, &[KeyValue::new("reason", "gateway_request")]);
+          warn!("ignore request from {peer}, it isn't an allowed gateway");
+          return;
+        }
 
         match request {
           GMRequest::NewTransaction(tx) => {
@@ -337,6 +550,16 @@ fn handle_request_response(
 
             _ = swarm.behaviour_mut().request_response.send_response(channel, GMResponse::Acknowledged);
           }
+          GMRequest::LeaseKeyRange(replaces) => {
+            pending_responses.leases.entry(peer).or_default().push(channel);
+            _ = to_app.send(Inbox::LeaseKeyRange(LeaseRequest { gateway: peer, replaces }));
+          }
+          GMRequest::GetTxStatus(ids) => {
+            let query_id = pending_responses.next_query_id;
+            pending_responses.next_query_id += 1;
+            pending_responses.tx_statuses.insert(query_id, channel);
+            _ = to_app.send(Inbox::GetTxStatus((query_id, ids)));
+          }
         }
       }
       _ => {}
@@ -349,17 +572,49 @@ fn handle_meta_exchange(
   id: PeerId,
   swarm: &mut Swarm<MaroonBehaviour>,
   meta_exchange: MEEvent,
-  alive_node_ids: &mut HashSet<PeerId>,
-  alive_gateway_ids: &mut HashSet<PeerId>,
+  peers: &mut Peers,
   to_app: &UnboundedSender<Inbox>,
 ) {
   let MEEvent::Message { message, peer, .. } = meta_exchange else {
     return;
   };
 
-  let mut insert_by_role = |role: Role| match role {
+  let (role, capabilities, channel) = match message {
+    RequestResponseMessage::Response { response, .. } => (response.role, response.capabilities, None),
+    RequestResponseMessage::Request { channel, request, .. } => (request.role, request.capabilities, Some(channel)),
+  };
+
+  // key of a gateway doesn't make a node and vice versa, refused peer doesn't get an answer
+  if !peers.allowlist.allows(&peer, &role) {
+    refused(id, Refusal::new(peer, "wrong_role", format!("not allowed as {role:?}")));
+    _ = swarm.disconnect_peer_id(peer);
+    return;
+  }
+  if let Some(channel) = channel {
+    let response = MEResponse { role: Role::Node, capabilities: Capabilities::current() };
+    let res = swarm.behaviour_mut().meta_exchange.send_response(channel, response);
+    debug!("MetaExchangeRequestRes: {:?}", res);
+  }
+
+  let compatibility = Capabilities::current().check(&capabilities);
+  _ = to_app.send(Inbox::PeerCompatibility((
+    peer,
+    PeerCompatibility { role: role.clone(), capabilities: capabilities.clone(), compatibility: compatibility.clone() },
+  )));
+  match &compatibility {
+    Compatibility::Full => {}
+    Compatibility::Degraded(missing) => info!("{role:?} {peer} doesn't support {missing:?}"),
+    Compatibility::Incompatible(reason) => {
+      refused(id, Refusal::new(peer, "incompatible", format!("{role:?}: {reason}")));
+      _ = swarm.disconnect_peer_id(peer);
+      return;
+    }
+  }
+  peers.capabilities.insert(peer, capabilities);
+
+  match role {
     Role::Gateway => {
-      alive_gateway_ids.insert(peer);
+      peers.gateways.insert(peer);
       state_log::log(LogEvent {
         timestamp_micros: now_microsec(),
         emitter: id,
@@ -367,21 +622,81 @@ fn handle_meta_exchange(
       });
     }
     Role::Node => {
-      alive_node_ids.insert(peer);
-      _ = to_app.send(Inbox::Nodes(alive_node_ids.clone()));
+      peers.nodes.insert(peer);
+      _ = to_app.send(Inbox::Nodes(peers.nodes.clone()));
     }
+  }
+}
+
+/// gossip is taken only from the allowed nodes and only about themselves <br>
+/// `source` is the signer of the message, strict validation has checked the signature
+fn validate_gossip(
+  allowlist: &Allowlist,
+  source: Option<PeerId>,
+  p2p_message: &GossipMessage,
+) -> Result<(), Refusal> {
+  let Some(source) = source else {
+    return Err(Refusal::new(p2p_message.peer_id, "unsigned", "gossip isn't signed".to_string()));
   };
+  if !allowlist.allows(&source, &Role::Node) {
+    return Err(Refusal::new(source, "unknown_author", "gossip from unknown node".to_string()));
+  }
+  if p2p_message.peer_id != source {
+    return Err(Refusal::new(source, "foreign_author", format!("gossip on behalf of {}", p2p_message.peer_id)));
+  }
+  if let GossipPayload::MembershipChanges(changes) = &p2p_message.payload
+    && let Some(change) = changes.iter().find(|c| !allowlist.allows(&c.node, &Role::Node))
+  {
+    return Err(Refusal::new(source, "unknown_member", format!("membership change of unknown node: {change}")));
+  }
+  Ok(())
+}
+
+/// why the connection or messages of a peer are refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Refusal {
+  peer: PeerId,
+  /// label of `refused_peers`, there are a few of them
+  kind: &'static str,
+  /// goes to the logs
+  reason: String,
+}
+
+impl Refusal {
+  fn new(
+    peer: PeerId,
+    kind: &'static str,
+    reason: String,
+  ) -> Refusal {
+    Refusal { peer, kind, reason }
+  }
+
+  fn metric_attributes(&self) -> [KeyValue; 1] {
+    [KeyValue::new("reason", self.kind)]
+  }
 
-  match message {
-    RequestResponseMessage::Response { response, .. } => insert_by_role(response.role),
-    RequestResponseMessage::Request { channel, request, .. } => {
-      let res = swarm.behaviour_mut().meta_exchange.send_response(channel, MEResponse { role: Role::Node });
-      debug!("MetaExchangeRequestRes: {:?}", res);
-      insert_by_role(request.role);
+  fn log_event(
+    &self,
+    emitter: PeerId,
+  ) -> LogEvent {
+    LogEvent {
+      timestamp_micros: now_microsec(),
+      emitter,
+      body: LogEventBody::PeerRefused { peer: self.peer, reason: self.reason.clone() },
     }
   }
 }
 
+/// counts and logs the refusal
+fn refused(
+  id: PeerId,
+  refusal: Refusal,
+) {
+  warn!("refuse {}: {}", refusal.peer, refusal.reason);
+  counter_refused().add(1, &refusal.metric_attributes());
+  state_log::log(refusal.log_event(id));
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct GossipMessage {
   peer_id: PeerId,
@@ -393,4 +708,130 @@ struct GossipMessage {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum GossipPayload {
   State(NodeState),
+  Digests(Vec<EpochDigest>),
+  MembershipChanges(Vec<MembershipChange>),
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use epoch_coordinator::epoch::MembershipAction;
+
+  #[test]
+  fn gossip_encoding_waits_for_capabilities() {
+    let (current, legacy, unknown) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut peers = Peers::default();
+    peers.capabilities.insert(current, Capabilities::current());
+    peers.capabilities.insert(legacy, Capabilities::legacy());
+
+    assert_eq!(Encoding::Binary, peers.gossip_encoding(&HashSet::from([current]), Encoding::Binary));
+    assert_eq!(Encoding::Json, peers.gossip_encoding(&HashSet::from([current]), Encoding::Json));
+    assert_eq!(Encoding::Json, peers.gossip_encoding(&HashSet::from([current, legacy]), Encoding::Binary));
+    // meta exchange with `unknown` hasn't finished yet
+    assert_eq!(Encoding::Json, peers.gossip_encoding(&HashSet::from([current, unknown]), Encoding::Binary));
+  }
+
+  #[test]
+  fn validate_gossip_refuses_strangers() {
+    let (node, other_node, stranger) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let peers = Allowlist::Peers { nodes: HashSet::from([node, other_node]), gateways: HashSet::new() };
+    let state =
+      |peer_id: PeerId| GossipMessage { peer_id, payload: GossipPayload::State(NodeState { offsets: HashMap::new() }) };
+    let add = |peer_id: PeerId, member: PeerId| GossipMessage {
+      peer_id,
+      payload: GossipPayload::MembershipChanges(vec![MembershipChange {
+        node: member,
+        action: MembershipAction::Add,
+        effective_from: 1,
+      }]),
+    };
+
+    struct Case<'a> {
+      label: &'a str,
+      allowlist: &'a Allowlist,
+      source: Option<PeerId>,
+      message: GossipMessage,
+      /// kind of the refusal and the refused peer
+      refused: Option<(&'a str, PeerId)>,
+    }
+
+    let cases = vec![
+      Case { label: "allowed node", allowlist: &peers, source: Some(node), message: state(node), refused: None },
+      Case {
+        label: "allowed node adds allowed node",
+        allowlist: &peers,
+        source: Some(node),
+        message: add(node, other_node),
+        refused: None,
+      },
+      Case {
+        label: "unsigned",
+        allowlist: &peers,
+        source: None,
+        message: state(node),
+        refused: Some(("unsigned", node)),
+      },
+      Case {
+        label: "unknown author",
+        allowlist: &peers,
+        source: Some(stranger),
+        message: state(stranger),
+        refused: Some(("unknown_author", stranger)),
+      },
+      Case {
+        label: "on behalf of another node",
+        allowlist: &peers,
+        source: Some(node),
+        message: state(other_node),
+        refused: Some(("foreign_author", node)),
+      },
+      Case {
+        label: "adds unknown node",
+        allowlist: &peers,
+        source: Some(node),
+        message: add(node, stranger),
+        refused: Some(("unknown_member", node)),
+      },
+      Case {
+        label: "open allows anybody",
+        allowlist: &Allowlist::Open,
+        source: Some(stranger),
+        message: add(stranger, PeerId::random()),
+        refused: None,
+      },
+      Case {
+        label: "open still needs the author",
+        allowlist: &Allowlist::Open,
+        source: Some(stranger),
+        message: state(node),
+        refused: Some(("foreign_author", stranger)),
+      },
+      Case {
+        label: "open still needs a signature",
+        allowlist: &Allowlist::Open,
+        source: None,
+        message: state(stranger),
+        refused: Some(("unsigned", stranger)),
+      },
+    ];
+
+    for case in cases {
+      let res = validate_gossip(case.allowlist, case.source, &case.message);
+      assert_eq!(case.refused, res.err().map(|r| (r.kind, r.peer)), "{}", case.label);
+    }
+  }
+
+  #[test]
+  fn refusal_is_counted_and_logged() {
+    let (id, peer) = (PeerId::random(), PeerId::random());
+    let refusal = Refusal::new(peer, "unknown_peer", "not in the allowlist".to_string());
+
+    assert_eq!([KeyValue::new("reason", "unknown_peer")], refusal.metric_attributes());
+    let event = refusal.log_event(id);
+    assert_eq!(id, event.emitter);
+    let LogEventBody::PeerRefused { peer: refused, reason } = event.body else {
+      panic!("refusal is logged as PeerRefused");
+    };
+    assert_eq!((peer, "not in the allowlist".to_string()), (refused, reason));
+  }
 }
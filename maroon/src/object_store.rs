@@ -0,0 +1,119 @@
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+  io,
+  path::{Path, PathBuf},
+};
+
+/// Blob storage where nodes put data that should outlive them(ex: snapshots) and from where new nodes bootstrap
+///
+/// keys are flat names without `/`, so any bucket-like storage(s3, gcs, local dir) can be a backend
+pub trait ObjectStore: Send + Sync {
+  /// writes the object, readers see either the previous version or the whole new one
+  fn put(
+    &self,
+    key: String,
+    data: Vec<u8>,
+  ) -> BoxFuture<'_, io::Result<()>>;
+
+  /// `None` if there is no object with this key
+  fn get(
+    &self,
+    key: String,
+  ) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>>;
+
+  /// keys of all the objects that start with `prefix`, sorted
+  fn list(
+    &self,
+    prefix: String,
+  ) -> BoxFuture<'_, io::Result<Vec<String>>>;
+}
+
+/// keeps every object as a file in the directory
+pub struct LocalDirStore {
+  dir: PathBuf,
+}
+
+impl LocalDirStore {
+  pub fn new(dir: impl AsRef<Path>) -> LocalDirStore {
+    LocalDirStore { dir: dir.as_ref().to_path_buf() }
+  }
+}
+
+impl ObjectStore for LocalDirStore {
+  fn put(
+    &self,
+    key: String,
+    data: Vec<u8>,
+  ) -> BoxFuture<'_, io::Result<()>> {
+    async move {
+      tokio::fs::create_dir_all(&self.dir).await?;
+
+      // write and rename, so a crash in the middle doesn't leave a partially written object
+      let tmp = self.dir.join(format!(".{key}.tmp"));
+      tokio::fs::write(&tmp, data).await?;
+      tokio::fs::rename(&tmp, self.dir.join(key)).await
+    }
+    .boxed()
+  }
+
+  fn get(
+    &self,
+    key: String,
+  ) -> BoxFuture<'_, io::Result<Option<Vec<u8>>>> {
+    async move {
+      match tokio::fs::read(self.dir.join(key)).await {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+      }
+    }
+    .boxed()
+  }
+
+  fn list(
+    &self,
+    prefix: String,
+  ) -> BoxFuture<'_, io::Result<Vec<String>>> {
+    async move {
+      let mut entries = match tokio::fs::read_dir(&self.dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+      };
+
+      let mut keys = vec![];
+      while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str()
+          && name.starts_with(&prefix)
+        {
+          keys.push(name.to_string());
+        }
+      }
+      keys.sort();
+      Ok(keys)
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_local_dir_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = LocalDirStore::new(dir.path().join("store"));
+
+    assert_eq!(Vec::<String>::new(), store.list("a".to_string()).await.unwrap());
+    assert_eq!(None, store.get("a_1".to_string()).await.unwrap());
+
+    store.put("a_2".to_string(), b"second".to_vec()).await.unwrap();
+    store.put("a_1".to_string(), b"first".to_vec()).await.unwrap();
+    store.put("b_1".to_string(), b"other".to_vec()).await.unwrap();
+    store.put("a_1".to_string(), b"first v2".to_vec()).await.unwrap();
+
+    assert_eq!(vec!["a_1".to_string(), "a_2".to_string()], store.list("a_".to_string()).await.unwrap());
+    assert_eq!(Some(b"first v2".to_vec()), store.get("a_1".to_string()).await.unwrap());
+  }
+}
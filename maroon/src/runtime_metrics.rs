@@ -0,0 +1,43 @@
+use opentelemetry::{global, metrics::ObservableGauge};
+use runtime::runtime::RuntimeGauges;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
+
+/// reports sizes of the runtime state
+/// gauges are registered once per process, so only the first runtime is reported
+pub fn register_gauges(gauges: Arc<RuntimeGauges>) {
+  static INIT: OnceLock<Vec<ObservableGauge<u64>>> = OnceLock::new();
+  INIT.get_or_init(|| {
+    let meter = global::meter("maroon_runtime");
+
+    let g = gauges.clone();
+    let live_futures = meter
+      .u64_observable_gauge("maroon_runtime_live_futures")
+      .with_description("How many futures fibers, queues or resolved values still have")
+      .with_callback(move |observer| {
+        observer.observe(g.live_futures.load(Ordering::Relaxed), &[]);
+      })
+      .build();
+
+    let g = gauges.clone();
+    let queues = meter
+      .u64_observable_gauge("maroon_runtime_queues")
+      .with_description("How many queues runtime has")
+      .with_callback(move |observer| {
+        observer.observe(g.queues.load(Ordering::Relaxed), &[]);
+      })
+      .build();
+
+    let g = gauges;
+    let fibers = meter
+      .u64_observable_gauge("maroon_runtime_fibers")
+      .with_description("How many fibers are active or awaiting something")
+      .with_callback(move |observer| {
+        observer.observe(g.fibers.load(Ordering::Relaxed), &[]);
+      })
+      .build();
+
+    // Keep the registrations so they're never dropped during process lifetime.
+    vec![live_futures, queues, fibers]
+  });
+}
@@ -0,0 +1,87 @@
+use crate::object_store::ObjectStore;
+use epoch_coordinator::epoch::EpochCheckpoint;
+use runtime::snapshot::{RuntimeSnapshot, SNAPSHOT_VERSION};
+use serde::{Deserialize, Serialize};
+use std::io;
+
+const KEY_PREFIX: &str = "snapshot_";
+
+/// Runtime state after executing all the epochs up to `checkpoint`(inclusive)
+///
+/// a node restored from it gets only the epochs after the checkpoint
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+  pub checkpoint: EpochCheckpoint,
+  pub runtime: RuntimeSnapshot,
+}
+
+/// zero-padded, so lexicographical order of keys is the order of sequence numbers
+fn key(sequence_number: u64) -> String {
+  format!("{}{:020}.json", KEY_PREFIX, sequence_number)
+}
+
+pub async fn upload(
+  store: &dyn ObjectStore,
+  snapshot: &Snapshot,
+) -> io::Result<()> {
+  let data = serde_json::to_vec(snapshot).map_err(io::Error::other)?;
+  store.put(key(snapshot.checkpoint.sequence_number), data).await
+}
+
+/// the snapshot with the biggest sequence number if there is any
+pub async fn load_latest(store: &dyn ObjectStore) -> io::Result<Option<Snapshot>> {
+  let Some(latest) = store.list(KEY_PREFIX.to_string()).await?.pop() else {
+    return Ok(None);
+  };
+  let Some(data) = store.get(latest.clone()).await? else {
+    return Err(io::Error::new(io::ErrorKind::NotFound, format!("snapshot {latest} has disappeared")));
+  };
+
+  // the version is read first, so a snapshot of another version isn't mistaken for a broken one
+  let version: SnapshotVersion =
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+  if version.runtime.version != Some(SNAPSHOT_VERSION) {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!(
+        "snapshot {latest} has runtime version {:?}, this node restores only version {SNAPSHOT_VERSION}",
+        version.runtime.version
+      ),
+    ));
+  }
+  serde_json::from_slice(&data).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[derive(Deserialize)]
+struct SnapshotVersion {
+  runtime: RuntimeVersion,
+}
+
+/// snapshots made before versioning don't have it
+#[derive(Deserialize)]
+struct RuntimeVersion {
+  version: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::object_store::LocalDirStore;
+
+  #[tokio::test]
+  async fn snapshot_of_another_version_is_not_restored() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = LocalDirStore::new(dir.path());
+    assert!(load_latest(&store).await.unwrap().is_none());
+
+    // made before snapshots have got versions
+    store.put(key(1), br#"{"checkpoint":{},"runtime":{"public_futures":{}}}"#.to_vec()).await.unwrap();
+    let err = load_latest(&store).await.unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("version None"), "{err}");
+
+    store.put(key(2), format!(r#"{{"runtime":{{"version":{}}}}}"#, SNAPSHOT_VERSION + 1).into_bytes()).await.unwrap();
+    let err = load_latest(&store).await.unwrap_err();
+    assert!(err.to_string().contains(&format!("version Some({})", SNAPSHOT_VERSION + 1)), "{err}");
+  }
+}
@@ -1,25 +1,41 @@
 use crate::app::{App, Params, Request, Response};
 use crate::linearizer::LogLineriazer;
 use crate::network::{Inbox, Outbox, P2P};
+use crate::object_store::ObjectStore;
+use crate::runtime_metrics;
+use crate::snapshot;
 use common::duplex_channel::create_a_b_duplex_pair;
+use common::identity;
 use common::invoker_handler::{InvokerInterface, create_invoker_handler_pair};
 use common::logical_clock::MonotonicTimer;
-use common::logical_time::LogicalTimeAbsoluteMs;
-use common::range_key::UniqueU64BlobId;
 use epoch_coordinator::etcd::EtcdEpochCoordinator;
-use epoch_coordinator::interface::create_interface_pair as create_epoch_coordinator_interface_pair;
-use generated::maroon_assembler::Value;
+use epoch_coordinator::interface::{
+  EpochCoordinator, create_interface_pair as create_epoch_coordinator_interface_pair,
+};
+use epoch_coordinator::memory::{InMemoryEpochCoordinator, InMemoryEpochStore};
 use libp2p::PeerId;
-use log::{debug, info};
-use runtime::runtime::{Runtime, TaskBlueprint};
-use tokio::sync::oneshot;
+use log::{error, info};
+use runtime::runtime::{Input as RuntimeInput, Output as RuntimeOutput, Runtime};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 
 pub struct MaroonStack {
   pub id: PeerId,
   p2p: P2P,
-  epoch_coordinator: EtcdEpochCoordinator,
+  epoch_coordinator: Box<dyn EpochCoordinator>,
   app: App<LogLineriazer>,
   runtime: Runtime<MonotonicTimer>,
+  /// if set - stack starts from the latest snapshot in the store
+  snapshot_store: Option<Arc<dyn ObjectStore>>,
+}
+
+/// where the stack commits epochs and from where it gets committed epochs
+pub enum EpochCoordinatorBackend {
+  /// etcd cluster endpoints
+  Etcd(Vec<String>),
+  /// in-process storage. All the stacks that got clones of the same store see the same epochs
+  InMemory(InMemoryEpochStore),
 }
 
 /// contains signals/interfaces to control/communicate with maroon stack
@@ -33,65 +49,128 @@ pub struct StackRemoteControl {
 impl MaroonStack {
   pub fn new(
     node_urls: Vec<String>,
-    etcd_urls: Vec<String>,
+    coordinator_backend: EpochCoordinatorBackend,
     self_url: String,
     params: Params,
   ) -> Result<(MaroonStack, StackRemoteControl), Box<dyn std::error::Error>> {
     let (a2b_endpoint, b2a_endpoint) = create_a_b_duplex_pair::<Inbox, Outbox>();
     let (epoch_coordinator, epoch_coordinator_controller) = create_epoch_coordinator_interface_pair();
-    let (a2b_runtime, b2a_runtime) =
-      create_a_b_duplex_pair::<(LogicalTimeAbsoluteMs, Vec<TaskBlueprint>), (UniqueU64BlobId, Value)>();
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<RuntimeInput, RuntimeOutput>();
 
-    let epoch_coordinator = EtcdEpochCoordinator::new(&etcd_urls, epoch_coordinator);
+    let epoch_coordinator: Box<dyn EpochCoordinator> = match coordinator_backend {
+      EpochCoordinatorBackend::Etcd(etcd_urls) => Box::new(EtcdEpochCoordinator::new(&etcd_urls, epoch_coordinator)),
+      EpochCoordinatorBackend::InMemory(store) => Box::new(InMemoryEpochCoordinator::new(store, epoch_coordinator)),
+    };
 
-    let p2p = P2P::new(node_urls, self_url, a2b_endpoint)?;
+    let keypair = identity::keypair(params.key_file.as_deref())?;
+    let p2p = P2P::new(node_urls, self_url, keypair, a2b_endpoint)?
+      .set_gossip_encoding(params.gossip_encoding)
+      .set_allowlist(params.allowlist.clone());
     let id = p2p.peer_id;
 
+    let snapshot_store = params.snapshots.as_ref().map(|s| s.store.clone());
     let (state_invoker, state_handler) = create_invoker_handler_pair();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let (digests_sender, digests_receiver) = mpsc::unbounded_channel();
     let app =
-      App::<LogLineriazer>::new(id, b2a_endpoint, a2b_runtime, state_handler, epoch_coordinator_controller, params)?;
+      App::<LogLineriazer>::new(id, b2a_endpoint, a2b_runtime, state_handler, epoch_coordinator_controller, params)?
+        .set_runtime_snapshot_invoker(snapshot_invoker)
+        .set_runtime_digests_receiver(digests_receiver);
 
     // TODO: copy it to other components as well
     let timer = MonotonicTimer::new();
 
-    let runtime = Runtime::new(timer.clone(), b2a_runtime);
+    // runtime time follows epochs, so every node fires timers at the same positions
+    let runtime = Runtime::new(timer.clone(), b2a_runtime)
+      .set_epoch_driven_time()
+      .set_snapshot_handler(snapshot_handler)
+      .set_digests_sender(digests_sender);
 
-    Ok((MaroonStack { id, p2p, epoch_coordinator, app: app, runtime }, StackRemoteControl { state_invoker }))
+    Ok((
+      MaroonStack { id, p2p, epoch_coordinator, app: app, runtime, snapshot_store },
+      StackRemoteControl { state_invoker },
+    ))
   }
 
   /// starts listening and network operations in a separate tokio threads
-  /// returns shutdown function
-  pub fn start(self) -> impl FnOnce() {
-    let (shutdown_tx, app_shutdown_rx) = oneshot::channel();
+  /// returns a handle that stops them
+  pub fn start(self) -> RunningStack {
+    let (app_shutdown_tx, app_shutdown_rx) = oneshot::channel();
+    let (runtime_shutdown_tx, runtime_shutdown_rx) = oneshot::channel();
 
-    let MaroonStack { id, mut p2p, epoch_coordinator, mut app, mut runtime } = self;
+    let MaroonStack { id, mut p2p, epoch_coordinator, mut app, runtime, snapshot_store } = self;
     _ = id;
+    let mut runtime = runtime.set_shutdown_signal(runtime_shutdown_rx);
 
     p2p.prepare().expect("if error occured - it won't work");
+    runtime_metrics::register_gauges(runtime.gauges());
 
-    tokio::spawn(async move {
-      // TODO(akantsevoi): add shutdown signal here
+    // p2p and epoch coordinator stop once the app has dropped its side of their interfaces
+    let p2p = tokio::spawn(async move {
       p2p.start_event_loop().await;
     });
-    tokio::spawn(async move {
-      // TODO(akantsevoi): add shutdown signal here
-      if let Err(e) = epoch_coordinator.start().await {
-        // TODO(akantsevoi): some errors are ok(ex: empty etcd nodes in a single node mode), but some are not ok
+    // coordinator starts after the snapshot is restored and delivers only the epochs after it
+    let (restored_sn_tx, restored_sn_rx) = oneshot::channel();
+    let epoch_coordinator = tokio::spawn(async move {
+      let restored_sn = restored_sn_rx.await.unwrap_or(None);
+      if let Err(e) = epoch_coordinator.set_last_applied_sequence_number(restored_sn).start().await {
+        // TODO(akantsevoi): some errors are ok, but some are not ok
         // I need to differentiate these errors. Log some of them and panic on others
-        debug!("epoch_coordinator_start: {e:?}");
+        error!("epoch_coordinator_start: {e:?}");
       }
     });
-    tokio::spawn(async move {
+    let execution = tokio::spawn(async move {
+      let mut restored_sn = None;
+      if let Some(store) = snapshot_store {
+        match snapshot::load_latest(store.as_ref()).await {
+          Ok(Some(snapshot)) => {
+            restored_sn = Some(snapshot.checkpoint.sequence_number);
+            app.restore_from_snapshot(snapshot.checkpoint, &snapshot.runtime);
+            runtime.restore(snapshot.runtime);
+          }
+          Ok(None) => info!("there are no snapshots yet, start from scratch"),
+          Err(e) => error!("couldnt load snapshot, start from scratch: {e}"),
+        }
+      }
+      _ = restored_sn_tx.send(restored_sn);
+
+      let runtime = tokio::spawn(async move {
+        runtime.run("root".to_string()).await;
+      });
+
       app.loop_until_shutdown(app_shutdown_rx).await;
+      // app has waited for runtime to execute everything it has sent
+      drop(app);
+      _ = runtime_shutdown_tx.send(());
+      if let Err(e) = runtime.await {
+        error!("runtime has stopped with error: {e}");
+      }
     });
 
-    tokio::spawn(async move {
-      runtime.run("root".to_string()).await;
-    });
+    RunningStack { app_shutdown: app_shutdown_tx, execution, epoch_coordinator, p2p }
+  }
+}
 
-    move || {
-      if let Err(e) = shutdown_tx.send(()) {
-        info!("app shutdown signal err: {e:?}");
+/// tasks of the started stack
+/// if it's dropped without `shutdown` - the app stops and the rest of the stack follows it
+pub struct RunningStack {
+  app_shutdown: oneshot::Sender<()>,
+  /// app with runtime
+  execution: JoinHandle<()>,
+  epoch_coordinator: JoinHandle<()>,
+  p2p: JoinHandle<()>,
+}
+
+impl RunningStack {
+  /// stops the stack and waits until it's done: the app finishes the epochs runtime has got and notifies gateways,
+  /// then runtime, epoch coordinator and p2p layer stop
+  pub async fn shutdown(self) {
+    if self.app_shutdown.send(()).is_err() {
+      info!("app has already stopped");
+    }
+    for (name, task) in [("app", self.execution), ("epoch coordinator", self.epoch_coordinator), ("p2p", self.p2p)] {
+      if let Err(e) = task.await {
+        error!("{name} has stopped with error: {e}");
       }
     }
   }
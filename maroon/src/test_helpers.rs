@@ -61,7 +61,9 @@ pub async fn reaches_state(
   exp_state: CurrentOffsets,
 ) -> bool {
   for _ in 0..attempts {
-    let AppStateResponse::State(current_state) = state_invoker.request(AppStateRequest::GetState).await;
+    let AppStateResponse::State(current_state) = state_invoker.request(AppStateRequest::GetState).await else {
+      unreachable!("GetState is answered with State");
+    };
 
     if exp_state == current_state {
       return true;
@@ -0,0 +1,404 @@
+use common::range_key::{KeyOffset, KeyRange, UniqueU64BlobId};
+use log::{info, warn};
+use protocol::{node2gw::TxUpdate, transaction::Transaction};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{HashMap, HashSet},
+  fs::{self, File, OpenOptions},
+  io::{self, BufRead, BufReader, Read, Write},
+  path::{Path, PathBuf},
+};
+use types::range_key::{range_from_unique_blob_id, range_offset_from_unique_blob_id};
+
+const SEGMENT_PREFIX: &str = "range_";
+const SEGMENT_EXTENSION: &str = "wal";
+/// segment is rewritten here and renamed over the old one, so a crash leaves one of them complete
+const TRUNCATED_EXTENSION: &str = "truncated";
+
+/// what node writes to the WAL
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WalRecord {
+  /// transaction has been received from gateway or another node
+  Transaction(Transaction),
+  /// transaction has been executed by runtime
+  Result(TxUpdate),
+}
+
+impl WalRecord {
+  fn range(&self) -> KeyRange {
+    range_from_unique_blob_id(self.id())
+  }
+
+  fn id(&self) -> UniqueU64BlobId {
+    match self {
+      WalRecord::Transaction(tx) => tx.meta.id,
+      WalRecord::Result(update) => update.meta.id,
+    }
+  }
+}
+
+/// Append-only write-ahead log of transactions and their results, so node doesn't lose them on restart
+///
+/// Every `KeyRange` has its own segment `<dir>/range_<n>.wal` with one json record per line.
+/// Records are synced to disk in batches, see `take_unsynced`, and truncated in the background, see `start_truncation`
+pub struct Wal {
+  dir: PathBuf,
+  segments: HashMap<KeyRange, File>,
+  /// segments that have been appended since the last `take_unsynced`
+  unsynced: HashSet<KeyRange>,
+  /// segments that are being rewritten by `WalTruncation`, records appended to them wait here until it's finished
+  truncating: HashMap<KeyRange, Vec<u8>>,
+}
+
+/// segments with records that aren't on disk yet, syncing blocks, so it's done outside of the event loop
+pub struct WalSync {
+  segments: Vec<File>,
+}
+
+impl WalSync {
+  pub fn sync(self) -> io::Result<()> {
+    for segment in self.segments {
+      segment.sync_data()?;
+    }
+    Ok(())
+  }
+}
+
+/// rewrites segments without the records that aren't needed anymore,
+/// it blocks, so it's done outside of the event loop and handed back to `Wal::finish_truncation`
+pub struct WalTruncation {
+  dir: PathBuf,
+  offsets: HashMap<KeyRange, KeyOffset>,
+  keep_results: HashSet<UniqueU64BlobId>,
+}
+
+impl WalTruncation {
+  pub fn run(&self) -> io::Result<()> {
+    let mut renamed = false;
+    for (range, up_to) in &self.offsets {
+      let path = segment_path(&self.dir, *range);
+
+      let mut content = Vec::new();
+      let mut dropped = 0;
+      for record in read_segment(&path)? {
+        let kept = matches!(&record, WalRecord::Result(update) if self.keep_results.contains(&update.meta.id));
+        if range_offset_from_unique_blob_id(record.id()).1 <= *up_to && !kept {
+          dropped += 1;
+          continue;
+        }
+        content.append(&mut serde_json::to_vec(&record).map_err(io::Error::other)?);
+        content.push(b'\n');
+      }
+      if dropped == 0 {
+        continue;
+      }
+
+      let truncated = path.with_extension(TRUNCATED_EXTENSION);
+      let mut file = File::create(&truncated)?;
+      file.write_all(&content)?;
+      file.sync_all()?;
+      fs::rename(&truncated, &path)?;
+      renamed = true;
+      info!("wal segment {:?} is truncated up to offset {}, {} records dropped", path, up_to.0, dropped);
+    }
+    // rename isn't durable until the directory is synced
+    if renamed {
+      File::open(&self.dir)?.sync_all()?;
+    }
+    Ok(())
+  }
+}
+
+impl Wal {
+  /// opens WAL in `dir`(creates it if needed) and returns everything that has been written there before
+  /// records of the same range go in the order they were appended
+  pub fn open(dir: impl AsRef<Path>) -> io::Result<(Wal, Vec<WalRecord>)> {
+    let dir = dir.as_ref().to_path_buf();
+    fs::create_dir_all(&dir)?;
+
+    let mut records = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+      let path = entry?.path();
+      if segment_range(&path).is_none() {
+        continue;
+      }
+      records.append(&mut read_segment(&path)?);
+    }
+
+    info!("wal {:?} is opened, {} records restored", dir, records.len());
+    Ok((Wal { dir, segments: HashMap::new(), unsynced: HashSet::new(), truncating: HashMap::new() }, records))
+  }
+
+  pub fn append(
+    &mut self,
+    record: &WalRecord,
+  ) -> io::Result<()> {
+    let range = record.range();
+    let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+    line.push(b'\n');
+    if let Some(pending) = self.truncating.get_mut(&range) {
+      pending.append(&mut line);
+      return Ok(());
+    }
+
+    let segment = match self.segments.get_mut(&range) {
+      Some(segment) => segment,
+      None => {
+        let file = OpenOptions::new().create(true).append(true).open(segment_path(&self.dir, range))?;
+        self.segments.entry(range).or_insert(file)
+      }
+    };
+
+    segment.write_all(&line)?;
+    self.unsynced.insert(range);
+    Ok(())
+  }
+
+  /// segments appended since the previous call, `None` if there are none
+  pub fn take_unsynced(&mut self) -> io::Result<Option<WalSync>> {
+    if self.unsynced.is_empty() {
+      return Ok(None);
+    }
+    let mut segments = Vec::with_capacity(self.unsynced.len());
+    for range in self.unsynced.drain() {
+      if let Some(segment) = self.segments.get(&range) {
+        segments.push(segment.try_clone()?);
+      }
+    }
+    Ok(Some(WalSync { segments }))
+  }
+
+  /// drops records of the transactions at or below `offsets` of their ranges,
+  /// ex: they are in a snapshot and the node restored from it doesn't need them <br>
+  /// results of `keep_results` stay, ex: recent results gateways can still ask for <br>
+  /// `None` if there is nothing to truncate or the previous truncation hasn't finished yet
+  pub fn start_truncation(
+    &mut self,
+    offsets: &HashMap<KeyRange, KeyOffset>,
+    keep_results: HashSet<UniqueU64BlobId>,
+  ) -> Option<WalTruncation> {
+    if !self.truncating.is_empty() {
+      return None;
+    }
+
+    let offsets: HashMap<KeyRange, KeyOffset> =
+      offsets.iter().filter(|(range, _)| segment_path(&self.dir, **range).exists()).map(|(r, o)| (*r, *o)).collect();
+    if offsets.is_empty() {
+      return None;
+    }
+    for range in offsets.keys() {
+      // everything written before is read by the truncation, so it doesn't need to be synced separately
+      self.segments.remove(range);
+      self.unsynced.remove(range);
+      self.truncating.insert(*range, Vec::new());
+    }
+    Some(WalTruncation { dir: self.dir.clone(), offsets, keep_results })
+  }
+
+  /// appends the records that have come while `truncation` was running, whether it has succeeded or not
+  pub fn finish_truncation(
+    &mut self,
+    truncation: WalTruncation,
+  ) -> io::Result<()> {
+    for range in truncation.offsets.keys() {
+      let Some(pending) = self.truncating.remove(range) else {
+        continue;
+      };
+      let mut file = OpenOptions::new().create(true).append(true).open(segment_path(&self.dir, *range))?;
+      if !pending.is_empty() {
+        file.write_all(&pending)?;
+        self.unsynced.insert(*range);
+      }
+      self.segments.insert(*range, file);
+    }
+    Ok(())
+  }
+
+  pub fn is_truncating(&self) -> bool {
+    !self.truncating.is_empty()
+  }
+}
+
+fn segment_path(
+  dir: &Path,
+  range: KeyRange,
+) -> PathBuf {
+  dir.join(format!("{}{}.{}", SEGMENT_PREFIX, range.0, SEGMENT_EXTENSION))
+}
+
+fn segment_range(path: &Path) -> Option<KeyRange> {
+  if path.extension()? != SEGMENT_EXTENSION {
+    return None;
+  }
+  path.file_stem()?.to_str()?.strip_prefix(SEGMENT_PREFIX)?.parse::<u64>().ok().map(KeyRange)
+}
+
+/// reads all the records from the segment
+/// the last record might be partially written if node crashed in the middle of `append`, it's cut off
+fn read_segment(path: &Path) -> io::Result<Vec<WalRecord>> {
+  let mut reader = BufReader::new(File::open(path)?);
+  let mut records = Vec::new();
+  let mut valid_len: u64 = 0;
+  let mut line = Vec::new();
+
+  loop {
+    line.clear();
+    let read = reader.read_until(b'\n', &mut line)?;
+    if read == 0 {
+      break;
+    }
+
+    let complete = line.last() == Some(&b'\n');
+    match serde_json::from_slice::<WalRecord>(&line) {
+      Ok(record) if complete => {
+        records.push(record);
+        valid_len += read as u64;
+      }
+      Ok(_) | Err(_) => {
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+        if !rest.is_empty() {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupted record in the middle of wal segment {:?}", path),
+          ));
+        }
+
+        warn!("cut off partially written record at the end of wal segment {:?}", path);
+        OpenOptions::new().write(true).open(path)?.set_len(valid_len)?;
+        break;
+      }
+    }
+  }
+
+  Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helpers::test_tx;
+  use common::range_key::{KeyOffset, unique_blob_id_from_range_and_offset};
+  use generated::maroon_assembler::Value;
+  use protocol::transaction::{Meta, TxStatus};
+
+  fn tx(
+    range: u64,
+    offset: u64,
+  ) -> Transaction {
+    test_tx(unique_blob_id_from_range_and_offset(KeyRange(range), KeyOffset(offset)).0)
+  }
+
+  #[test]
+  fn test_wal_restores_records() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let result = WalRecord::Result(TxUpdate {
+      meta: Meta { id: tx(0, 0).meta.id, status: TxStatus::Finished },
+      result: Some(Value::U64(35)),
+    });
+    let records = vec![
+      WalRecord::Transaction(tx(0, 0)),
+      WalRecord::Transaction(tx(1, 0)),
+      WalRecord::Transaction(tx(0, 1)),
+      result.clone(),
+    ];
+
+    {
+      let (mut wal, restored) = Wal::open(dir.path()).unwrap();
+      assert!(restored.is_empty());
+      for record in &records {
+        wal.append(record).unwrap();
+      }
+    }
+
+    let (mut wal, restored) = Wal::open(dir.path()).unwrap();
+    let by_range = |restored: &Vec<WalRecord>, range: u64| {
+      restored.iter().filter(|r| r.range() == KeyRange(range)).cloned().collect::<Vec<WalRecord>>()
+    };
+    assert_eq!(
+      vec![WalRecord::Transaction(tx(0, 0)), WalRecord::Transaction(tx(0, 1)), result.clone()],
+      by_range(&restored, 0)
+    );
+    assert_eq!(vec![WalRecord::Transaction(tx(1, 0))], by_range(&restored, 1));
+
+    // appending continues the same segment
+    wal.append(&WalRecord::Transaction(tx(1, 1))).unwrap();
+    let (_, restored) = Wal::open(dir.path()).unwrap();
+    assert_eq!(vec![WalRecord::Transaction(tx(1, 0)), WalRecord::Transaction(tx(1, 1))], by_range(&restored, 1));
+  }
+
+  #[test]
+  fn test_wal_truncates_snapshotted_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let result = |tx: Transaction| {
+      WalRecord::Result(TxUpdate { meta: Meta { id: tx.meta.id, status: TxStatus::Finished }, result: None })
+    };
+
+    let (mut wal, _) = Wal::open(dir.path()).unwrap();
+    for record in [
+      WalRecord::Transaction(tx(0, 0)),
+      WalRecord::Transaction(tx(0, 1)),
+      result(tx(0, 0)),
+      WalRecord::Transaction(tx(0, 2)),
+      result(tx(0, 1)),
+      WalRecord::Transaction(tx(1, 0)),
+    ] {
+      wal.append(&record).unwrap();
+    }
+    assert_eq!(2, wal.take_unsynced().unwrap().expect("both segments are written").segments.len());
+    assert!(wal.take_unsynced().unwrap().is_none());
+
+    // range 1 isn't in the snapshot yet, the recent result is kept
+    let truncation = wal
+      .start_truncation(&HashMap::from([(KeyRange(0), KeyOffset(1))]), HashSet::from([tx(0, 1).meta.id]))
+      .expect("range 0 has records to drop");
+    assert!(wal.start_truncation(&HashMap::from([(KeyRange(1), KeyOffset(0))]), HashSet::new()).is_none());
+    // records that come while the segment is rewritten are appended after it
+    wal.append(&WalRecord::Transaction(tx(0, 3))).unwrap();
+    wal.append(&WalRecord::Transaction(tx(1, 1))).unwrap();
+    truncation.run().unwrap();
+    wal.finish_truncation(truncation).unwrap();
+    wal.append(&WalRecord::Transaction(tx(0, 4))).unwrap();
+    assert_eq!(2, wal.take_unsynced().unwrap().expect("both segments are written").segments.len());
+
+    let (_, restored) = Wal::open(dir.path()).unwrap();
+    let by_range = |range: u64| restored.iter().filter(|r| r.range() == KeyRange(range)).cloned().collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        WalRecord::Transaction(tx(0, 2)),
+        result(tx(0, 1)),
+        WalRecord::Transaction(tx(0, 3)),
+        WalRecord::Transaction(tx(0, 4))
+      ],
+      by_range(0)
+    );
+    assert_eq!(vec![WalRecord::Transaction(tx(1, 0)), WalRecord::Transaction(tx(1, 1))], by_range(1));
+  }
+
+  #[test]
+  fn test_wal_cuts_off_partial_record() {
+    let dir = tempfile::tempdir().unwrap();
+
+    {
+      let (mut wal, _) = Wal::open(dir.path()).unwrap();
+      wal.append(&WalRecord::Transaction(tx(0, 0))).unwrap();
+    }
+
+    // node crashed in the middle of writing the next record
+    let segment = segment_path(dir.path(), KeyRange(0));
+    let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+    file.write_all(b"{\"Transaction\":{\"meta\":").unwrap();
+
+    let (mut wal, restored) = Wal::open(dir.path()).unwrap();
+    assert_eq!(vec![WalRecord::Transaction(tx(0, 0))], restored);
+
+    wal.append(&WalRecord::Transaction(tx(0, 1))).unwrap();
+    let (_, restored) = Wal::open(dir.path()).unwrap();
+    assert_eq!(vec![WalRecord::Transaction(tx(0, 0)), WalRecord::Transaction(tx(0, 1))], restored);
+
+    // corruption in the middle is not something that can happen because of a crash
+    fs::write(&segment, b"garbage\n{}\n").unwrap();
+    assert_eq!(io::ErrorKind::InvalidData, Wal::open(dir.path()).err().unwrap().kind());
+  }
+}
@@ -0,0 +1,65 @@
+//! size and encoding/decoding time of the messages nodes exchange the most, JSON vs binary
+//!
+//! `cargo bench -p protocol --bench wire`
+
+use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
+use libp2p::PeerId;
+use protocol::m2m_request_response::Request as M2MRequest;
+use protocol::node2gw::{GossipMessage, GossipPayload};
+use protocol::transaction::{Meta, TaskBlueprint, Transaction, TxStatus, TxUpdate};
+use protocol::wire::{self, Encoding};
+use serde::{Serialize, de::DeserializeOwned};
+use std::time::{Duration, Instant};
+use types::range_key::UniqueU64BlobId;
+
+const TXS: u64 = 1_000;
+const ROUNDS: u32 = 200;
+
+fn measure<T: Serialize + DeserializeOwned>(
+  name: &str,
+  message: &T,
+) {
+  for encoding in [Encoding::Json, Encoding::Binary] {
+    let bytes = wire::encode(encoding, message).unwrap();
+
+    let started = Instant::now();
+    for _ in 0..ROUNDS {
+      std::hint::black_box(wire::encode(encoding, message).unwrap());
+    }
+    let encode: Duration = started.elapsed() / ROUNDS;
+
+    let started = Instant::now();
+    for _ in 0..ROUNDS {
+      std::hint::black_box(wire::decode::<T>(encoding, &bytes).unwrap());
+    }
+    let decode: Duration = started.elapsed() / ROUNDS;
+
+    println!(
+      "{name:>10} {:>8}: {:.1} bytes/tx, encode {encode:?}, decode {decode:?} per {TXS} txs",
+      format!("{encoding:?}"),
+      bytes.len() as f64 / TXS as f64,
+    );
+  }
+}
+
+fn main() {
+  let base = 1 << 40;
+  let txs: Vec<Transaction> = (0..TXS)
+    .map(|i| Transaction {
+      meta: Meta { id: UniqueU64BlobId(base + i), status: TxStatus::Pending },
+      blueprint: TaskBlueprint {
+        queue_name: "testInfiniteCalculatorQueue".to_string(),
+        param: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: i, b: i * 7 }),
+      },
+    })
+    .collect();
+  measure("missing_tx", &M2MRequest::MissingTx(txs));
+
+  let updates: Vec<TxUpdate> = (0..TXS)
+    .map(|i| TxUpdate {
+      meta: Meta { id: UniqueU64BlobId(base + i), status: TxStatus::Finished },
+      result: Some(Value::U64(i * 8)),
+    })
+    .collect();
+  measure("tx_updates", &GossipMessage { peer_id: PeerId::random(), payload: GossipPayload::Node2GWTxUpdate(updates) });
+}
@@ -1,30 +1,38 @@
-use crate::transaction::Transaction;
+use crate::transaction::{Transaction, TxUpdate};
+use crate::wire;
 use libp2p::swarm::StreamProtocol;
-use libp2p_request_response::{
-  self as request_response, Event as RequestResponseEvent, ProtocolSupport,
-  json::{self},
-};
+use libp2p_request_response::{self as request_response, Event as RequestResponseEvent, ProtocolSupport};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use types::range_key::{KeyRange, UniqueU64BlobId};
 
 pub type Event = RequestResponseEvent<Request, Response>;
-pub type Behaviour = json::Behaviour<Request, Response>;
+pub type Behaviour = request_response::Behaviour<wire::Codec<Request, Response>>;
 
-pub fn create_behaviour(protocol: ProtocolSupport) -> json::Behaviour<Request, Response> {
-  json::Behaviour::<Request, Response>::new(
-    [(StreamProtocol::new("/maroon/request/1.0.0"), protocol)],
-    request_response::Config::default(),
-  )
+pub const PROTOCOL_BINARY: StreamProtocol = StreamProtocol::new("/maroon/request/2.0.0");
+/// is kept for nodes and gateways that haven't been upgraded yet
+pub const PROTOCOL_JSON: StreamProtocol = StreamProtocol::new("/maroon/request/1.0.0");
+
+pub fn create_behaviour(protocol: ProtocolSupport) -> Behaviour {
+  wire::create_behaviour(PROTOCOL_BINARY, PROTOCOL_JSON, protocol)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", content = "data")]
 pub enum Request {
   NewTransaction(Transaction),
+  /// asks for a new key range instead of the current one(`None` if gateway doesn't have one yet)
+  /// is answered once the lease is committed in an epoch
+  LeaseKeyRange(Option<KeyRange>),
+  /// asks for the latest statuses of transactions, ex: after reconnect when some notifications could be missed
+  GetTxStatus(Vec<UniqueU64BlobId>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Response {
   Acknowledged,
   Rejected,
+  KeyRangeLeased(KeyRange),
+  /// statuses of the requested transactions the node knows about
+  TxStatuses(Vec<TxUpdate>),
 }
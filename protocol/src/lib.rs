@@ -3,3 +3,4 @@ pub mod m2m_request_response;
 pub mod meta_exchange;
 pub mod node2gw;
 pub mod transaction;
+pub mod wire;
@@ -1,34 +1,72 @@
 use crate::transaction::Transaction;
+use crate::wire;
 use libp2p::swarm::StreamProtocol;
-use libp2p_request_response::{
-  self as request_response, Event as RequestResponseEvent, ProtocolSupport,
-  json::{self},
-};
+use libp2p_request_response::{self as request_response, Event as RequestResponseEvent, ProtocolSupport};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
-use types::range_key::U64BlobIdClosedInterval;
+use types::range_key::{U64BlobIdClosedInterval, UniqueU64BlobId};
 
 pub type Event = RequestResponseEvent<Request, Response>;
-pub type Behaviour = json::Behaviour<Request, Response>;
+pub type Behaviour = request_response::Behaviour<wire::Codec<Request, Response>>;
 
-pub fn create_behaviour() -> json::Behaviour<Request, Response> {
-  json::Behaviour::<Request, Response>::new(
-    [(StreamProtocol::new("/maroon/p2p_direct/1.0.0"), ProtocolSupport::Full)],
-    request_response::Config::default(),
-  )
+pub const PROTOCOL_BINARY: StreamProtocol = StreamProtocol::new("/maroon/p2p_direct/2.0.0");
+/// is kept for nodes and gateways that haven't been upgraded yet
+pub const PROTOCOL_JSON: StreamProtocol = StreamProtocol::new("/maroon/p2p_direct/1.0.0");
+
+pub fn create_behaviour() -> Behaviour {
+  wire::create_behaviour(PROTOCOL_BINARY, PROTOCOL_JSON, ProtocolSupport::Full)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", content = "data")]
 pub enum Request {
-  /// request missing transactions for given ranges
+  /// request missing transactions for given ranges <br>
+  /// is sent only to the nodes without `features::TX_PAGES`, they answer with `MissingTx`
   GetMissingTx(Vec<U64BlobIdClosedInterval>),
 
   /// sends missing transactions
   MissingTx(Vec<Transaction>),
+
+  /// asks for one page of transactions, is answered with `Response::TxPage` or `Response::Throttled`
+  GetTxPage(TxPageRequest),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Response {
   Ack,
+  TxPage(TxPage),
+  /// the peer has asked for too many pages recently, it should ask again later <br>
+  /// has `TxPageRequest::id` of the request
+  Throttled(u64),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TxPageRequest {
+  /// is echoed in the answer, so the asking node can tell a late page of a request it has already given up on
+  pub id: u64,
+  /// are served in this order
+  pub intervals: Vec<U64BlobIdClosedInterval>,
+  /// `TxPage::next` of the previous page, `None` for the first one
+  pub resume: Option<ResumeToken>,
+  /// ids the page covers at most, the serving node can cut it further
+  pub limit: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TxPage {
+  /// `TxPageRequest::id` of the request
+  pub request_id: u64,
+  /// transactions the serving node has, ids it doesn't have are skipped
+  pub txs: Vec<Transaction>,
+  /// where the next page starts, `None` if the intervals are done
+  pub next: Option<ResumeToken>,
+}
+
+/// position in the requested intervals, serving node doesn't keep any state between pages
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+  /// index of the interval in `TxPageRequest::intervals`
+  pub interval: u32,
+  /// the first id that hasn't been covered yet
+  pub next_id: UniqueU64BlobId,
 }
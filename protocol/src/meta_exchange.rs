@@ -1,33 +1,151 @@
+use crate::wire;
+use generated::maroon_assembler::IR_SCHEMA_HASH;
 use libp2p::swarm::StreamProtocol;
-use libp2p_request_response::{
-  self as request_response, Event as RequestResponseEvent, ProtocolSupport,
-  json::{self},
-};
+use libp2p_request_response::{self as request_response, Event as RequestResponseEvent, ProtocolSupport};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 
 pub type Event = RequestResponseEvent<Request, Response>;
-pub type Behaviour = json::Behaviour<Request, Response>;
+pub type Behaviour = request_response::Behaviour<wire::Codec<Request, Response>>;
 
-pub fn create_behaviour() -> json::Behaviour<Request, Response> {
-  json::Behaviour::<Request, Response>::new(
-    [(StreamProtocol::new("/maroon/meta_exchange/1.0.0"), ProtocolSupport::Full)],
-    request_response::Config::default(),
-  )
+pub const PROTOCOL_BINARY: StreamProtocol = StreamProtocol::new("/maroon/meta_exchange/2.0.0");
+/// is kept for nodes and gateways that haven't been upgraded yet
+pub const PROTOCOL_JSON: StreamProtocol = StreamProtocol::new("/maroon/meta_exchange/1.0.0");
+
+pub fn create_behaviour() -> Behaviour {
+  wire::create_behaviour(PROTOCOL_BINARY, PROTOCOL_JSON, ProtocolSupport::Full)
+}
+
+/// is bumped when peers of the previous version can't understand the messages anymore
+pub const PROTOCOL_VERSION: u32 = 2;
+/// the oldest version this build still talks to. Peers that don't report a version have version 1
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// optional parts of the protocol, peer that doesn't have some of them still can be talked to
+pub mod features {
+  /// gossip can be encoded with `wire::Encoding::Binary`
+  pub const BINARY_GOSSIP: &str = "binary_gossip";
+  /// nodes gossip membership changes, see `epoch_coordinator::epoch::Membership`
+  pub const MEMBERSHIP: &str = "membership";
+  /// missing transactions are served in pages, see `m2m_request_response::Request::GetTxPage`
+  pub const TX_PAGES: &str = "tx_pages";
+
+  pub const ALL: [&str; 3] = [BINARY_GOSSIP, MEMBERSHIP, TX_PAGES];
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Request {
   pub role: Role,
+  #[serde(default = "Capabilities::legacy")]
+  pub capabilities: Capabilities,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Response {
   pub role: Role,
+  #[serde(default = "Capabilities::legacy")]
+  pub capabilities: Capabilities,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum Role {
   Gateway,
   Node,
 }
+
+/// what the build of a peer understands
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+  pub protocol_version: u32,
+  /// `generated::maroon_assembler::IR_SCHEMA_HASH`, `None` if the peer doesn't report it
+  pub ir_schema_hash: Option<u64>,
+  /// strings, so features of newer builds don't break the older ones
+  pub features: BTreeSet<String>,
+}
+
+/// how this node can talk to a peer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "data")]
+pub enum Compatibility {
+  Full,
+  /// peer doesn't have these features, they aren't used with it
+  Degraded(BTreeSet<String>),
+  /// connection is refused
+  Incompatible(String),
+}
+
+impl Capabilities {
+  /// capabilities of this build
+  pub fn current() -> Capabilities {
+    Capabilities {
+      protocol_version: PROTOCOL_VERSION,
+      ir_schema_hash: Some(IR_SCHEMA_HASH),
+      features: features::ALL.iter().map(|f| f.to_string()).collect(),
+    }
+  }
+
+  /// peers that were built before meta exchange had capabilities
+  pub fn legacy() -> Capabilities {
+    Capabilities { protocol_version: 1, ir_schema_hash: None, features: BTreeSet::new() }
+  }
+
+  pub fn supports(
+    &self,
+    feature: &str,
+  ) -> bool {
+    self.features.contains(feature)
+  }
+
+  /// checks the peer from the point of view of `self` <br>
+  /// peer makes its own decision, ex: it can refuse `self` when `self` is too old for it
+  pub fn check(
+    &self,
+    peer: &Capabilities,
+  ) -> Compatibility {
+    if peer.protocol_version < MIN_PROTOCOL_VERSION {
+      return Compatibility::Incompatible(format!(
+        "protocol version {} is older than {MIN_PROTOCOL_VERSION}",
+        peer.protocol_version
+      ));
+    }
+    if let (Some(own), Some(peers)) = (self.ir_schema_hash, peer.ir_schema_hash)
+      && own != peers
+    {
+      return Compatibility::Incompatible(format!("IR schema {peers:016x} differs from {own:016x}"));
+    }
+
+    let missing: BTreeSet<String> = self.features.difference(&peer.features).cloned().collect();
+    if missing.is_empty() { Compatibility::Full } else { Compatibility::Degraded(missing) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_compatibility() {
+    let current = Capabilities::current();
+    assert_eq!(Compatibility::Full, current.check(&Capabilities::current()));
+
+    // peers that don't know about capabilities are talked to without the new features
+    let legacy: Request = serde_json::from_str(r#"{"role":"Node"}"#).unwrap();
+    assert_eq!(Capabilities::legacy(), legacy.capabilities);
+    let Compatibility::Degraded(missing) = current.check(&legacy.capabilities) else {
+      panic!("legacy peer should be degraded");
+    };
+    assert!(missing.contains(features::BINARY_GOSSIP));
+
+    // features this build doesn't know about don't matter
+    let mut newer = Capabilities::current();
+    newer.protocol_version += 1;
+    newer.features.insert("from_the_future".to_string());
+    assert_eq!(Compatibility::Full, current.check(&newer));
+
+    let other_ir = Capabilities { ir_schema_hash: Some(IR_SCHEMA_HASH ^ 1), ..Capabilities::current() };
+    assert!(matches!(current.check(&other_ir), Compatibility::Incompatible(_)));
+    let too_old = Capabilities { protocol_version: 0, ..Capabilities::legacy() };
+    assert!(matches!(current.check(&too_old), Compatibility::Incompatible(_)));
+  }
+}
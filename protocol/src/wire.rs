@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use futures::prelude::*;
+use libp2p::swarm::StreamProtocol;
+use libp2p_request_response::{self as request_response, ProtocolSupport};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{io, marker::PhantomData};
+
+/// how messages are encoded on the wire
+/// nodes understand both, so JSON nodes and binary nodes can work together while the cluster is upgraded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+  /// the first version of all the protocols
+  Json,
+  /// CBOR, it keeps the same serde model as JSON but numbers and bytes aren't encoded as text
+  #[default]
+  Binary,
+}
+
+/// binary gossip messages start with it, JSON can't start with this byte
+const BINARY_GOSSIP_TAG: u8 = 0x02;
+
+const REQUEST_SIZE_MAXIMUM: u64 = 1024 * 1024;
+const RESPONSE_SIZE_MAXIMUM: u64 = 10 * 1024 * 1024;
+
+pub fn encode<T: Serialize>(
+  encoding: Encoding,
+  message: &T,
+) -> io::Result<Vec<u8>> {
+  match encoding {
+    Encoding::Json => serde_json::to_vec(message).map_err(io::Error::other),
+    Encoding::Binary => cbor4ii::serde::to_vec(Vec::new(), message).map_err(io::Error::other),
+  }
+}
+
+pub fn decode<T: DeserializeOwned>(
+  encoding: Encoding,
+  bytes: &[u8],
+) -> io::Result<T> {
+  match encoding {
+    Encoding::Json => serde_json::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+    Encoding::Binary => {
+      cbor4ii::serde::from_slice(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+  }
+}
+
+/// gossip isn't negotiated, so binary messages are tagged and receivers accept both encodings
+pub fn encode_gossip<T: Serialize>(
+  encoding: Encoding,
+  message: &T,
+) -> io::Result<Vec<u8>> {
+  match encoding {
+    Encoding::Json => encode(Encoding::Json, message),
+    Encoding::Binary => {
+      let mut bytes = vec![BINARY_GOSSIP_TAG];
+      bytes.extend(encode(Encoding::Binary, message)?);
+      Ok(bytes)
+    }
+  }
+}
+
+pub fn decode_gossip<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<T> {
+  match bytes.split_first() {
+    Some((&BINARY_GOSSIP_TAG, rest)) => decode(Encoding::Binary, rest),
+    _ => decode(Encoding::Json, bytes),
+  }
+}
+
+/// request-response behaviour that speaks both versions of the protocol <br>
+/// `binary` is preferred, peers that don't know it yet negotiate `json`
+pub fn create_behaviour<Req, Resp>(
+  binary: StreamProtocol,
+  json: StreamProtocol,
+  support: ProtocolSupport,
+) -> request_response::Behaviour<Codec<Req, Resp>>
+where
+  Req: Send + Serialize + DeserializeOwned,
+  Resp: Send + Serialize + DeserializeOwned,
+{
+  let codec = Codec { json: json.clone(), phantom: PhantomData };
+  request_response::Behaviour::with_codec(
+    codec,
+    [(binary, support.clone()), (json, support)],
+    request_response::Config::default(),
+  )
+}
+
+/// picks the encoding by the negotiated protocol, one message per stream
+pub struct Codec<Req, Resp> {
+  /// everything else is binary
+  json: StreamProtocol,
+  phantom: PhantomData<(Req, Resp)>,
+}
+
+impl<Req, Resp> Clone for Codec<Req, Resp> {
+  fn clone(&self) -> Self {
+    Codec { json: self.json.clone(), phantom: PhantomData }
+  }
+}
+
+impl<Req, Resp> Codec<Req, Resp> {
+  fn encoding(
+    &self,
+    protocol: &StreamProtocol,
+  ) -> Encoding {
+    if *protocol == self.json { Encoding::Json } else { Encoding::Binary }
+  }
+}
+
+#[async_trait]
+impl<Req, Resp> request_response::Codec for Codec<Req, Resp>
+where
+  Req: Send + Serialize + DeserializeOwned,
+  Resp: Send + Serialize + DeserializeOwned,
+{
+  type Protocol = StreamProtocol;
+  type Request = Req;
+  type Response = Resp;
+
+  async fn read_request<T>(
+    &mut self,
+    protocol: &StreamProtocol,
+    io: &mut T,
+  ) -> io::Result<Req>
+  where
+    T: AsyncRead + Unpin + Send,
+  {
+    let mut bytes = Vec::new();
+    io.take(REQUEST_SIZE_MAXIMUM).read_to_end(&mut bytes).await?;
+    decode(self.encoding(protocol), &bytes)
+  }
+
+  async fn read_response<T>(
+    &mut self,
+    protocol: &StreamProtocol,
+    io: &mut T,
+  ) -> io::Result<Resp>
+  where
+    T: AsyncRead + Unpin + Send,
+  {
+    let mut bytes = Vec::new();
+    io.take(RESPONSE_SIZE_MAXIMUM).read_to_end(&mut bytes).await?;
+    decode(self.encoding(protocol), &bytes)
+  }
+
+  async fn write_request<T>(
+    &mut self,
+    protocol: &StreamProtocol,
+    io: &mut T,
+    req: Req,
+  ) -> io::Result<()>
+  where
+    T: AsyncWrite + Unpin + Send,
+  {
+    io.write_all(&encode(self.encoding(protocol), &req)?).await
+  }
+
+  async fn write_response<T>(
+    &mut self,
+    protocol: &StreamProtocol,
+    io: &mut T,
+    resp: Resp,
+  ) -> io::Result<()>
+  where
+    T: AsyncWrite + Unpin + Send,
+  {
+    io.write_all(&encode(self.encoding(protocol), &resp)?).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::m2m_request_response::{self as m2m, PROTOCOL_BINARY, PROTOCOL_JSON};
+  use crate::node2gw::{GossipMessage, GossipPayload};
+  use crate::transaction::{Meta, TaskBlueprint, Transaction, TxStatus, TxUpdate};
+  use futures::io::Cursor;
+  use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
+  use libp2p::PeerId;
+  use libp2p_request_response::Codec as _;
+  use types::range_key::UniqueU64BlobId;
+
+  fn transaction(id: u64) -> Transaction {
+    Transaction {
+      meta: Meta { id: UniqueU64BlobId(id), status: TxStatus::Pending },
+      blueprint: TaskBlueprint {
+        queue_name: "testInfiniteCalculatorQueue".to_string(),
+        param: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: 5, b: 7 }),
+      },
+    }
+  }
+
+  #[test]
+  fn requests_go_through_both_protocol_versions() {
+    futures::executor::block_on(async {
+      let mut codec = Codec::<m2m::Request, m2m::Response> { json: PROTOCOL_JSON, phantom: PhantomData };
+      let request = m2m::Request::MissingTx(vec![transaction(0), transaction(1)]);
+
+      let mut sizes = vec![];
+      for protocol in [PROTOCOL_JSON, PROTOCOL_BINARY] {
+        let mut io = Cursor::new(Vec::new());
+        codec.write_request(&protocol, &mut io, request.clone()).await.unwrap();
+        sizes.push(io.get_ref().len());
+
+        io.set_position(0);
+        let m2m::Request::MissingTx(got) = codec.read_request(&protocol, &mut io).await.unwrap() else {
+          panic!("request is decoded as another variant");
+        };
+        assert_eq!(vec![transaction(0), transaction(1)], got);
+      }
+      assert!(sizes[1] < sizes[0], "binary {} >= json {}", sizes[1], sizes[0]);
+    });
+  }
+
+  #[test]
+  fn gossip_is_decoded_from_both_encodings() {
+    let update =
+      TxUpdate { meta: Meta { id: UniqueU64BlobId(7), status: TxStatus::Finished }, result: Some(Value::U64(35)) };
+    let message =
+      GossipMessage { peer_id: PeerId::random(), payload: GossipPayload::Node2GWTxUpdate(vec![update.clone()]) };
+
+    for encoding in [Encoding::Json, Encoding::Binary] {
+      let bytes = encode_gossip(encoding, &message).unwrap();
+      let decoded: GossipMessage = decode_gossip(&bytes).unwrap();
+      let GossipPayload::Node2GWTxUpdate(updates) = decoded.payload;
+      assert_eq!(message.peer_id, decoded.peer_id);
+      assert_eq!(vec![update.clone()], updates);
+    }
+  }
+}
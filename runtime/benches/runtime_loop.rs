@@ -0,0 +1,73 @@
+//! throughput and latency of the runtime loop: tasks go through a public queue to the summator fiber and back
+//!
+//! `cargo bench -p runtime --bench runtime_loop`
+
+use common::duplex_channel::create_a_b_duplex_pair;
+use common::logical_clock::MonotonicTimer;
+use common::logical_time::LogicalTimeAbsoluteMs;
+use common::range_key::UniqueU64BlobId;
+use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
+use runtime::runtime::{Input, Output, Runtime, TaskBlueprint};
+use std::time::{Duration, Instant};
+
+const WARMUP: u64 = 1_000;
+const SEQUENTIAL: u64 = 5_000;
+const BATCHED: u64 = 100_000;
+const BATCH_SIZE: u64 = 100;
+
+fn summator_task(id: u64) -> TaskBlueprint {
+  TaskBlueprint {
+    global_id: UniqueU64BlobId(id),
+    q_name: "testInfiniteCalculatorQueue".to_string(),
+    value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: id, b: 1 }),
+  }
+}
+
+fn percentile(
+  sorted: &[Duration],
+  p: f64,
+) -> Duration {
+  sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+#[tokio::main]
+async fn main() {
+  let (mut a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+  let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime);
+  tokio::spawn(async move {
+    rt.run("root".to_string()).await;
+  });
+
+  let mut next_id = 0;
+  let mut send_and_wait = async |count: u64, batch_size: u64| -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(count as usize);
+    for _ in 0..count / batch_size {
+      let started = Instant::now();
+      let tasks: Vec<TaskBlueprint> = (0..batch_size).map(|i| summator_task(next_id + i)).collect();
+      next_id += batch_size;
+      a2b_runtime.send((LogicalTimeAbsoluteMs(0), tasks));
+      for _ in 0..batch_size {
+        let (_, result) = a2b_runtime.receiver.recv().await.expect("runtime is running");
+        result.expect("summator accepts the task");
+        latencies.push(started.elapsed());
+      }
+    }
+    latencies
+  };
+
+  send_and_wait(WARMUP, 1).await;
+
+  for (name, count, batch_size) in [("sequential", SEQUENTIAL, 1), ("batched", BATCHED, BATCH_SIZE)] {
+    let started = Instant::now();
+    let mut latencies = send_and_wait(count, batch_size).await;
+    let elapsed = started.elapsed();
+    latencies.sort();
+    println!(
+      "{name:>10}: {count} tasks in {elapsed:?}, {:.0} tasks/s, latency p50 {:?} p99 {:?} max {:?}",
+      count as f64 / elapsed.as_secs_f64(),
+      percentile(&latencies, 0.50),
+      percentile(&latencies, 0.99),
+      latencies.last().expect("there are tasks"),
+    );
+  }
+}
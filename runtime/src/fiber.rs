@@ -1,10 +1,11 @@
 use dsl::ir::FiberType;
 use generated::maroon_assembler::{
   CreatePrimitiveValue, Heap, SelectArm, SetPrimitiveValue, StackEntry, State, StepResult, SuccessBindKind, Value,
-  func_args_count, get_heap_init_fn, get_prepare_fn, global_step,
+  func_args_count, get_heap_init_fn, get_prepare_fn, global_step, heap_future_ids, value_future_ids,
 };
 
 use crate::trace::TraceEvent;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug)]
 pub struct Fiber {
@@ -43,6 +44,58 @@ pub enum RunResult {
     fail_next: State,
     fail_binds: Vec<String>,
   },
+  /// fiber can't continue and is terminated, runtime shouldn't run it anymore
+  Failed(Failure),
+}
+
+/// why a fiber has been terminated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureKind {
+  /// stack doesn't have what the state expects
+  StackCorrupted,
+  /// there is no local to bind a value into
+  MissingLocal,
+  /// message is sent to a queue that doesn't exist
+  UnknownQueue,
+  /// message is sent to a queue that only runtime can send into
+  ReservedQueue,
+}
+
+impl FailureKind {
+  /// machine-readable kind for `SystemFiberEvent::FiberFailed`
+  pub fn code(&self) -> &'static str {
+    match self {
+      FailureKind::StackCorrupted => "stack_corrupted",
+      FailureKind::MissingLocal => "missing_local",
+      FailureKind::UnknownQueue => "unknown_queue",
+      FailureKind::ReservedQueue => "reserved_queue",
+    }
+  }
+}
+
+/// fatal error of a fiber, see "Failure and error model" in docs/language-whitepaper.md
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+  pub kind: FailureKind,
+  pub detail: String,
+}
+
+impl Failure {
+  pub fn new(
+    kind: FailureKind,
+    detail: String,
+  ) -> Failure {
+    Failure { kind, detail }
+  }
+}
+
+impl std::fmt::Display for Failure {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}: {}", self.kind.code(), self.detail)
+  }
 }
 
 impl std::fmt::Display for Fiber {
@@ -55,7 +108,7 @@ impl std::fmt::Display for Fiber {
 }
 
 /// Runtime-only Future identifier. Unique per-fiber using suffixing policy.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FutureId(pub String);
 impl std::fmt::Display for FutureId {
   fn fmt(
@@ -93,18 +146,33 @@ impl Fiber {
     }
   }
 
+  /// ids of all futures the fiber can reach: in its stack and heap
+  pub fn future_ids(&self) -> Vec<String> {
+    let mut ids = Vec::new();
+    for entry in &self.stack {
+      match entry {
+        StackEntry::Value(_, val) => value_future_ids(val, &mut ids),
+        StackEntry::FrameAssign(updates) => updates.iter().for_each(|(_, val)| value_future_ids(val, &mut ids)),
+        StackEntry::State(_) | StackEntry::Retrn(_) => {}
+      }
+    }
+    heap_future_ids(&self.heap, &mut ids);
+    ids
+  }
+
   /// Assigns a value to the first matching named value entry from the back (top) of the stack.
   pub fn assign_local(
     &mut self,
     name: String,
     val: Value,
-  ) {
+  ) -> Result<(), Failure> {
     if let Some(StackEntry::Value(_, slot)) =
       self.stack.iter_mut().rev().find(|se| matches!(se, StackEntry::Value(n, _) if *n == name))
     {
       *slot = val;
+      Ok(())
     } else {
-      panic!("didnt find the value with the right name, something is completely wrong");
+      Err(Failure::new(FailureKind::MissingLocal, format!("no local {name} on the stack")))
     }
   }
 
@@ -114,9 +182,10 @@ impl Fiber {
     name: String,
     val: Value,
     next: State,
-  ) {
-    self.assign_local(name, val);
+  ) -> Result<(), Failure> {
+    self.assign_local(name, val)?;
     self.stack.push(StackEntry::State(next));
+    Ok(())
   }
 
   /// Push next state on stack
@@ -148,7 +217,10 @@ impl Fiber {
 
       let arguments_number = func_args_count(&state);
       if arguments_number > self.stack.len() {
-        panic!("miss amount of variables: need {arguments_number}, have {}", self.stack.len());
+        return RunResult::Failed(Failure::new(
+          FailureKind::StackCorrupted,
+          format!("{state:?} needs {arguments_number} variables, stack has {}", self.stack.len()),
+        ));
       }
 
       // index on stack where current function starts
@@ -157,7 +229,7 @@ impl Fiber {
 
       let state_cp = state.clone();
       let result = global_step(state, &self.stack[start..], &mut self.heap);
-      self.trace_sink.push(TraceEvent { state: state_cp, result: result.clone() });
+      self.trace_sink.push(TraceEvent { state: state_cp.clone(), result: result.clone() });
 
       match result {
         StepResult::Debug(msg, next) => {
@@ -195,10 +267,8 @@ impl Fiber {
           self.stack.truncate(start);
 
           // since we're returning from function we should have a record of return 'address' info
-          let StackEntry::Retrn(return_instruction) =
-            self.stack.pop().expect("stack is corrupted. No return instruction")
-          else {
-            panic!("there is no return instruction on stack. Stack is corrupted");
+          let Some(StackEntry::Retrn(return_instruction)) = self.stack.pop() else {
+            return RunResult::Failed(no_return_instruction(&state_cp));
           };
 
           if let Some(offset) = return_instruction {
@@ -219,10 +289,8 @@ impl Fiber {
           self.stack.truncate(start);
 
           // since we're returning from function we should have a record of return 'address' info
-          let StackEntry::Retrn(_return_instruction) =
-            self.stack.pop().expect("stack is corrupted. No return instruction")
-          else {
-            panic!("there is no return instruction on stack. Stack is corrupted");
+          let Some(StackEntry::Retrn(_return_instruction)) = self.stack.pop() else {
+            return RunResult::Failed(no_return_instruction(&state_cp));
           };
         }
         StepResult::GoTo(state) => {
@@ -269,3 +337,10 @@ impl Fiber {
     }
   }
 }
+
+fn no_return_instruction(state: &State) -> Failure {
+  Failure::new(
+    FailureKind::StackCorrupted,
+    format!("{state:?} returns, but there is no return instruction on the stack"),
+  )
+}
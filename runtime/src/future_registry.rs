@@ -0,0 +1,269 @@
+use crate::fiber::FutureId;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// who keeps a future alive
+#[derive(Debug, Default)]
+struct FutureOwners {
+  /// fiber that has created the future, `None` - runtime has created it for a task from the outside
+  creator: Option<u64>,
+  /// fibers that have the future in their stacks, heaps or selects
+  fibers: BTreeSet<u64>,
+  /// how many messages in each queue have the future
+  queues: BTreeMap<String, usize>,
+  /// how many values of resolved futures have the future
+  resolved_values: usize,
+}
+
+impl FutureOwners {
+  fn is_empty(&self) -> bool {
+    self.fibers.is_empty() && self.queues.is_empty() && self.resolved_values == 0
+  }
+}
+
+/// tracks owners of the futures runtime has created <br>
+/// future that nobody owns can't be awaited or resolved anymore, so runtime can reclaim it <br>
+/// ids that haven't been tracked are ignored
+#[derive(Debug, Default)]
+pub(crate) struct FutureRegistry {
+  futures: HashMap<FutureId, FutureOwners>,
+  /// futures each fiber had when it was scanned last time
+  fibers: HashMap<u64, BTreeSet<FutureId>>,
+}
+
+impl FutureRegistry {
+  /// starts tracking a new future, it has no owners until they are added
+  pub(crate) fn track(
+    &mut self,
+    id: FutureId,
+    creator: Option<u64>,
+  ) {
+    self.futures.insert(id, FutureOwners { creator, ..Default::default() });
+  }
+
+  /// future is resolved and consumed or reclaimed, nobody can use it anymore
+  pub(crate) fn untrack(
+    &mut self,
+    id: &FutureId,
+  ) {
+    let Some(owners) = self.futures.remove(id) else {
+      return;
+    };
+    for fiber_id in owners.fibers {
+      if let Some(held) = self.fibers.get_mut(&fiber_id) {
+        held.remove(id);
+      }
+    }
+  }
+
+  pub(crate) fn len(&self) -> usize {
+    self.futures.len()
+  }
+
+  /// tracked futures with their creators, sorted by id
+  pub(crate) fn creators(&self) -> Vec<(String, Option<u64>)> {
+    let mut creators: Vec<(String, Option<u64>)> =
+      self.futures.iter().map(|(id, owners)| (id.0.clone(), owners.creator)).collect();
+    creators.sort();
+    creators
+  }
+
+  /// fiber has got these futures in addition to the ones it has
+  pub(crate) fn add_to_fiber(
+    &mut self,
+    fiber_id: u64,
+    ids: Vec<String>,
+  ) {
+    for id in ids {
+      let id = FutureId(id);
+      let Some(owners) = self.futures.get_mut(&id) else {
+        continue;
+      };
+      owners.fibers.insert(fiber_id);
+      self.fibers.entry(fiber_id).or_default().insert(id);
+    }
+  }
+
+  /// fiber doesn't have these futures anymore, returns futures nobody owns anymore
+  pub(crate) fn remove_from_fiber(
+    &mut self,
+    fiber_id: u64,
+    ids: Vec<FutureId>,
+  ) -> Vec<FutureId> {
+    let mut released = Vec::new();
+    for id in ids {
+      if let Some(held) = self.fibers.get_mut(&fiber_id) {
+        held.remove(&id);
+      }
+      let Some(owners) = self.futures.get_mut(&id) else {
+        continue;
+      };
+      owners.fibers.remove(&fiber_id);
+      if owners.is_empty() && !released.contains(&id) {
+        released.push(id);
+      }
+    }
+    released
+  }
+
+  /// replaces futures the fiber has, returns futures nobody owns anymore
+  pub(crate) fn set_fiber(
+    &mut self,
+    fiber_id: u64,
+    ids: Vec<String>,
+  ) -> Vec<FutureId> {
+    let held: BTreeSet<FutureId> = ids.into_iter().map(FutureId).filter(|id| self.futures.contains_key(id)).collect();
+    let before = self.fibers.remove(&fiber_id).unwrap_or_default();
+
+    let mut released = Vec::new();
+    for id in before.difference(&held) {
+      if let Some(owners) = self.futures.get_mut(id) {
+        owners.fibers.remove(&fiber_id);
+        if owners.is_empty() {
+          released.push(id.clone());
+        }
+      }
+    }
+    for id in held.difference(&before) {
+      if let Some(owners) = self.futures.get_mut(id) {
+        owners.fibers.insert(fiber_id);
+      }
+    }
+    if !held.is_empty() {
+      self.fibers.insert(fiber_id, held);
+    }
+    released
+  }
+
+  /// fiber is gone, returns futures nobody owns anymore
+  pub(crate) fn remove_fiber(
+    &mut self,
+    fiber_id: u64,
+  ) -> Vec<FutureId> {
+    self.set_fiber(fiber_id, vec![])
+  }
+
+  /// message with these futures is put into the queue
+  pub(crate) fn add_to_queue(
+    &mut self,
+    queue_name: &str,
+    ids: Vec<String>,
+  ) {
+    for id in ids {
+      if let Some(owners) = self.futures.get_mut(&FutureId(id)) {
+        *owners.queues.entry(queue_name.to_string()).or_default() += 1;
+      }
+    }
+  }
+
+  /// message with these futures is taken from the queue, returns futures nobody owns anymore
+  pub(crate) fn remove_from_queue(
+    &mut self,
+    queue_name: &str,
+    ids: Vec<String>,
+  ) -> Vec<FutureId> {
+    let mut released = Vec::new();
+    for id in ids {
+      let id = FutureId(id);
+      let Some(owners) = self.futures.get_mut(&id) else {
+        continue;
+      };
+      if let Some(count) = owners.queues.get_mut(queue_name) {
+        *count -= 1;
+        if *count == 0 {
+          owners.queues.remove(queue_name);
+        }
+      }
+      if owners.is_empty() && !released.contains(&id) {
+        released.push(id);
+      }
+    }
+    released
+  }
+
+  /// value of a resolved future has these futures
+  pub(crate) fn add_to_resolved(
+    &mut self,
+    ids: Vec<String>,
+  ) {
+    for id in ids {
+      if let Some(owners) = self.futures.get_mut(&FutureId(id)) {
+        owners.resolved_values += 1;
+      }
+    }
+  }
+
+  /// value of a resolved future is consumed or dropped, returns futures nobody owns anymore
+  pub(crate) fn remove_from_resolved(
+    &mut self,
+    ids: Vec<String>,
+  ) -> Vec<FutureId> {
+    let mut released = Vec::new();
+    for id in ids {
+      let id = FutureId(id);
+      let Some(owners) = self.futures.get_mut(&id) else {
+        continue;
+      };
+      owners.resolved_values = owners.resolved_values.saturating_sub(1);
+      if owners.is_empty() && !released.contains(&id) {
+        released.push(id);
+      }
+    }
+    released
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+  }
+
+  fn future(id: &str) -> FutureId {
+    FutureId(id.to_string())
+  }
+
+  #[test]
+  fn futures_are_released_when_the_last_owner_is_gone() {
+    let mut registry = FutureRegistry::default();
+    registry.track(future("0"), Some(1));
+    registry.track(future("1"), Some(1));
+    registry.track(future("2"), None);
+    registry.add_to_fiber(1, ids(&["0", "1"]));
+
+    // fiber 1 sends future 0 to a queue and overwrites future 1
+    registry.add_to_queue("q", ids(&["0"]));
+    assert_eq!(vec![future("1")], registry.set_fiber(1, ids(&["0"])));
+    registry.untrack(&future("1"));
+
+    // fiber 2 takes the message, fiber 1 exits
+    registry.add_to_fiber(2, ids(&["0"]));
+    assert!(registry.remove_from_queue("q", ids(&["0"])).is_empty());
+    assert!(registry.remove_fiber(1).is_empty());
+    assert_eq!(vec![future("0")], registry.remove_fiber(2));
+
+    // untracked futures are ignored, tracked ones can be kept by resolved values
+    registry.add_to_resolved(ids(&["2", "3"]));
+    assert_eq!(vec![future("2")], registry.remove_from_resolved(ids(&["2", "3"])));
+
+    assert_eq!(vec![("0".to_string(), Some(1)), ("2".to_string(), None)], registry.creators());
+    registry.untrack(&future("0"));
+    registry.untrack(&future("2"));
+    assert_eq!(0, registry.len());
+    assert!(registry.fibers.is_empty());
+  }
+
+  #[test]
+  fn futures_of_losing_arms_are_released_only_without_owners() {
+    let mut registry = FutureRegistry::default();
+    registry.track(future("0"), Some(1));
+    registry.track(future("1"), Some(1));
+    registry.add_to_fiber(1, ids(&["0", "1"]));
+    registry.add_to_fiber(2, ids(&["1"]));
+
+    // fiber 2 awaits future 1 as well, untracked futures are ignored
+    assert_eq!(vec![future("0")], registry.remove_from_fiber(1, vec![future("0"), future("1"), future("2")]));
+    assert_eq!(vec![future("1")], registry.remove_fiber(2));
+  }
+}
@@ -31,11 +31,13 @@ fn test_future_response() {
     inStrRespQueueName: "my_test_queue_name".to_string(),
   };
 
-  fiber.assign_local_and_push_next(
-    "f_task".to_string(),
-    Value::TestIncrementTask(input_task.clone()),
-    State::TestTaskExecutorIncrementerMainIncrement,
-  );
+  fiber
+    .assign_local_and_push_next(
+      "f_task".to_string(),
+      Value::TestIncrementTask(input_task.clone()),
+      State::TestTaskExecutorIncrementerMainIncrement,
+    )
+    .expect("local exists");
 
   let second_result = fiber.run(&mut dbg);
   assert_eq!(
@@ -123,19 +125,15 @@ fn test_select_resume_mechanism() {
   {
     // queue imitation
     // we pass counter == 1 - so counter will start from 1
-    queue_response.assign_local_and_push_next(
-      "counter".to_string(),
-      Value::U64(1),
-      State::TestSelectQueueMainStartWork,
-    );
+    queue_response
+      .assign_local_and_push_next("counter".to_string(), Value::U64(1), State::TestSelectQueueMainStartWork)
+      .expect("local exists");
 
     // future imitation
     // we pass responseFromFut == 2 - so counter will start from 1
-    future_response.assign_local_and_push_next(
-      "responseFromFut".to_string(),
-      Value::U64(2),
-      State::TestSelectQueueMainIncFromFut,
-    );
+    future_response
+      .assign_local_and_push_next("responseFromFut".to_string(), Value::U64(2), State::TestSelectQueueMainIncFromFut)
+      .expect("local exists");
   }
 
   // Continue execution; should complete
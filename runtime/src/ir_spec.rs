@@ -216,8 +216,8 @@ pub fn sample_ir() -> IR {
                   StepId::new("wrong_queue_creation"),
                   Step::Create {
                     primitives: vec![
-                      RuntimePrimitive::Queue { name: LocalVarRef("f_queueName"), public: true },
-                      RuntimePrimitive::Queue { name: LocalVarRef("f_queueName"), public: true },
+                      RuntimePrimitive::Queue { name: LocalVarRef("f_queueName"), public: true, message: Some("TestCreateQueueMessage".to_string()) },
+                      RuntimePrimitive::Queue { name: LocalVarRef("f_queueName"), public: true, message: Some("TestCreateQueueMessage".to_string()) },
                     ],
                     success: SuccessCreateBranch { next: StepId::new("return"), id_binds: vec![LocalVarRef("created_queue_name"), LocalVarRef("created_queue_name")] },
                     fail: FailCreateBranch { next: StepId::new("debug_vars"), error_binds: vec![LocalVarRef("f_queueCreationError"), LocalVarRef("f_queueCreationError")] },
@@ -242,7 +242,7 @@ pub fn sample_ir() -> IR {
                   StepId::new("correct_creation"),
                   Step::Create {
                     primitives: vec![
-                      RuntimePrimitive::Queue { name: LocalVarRef("f_queueName"), public: true },
+                      RuntimePrimitive::Queue { name: LocalVarRef("f_queueName"), public: true, message: Some("TestCreateQueueMessage".to_string()) },
                     ],
                     success: SuccessCreateBranch { next: StepId::new("debug_vars_2"), id_binds: vec![LocalVarRef("created_queue_name")] },
                     fail: FailCreateBranch { next: StepId::new("return"), error_binds: vec![LocalVarRef("f_queueCreationError")] },
@@ -334,7 +334,8 @@ pub fn sample_ir() -> IR {
                       primitives: vec![
                         RuntimePrimitive::Queue {
                           name: LocalVarRef("rootQueueName"), 
-                          public: true,
+                          public: false,
+                          message: None,
                         },
                       ],
                       success: SuccessCreateBranch { next: StepId::new("create_fiber"), id_binds: vec![LocalVarRef("rootQueueName")] },
@@ -589,7 +590,7 @@ pub fn sample_ir() -> IR {
                   (
                     StepId::new("create_queue"),
                     Step::Create {
-                      primitives: vec![RuntimePrimitive::Queue { name: LocalVarRef("infiniteCalculatorQueue"), public: true }],
+                      primitives: vec![RuntimePrimitive::Queue { name: LocalVarRef("infiniteCalculatorQueue"), public: true, message: Some("TestInfiniteSummatorQueueMessage".to_string()) }],
                       success: SuccessCreateBranch {
                         next: StepId::new("select_queue"), 
                         id_binds: vec![LocalVarRef("infiniteCalculatorQueue")],
@@ -947,6 +948,379 @@ out
           ]),
         },
       ),
+      (
+        // awaits timers with timeouts: the first one times out, the second one is resolved before its timeout
+        FiberType::new("testSelectTimeout"),
+        Fiber {
+          init_vars: vec![],
+          heap: HashMap::new(),
+          funcs: HashMap::from([(
+            "main".to_string(),
+            Func {
+              in_vars: vec![],
+              out: Type::Void,
+              locals: vec![
+                LocalVar("slowMs", Type::UInt64),
+                LocalVar("fastMs", Type::UInt64),
+                LocalVar("slowTimer", Type::Future(Box::new(Type::Void))),
+                LocalVar("fastTimer", Type::Future(Box::new(Type::Void))),
+                LocalVar("createError", Type::Option(Box::new(Type::String))),
+              ],
+              steps: vec![
+                (
+                  StepId::new("entry"),
+                  Step::Let { local: "slowMs".to_string(), expr: Expr::UInt64(100), next: StepId::new("init_fast_ms") },
+                ),
+                (
+                  StepId::new("init_fast_ms"),
+                  Step::Let { local: "fastMs".to_string(), expr: Expr::UInt64(10), next: StepId::new("create_timers") },
+                ),
+                (
+                  StepId::new("create_timers"),
+                  Step::Create {
+                    primitives: vec![
+                      RuntimePrimitive::Schedule { ms_var: LocalVarRef("slowMs") },
+                      RuntimePrimitive::Schedule { ms_var: LocalVarRef("fastMs") },
+                    ],
+                    success: SuccessCreateBranch {
+                      next: StepId::new("await_slow"),
+                      id_binds: vec![LocalVarRef("slowTimer"), LocalVarRef("fastTimer")],
+                    },
+                    fail: FailCreateBranch {
+                      next: StepId::new("return"),
+                      error_binds: vec![LocalVarRef("createError"), LocalVarRef("createError")],
+                    },
+                  },
+                ),
+                (
+                  StepId::new("await_slow"),
+                  Step::Select {
+                    arms: vec![
+                      AwaitSpec::Future {
+                        bind: None,
+                        ret_to: StepId::new("slow_fired"),
+                        future_id: LocalVarRef("slowTimer"),
+                      },
+                      AwaitSpec::Timeout { ms: 30, next: StepId::new("slow_timed_out") },
+                    ],
+                  },
+                ),
+                (StepId::new("slow_fired"), Step::Debug("slow timer fired", StepId::new("return"))),
+                (StepId::new("slow_timed_out"), Step::Debug("slow timer timed out", StepId::new("await_fast"))),
+                (
+                  StepId::new("await_fast"),
+                  Step::Select {
+                    arms: vec![
+                      AwaitSpec::Future {
+                        bind: None,
+                        ret_to: StepId::new("fast_fired"),
+                        future_id: LocalVarRef("fastTimer"),
+                      },
+                      AwaitSpec::Timeout { ms: 30, next: StepId::new("fast_timed_out") },
+                    ],
+                  },
+                ),
+                (StepId::new("fast_fired"), Step::Debug("fast timer fired", StepId::new("return"))),
+                (StepId::new("fast_timed_out"), Step::Debug("fast timer timed out", StepId::new("return"))),
+                (StepId::new("return"), Step::ReturnVoid),
+              ],
+            },
+          )]),
+        },
+      ),
+      (
+        // awaits the same timer in a loop, the timer loses to timeouts until it fires
+        FiberType::new("testSelectTick"),
+        Fiber {
+          init_vars: vec![],
+          heap: HashMap::new(),
+          funcs: HashMap::from([(
+            "main".to_string(),
+            Func {
+              in_vars: vec![],
+              out: Type::Void,
+              locals: vec![
+                LocalVar("tickMs", Type::UInt64),
+                LocalVar("tickTimer", Type::Future(Box::new(Type::Void))),
+                LocalVar("createError", Type::Option(Box::new(Type::String))),
+              ],
+              steps: vec![
+                (
+                  StepId::new("entry"),
+                  Step::Let { local: "tickMs".to_string(), expr: Expr::UInt64(50), next: StepId::new("create_timer") },
+                ),
+                (
+                  StepId::new("create_timer"),
+                  Step::Create {
+                    primitives: vec![RuntimePrimitive::Schedule { ms_var: LocalVarRef("tickMs") }],
+                    success: SuccessCreateBranch {
+                      next: StepId::new("await_tick"),
+                      id_binds: vec![LocalVarRef("tickTimer")],
+                    },
+                    fail: FailCreateBranch {
+                      next: StepId::new("return"),
+                      error_binds: vec![LocalVarRef("createError")],
+                    },
+                  },
+                ),
+                (
+                  StepId::new("await_tick"),
+                  Step::Select {
+                    arms: vec![
+                      AwaitSpec::Future {
+                        bind: None,
+                        ret_to: StepId::new("tick_fired"),
+                        future_id: LocalVarRef("tickTimer"),
+                      },
+                      AwaitSpec::Timeout { ms: 30, next: StepId::new("tick_pending") },
+                    ],
+                  },
+                ),
+                (StepId::new("tick_pending"), Step::Debug("tick timer is pending", StepId::new("await_tick"))),
+                (StepId::new("tick_fired"), Step::Debug("tick timer fired", StepId::new("return"))),
+                (StepId::new("return"), Step::ReturnVoid),
+              ],
+            },
+          )]),
+        },
+      ),
+      (
+        // takes a request from a public queue and exits without answering it
+        FiberType::new("testDroppedRequest"),
+        Fiber {
+          init_vars: vec![],
+          heap: HashMap::new(),
+          funcs: HashMap::from([(
+            "main".to_string(),
+            Func {
+              in_vars: vec![],
+              out: Type::Void,
+              locals: vec![
+                LocalVar("queueName", Type::String),
+                LocalVar("createdQueueName", Type::String),
+                LocalVar("createError", Type::Option(Box::new(Type::String))),
+                LocalVar("request", Type::Custom("TestCreateQueueMessage".to_string())),
+              ],
+              steps: vec![
+                (
+                  StepId::new("entry"),
+                  Step::Let {
+                    local: "queueName".to_string(),
+                    expr: Expr::Str("droppedRequests".to_string()),
+                    next: StepId::new("create_queue"),
+                  },
+                ),
+                (
+                  StepId::new("create_queue"),
+                  Step::Create {
+                    primitives: vec![RuntimePrimitive::Queue {
+                      name: LocalVarRef("queueName"),
+                      public: true,
+                      message: Some("TestCreateQueueMessage".to_string()),
+                    }],
+                    success: SuccessCreateBranch {
+                      next: StepId::new("await_request"),
+                      id_binds: vec![LocalVarRef("createdQueueName")],
+                    },
+                    fail: FailCreateBranch {
+                      next: StepId::new("return"),
+                      error_binds: vec![LocalVarRef("createError")],
+                    },
+                  },
+                ),
+                (
+                  StepId::new("await_request"),
+                  Step::Select {
+                    arms: vec![AwaitSpec::Queue {
+                      queue_name: LocalVarRef("createdQueueName"),
+                      message_var: LocalVarRef("request"),
+                      next: StepId::new("return"),
+                    }],
+                  },
+                ),
+                (StepId::new("return"), Step::ReturnVoid),
+              ],
+            },
+          )]),
+        },
+      ),
+      (
+        // creates futures, never awaits them and exits, runtime should reclaim all of them
+        FiberType::new("testAbandonedFutures"),
+        Fiber {
+          init_vars: vec![],
+          heap: HashMap::new(),
+          funcs: HashMap::from([(
+            "main".to_string(),
+            Func {
+              in_vars: vec![],
+              out: Type::Void,
+              locals: vec![
+                LocalVar("timerMs", Type::UInt64),
+                LocalVar("abandoned", Type::Future(Box::new(Type::UInt64))),
+                LocalVar("abandonedTimer", Type::Future(Box::new(Type::Void))),
+                LocalVar("createError", Type::Option(Box::new(Type::String))),
+              ],
+              steps: vec![
+                (
+                  StepId::new("entry"),
+                  Step::Let {
+                    local: "timerMs".to_string(),
+                    expr: Expr::UInt64(1000),
+                    next: StepId::new("create_futures"),
+                  },
+                ),
+                (
+                  StepId::new("create_futures"),
+                  Step::Create {
+                    primitives: vec![
+                      RuntimePrimitive::Future,
+                      RuntimePrimitive::Schedule { ms_var: LocalVarRef("timerMs") },
+                    ],
+                    success: SuccessCreateBranch {
+                      next: StepId::new("sleep"),
+                      id_binds: vec![LocalVarRef("abandoned"), LocalVarRef("abandonedTimer")],
+                    },
+                    fail: FailCreateBranch {
+                      next: StepId::new("return"),
+                      error_binds: vec![LocalVarRef("createError"), LocalVarRef("createError")],
+                    },
+                  },
+                ),
+                (
+                  StepId::new("sleep"),
+                  Step::Select { arms: vec![AwaitSpec::Timeout { ms: 10, next: StepId::new("woke_up") }] },
+                ),
+                (StepId::new("woke_up"), Step::Debug("woke up", StepId::new("return"))),
+                (StepId::new("return"), Step::ReturnVoid),
+              ],
+            },
+          )]),
+        },
+      ),
+      (
+        // supervision fiber: starts fibers that fail and reports their failures from the system queue
+        FiberType::new("testSupervisor"),
+        Fiber {
+          init_vars: vec![],
+          heap: HashMap::new(),
+          funcs: HashMap::from([(
+            "main".to_string(),
+            Func {
+              in_vars: vec![],
+              out: Type::Void,
+              locals: vec![
+                LocalVar("systemQueueName", Type::String),
+                LocalVar("missingQueueName", Type::String),
+                LocalVar("event", Type::Custom("SystemFiberEvent".to_string())),
+                LocalVar("failedFiber", Type::String),
+                LocalVar("failureKind", Type::String),
+              ],
+              steps: vec![
+                (
+                  StepId::new("entry"),
+                  Step::Let {
+                    local: "systemQueueName".to_string(),
+                    expr: Expr::Str("mrn.system.fibers".to_string()),
+                    next: StepId::new("init_missing_queue_name"),
+                  },
+                ),
+                (
+                  StepId::new("init_missing_queue_name"),
+                  Step::Let {
+                    local: "missingQueueName".to_string(),
+                    expr: Expr::Str("missingQueue".to_string()),
+                    next: StepId::new("create_fibers"),
+                  },
+                ),
+                (
+                  StepId::new("create_fibers"),
+                  Step::CreateFibers {
+                    details: vec![
+                      CreateFiberDetail {
+                        f_name: FiberType::new("testFailingSender"),
+                        init_vars: vec![LocalVarRef("missingQueueName")],
+                      },
+                      CreateFiberDetail {
+                        f_name: FiberType::new("testFailingSender"),
+                        init_vars: vec![LocalVarRef("systemQueueName")],
+                      },
+                    ],
+                    next: StepId::new("await_failure"),
+                  },
+                ),
+                (
+                  StepId::new("await_failure"),
+                  Step::Select {
+                    arms: vec![AwaitSpec::Queue {
+                      queue_name: LocalVarRef("systemQueueName"),
+                      message_var: LocalVarRef("event"),
+                      next: StepId::new("unpack_failure"),
+                    }],
+                  },
+                ),
+                (
+                  StepId::new("unpack_failure"),
+                  Step::RustBlock {
+                    binds: vec![LocalVarRef("failedFiber"), LocalVarRef("failureKind")],
+                    code: r#"
+                      let SystemFiberEvent::FiberFailed { fiber_id, fiber_type, kind, .. } = event;
+                      (format!("{fiber_type}:{fiber_id}"), kind)
+                    "#
+                    .to_string(),
+                    next: StepId::new("report_failure"),
+                  },
+                ),
+                (
+                  StepId::new("report_failure"),
+                  Step::Debug("fiber failed", StepId::new("debug_vars")),
+                ),
+                (
+                  StepId::new("debug_vars"),
+                  Step::DebugPrintVars(StepId::new("await_failure")),
+                ),
+              ],
+            },
+          )]),
+        },
+      ),
+      (
+        // sends a message to the queue from init vars, fails if it doesn't exist or is reserved by runtime
+        FiberType::new("testFailingSender"),
+        Fiber {
+          init_vars: vec![InVar("in_queueName", Type::String)],
+          heap: HashMap::new(),
+          funcs: HashMap::from([(
+            "main".to_string(),
+            Func {
+              in_vars: vec![],
+              out: Type::Void,
+              locals: vec![LocalVar("queueName", Type::String), LocalVar("message", Type::UInt64)],
+              steps: vec![
+                (
+                  StepId::new("entry"),
+                  Step::RustBlock {
+                    binds: vec![LocalVarRef("queueName")],
+                    code: "heap.testFailingSender.in_vars.inQueuename.clone()".to_string(),
+                    next: StepId::new("send"),
+                  },
+                ),
+                (
+                  StepId::new("send"),
+                  Step::SetValues {
+                    values: vec![SetPrimitive::QueueMessage {
+                      f_var_queue_name: LocalVarRef("queueName"),
+                      var_name: LocalVarRef("message"),
+                    }],
+                    next: StepId::new("return"),
+                  },
+                ),
+                (StepId::new("return"), Step::ReturnVoid),
+              ],
+            },
+          )]),
+        },
+      ),
     ]),
     types: vec![
       Type::Struct(
@@ -1,4 +1,5 @@
 mod fiber;
+mod future_registry;
 pub mod ir_spec;
 // Re-export IR types so generated code can refer to `crate::ir::...`.
 pub use dsl::ir;
@@ -7,6 +8,7 @@ mod generated_test;
 #[cfg(test)]
 mod ir_test;
 pub mod runtime;
+pub mod snapshot;
 #[cfg(test)]
 mod test_helpers;
 mod trace;
@@ -1,18 +1,27 @@
 use crate::fiber::*;
+use crate::future_registry::FutureRegistry;
+use crate::snapshot::{FiberSnapshot, RuntimeSnapshot, SNAPSHOT_VERSION, SnapshotRequest};
 use crate::wait_registry::{WaitKey, WaitRegistry};
 use common::duplex_channel::Endpoint;
+use common::invoker_handler::{HandlerInterface, RequestWrapper};
 use common::logical_clock::Timer;
 use common::logical_time::LogicalTimeAbsoluteMs;
 use common::range_key::UniqueU64BlobId;
 use dsl::ir::FiberType;
 use generated::maroon_assembler::{
-  CreatePrimitiveValue, SetPrimitiveValue, StackEntry, SuccessBindKind, Value, pub_to_private, wrap_future_id,
+  CreatePrimitiveValue, SelectArm, SetPrimitiveValue, StackEntry, SuccessBindKind, SystemFiberEvent, Value,
+  pub_queue_message_type, pub_to_private, value_future_ids, wrap_future_id,
 };
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::{BinaryHeap, HashMap, HashSet, LinkedList, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TaskBlueprint {
   pub global_id: UniqueU64BlobId,
   pub q_name: String,
@@ -63,12 +72,121 @@ impl PartialOrd for ScheduledBlob {
   }
 }
 
+/// queues with this prefix are created by runtime and only runtime can send into them
+pub const SYSTEM_QUEUE_PREFIX: &str = "mrn.system.";
+/// runtime sends `SystemFiberEvent`s here, supervision fibers await it to restart the work of failed fibers
+pub const SYSTEM_FIBERS_QUEUE: &str = "mrn.system.fibers";
+/// how many of the latest failures runtime keeps, they get into every snapshot
+/// supervisors get all of them through `SYSTEM_FIBERS_QUEUE` anyway
+const FAILED_FIBERS_KEPT: usize = 256;
+
+/// what has woken idle runtime up
+enum Event {
+  Input(Option<Input>),
+  SnapshotRequest(Option<RequestWrapper<SnapshotRequest, RuntimeSnapshot>>),
+  Deadline,
+  Shutdown,
+}
+
+/// sizes of the runtime state, runtime updates them while it's running
+#[derive(Debug, Default)]
+pub struct RuntimeGauges {
+  /// futures that fibers, queues or resolved values still have
+  pub live_futures: AtomicU64,
+  pub queues: AtomicU64,
+  /// active and awaiting fibers
+  pub fibers: AtomicU64,
+}
+
+fn future_ids(value: &Value) -> Vec<String> {
+  let mut ids = Vec::new();
+  value_future_ids(value, &mut ids);
+  ids
+}
+
+fn arms_future_ids(arms: &[SelectArm]) -> Vec<String> {
+  arms
+    .iter()
+    .filter_map(|arm| match arm {
+      SelectArm::FutureVar { future_id, .. } => Some(future_id.clone()),
+      _ => None,
+    })
+    .collect()
+}
+
+/// fiber that has been terminated because of a failure
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedFiber {
+  pub fiber_id: u64,
+  pub fiber_type: String,
+  pub failure: Failure,
+}
+
+impl From<&FailedFiber> for SystemFiberEvent {
+  fn from(failed: &FailedFiber) -> SystemFiberEvent {
+    SystemFiberEvent::FiberFailed {
+      fiber_id: failed.fiber_id.to_string(),
+      fiber_type: failed.fiber_type.clone(),
+      kind: failed.failure.kind.code().to_string(),
+      detail: failed.failure.detail.clone(),
+    }
+  }
+}
+
 pub type Input = (LogicalTimeAbsoluteMs, Vec<TaskBlueprint>);
-pub type Output = (UniqueU64BlobId, Value);
+pub type Output = (UniqueU64BlobId, Result<Value, Rejection>);
+
+/// why a task from the outside isn't accepted
+/// it depends only on the task and runtime state, so every node rejects the same tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rejection {
+  /// there is no queue with such name
+  UnknownQueue,
+  /// queue accepts messages only from fibers
+  PrivateQueue,
+  /// value is not a public queue message
+  NotPublicMessage,
+  /// queue accepts another type of messages
+  WrongMessageType,
+  /// the message has been dropped without an answer, nobody can answer it anymore
+  Abandoned,
+}
+
+impl Rejection {
+  /// machine-readable reason for `TxStatus::Rejected`
+  pub fn reason(&self) -> &'static str {
+    match self {
+      Rejection::UnknownQueue => "unknown_queue",
+      Rejection::PrivateQueue => "private_queue",
+      Rejection::NotPublicMessage => "not_public_message",
+      Rejection::WrongMessageType => "wrong_message_type",
+      Rejection::Abandoned => "abandoned",
+    }
+  }
+}
+
+impl std::fmt::Display for Rejection {
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    write!(f, "{}", self.reason())
+  }
+}
+
+/// checks what doesn't depend on runtime state, so the node can reject a task as soon as it gets it
+/// returns the name of the public message type
+pub fn check_public_message(value: &Value) -> Result<&'static str, Rejection> {
+  pub_queue_message_type(value).ok_or(Rejection::NotPublicMessage)
+}
 // TODO: Don't like these names, I think it makes sense to have them.
 // It provides a bit more clarity and clearnes, but should think more no naming
 pub type B2AEndpoint = Endpoint<Output, Input>;
 pub type A2BEndpoint = Endpoint<Input, Output>;
+/// rolling sha256 of runtime inputs, outputs and state, the same inputs give the same digests on every node
+pub type StateDigest = [u8; 32];
+/// serializing the whole state is too expensive for every input, so it gets into the digest only once in a while
+pub const FULL_STATE_DIGEST_EVERY: u64 = 64;
 
 pub struct Runtime<T: Timer> {
   // communication interface
@@ -95,6 +213,8 @@ pub struct Runtime<T: Timer> {
   scheduled: BinaryHeap<ScheduledBlob>,
 
   timer: T,
+  /// if set - runtime is in epoch-driven mode: time moves only with inputs and `timer` isn't used
+  logical_time: Option<LogicalTimeAbsoluteMs>,
 
   // monotonically increasing id for newly created fibers
   next_fiber_id: u64,
@@ -107,6 +227,10 @@ pub struct Runtime<T: Timer> {
   /// key - queue name
   /// value - queue of messages
   queue_messages: HashMap<String, VecDeque<Value>>,
+  /// queues that accept messages from gateways
+  /// key - queue name
+  /// value - name of the message type the queue accepts
+  public_queues: HashMap<String, String>,
   /// order of non-empty queues in which I should check queues
   /// when smth adds message to the empty `queue_messages` - it should add queueName to this queue
   /// when smth works with this list it should:
@@ -119,6 +243,10 @@ pub struct Runtime<T: Timer> {
   /// push resolved futures with their results
   /// fiber awakening will happen in the same order as resolved futures get into the queue
   resolved_futures: VecDeque<(FutureId, Value)>,
+  /// owners of the futures runtime has created, futures nobody owns anymore are reclaimed
+  futures: FutureRegistry,
+  /// sizes of the runtime state for metrics
+  gauges: Arc<RuntimeGauges>,
 
   /// Shared debug output sink used by all fibers, safe to share with tests
   /// I don't think it's a good way of doing it longterm,
@@ -130,32 +258,235 @@ pub struct Runtime<T: Timer> {
   /// parked fibers that are awaiting smth
   /// key - fiber_id
   awaiting_fibers: HashMap<u64, Fiber>,
+  /// the latest terminated fibers in the order they have failed, `FAILED_FIBERS_KEPT` at most
+  failed_fibers: VecDeque<FailedFiber>,
+
+  /// requests for a snapshot of the runtime state, if not set - runtime doesn't make snapshots
+  snapshot_handler: Option<HandlerInterface<SnapshotRequest, RuntimeSnapshot>>,
+  /// runtime stops once it has nothing to do and the signal has come(or its sender has gone)
+  shutdown: Option<oneshot::Receiver<()>>,
+  /// how many inputs have been taken from `interface`
+  received_inputs: u64,
+
+  /// if set - runtime sends a digest after every input it has processed, works only with epoch-driven time
+  digests: Option<UnboundedSender<StateDigest>>,
+  /// folds all the inputs and outputs, and the state after every `FULL_STATE_DIGEST_EVERY` processed input
+  digest: StateDigest,
+  /// the last enqueued input hasn't got into `digest` yet
+  digest_pending: bool,
+  /// how many digests have been sent, it's a part of the state so restored runtime folds the state at the same inputs
+  sent_digests: u64,
 }
 
 impl<T: Timer> Runtime<T> {
   pub fn new(
     timer: T,
-    interface: Endpoint<(UniqueU64BlobId, Value), (LogicalTimeAbsoluteMs, Vec<TaskBlueprint>)>,
+    interface: Endpoint<Output, Input>,
   ) -> Runtime<T> {
     Runtime {
       active_fibers: VecDeque::new(),
       active_tasks: LinkedList::new(),
       scheduled: BinaryHeap::new(),
       timer: timer,
+      logical_time: None,
       next_fiber_id: 0,
       next_created_future_id: 0,
       public_futures: HashMap::new(),
       awaiting_fibers: HashMap::new(),
+      failed_fibers: VecDeque::new(),
 
-      queue_messages: HashMap::new(),
+      queue_messages: HashMap::from([(SYSTEM_FIBERS_QUEUE.to_string(), VecDeque::new())]),
+      public_queues: HashMap::new(),
       non_empty_queues: VecDeque::new(),
       resolved_futures: VecDeque::new(),
+      futures: FutureRegistry::default(),
+      gauges: Arc::new(RuntimeGauges::default()),
 
       dbg_out: Arc::new(Mutex::new(String::new())),
 
       wait_index: WaitRegistry::default(),
 
       interface,
+
+      snapshot_handler: None,
+      shutdown: None,
+      received_inputs: 0,
+
+      digests: None,
+      digest: [0; 32],
+      digest_pending: false,
+      sent_digests: 0,
+    }
+  }
+
+  /// switches runtime to epoch-driven time: it moves only to the time of the taken input(creation time of the epoch)
+  /// inputs are taken one by one when there is nothing else to do and due timers fire before the input,
+  /// so timers fire at the same positions in the transaction stream and every node gets the same fiber traces
+  pub fn set_epoch_driven_time(mut self) -> Runtime<T> {
+    self.logical_time = Some(LogicalTimeAbsoluteMs(0));
+    self
+  }
+
+  fn now(&self) -> LogicalTimeAbsoluteMs {
+    self.logical_time.unwrap_or_else(|| self.timer.from_start())
+  }
+
+  pub fn set_snapshot_handler(
+    mut self,
+    handler: HandlerInterface<SnapshotRequest, RuntimeSnapshot>,
+  ) -> Runtime<T> {
+    self.snapshot_handler = Some(handler);
+    self
+  }
+
+  /// `run` returns after the signal, work that can be done without new inputs is finished before that
+  pub fn set_shutdown_signal(
+    mut self,
+    shutdown: oneshot::Receiver<()>,
+  ) -> Runtime<T> {
+    self.shutdown = Some(shutdown);
+    self
+  }
+
+  /// digest is sent once runtime has nothing to do with the input, in the same order inputs come
+  /// so the n-th digest describes the state after the n-th input on every node
+  pub fn set_digests_sender(
+    mut self,
+    sender: UnboundedSender<StateDigest>,
+  ) -> Runtime<T> {
+    self.digests = Some(sender);
+    self
+  }
+
+  /// makes a snapshot of everything runtime has, including `inputs_count` inputs that might be still in the channel
+  pub fn snapshot(
+    &mut self,
+    inputs_count: u64,
+  ) -> RuntimeSnapshot {
+    // inputs that have been sent before the request are already in the channel
+    while self.received_inputs < inputs_count {
+      let Ok((time, requests)) = self.interface.receiver.try_recv() else {
+        break;
+      };
+      self.received_inputs += 1;
+      self.active_tasks.push_back((time, VecDeque::from(requests)));
+    }
+
+    let mut scheduled: Vec<(LogicalTimeAbsoluteMs, String)> =
+      self.scheduled.iter().map(|s| (s.when, s.what.0.clone())).collect();
+    scheduled.sort();
+
+    let awaiting_fibers = self
+      .wait_index
+      .registrations()
+      .into_iter()
+      .map(|(fiber_id, arms)| {
+        let fiber = self.awaiting_fibers.get(&fiber_id).expect("if fiber is in wait_index, it should be in awaiters");
+        (FiberSnapshot::from(fiber), arms)
+      })
+      .collect();
+
+    RuntimeSnapshot {
+      version: SNAPSHOT_VERSION,
+      logical_time: self.logical_time,
+      next_fiber_id: self.next_fiber_id,
+      next_created_future_id: self.next_created_future_id,
+      active_tasks: self.active_tasks.iter().map(|(time, tasks)| (*time, tasks.iter().cloned().collect())).collect(),
+      active_fibers: self.active_fibers.iter().map(FiberSnapshot::from).collect(),
+      awaiting_fibers,
+      failed_fibers: self.failed_fibers.iter().cloned().collect(),
+      scheduled,
+      public_futures: self.public_futures.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+      queue_messages: self.queue_messages.iter().map(|(k, v)| (k.clone(), v.iter().cloned().collect())).collect(),
+      public_queues: self.public_queues.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+      non_empty_queues: self.non_empty_queues.iter().cloned().collect(),
+      resolved_futures: self.resolved_futures.iter().map(|(id, v)| (id.0.clone(), v.clone())).collect(),
+      futures: self.futures.creators(),
+      digest: self.digest,
+      digest_pending: self.digest_pending,
+      sent_digests: self.sent_digests,
+    }
+  }
+
+  /// replaces the whole state with the snapshot, after that `run` continues from it instead of starting a root fiber
+  /// inputs that come after that are the ones that haven't got into the snapshot
+  pub fn restore(
+    &mut self,
+    snapshot: RuntimeSnapshot,
+  ) {
+    if self.logical_time.is_some() {
+      self.logical_time = Some(snapshot.logical_time.unwrap_or(LogicalTimeAbsoluteMs(0)));
+    }
+    // TODO: without epoch-driven time scheduled timers are in the time of the node that made the snapshot,
+    // they should be shifted to the local timer
+    self.next_fiber_id = snapshot.next_fiber_id;
+    self.next_created_future_id = snapshot.next_created_future_id;
+    self.active_tasks = snapshot.active_tasks.into_iter().map(|(time, tasks)| (time, VecDeque::from(tasks))).collect();
+    self.active_fibers = snapshot.active_fibers.into_iter().map(Fiber::from).collect();
+
+    self.wait_index = WaitRegistry::default();
+    self.awaiting_fibers = HashMap::with_capacity(snapshot.awaiting_fibers.len());
+    for (fiber, arms) in snapshot.awaiting_fibers {
+      let fiber = Fiber::from(fiber);
+      self.wait_index.register_select(fiber.unique_id, arms);
+      self.awaiting_fibers.insert(fiber.unique_id, fiber);
+    }
+
+    self.scheduled =
+      snapshot.scheduled.into_iter().map(|(when, what)| ScheduledBlob { when, what: FutureId(what) }).collect();
+    self.public_futures = snapshot.public_futures.into_iter().collect();
+    self.failed_fibers = VecDeque::from(snapshot.failed_fibers);
+    self.queue_messages = snapshot.queue_messages.into_iter().map(|(k, v)| (k, VecDeque::from(v))).collect();
+    self.queue_messages.entry(SYSTEM_FIBERS_QUEUE.to_string()).or_default();
+    self.public_queues = snapshot.public_queues.into_iter().collect();
+    self.non_empty_queues = VecDeque::from(snapshot.non_empty_queues);
+    self.resolved_futures = snapshot.resolved_futures.into_iter().map(|(id, v)| (FutureId(id), v)).collect();
+    self.restore_future_owners(snapshot.futures);
+    self.digest = snapshot.digest;
+    self.digest_pending = snapshot.digest_pending;
+    self.sent_digests = snapshot.sent_digests;
+    self.received_inputs = 0;
+  }
+
+  fn handle_snapshot_requests(&mut self) {
+    loop {
+      let Some(handler) = &mut self.snapshot_handler else {
+        return;
+      };
+      let Ok(wrapper) = handler.receiver.try_recv() else {
+        return;
+      };
+
+      let snapshot = self.snapshot(wrapper.request.inputs_count);
+      if wrapper.response.send(snapshot).is_err() {
+        println!("snapshot requester has gone");
+      }
+    }
+  }
+
+  /// owners aren't in the snapshot, they are found again in fibers, queues and resolved values
+  fn restore_future_owners(
+    &mut self,
+    futures: Vec<(String, Option<u64>)>,
+  ) {
+    self.futures = FutureRegistry::default();
+    for (id, creator) in futures {
+      self.futures.track(FutureId(id), creator);
+    }
+    for fiber in self.active_fibers.iter() {
+      self.futures.add_to_fiber(fiber.unique_id, fiber.future_ids());
+    }
+    for (fiber_id, arms) in self.wait_index.registrations() {
+      let fiber = self.awaiting_fibers.get(&fiber_id).expect("if fiber is in wait_index, it should be in awaiters");
+      self.futures.add_to_fiber(fiber_id, [fiber.future_ids(), arms_future_ids(&arms)].concat());
+    }
+    for (queue_name, messages) in self.queue_messages.iter() {
+      for message in messages {
+        self.futures.add_to_queue(queue_name, future_ids(message));
+      }
+    }
+    for (_, value) in self.resolved_futures.iter() {
+      self.futures.add_to_resolved(future_ids(value));
     }
   }
 
@@ -164,6 +495,11 @@ impl<T: Timer> Runtime<T> {
     self.dbg_out.clone()
   }
 
+  /// sizes of the runtime state, updated while runtime is running
+  pub fn gauges(&self) -> Arc<RuntimeGauges> {
+    self.gauges.clone()
+  }
+
   pub fn dump(&self) {
     println!(
       r"------STATE---------
@@ -173,7 +509,7 @@ scheduled:
 active fibers:
 {}
 -----END STATE------",
-      self.timer.from_start(),
+      self.now(),
       self.scheduled.iter().map(|s| format!("  t:{} f:{}", s.when, s.what)).collect::<Vec<String>>().join("\n"),
       self
         .active_fibers
@@ -188,12 +524,18 @@ active fibers:
     &mut self,
     root_type: String,
   ) {
-    let root = Fiber::new(FiberType(root_type), 0, &vec![]);
-    self.next_fiber_id = 1;
-    self.active_fibers.push_back(root);
+    // restored runtime already has its root fiber
+    if self.next_fiber_id == 0 {
+      let root = Fiber::new(FiberType(root_type), 0, &vec![]);
+      self.next_fiber_id = 1;
+      self.active_fibers.push_back(root);
+    }
 
     'main_loop: loop {
-      let now = self.timer.from_start();
+      self.handle_snapshot_requests();
+      self.update_gauges();
+
+      let now = self.now();
 
       // take scheduled futures and either wake parked fibers (old AwaitOld path)
       // or enqueue a resolved Unit value for Select-based waiters
@@ -214,9 +556,13 @@ active fibers:
         match res {
           RunResult::Done => {
             local_dbg.push_str(&format!("--- exit {}:{} ---\n", fiber.f_type, fiber.unique_id));
+            let released = self.futures.remove_fiber(fiber.unique_id);
+            self.reclaim_futures(released);
           }
-          RunResult::Select(states) => {
-            self.wait_index.register_select(fiber.unique_id, states);
+          RunResult::Select(arms) => {
+            let arms = self.schedule_timeouts(fiber.unique_id, arms);
+            self.rescan_fiber(&fiber, &arms);
+            self.wait_index.register_select(fiber.unique_id, arms);
             self.awaiting_fibers.insert(fiber.unique_id, fiber);
           }
           RunResult::CreateFibers { details } => {
@@ -226,39 +572,44 @@ active fibers:
                 local_dbg.push_str(&format!("    {:?}\n", v));
               }
               let nf = Fiber::new(f_type, self.next_fiber_id, &init_vars);
+              self.futures.add_to_fiber(nf.unique_id, init_vars.iter().flat_map(future_ids).collect());
               self.next_fiber_id += 1;
               self.active_fibers.push_back(nf);
             }
+            self.rescan_fiber(&fiber, &[]);
             self.active_fibers.push_front(fiber);
           }
           RunResult::SetValues(values) => {
-            for v in values {
-              match v {
-                SetPrimitiveValue::QueueMessage { queue_name, value } => {
-                  if let Some(queue) = self.queue_messages.get_mut(&queue_name) {
-                    let is_empty = queue.is_empty();
-                    queue.push_back(value);
-                    if is_empty {
-                      self.non_empty_queues.push_back(queue_name);
-                    }
-                  } else {
-                    // todo: how to send an error here? should I send an error here?
-                    panic!("it means smb is trying to send value to non-existing queue");
-                    // self.queue_messages.insert(queue_name.clone(), VecDeque::from(vec![value]));
-                    // self.non_empty_queues.push_back(queue_name);
+            // values are set all or none
+            if let Some(failure) = values.iter().find_map(|v| self.check_set_value(v)) {
+              self.fail_fiber(fiber, failure, &mut local_dbg);
+            } else {
+              for v in values {
+                match v {
+                  SetPrimitiveValue::QueueMessage { queue_name, value } => {
+                    self.push_message(queue_name, value);
                   }
-                }
-                SetPrimitiveValue::Future { id, value } => {
-                  if let Some(u_id) = self.public_futures.remove(&id) {
-                    self.interface.send((u_id, value));
-                  } else {
-                    self.resolved_futures.push_back((FutureId(id), value));
+                  SetPrimitiveValue::Future { id, value } => {
+                    let id = FutureId(id);
+                    if let Some(u_id) = self.public_futures.remove(&id.0) {
+                      let output = Ok(value);
+                      self.fold_output(u_id, &output);
+                      self.interface.send((u_id, output));
+                      self.futures.untrack(&id);
+                    } else {
+                      self.futures.add_to_resolved(future_ids(&value));
+                      self.resolved_futures.push_back((id, value));
+                    }
                   }
                 }
               }
+              self.rescan_fiber(&fiber, &[]);
+              // continue immediately, no need to wait anything
+              self.active_fibers.push_front(fiber);
             }
-            // continue immediately, no need to wait anything
-            self.active_fibers.push_front(fiber);
+          }
+          RunResult::Failed(failure) => {
+            self.fail_fiber(fiber, failure, &mut local_dbg);
           }
           RunResult::Create { primitives, success_next, success_binds, success_kinds, fail_next, fail_binds } => {
             let mut candidate_queues = HashSet::<String>::new();
@@ -268,8 +619,11 @@ active fibers:
             // Validate and compute ids for all primitives first (atomic behavior)
             for primitive in primitives.iter() {
               match primitive {
-                CreatePrimitiveValue::Queue { name, public: _ } => {
-                  if self.queue_messages.contains_key(name) || candidate_queues.contains(name) {
+                CreatePrimitiveValue::Queue { name, .. } => {
+                  if name.starts_with(SYSTEM_QUEUE_PREFIX) {
+                    errors.push(Some("reserved_name".to_string()));
+                    has_error = true;
+                  } else if self.queue_messages.contains_key(name) || candidate_queues.contains(name) {
                     errors.push(Some("already_exists".to_string()));
                     has_error = true;
                   } else {
@@ -288,23 +642,32 @@ active fibers:
 
             if has_error {
               // Bind per-primitive Option<String> errors and go to fail branch
-              for (idx, var_name) in fail_binds.iter().enumerate() {
+              let bound = fail_binds.iter().enumerate().try_for_each(|(idx, var_name)| {
                 let v = match errors.get(idx).cloned().unwrap_or(None) {
                   Some(e) => Value::OptionString(Some(e)),
                   None => Value::OptionString(None),
                 };
-                fiber.assign_local(var_name.clone(), v);
+                fiber.assign_local(var_name.clone(), v)
+              });
+              match bound {
+                Ok(()) => {
+                  fiber.stack.push(StackEntry::State(fail_next));
+                  self.rescan_fiber(&fiber, &[]);
+                  self.active_fibers.push_front(fiber);
+                }
+                Err(failure) => self.fail_fiber(fiber, failure, &mut local_dbg),
               }
-              fiber.stack.push(StackEntry::State(fail_next));
-              self.active_fibers.push_front(fiber);
             } else {
               let mut ids = Vec::<String>::with_capacity(primitives.len());
 
               // Apply creations for all primitives since validation succeeded
               for primitive in primitives {
                 match primitive {
-                  CreatePrimitiveValue::Queue { name, public: _ } => {
+                  CreatePrimitiveValue::Queue { name, public, message } => {
                     ids.push(name.clone());
+                    if public && let Some(message) = message {
+                      self.public_queues.insert(name.clone(), message);
+                    }
                     self.queue_messages.insert(name, VecDeque::new());
                   }
                   CreatePrimitiveValue::Future => {
@@ -312,35 +675,38 @@ active fibers:
                     self.next_created_future_id += 1;
                   }
                   CreatePrimitiveValue::Schedule { ms } => {
-                    let id = format!("{}", self.next_created_future_id);
-                    self.next_created_future_id += 1;
-                    self.scheduled.push(ScheduledBlob {
-                      when: self.timer.from_start() + LogicalTimeAbsoluteMs(ms),
-                      what: FutureId(id.clone()),
-                    });
-                    ids.push(id);
+                    ids.push(self.schedule(ms));
                   }
                 }
               }
+              // futures that are bound as futures are tracked, ids bound as strings can't be followed
+              for (idx, id) in ids.iter().enumerate() {
+                if let Some(SuccessBindKind::Future(_)) = success_kinds.get(idx) {
+                  self.futures.track(FutureId(id.clone()), Some(fiber.unique_id));
+                  self.futures.add_to_fiber(fiber.unique_id, vec![id.clone()]);
+                }
+              }
               // Bind success ids into locals
-              for (idx, var_name) in success_binds.iter().enumerate() {
+              let bound = success_binds.iter().enumerate().try_for_each(|(idx, var_name)| {
                 let id = ids.get(idx).cloned().expect("no way it doesn't exist");
                 let v = match success_kinds.get(idx) {
                   Some(SuccessBindKind::String) | None => Value::String(id),
                   Some(SuccessBindKind::Future(kind)) => wrap_future_id(kind.clone(), id),
                 };
-                fiber.assign_local(var_name.clone(), v);
+                fiber.assign_local(var_name.clone(), v)
+              });
+              match bound {
+                Ok(()) => {
+                  fiber.stack.push(StackEntry::State(success_next));
+                  self.rescan_fiber(&fiber, &[]);
+                  self.active_fibers.push_front(fiber);
+                }
+                Err(failure) => self.fail_fiber(fiber, failure, &mut local_dbg),
               }
-              fiber.stack.push(StackEntry::State(success_next));
-              self.active_fibers.push_front(fiber);
             }
           }
         }
-        if !local_dbg.is_empty() {
-          if let Ok(mut g) = self.dbg_out.lock() {
-            g.push_str(&local_dbg);
-          }
-        }
+        self.flush_dbg(local_dbg);
       }
 
       {
@@ -350,20 +716,28 @@ active fibers:
             // It's not possible that I have smth in wait_index but don't have it in awaiting_fibers
             // if fiber has been removed from awaiting_fibers it should be removed from wait_index as well, no exceptions
             let mut w_fiber = self.awaiting_fibers.remove(&awaiter.fiber_id).expect("data consistency violation");
-            if let Some(bind_var) = awaiter.bind {
-              w_fiber.assign_local_and_push_next(bind_var, value, awaiter.next);
+            self.drop_futures(&w_fiber, awaiter.dropped_futures);
+            // the future is consumed, its value moves to the fiber
+            self.futures.untrack(&future_id);
+            let value_ids = future_ids(&value);
+            if awaiter.bind.is_some() {
+              self.futures.add_to_fiber(w_fiber.unique_id, value_ids.clone());
+            }
+            let released = self.futures.remove_from_resolved(value_ids);
+            self.reclaim_futures(released);
+            let resumed = if let Some(bind_var) = awaiter.bind {
+              w_fiber.assign_local_and_push_next(bind_var, value, awaiter.next)
             } else {
               w_fiber.push_next(awaiter.next);
-            }
-            self.active_fibers.push_front(w_fiber);
+              Ok(())
+            };
+            self.resume_fiber(w_fiber, resumed);
           } else {
             // if nobody is here for this future - probably it's because they haven't started to await it yet, but they will at some point
             // that's why I'm pushing it back to the queue
             //
-            // TODO: potential memory leak if we create future but for some reason are not waiting for it
-            // that's quite critical and will hit us at some point if won't be fixed
-            // I don't know yet where it should be fixed, probably on IR/DSL level? like in Rust?
-            // if variable with future is dropped - we can remove it, if not yet - then it's needed
+            // futures of the non-chosen select arms stay here only if their fibers keep them to await again
+            // futures that nobody can await anymore are reclaimed together with their values
             self.resolved_futures.push_back((future_id, value));
           }
         }
@@ -377,6 +751,7 @@ active fibers:
             .awaiting_fibers
             .remove(&awaiter_info.fiber_id)
             .expect("if fiber is in wait_index, it should be in awaiters. Otherwise data consistency is violated");
+          self.drop_futures(&fb, awaiter_info.dropped_futures);
 
           let m_queue = self
             .queue_messages
@@ -384,72 +759,406 @@ active fibers:
             .expect("should be here and non empty. Otherwise it shouldn't end up in non_empty_queues");
           let v = m_queue.pop_front().expect("should be non empty. Otherwise it shouldn't end up in non_empty_queues");
 
+          if !m_queue.is_empty() {
+            self.non_empty_queues.push_back(q_name.clone());
+          }
+
+          let message_ids = future_ids(&v);
+          if awaiter_info.bind.is_some() {
+            self.futures.add_to_fiber(fb.unique_id, message_ids.clone());
+          }
+          let released = self.futures.remove_from_queue(&q_name, message_ids);
+          self.reclaim_futures(released);
+
           // Bind the dequeued message into the awaiting fiber and push its next state
-          if let Some(bind_name) = awaiter_info.bind {
-            fb.assign_local_and_push_next(bind_name, v, awaiter_info.next);
+          let resumed = if let Some(bind_name) = awaiter_info.bind {
+            fb.assign_local_and_push_next(bind_name, v, awaiter_info.next)
           } else {
             // No bind requested; just continue to the next state
             fb.stack.push(StackEntry::State(awaiter_info.next));
-          }
-
-          self.active_fibers.push_front(fb);
-          if !m_queue.is_empty() {
-            self.non_empty_queues.push_back(q_name);
-          }
+            Ok(())
+          };
+          self.resume_fiber(fb, resumed);
           continue 'main_loop;
         } else {
           self.non_empty_queues.push_back(q_name);
         }
       }
 
-      {
-        // this part reads messages from external source
-        // and puts them where they should be
-        loop {
-          let now = self.timer.from_start();
-
-          let mut next = self.active_tasks.pop_front();
-          if next.is_none() {
-            if self.interface.receiver.is_empty() {
-              tokio::time::sleep(Duration::from_millis(5)).await;
-              break;
-            } else {
-              let (time, requests) = self.interface.receiver.recv().await.expect("checked, not empty");
-              next = Some((time, VecDeque::from(requests)));
-            }
-          }
+      if self.logical_time.is_some() {
+        if !self.take_input_at_logical_time().await {
+          break 'main_loop;
+        }
+        continue 'main_loop;
+      }
 
-          let Some((time_stamp, mut current_queue)) = next else {
-            break;
-          };
+      // this part reads messages from external source
+      // and puts them where they should be
+      while let Ok((time, requests)) = self.interface.receiver.try_recv() {
+        self.received_inputs += 1;
+        self.active_tasks.push_back((time, VecDeque::from(requests)));
+      }
+      let now = self.now();
+      while self.active_tasks.front().is_some_and(|(time_stamp, _)| *time_stamp <= now) {
+        let (_, tasks) = self.active_tasks.pop_front().expect("checked above");
+        self.enqueue_tasks(tasks);
+      }
 
-          if time_stamp > now {
-            // TODO: not continue but some sleep, since we shouldn't work on it yet + we shouldn't just waste CPU cycles
-            // but it should be probably not just sleep, but select or smth
-            let sleep_distance = time_stamp.0 - now.0;
-            tokio::time::sleep(Duration::from_millis(sleep_distance)).await;
-            println!("smth in active_tasks, but not now, sleeping {}ms", sleep_distance);
-            self.active_tasks.push_front((time_stamp, current_queue));
-            break;
-          }
+      if !self.is_idle() {
+        continue 'main_loop;
+      }
+      // tasks from the future and timers wake runtime up when their time comes
+      let deadline =
+        [self.scheduled.peek().map(|blob| blob.when), self.active_tasks.front().map(|(time_stamp, _)| *time_stamp)]
+          .into_iter()
+          .flatten()
+          .min();
+      if !self.wait_for_event(deadline).await {
+        break 'main_loop;
+      }
+    }
+  }
 
-          while let Some(blueprint) = current_queue.pop_front() {
-            if let Some(queue) = self.queue_messages.get_mut(&blueprint.q_name) {
-              let was_empty = queue.is_empty();
-              // here I can have only messages that `can`` be passed from the outside
-              // so for them this function won't fail but for other types it will panic
-              let p_value = pub_to_private(blueprint.value, format!("{}", self.next_created_future_id));
-              self.public_futures.insert(format!("{}", self.next_created_future_id), blueprint.global_id);
-              self.next_created_future_id += 1;
-
-              queue.push_back(p_value);
-              if was_empty {
-                // if it was empty => not in non_empty_queues => adding
-                self.non_empty_queues.push_back(blueprint.q_name);
-              }
-            }
-          }
+  /// parks runtime until a new input, a snapshot request, the deadline or the shutdown signal comes
+  /// returns false if runtime should stop
+  async fn wait_for_event(
+    &mut self,
+    deadline: Option<LogicalTimeAbsoluteMs>,
+  ) -> bool {
+    self.update_gauges();
+    let sleep = Duration::from_millis(deadline.map_or(0, |when| when.0.saturating_sub(self.now().0)));
+    let inputs = &mut self.interface.receiver;
+    let snapshot_requests = self.snapshot_handler.as_mut().map(|handler| &mut handler.receiver);
+    let shutdown = self.shutdown.as_mut();
+
+    let event = tokio::select! {
+      input = inputs.recv() => Event::Input(input),
+      request = async { snapshot_requests?.recv().await }, if snapshot_requests.is_some() => Event::SnapshotRequest(request),
+      _ = tokio::time::sleep(sleep), if deadline.is_some() => Event::Deadline,
+      _ = async { shutdown?.await.ok() }, if shutdown.is_some() => Event::Shutdown,
+    };
+
+    match event {
+      Event::Input(Some((time, requests))) => {
+        self.received_inputs += 1;
+        self.active_tasks.push_back((time, VecDeque::from(requests)));
+        true
+      }
+      // nobody can send inputs or receive outputs anymore
+      Event::Input(None) => false,
+      Event::SnapshotRequest(Some(wrapper)) => {
+        let snapshot = self.snapshot(wrapper.request.inputs_count);
+        if wrapper.response.send(snapshot).is_err() {
+          println!("snapshot requester has gone");
         }
+        true
+      }
+      Event::SnapshotRequest(None) => {
+        self.snapshot_handler = None;
+        true
+      }
+      Event::Deadline => true,
+      Event::Shutdown => false,
+    }
+  }
+
+  /// epoch-driven counterpart of reading inputs, see `set_epoch_driven_time`
+  /// returns false if runtime should stop
+  async fn take_input_at_logical_time(&mut self) -> bool {
+    if !self.is_idle() {
+      return true;
+    }
+
+    if self.digest_pending {
+      self.emit_digest();
+    }
+
+    // timers move only with inputs, so there is nothing to wait except them
+    let Some((time_stamp, _)) = self.active_tasks.front() else {
+      return self.wait_for_event(None).await;
+    };
+    // epochs are created by different nodes, so their time might go backwards
+    let now = self.now();
+    let input_time = (*time_stamp).max(now);
+
+    // timers that are due before the input fire first
+    if let Some(blob) = self.scheduled.peek()
+      && blob.when <= input_time
+    {
+      self.logical_time = Some(blob.when.max(now));
+      return true;
+    }
+
+    self.logical_time = Some(input_time);
+    let (_, tasks) = self.active_tasks.pop_front().expect("checked above");
+    self.fold_input(input_time, &tasks);
+    self.enqueue_tasks(tasks);
+    self.digest_pending = self.digests.is_some();
+    true
+  }
+
+  fn fold_input(
+    &mut self,
+    time: LogicalTimeAbsoluteMs,
+    tasks: &VecDeque<TaskBlueprint>,
+  ) {
+    if self.digests.is_none() {
+      return;
+    }
+    let input = serde_json::to_vec(&(time, tasks)).expect("inputs are always serializable");
+    self.digest = Sha256::new().chain_update(self.digest).chain_update(input).finalize().into();
+  }
+
+  fn fold_output(
+    &mut self,
+    id: UniqueU64BlobId,
+    output: &Result<Value, Rejection>,
+  ) {
+    if self.digests.is_none() {
+      return;
+    }
+    let output = serde_json::to_vec(&(id, output)).expect("outputs are always serializable");
+    self.digest = Sha256::new().chain_update(self.digest).chain_update(output).finalize().into();
+  }
+
+  /// sends the digest, every `FULL_STATE_DIGEST_EVERY` one has the current state folded into it
+  fn emit_digest(&mut self) {
+    self.digest_pending = false;
+    self.sent_digests += 1;
+
+    if self.sent_digests.is_multiple_of(FULL_STATE_DIGEST_EVERY) {
+      let mut state = self.snapshot(self.received_inputs);
+      // inputs that are waiting here depend on how fast they've been sent, they get into the digest after processing
+      state.active_tasks.clear();
+      let state = serde_json::to_vec(&state).expect("runtime state is always serializable");
+      self.digest = Sha256::new().chain_update(self.digest).chain_update(state).finalize().into();
+    }
+
+    if let Some(sender) = &self.digests
+      && sender.send(self.digest).is_err()
+    {
+      println!("digests receiver has gone");
+    }
+  }
+
+  /// task can go only to a public queue with a message of the type the queue accepts
+  fn check_task(
+    &self,
+    blueprint: &TaskBlueprint,
+  ) -> Result<(), Rejection> {
+    let message = check_public_message(&blueprint.value)?;
+    if !self.queue_messages.contains_key(&blueprint.q_name) {
+      return Err(Rejection::UnknownQueue);
+    }
+    match self.public_queues.get(&blueprint.q_name) {
+      None => Err(Rejection::PrivateQueue),
+      Some(accepted) if accepted != message => Err(Rejection::WrongMessageType),
+      Some(_) => Ok(()),
+    }
+  }
+
+  /// nothing can move without a new input or time
+  fn is_idle(&self) -> bool {
+    let now = self.now();
+    self.active_fibers.is_empty()
+      && self.scheduled.peek().is_none_or(|blob| blob.when > now)
+      && self.resolved_futures.iter().all(|(id, _)| !self.wait_index.has_waiters(&WaitKey::Future(id.clone())))
+      && self.non_empty_queues.iter().all(|q| !self.wait_index.has_waiters(&WaitKey::Queue(q.clone())))
+  }
+
+  fn enqueue_tasks(
+    &mut self,
+    mut tasks: VecDeque<TaskBlueprint>,
+  ) {
+    while let Some(blueprint) = tasks.pop_front() {
+      if let Err(rejection) = self.check_task(&blueprint) {
+        let output = Err(rejection);
+        self.fold_output(blueprint.global_id, &output);
+        self.interface.send((blueprint.global_id, output));
+        continue;
+      }
+
+      // task is checked, so it's a public message and this function won't panic
+      let p_value = pub_to_private(blueprint.value, format!("{}", self.next_created_future_id));
+      self.public_futures.insert(format!("{}", self.next_created_future_id), blueprint.global_id);
+      self.futures.track(FutureId(format!("{}", self.next_created_future_id)), None);
+      self.next_created_future_id += 1;
+      self.push_message(blueprint.q_name, p_value);
+    }
+  }
+
+  /// creates a timer future that resolves after `ms`
+  fn schedule(
+    &mut self,
+    ms: u64,
+  ) -> String {
+    let id = format!("{}", self.next_created_future_id);
+    self.next_created_future_id += 1;
+    self.scheduled.push(ScheduledBlob { when: self.now() + LogicalTimeAbsoluteMs(ms), what: FutureId(id.clone()) });
+    id
+  }
+
+  /// timeout arm awaits a new timer future, so select resolves with whatever comes first
+  fn schedule_timeouts(
+    &mut self,
+    fiber_id: u64,
+    arms: Vec<SelectArm>,
+  ) -> Vec<SelectArm> {
+    arms
+      .into_iter()
+      .map(|arm| match arm {
+        SelectArm::Timeout { ms, next } => {
+          let future_id = self.schedule(ms);
+          self.futures.track(FutureId(future_id.clone()), Some(fiber_id));
+          SelectArm::FutureVar { future_id, bind: None, next }
+        }
+        arm => arm,
+      })
+      .collect()
+  }
+
+  /// non-chosen arms of a select are dropped, but the fiber can await the futures it keeps in locals again <br>
+  /// timers of timeout arms aren't in locals, so they are cancelled
+  /// other futures are discarded only if nobody owns them anymore, ex: another select awaits them
+  fn drop_futures(
+    &mut self,
+    fiber: &Fiber,
+    future_ids: Vec<FutureId>,
+  ) {
+    let held: HashSet<String> = fiber.future_ids().into_iter().collect();
+    let dropped = future_ids.into_iter().filter(|id| !held.contains(&id.0)).collect();
+    let released = self.futures.remove_from_fiber(fiber.unique_id, dropped);
+    self.reclaim_futures(released);
+  }
+
+  /// cancels the timer of the future or removes its value if it's resolved
+  fn discard_future(
+    &mut self,
+    id: &FutureId,
+  ) {
+    if self.scheduled.iter().any(|blob| blob.what == *id) {
+      self.scheduled.retain(|blob| blob.what != *id);
+      return;
+    }
+    let Some(idx) = self.resolved_futures.iter().position(|(resolved, _)| resolved == id) else {
+      return;
+    };
+    let (_, value) = self.resolved_futures.remove(idx).expect("index is found above");
+    let released = self.futures.remove_from_resolved(future_ids(&value));
+    self.reclaim_futures(released);
+  }
+
+  /// nobody has these futures anymore, so nobody can await or resolve them
+  fn reclaim_futures(
+    &mut self,
+    ids: Vec<FutureId>,
+  ) {
+    for id in ids {
+      self.futures.untrack(&id);
+      self.discard_future(&id);
+      // gateway would wait for the answer forever otherwise
+      if let Some(u_id) = self.public_futures.remove(&id.0) {
+        let output = Err(Rejection::Abandoned);
+        self.fold_output(u_id, &output);
+        self.interface.send((u_id, output));
+      }
+    }
+  }
+
+  /// fiber has yielded, so its locals might have changed since the last time
+  fn rescan_fiber(
+    &mut self,
+    fiber: &Fiber,
+    arms: &[SelectArm],
+  ) {
+    let released = self.futures.set_fiber(fiber.unique_id, [fiber.future_ids(), arms_future_ids(arms)].concat());
+    self.reclaim_futures(released);
+  }
+
+  fn update_gauges(&self) {
+    self.gauges.live_futures.store(self.futures.len() as u64, Ordering::Relaxed);
+    self.gauges.queues.store(self.queue_messages.len() as u64, Ordering::Relaxed);
+    let fibers = self.active_fibers.len() + self.awaiting_fibers.len();
+    self.gauges.fibers.store(fibers as u64, Ordering::Relaxed);
+  }
+
+  /// puts the message into the existing queue
+  fn push_message(
+    &mut self,
+    queue_name: String,
+    value: Value,
+  ) {
+    let Some(queue) = self.queue_messages.get_mut(&queue_name) else {
+      return;
+    };
+    let ids = future_ids(&value);
+    let was_empty = queue.is_empty();
+    queue.push_back(value);
+    self.futures.add_to_queue(&queue_name, ids);
+    if was_empty {
+      // if it was empty => not in non_empty_queues => adding
+      self.non_empty_queues.push_back(queue_name);
+    }
+  }
+
+  /// fiber can send messages only into existing queues that are not reserved by runtime
+  fn check_set_value(
+    &self,
+    value: &SetPrimitiveValue,
+  ) -> Option<Failure> {
+    let SetPrimitiveValue::QueueMessage { queue_name, .. } = value else {
+      return None;
+    };
+    if queue_name.starts_with(SYSTEM_QUEUE_PREFIX) {
+      Some(Failure::new(FailureKind::ReservedQueue, format!("only runtime can send into {queue_name}")))
+    } else if !self.queue_messages.contains_key(queue_name) {
+      Some(Failure::new(FailureKind::UnknownQueue, format!("there is no queue {queue_name}")))
+    } else {
+      None
+    }
+  }
+
+  /// puts awoken fiber back to work unless it has failed on binding the awaited value
+  fn resume_fiber(
+    &mut self,
+    fiber: Fiber,
+    resumed: Result<(), Failure>,
+  ) {
+    match resumed {
+      Ok(()) => self.active_fibers.push_front(fiber),
+      Err(failure) => {
+        let mut local_dbg = String::new();
+        self.fail_fiber(fiber, failure, &mut local_dbg);
+        self.flush_dbg(local_dbg);
+      }
+    }
+  }
+
+  /// terminates the fiber, records the failure and lets supervision fibers know about it
+  fn fail_fiber(
+    &mut self,
+    fiber: Fiber,
+    failure: Failure,
+    local_dbg: &mut String,
+  ) {
+    local_dbg.push_str(&format!("--- failed {}:{} {} ---\n", fiber.f_type, fiber.unique_id, failure));
+    let released = self.futures.remove_fiber(fiber.unique_id);
+    self.reclaim_futures(released);
+    let failed = FailedFiber { fiber_id: fiber.unique_id, fiber_type: fiber.f_type.0.clone(), failure };
+    self.push_message(SYSTEM_FIBERS_QUEUE.to_string(), Value::SystemFiberEvent(SystemFiberEvent::from(&failed)));
+    self.failed_fibers.push_back(failed);
+    if self.failed_fibers.len() > FAILED_FIBERS_KEPT {
+      self.failed_fibers.pop_front();
+    }
+  }
+
+  fn flush_dbg(
+    &self,
+    local_dbg: String,
+  ) {
+    if !local_dbg.is_empty() {
+      if let Ok(mut g) = self.dbg_out.lock() {
+        g.push_str(&local_dbg);
       }
     }
   }
@@ -458,8 +1167,9 @@ active fibers:
 #[cfg(test)]
 mod tests {
   use common::duplex_channel::create_a_b_duplex_pair;
+  use common::invoker_handler::create_invoker_handler_pair;
   use common::logical_clock::MonotonicTimer;
-  use generated::maroon_assembler::TestCreateQueueMessagePub;
+  use generated::maroon_assembler::{TestCreateQueueMessagePub, TestInfiniteSummatorQueueMessagePub};
   use std::fmt::Debug;
   use tokio::sync::mpsc::UnboundedReceiver;
 
@@ -471,8 +1181,7 @@ mod tests {
   async fn scheduled_select() {
     // wait more than 150 ms
     {
-      let (_a2b_runtime, b2a_runtime) =
-        create_a_b_duplex_pair::<(LogicalTimeAbsoluteMs, Vec<TaskBlueprint>), (UniqueU64BlobId, Value)>();
+      let (_a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
 
       let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime);
       let debug_out = rt.debug_handle();
@@ -502,8 +1211,7 @@ await_milliseconds=150
     // wait less than 150 ms
     // see that fiber started to await but hasn't been resolved after 10 ms awaiting
     {
-      let (_a2b_runtime, b2a_runtime) =
-        create_a_b_duplex_pair::<(LogicalTimeAbsoluteMs, Vec<TaskBlueprint>), (UniqueU64BlobId, Value)>();
+      let (_a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
 
       let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime);
       let debug_out = rt.debug_handle();
@@ -525,10 +1233,49 @@ await_milliseconds=150
     }
   }
 
+  #[tokio::test(flavor = "multi_thread")]
+  async fn scheduled_select_with_epoch_driven_time() {
+    let sleep_started = r#"--- start testRootFiberSleepTest:0 ---
+--- await testRootFiberSleepTest:0 ---
+--- start testRootFiberSleepTest:0 ---
+--- await testRootFiberSleepTest:0 ---
+"#;
+    let sleep_finished = r#"--- start testRootFiberSleepTest:0 ---
+scheduledFutId=FutureUnit(FutureUnit("0"))
+createScheduleError=OptionString(None)
+await_milliseconds=150
+--- await testRootFiberSleepTest:0 ---
+--- exit testRootFiberSleepTest:0 ---
+"#;
+
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_epoch_driven_time();
+    let debug_out = rt.debug_handle();
+    tokio::spawn(async move {
+      rt.run("testRootFiberSleepTest".to_string()).await;
+    });
+
+    // wall-clock time doesn't matter, only epochs move time
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_str_eq_by_lines(sleep_started, debug_out.lock().unwrap().as_str());
+
+    a2b_runtime.send((LogicalTimeAbsoluteMs(100), vec![]));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_str_eq_by_lines(sleep_started, debug_out.lock().unwrap().as_str());
+
+    // epochs might come with time that goes backwards
+    a2b_runtime.send((LogicalTimeAbsoluteMs(20), vec![]));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_str_eq_by_lines(sleep_started, debug_out.lock().unwrap().as_str());
+
+    a2b_runtime.send((LogicalTimeAbsoluteMs(150), vec![]));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_str_eq_by_lines(&format!("{sleep_started}{sleep_finished}"), debug_out.lock().unwrap().as_str());
+  }
+
   #[tokio::test(flavor = "multi_thread")]
   async fn create_queues_and_external_communication() {
-    let (a2b_runtime, b2a_runtime) =
-      create_a_b_duplex_pair::<(LogicalTimeAbsoluteMs, Vec<TaskBlueprint>), (UniqueU64BlobId, Value)>();
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
 
     let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime);
     let debug_out = rt.debug_handle();
@@ -547,7 +1294,7 @@ await_milliseconds=150
 
     tokio::time::sleep(Duration::from_millis(10)).await;
 
-    compare_channel_data_with_exp(vec![(UniqueU64BlobId(9), Value::U64(12))], a2b_runtime.receiver).await;
+    compare_channel_data_with_exp(vec![(UniqueU64BlobId(9), Ok(Value::U64(12)))], a2b_runtime.receiver).await;
 
     let result = debug_out.lock();
     assert_str_eq_by_lines(
@@ -587,8 +1334,7 @@ f_res_inc=12
 
   #[tokio::test(flavor = "multi_thread")]
   async fn creating_fiber_cross_fiber_communication() {
-    let (_a2b_runtime, b2a_runtime) =
-      create_a_b_duplex_pair::<(LogicalTimeAbsoluteMs, Vec<TaskBlueprint>), (UniqueU64BlobId, Value)>();
+    let (_a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
 
     let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime);
     let debug_out = rt.debug_handle();
@@ -662,6 +1408,402 @@ createFutureError2=OptionString(None)
     );
   }
 
+  #[tokio::test(flavor = "multi_thread")]
+  async fn snapshot_and_restore() {
+    let summator_task = |id: u64, a: u64, b: u64| TaskBlueprint {
+      global_id: UniqueU64BlobId(id),
+      q_name: "testInfiniteCalculatorQueue".to_string(),
+      value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a, b }),
+    };
+
+    let (mut a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_snapshot_handler(snapshot_handler);
+    tokio::spawn(async move {
+      rt.run("root".to_string()).await;
+    });
+
+    a2b_runtime.send((LogicalTimeAbsoluteMs(0), vec![summator_task(1, 2, 3)]));
+    assert_eq!(Some((UniqueU64BlobId(1), Ok(Value::U64(6)))), a2b_runtime.receiver.recv().await);
+
+    // the second input is in the snapshot even if runtime hasn't taken it from the channel yet
+    a2b_runtime.send((LogicalTimeAbsoluteMs(0), vec![summator_task(2, 4, 5)]));
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 2 }).await;
+
+    // restored runtime continues from the same point and doesn't start a new root fiber
+    let (restored_a2b, restored_b2a) = create_a_b_duplex_pair::<Input, Output>();
+    let mut restored = Runtime::new(MonotonicTimer::new(), restored_b2a);
+    restored.restore(snapshot.clone());
+
+    // the same state gives the same bytes
+    assert_eq!(serde_json::to_vec(&snapshot).unwrap(), serde_json::to_vec(&restored.snapshot(0)).unwrap());
+
+    tokio::spawn(async move {
+      restored.run("root".to_string()).await;
+    });
+    restored_a2b.send((LogicalTimeAbsoluteMs(0), vec![summator_task(3, 7, 7)]));
+
+    compare_channel_data_with_exp(
+      vec![(UniqueU64BlobId(2), Ok(Value::U64(20))), (UniqueU64BlobId(3), Ok(Value::U64(49)))],
+      restored_a2b.receiver,
+    )
+    .await;
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn idle_runtime_reacts_to_inputs_and_stops_on_shutdown() {
+    // summator multiplies
+    let summator_task = |id: u64| TaskBlueprint {
+      global_id: UniqueU64BlobId(id),
+      q_name: "testInfiniteCalculatorQueue".to_string(),
+      value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: id, b: 1 }),
+    };
+
+    let (mut a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_shutdown_signal(shutdown_rx);
+    let handle = tokio::spawn(async move {
+      rt.run("root".to_string()).await;
+    });
+
+    // runtime is parked, every input wakes it up right away
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    for id in 1..=3 {
+      a2b_runtime.send((LogicalTimeAbsoluteMs(0), vec![summator_task(id)]));
+      let response = tokio::time::timeout(Duration::from_millis(100), a2b_runtime.receiver.recv()).await;
+      assert_eq!(Ok(Some((UniqueU64BlobId(id), Ok(Value::U64(id))))), response);
+    }
+
+    shutdown_tx.send(()).expect("runtime is running");
+    tokio::time::timeout(Duration::from_millis(100), handle).await.expect("runtime stops").expect("doesn't panic");
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn rejects_malformed_tasks() {
+    let task = |id: u64, q_name: &str, value: Value| TaskBlueprint {
+      global_id: UniqueU64BlobId(id),
+      q_name: q_name.to_string(),
+      value,
+    };
+    let summator_message =
+      || Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a: 2, b: 3 });
+
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime);
+    tokio::spawn(async move {
+      rt.run("root".to_string()).await;
+    });
+
+    a2b_runtime.send((
+      LogicalTimeAbsoluteMs(0),
+      vec![
+        task(1, "noSuchQueue", summator_message()),
+        task(2, "testInfiniteCalculatorQueue", Value::U64(5)),
+        task(
+          3,
+          "testInfiniteCalculatorQueue",
+          Value::TestCreateQueueMessagePub(TestCreateQueueMessagePub { value: 1 }),
+        ),
+        task(4, "testInfiniteCalculatorQueue", summator_message()),
+      ],
+    ));
+
+    compare_channel_data_with_exp(
+      vec![
+        (UniqueU64BlobId(1), Err(Rejection::UnknownQueue)),
+        (UniqueU64BlobId(2), Err(Rejection::NotPublicMessage)),
+        (UniqueU64BlobId(3), Err(Rejection::WrongMessageType)),
+        (UniqueU64BlobId(4), Ok(Value::U64(6))),
+      ],
+      a2b_runtime.receiver,
+    )
+    .await;
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn select_timeouts_drop_losing_arms() {
+    let (_a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_snapshot_handler(snapshot_handler);
+    let debug_out = rt.debug_handle();
+    tokio::spawn(async move {
+      rt.run("testSelectTimeout".to_string()).await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    // slow timer and the second timeout are cancelled, nothing is left behind
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 0 }).await;
+    assert!(snapshot.scheduled.is_empty(), "{:?}", snapshot.scheduled);
+    assert!(snapshot.resolved_futures.is_empty(), "{:?}", snapshot.resolved_futures);
+    assert!(snapshot.futures.is_empty(), "{:?}", snapshot.futures);
+    assert!(snapshot.awaiting_fibers.is_empty());
+
+    let result = debug_out.lock();
+    assert_str_eq_by_lines(
+      r#"--- start testSelectTimeout:0 ---
+--- await testSelectTimeout:0 ---
+--- start testSelectTimeout:0 ---
+--- await testSelectTimeout:0 ---
+--- start testSelectTimeout:0 ---
+slow timer timed out
+--- await testSelectTimeout:0 ---
+--- start testSelectTimeout:0 ---
+fast timer fired
+--- await testSelectTimeout:0 ---
+--- exit testSelectTimeout:0 ---
+"#,
+      result.expect("should be object").as_str(),
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn select_keeps_losing_futures_of_fiber() {
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut rt =
+      Runtime::new(MonotonicTimer::new(), b2a_runtime).set_epoch_driven_time().set_snapshot_handler(snapshot_handler);
+    let debug_out = rt.debug_handle();
+    tokio::spawn(async move {
+      rt.run("testSelectTick".to_string()).await;
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // timeout wins, the tick timer stays, the next timeout is scheduled
+    a2b_runtime.send((LogicalTimeAbsoluteMs(30), vec![]));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 1 }).await;
+    assert_eq!(
+      vec![(LogicalTimeAbsoluteMs(50), "0".to_string()), (LogicalTimeAbsoluteMs(60), "2".to_string())],
+      snapshot.scheduled
+    );
+
+    // the tick timer is awaited again and wins, the timeout is cancelled
+    a2b_runtime.send((LogicalTimeAbsoluteMs(50), vec![]));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 2 }).await;
+    assert!(snapshot.scheduled.is_empty(), "{:?}", snapshot.scheduled);
+    assert!(snapshot.futures.is_empty(), "{:?}", snapshot.futures);
+
+    assert_str_eq_by_lines(
+      r#"--- start testSelectTick:0 ---
+--- await testSelectTick:0 ---
+--- start testSelectTick:0 ---
+--- await testSelectTick:0 ---
+--- start testSelectTick:0 ---
+tick timer is pending
+--- await testSelectTick:0 ---
+--- start testSelectTick:0 ---
+tick timer fired
+--- await testSelectTick:0 ---
+--- exit testSelectTick:0 ---
+"#,
+      debug_out.lock().expect("should be object").as_str(),
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn abandoned_futures_are_reclaimed() {
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut rt =
+      Runtime::new(MonotonicTimer::new(), b2a_runtime).set_epoch_driven_time().set_snapshot_handler(snapshot_handler);
+    let debug_out = rt.debug_handle();
+    let gauges = rt.gauges();
+    tokio::spawn(async move {
+      rt.run("testAbandonedFutures".to_string()).await;
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // future, timer and timeout of the select are owned by the sleeping fiber
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 0 }).await;
+    assert_eq!(
+      vec![("0".to_string(), Some(0)), ("1".to_string(), Some(0)), ("2".to_string(), Some(0))],
+      snapshot.futures
+    );
+    assert_eq!(3, gauges.live_futures.load(Ordering::Relaxed));
+    assert_eq!(1, gauges.fibers.load(Ordering::Relaxed));
+    assert_eq!(1, gauges.queues.load(Ordering::Relaxed));
+
+    a2b_runtime.send((LogicalTimeAbsoluteMs(20), vec![]));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // fiber has exited without awaiting its futures, the timer is cancelled
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 1 }).await;
+    assert!(snapshot.futures.is_empty(), "{:?}", snapshot.futures);
+    assert!(snapshot.scheduled.is_empty(), "{:?}", snapshot.scheduled);
+    assert!(snapshot.resolved_futures.is_empty(), "{:?}", snapshot.resolved_futures);
+    assert_eq!(0, gauges.live_futures.load(Ordering::Relaxed));
+    assert_eq!(0, gauges.fibers.load(Ordering::Relaxed));
+
+    assert_str_eq_by_lines(
+      r#"--- start testAbandonedFutures:0 ---
+--- await testAbandonedFutures:0 ---
+--- start testAbandonedFutures:0 ---
+--- await testAbandonedFutures:0 ---
+--- start testAbandonedFutures:0 ---
+woke up
+--- await testAbandonedFutures:0 ---
+--- exit testAbandonedFutures:0 ---
+"#,
+      debug_out.lock().expect("should be object").as_str(),
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn dropped_requests_are_answered() {
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_snapshot_handler(snapshot_handler);
+    tokio::spawn(async move {
+      rt.run("testDroppedRequest".to_string()).await;
+    });
+
+    a2b_runtime.send((
+      LogicalTimeAbsoluteMs(0),
+      vec![TaskBlueprint {
+        global_id: UniqueU64BlobId(7),
+        q_name: "droppedRequests".to_string(),
+        value: Value::TestCreateQueueMessagePub(TestCreateQueueMessagePub { value: 1 }),
+      }],
+    ));
+    compare_channel_data_with_exp(vec![(UniqueU64BlobId(7), Err(Rejection::Abandoned))], a2b_runtime.receiver).await;
+
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 1 }).await;
+    assert!(snapshot.public_futures.is_empty(), "{:?}", snapshot.public_futures);
+    assert!(snapshot.futures.is_empty(), "{:?}", snapshot.futures);
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn failed_fibers_are_reported_to_supervisor() {
+    let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+    let (snapshot_invoker, snapshot_handler) = create_invoker_handler_pair();
+    let mut rt = Runtime::new(MonotonicTimer::new(), b2a_runtime).set_snapshot_handler(snapshot_handler);
+    let debug_out = rt.debug_handle();
+    tokio::spawn(async move {
+      rt.run("testSupervisor".to_string()).await;
+    });
+
+    // gateways can't send into the system queue either
+    a2b_runtime.send((
+      LogicalTimeAbsoluteMs(0),
+      vec![TaskBlueprint {
+        global_id: UniqueU64BlobId(1),
+        q_name: SYSTEM_FIBERS_QUEUE.to_string(),
+        value: Value::TestCreateQueueMessagePub(TestCreateQueueMessagePub { value: 1 }),
+      }],
+    ));
+    compare_channel_data_with_exp(vec![(UniqueU64BlobId(1), Err(Rejection::PrivateQueue))], a2b_runtime.receiver).await;
+
+    let snapshot = snapshot_invoker.request(SnapshotRequest { inputs_count: 1 }).await;
+    assert_eq!(
+      vec![
+        FailedFiber {
+          fiber_id: 1,
+          fiber_type: "testFailingSender".to_string(),
+          failure: Failure::new(FailureKind::UnknownQueue, "there is no queue missingQueue".to_string()),
+        },
+        FailedFiber {
+          fiber_id: 2,
+          fiber_type: "testFailingSender".to_string(),
+          failure: Failure::new(FailureKind::ReservedQueue, "only runtime can send into mrn.system.fibers".to_string()),
+        },
+      ],
+      snapshot.failed_fibers
+    );
+
+    // failed fibers are terminated, supervisor gets their failures in the order they have happened
+    let result = debug_out.lock();
+    assert_str_eq_by_lines(
+      r#"--- start testSupervisor:0 ---
+--- await testSupervisor:0 ---
+created: FiberType("testFailingSender"):1. init_vars:
+    String("missingQueue")
+created: FiberType("testFailingSender"):2. init_vars:
+    String("mrn.system.fibers")
+--- start testSupervisor:0 ---
+--- await testSupervisor:0 ---
+--- start testFailingSender:1 ---
+--- await testFailingSender:1 ---
+--- failed testFailingSender:1 unknown_queue: there is no queue missingQueue ---
+--- start testFailingSender:2 ---
+--- await testFailingSender:2 ---
+--- failed testFailingSender:2 reserved_queue: only runtime can send into mrn.system.fibers ---
+--- start testSupervisor:0 ---
+fiber failed
+systemQueueName=mrn.system.fibers
+missingQueueName=missingQueue
+event=SystemFiberEvent(FiberFailed { fiber_id: "1", fiber_type: "testFailingSender", kind: "unknown_queue", detail: "there is no queue missingQueue" })
+failedFiber=testFailingSender:1
+failureKind=unknown_queue
+--- await testSupervisor:0 ---
+--- start testSupervisor:0 ---
+fiber failed
+systemQueueName=mrn.system.fibers
+missingQueueName=missingQueue
+event=SystemFiberEvent(FiberFailed { fiber_id: "2", fiber_type: "testFailingSender", kind: "reserved_queue", detail: "only runtime can send into mrn.system.fibers" })
+failedFiber=testFailingSender:2
+failureKind=reserved_queue
+--- await testSupervisor:0 ---
+"#,
+      result.expect("should be object").as_str(),
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn digests_dont_depend_on_input_pacing() {
+    let summator_task = |id: u64, a: u64, b: u64| TaskBlueprint {
+      global_id: UniqueU64BlobId(id),
+      q_name: "testInfiniteCalculatorQueue".to_string(),
+      value: Value::TestInfiniteSummatorQueueMessagePub(TestInfiniteSummatorQueueMessagePub { a, b }),
+    };
+    let mut inputs = vec![
+      (LogicalTimeAbsoluteMs(10), vec![summator_task(1, 2, 3), summator_task(2, 4, 5)]),
+      (LogicalTimeAbsoluteMs(20), vec![]),
+      (LogicalTimeAbsoluteMs(30), vec![summator_task(3, 7, 7)]),
+    ];
+    // the last digest has the full state in it
+    for n in inputs.len() as u64..FULL_STATE_DIGEST_EVERY {
+      inputs.push((LogicalTimeAbsoluteMs(30 + n), vec![summator_task(n + 1, n, n)]));
+    }
+    let inputs_count = inputs.len();
+
+    let start = || {
+      let (a2b_runtime, b2a_runtime) = create_a_b_duplex_pair::<Input, Output>();
+      let (digests_tx, digests_rx) = tokio::sync::mpsc::unbounded_channel();
+      let mut rt =
+        Runtime::new(MonotonicTimer::new(), b2a_runtime).set_epoch_driven_time().set_digests_sender(digests_tx);
+      tokio::spawn(async move {
+        rt.run("root".to_string()).await;
+      });
+      (a2b_runtime, digests_rx)
+    };
+
+    // all the inputs at once
+    let (burst_a2b, mut burst_digests) = start();
+    for input in inputs.clone() {
+      burst_a2b.send(input);
+    }
+
+    // one input after another
+    let (paced_a2b, mut paced_digests) = start();
+    let mut paced = vec![];
+    for input in inputs {
+      paced_a2b.send(input);
+      paced.push(paced_digests.recv().await.expect("runtime is running"));
+    }
+
+    let mut burst = vec![];
+    for _ in 0..inputs_count {
+      burst.push(burst_digests.recv().await.expect("runtime is running"));
+    }
+
+    assert_eq!(burst, paced);
+    assert_ne!(burst[0], burst[1]);
+    assert_ne!(burst[1], burst[2]);
+  }
+
   async fn compare_channel_data_with_exp<T: PartialEq + Debug>(
     expected: Vec<T>,
     mut ch: UnboundedReceiver<T>,
@@ -0,0 +1,91 @@
+use crate::fiber::Fiber;
+use crate::runtime::{FailedFiber, StateDigest, TaskBlueprint};
+use common::logical_time::LogicalTimeAbsoluteMs;
+use common::range_key::UniqueU64BlobId;
+use dsl::ir::FiberType;
+use generated::maroon_assembler::{Heap, SelectArm, StackEntry, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// asks runtime to make a snapshot
+pub struct SnapshotRequest {
+  /// how many inputs have been sent to runtime before the request
+  /// snapshot includes exactly these inputs, even if some of them are not processed yet
+  pub inputs_count: u64,
+}
+
+/// bumped when a field is added or its meaning changes, snapshots of other versions aren't restored
+/// ex: a snapshot without public queues would reject every task of gateways
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// Everything runtime keeps in memory. Runtime restored from it continues from the same point
+///
+/// collections are ordered, so the same runtime state is always serialized into the same bytes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuntimeSnapshot {
+  /// `SNAPSHOT_VERSION` of the runtime that has made it
+  pub version: u32,
+  /// `None` if runtime doesn't use epoch-driven time
+  pub(crate) logical_time: Option<LogicalTimeAbsoluteMs>,
+  pub(crate) next_fiber_id: u64,
+  pub(crate) next_created_future_id: u64,
+  pub(crate) active_tasks: Vec<(LogicalTimeAbsoluteMs, Vec<TaskBlueprint>)>,
+  pub(crate) active_fibers: Vec<FiberSnapshot>,
+  /// parked fibers in the order their selects have been registered
+  pub(crate) awaiting_fibers: Vec<(FiberSnapshot, Vec<SelectArm>)>,
+  pub(crate) failed_fibers: Vec<FailedFiber>,
+  /// sorted by (when, future id)
+  pub(crate) scheduled: Vec<(LogicalTimeAbsoluteMs, String)>,
+  pub(crate) public_futures: BTreeMap<String, UniqueU64BlobId>,
+  pub(crate) queue_messages: BTreeMap<String, Vec<Value>>,
+  pub(crate) public_queues: BTreeMap<String, String>,
+  pub(crate) non_empty_queues: Vec<String>,
+  pub(crate) resolved_futures: Vec<(String, Value)>,
+  /// futures runtime tracks with the fibers that have created them, sorted
+  pub(crate) futures: Vec<(String, Option<u64>)>,
+  pub(crate) digest: StateDigest,
+  pub(crate) digest_pending: bool,
+  pub(crate) sent_digests: u64,
+}
+
+impl RuntimeSnapshot {
+  /// how many inputs in the snapshot haven't been digested yet, runtime sends their digests after restore
+  pub fn undigested_inputs(&self) -> u64 {
+    self.active_tasks.len() as u64 + self.digest_pending as u64
+  }
+}
+
+/// fiber without its debug trace
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct FiberSnapshot {
+  pub(crate) f_type: String,
+  pub(crate) unique_id: u64,
+  pub(crate) function_key: String,
+  pub(crate) stack: Vec<StackEntry>,
+  pub(crate) heap: Heap,
+}
+
+impl From<&Fiber> for FiberSnapshot {
+  fn from(fiber: &Fiber) -> FiberSnapshot {
+    FiberSnapshot {
+      f_type: fiber.f_type.0.clone(),
+      unique_id: fiber.unique_id,
+      function_key: fiber.function_key.clone(),
+      stack: fiber.stack.clone(),
+      heap: fiber.heap.clone(),
+    }
+  }
+}
+
+impl From<FiberSnapshot> for Fiber {
+  fn from(snapshot: FiberSnapshot) -> Fiber {
+    Fiber {
+      stack: snapshot.stack,
+      heap: snapshot.heap,
+      function_key: snapshot.function_key,
+      f_type: FiberType(snapshot.f_type),
+      unique_id: snapshot.unique_id,
+      trace_sink: vec![],
+    }
+  }
+}
@@ -11,6 +11,8 @@ pub struct WaitRegistry {
   nodes: Slab<WaitNode>,
   /// Active select registrations keyed by id
   regs: Slab<SelectReg>,
+  /// monotonically increasing number of registrations, slab ids are reused so they can't give the order
+  next_reg_seq: u64,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -61,6 +63,8 @@ struct ArmHandle {
 
 #[derive(Clone, Debug, Default)]
 struct SelectReg {
+  /// order in which selects have been registered
+  seq: u64,
   /// Awaiting fiber identity
   fiber_id: u64,
   /// All arms registered for this select
@@ -74,6 +78,8 @@ pub struct WakeOutcome {
   /// to which variable bind the result
   pub bind: Option<String>,
   pub next: State,
+  /// futures of the arms that haven't been chosen, nobody awaits them anymore
+  pub dropped_futures: Vec<FutureId>,
 }
 
 /// Uniquily identifies in-flight select registration inside WaitRegistry
@@ -85,7 +91,12 @@ impl WaitRegistry {
   pub fn default() -> WaitRegistry {
     // No particular reason for 1024 const
     // Maybe later it will be changed, maybe not
-    WaitRegistry { per_key: HashMap::default(), nodes: Slab::with_capacity(1024), regs: Slab::with_capacity(1024) }
+    WaitRegistry {
+      per_key: HashMap::default(),
+      nodes: Slab::with_capacity(1024),
+      regs: Slab::with_capacity(1024),
+      next_reg_seq: 0,
+    }
   }
 
   pub fn register_select(
@@ -95,7 +106,8 @@ impl WaitRegistry {
   ) -> RegisteredSelectId {
     // Allocate a registration slot in Slab (empty arms for now)
     // O(1)
-    let reg_id = self.regs.insert(SelectReg { fiber_id, arms: Vec::with_capacity(arms.len()) });
+    let reg_id = self.regs.insert(SelectReg { seq: self.next_reg_seq, fiber_id, arms: Vec::with_capacity(arms.len()) });
+    self.next_reg_seq += 1;
 
     let mut arm_handles: Vec<ArmHandle> = Vec::with_capacity(arms.len());
 
@@ -118,6 +130,9 @@ impl WaitRegistry {
           self.list_push_back(&key, node_id);
           arm_handles.push(ArmHandle { key, node_id, kind: ArmKind::Future, resume: ArmResume { bind, next } });
         }
+        SelectArm::Timeout { .. } => {
+          panic!("runtime turns timeouts into timer futures before registering a select");
+        }
       }
     }
 
@@ -153,18 +168,24 @@ impl WaitRegistry {
     // if node is in nodes and per_key but not here - it's a consistency error
     let reg = self.regs.try_remove(reg_id).expect("if not here - huge consistency problem");
 
+    let mut dropped_futures = Vec::new();
     let winner_resume: ArmResume = {
       let mut to_return: Option<ArmResume> = None;
       for arm in reg.arms {
         self.list_unlink(&arm.key, arm.node_id);
         if arm.node_id == head_id {
           to_return = Some(arm.resume);
+        } else if let WaitKey::Future(future_id) = arm.key
+          && WaitKey::Future(future_id.clone()) != *key
+          && !dropped_futures.contains(&future_id)
+        {
+          dropped_futures.push(future_id);
         }
       }
       to_return.expect("if not here - huge consistency problem ಠ_ಠ")
     };
 
-    Some(WakeOutcome { fiber_id, bind: winner_resume.bind, next: winner_resume.next })
+    Some(WakeOutcome { fiber_id, bind: winner_resume.bind, next: winner_resume.next, dropped_futures })
   }
 
   /// appends a waiter node to the end of the per-source FIFO list
@@ -245,6 +266,45 @@ impl WaitRegistry {
     }
   }
 
+  /// true if at least one select awaits the key
+  pub fn has_waiters(
+    &self,
+    key: &WaitKey,
+  ) -> bool {
+    self.per_key.contains_key(key)
+  }
+
+  /// all in-flight selects as (fiber_id, arms) in the order they have been registered
+  /// registering them in the same order into an empty registry gives the same per-key FIFOs
+  pub fn registrations(&self) -> Vec<(u64, Vec<SelectArm>)> {
+    let mut regs: Vec<&SelectReg> = self.regs.iter().map(|(_, reg)| reg).collect();
+    regs.sort_by_key(|reg| reg.seq);
+
+    regs
+      .into_iter()
+      .map(|reg| {
+        let arms = reg
+          .arms
+          .iter()
+          .map(|arm| match (&arm.key, &arm.kind) {
+            (WaitKey::Queue(queue_name), ArmKind::Queue) => SelectArm::Queue {
+              queue_name: queue_name.clone(),
+              bind: arm.resume.bind.clone().expect("queue arm always binds the message"),
+              next: arm.resume.next.clone(),
+            },
+            (WaitKey::Future(future_id), ArmKind::Future) => SelectArm::FutureVar {
+              future_id: future_id.0.clone(),
+              bind: arm.resume.bind.clone(),
+              next: arm.resume.next.clone(),
+            },
+            _ => panic!("arm kind doesn't match its key, registry is corrupted"),
+          })
+          .collect();
+        (reg.fiber_id, arms)
+      })
+      .collect()
+  }
+
   /// Cancels a specific in-flight select by its registration id; returns number of arms unlinked.
   /// O(selected_arms) ~ O(1)
   pub fn cancel_by_registered_select_id(
@@ -363,12 +423,49 @@ mod tests {
     );
 
     let result = registry.wake_one(&WaitKey::Queue("q2".to_string()));
-    assert_eq!(Some(WakeOutcome { fiber_id: 100500, bind: Some("var2".to_string()), next: State::Idle }), result);
+    assert_eq!(
+      Some(WakeOutcome {
+        fiber_id: 100500,
+        bind: Some("var2".to_string()),
+        next: State::Idle,
+        dropped_futures: vec![FutureId("id1".to_string())]
+      }),
+      result
+    );
     assert!(registry.nodes.len() == 0, "{:?}", registry.nodes);
     assert!(registry.per_key.len() == 0, "{:?}", registry.nodes);
     assert!(registry.regs.len() == 0, "{:?}", registry.nodes);
   }
 
+  #[test]
+  fn wake_drops_futures_of_losing_arms() {
+    let mut wr = WaitRegistry::default();
+    let future_arm = |f: &str| SelectArm::FutureVar { future_id: f.to_string(), bind: None, next: State::Idle };
+    wr.register_select(
+      1,
+      vec![
+        future_arm("0"),
+        SelectArm::Queue { queue_name: "q".to_string(), bind: "a".to_string(), next: State::Completed },
+        future_arm("1"),
+        future_arm("1"),
+      ],
+    );
+    wr.register_select(2, vec![future_arm("1"), future_arm("2")]);
+
+    // the same future in two arms is dropped once, queue arms don't drop anything
+    let out = wr.wake_one(&WaitKey::Queue("q".to_string())).expect("wake on q");
+    assert_eq!(vec![FutureId("0".to_string()), FutureId("1".to_string())], out.dropped_futures);
+
+    // the winning future isn't dropped even if another arm awaits it too
+    wr.register_select(3, vec![future_arm("3"), future_arm("3")]);
+    let out = wr.wake_one(&WaitKey::Future(FutureId("3".to_string()))).expect("wake on 3");
+    assert_eq!(3, out.fiber_id);
+    assert!(out.dropped_futures.is_empty());
+
+    // fiber 2 still awaits future 1, it's up to runtime to keep futures that have other waiters
+    assert!(wr.has_waiters(&WaitKey::Future(FutureId("1".to_string()))));
+  }
+
   #[test]
   fn empty_wake_returns_none() {
     let mut wr = WaitRegistry::default();
@@ -500,4 +597,31 @@ mod tests {
     let out2 = wr.wake_one(&WaitKey::Queue(q.clone())).unwrap();
     assert_eq!((out1.fiber_id, out2.fiber_id), (3, 4));
   }
+
+  #[test]
+  fn registrations_keep_registration_order() {
+    let mut wr = WaitRegistry::default();
+    let queue_arm =
+      |q: &str, bind: &str| SelectArm::Queue { queue_name: q.to_string(), bind: bind.to_string(), next: State::Idle };
+    let future_arm = |f: &str| SelectArm::FutureVar { future_id: f.to_string(), bind: None, next: State::Completed };
+
+    let id1 = wr.register_select(1, vec![queue_arm("a", "a1")]);
+    wr.register_select(2, vec![queue_arm("a", "a2"), future_arm("0")]);
+    wr.cancel_by_registered_select_id(id1);
+    // slab reuses the slot of the cancelled select, but it's still registered after fiber 2
+    wr.register_select(3, vec![future_arm("0"), queue_arm("b", "b1")]);
+
+    let registrations = wr.registrations();
+    assert_eq!(
+      vec![(2, vec![queue_arm("a", "a2"), future_arm("0")]), (3, vec![future_arm("0"), queue_arm("b", "b1")])],
+      registrations
+    );
+
+    let mut restored = WaitRegistry::default();
+    for (fiber_id, arms) in registrations {
+      restored.register_select(fiber_id, arms);
+    }
+    assert_eq!(Some(2), restored.wake_one(&WaitKey::Future(FutureId("0".to_string()))).map(|w| w.fiber_id));
+    assert_eq!(Some(3), restored.wake_one(&WaitKey::Future(FutureId("0".to_string()))).map(|w| w.fiber_id));
+  }
 }
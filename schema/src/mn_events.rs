@@ -28,6 +28,8 @@ pub enum LogEventBody {
   MaroonNodeUp,
   MaroonNodeDown,
   GatewaySentCommand { eid: Eid, mnid: PeerId, body: CommandBody },
+  ReplicaDiverged { sequence_number: u64, peer: PeerId },
+  PeerRefused { peer: PeerId, reason: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
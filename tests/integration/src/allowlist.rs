@@ -0,0 +1,80 @@
+#![allow(unused_imports)]
+
+use std::time::Duration;
+
+use common::{identity, invoker_handler::InvokerInterface};
+use epoch_coordinator::memory::InMemoryEpochStore;
+use libp2p::{PeerId, identity::Keypair};
+use maroon::{
+  app::{Params, Request as AppRequest, Response as AppResponse},
+  network::Allowlist,
+  stack::{self, EpochCoordinatorBackend},
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn allowlist_refuses_unknown_nodes() {
+  _ = env_logger::try_init();
+
+  // allowed nodes need their peer ids before they start
+  let dir = std::env::temp_dir().join(format!("maroon_allowlist_{}", PeerId::random()));
+  let (key0, key1) = (dir.join("node0.key"), dir.join("node1.key"));
+  let node0 = identity::write(&key0, &Keypair::generate_ed25519()).unwrap();
+  let node1 = identity::write(&key1, &Keypair::generate_ed25519()).unwrap();
+  let allowlist = Allowlist::parse(&format!("{node0},{node1}"), "").unwrap();
+
+  let params = Params::default().set_advertise_period(Duration::from_millis(100));
+  let epoch_store = InMemoryEpochStore::new();
+
+  let (stack0, remote_control_0) = stack::MaroonStack::new(
+    vec!["/ip4/127.0.0.1/tcp/3011".to_string()],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
+    "/ip4/0.0.0.0/tcp/3010".to_string(),
+    params.clone().set_key_file(&key0).set_allowlist(allowlist.clone()),
+  )
+  .unwrap();
+  let (stack1, _remote_control_1) = stack::MaroonStack::new(
+    vec!["/ip4/127.0.0.1/tcp/3010".to_string()],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
+    "/ip4/0.0.0.0/tcp/3011".to_string(),
+    params.clone().set_key_file(&key1).set_allowlist(allowlist),
+  )
+  .unwrap();
+  // intruder doesn't check anybody, but it's not in their allowlist
+  let (intruder, remote_control_intruder) = stack::MaroonStack::new(
+    vec!["/ip4/127.0.0.1/tcp/3010".to_string(), "/ip4/127.0.0.1/tcp/3011".to_string()],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
+    "/ip4/0.0.0.0/tcp/3012".to_string(),
+    params,
+  )
+  .unwrap();
+  let intruder_id = intruder.id;
+
+  let _s0 = stack0.start();
+  let _s1 = stack1.start();
+  let _intruder = intruder.start();
+
+  let peers = async |state_invoker: &InvokerInterface<AppRequest, AppResponse>| {
+    let AppResponse::PeersCompatibility(peers) = state_invoker.request(AppRequest::GetPeersCompatibility).await else {
+      unreachable!("GetPeersCompatibility is answered with PeersCompatibility");
+    };
+    peers
+  };
+
+  let mut connected = false;
+  for _ in 0..20 {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    if peers(&remote_control_0.state_invoker).await.contains_key(&node1) {
+      connected = true;
+      break;
+    }
+  }
+  assert!(connected, "allowed nodes should connect to each other");
+
+  // intruder has dialed both of them by now, it's disconnected before meta exchange goes either way
+  tokio::time::sleep(Duration::from_secs(1)).await;
+  assert!(!peers(&remote_control_0.state_invoker).await.contains_key(&intruder_id));
+  let intruder_peers = peers(&remote_control_intruder.state_invoker).await;
+  assert!(!intruder_peers.contains_key(&node0) && !intruder_peers.contains_key(&node1));
+
+  _ = std::fs::remove_dir_all(dir);
+}
@@ -7,11 +7,12 @@ use common::{
   invoker_handler::InvokerInterface,
   range_key::{KeyOffset, KeyRange, UniqueU64BlobId},
 };
-use gateway::core::Gateway;
+use epoch_coordinator::memory::InMemoryEpochStore;
+use gateway::core::{Gateway, MonitorEvent};
 use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
 use maroon::{
   app::{App, CurrentOffsets, Params, Request as AppRequest, Response as AppResponse},
-  stack,
+  stack::{self, EpochCoordinatorBackend},
 };
 use protocol::gm_request_response::Request;
 use protocol::meta_exchange::Response;
@@ -24,45 +25,50 @@ async fn basic() {
 
   let params = Params::default().set_advertise_period(Duration::from_millis(500));
 
+  // all nodes share the same in-process epoch storage instead of etcd
+  let epoch_store = InMemoryEpochStore::new();
+
   // create nodes and gateway
 
   let (stack0, remote_control_0) = stack::MaroonStack::new(
     vec!["/ip4/127.0.0.1/tcp/3001".to_string(), "/ip4/127.0.0.1/tcp/3002".to_string()],
-    vec![],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
     "/ip4/0.0.0.0/tcp/3000".to_string(),
     params.clone(),
   )
   .unwrap();
   let (stack1, remote_control_1) = stack::MaroonStack::new(
     vec!["/dns4/localhost/tcp/3000".to_string(), "/dns4/localhost/tcp/3002".to_string()],
-    vec![],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
     "/ip4/0.0.0.0/tcp/3001".to_string(),
     params.clone(),
   )
   .unwrap();
   let (stack2, remote_control_2) = stack::MaroonStack::new(
     vec!["/ip4/127.0.0.1/tcp/3000".to_string(), "/ip4/127.0.0.1/tcp/3001".to_string()],
-    vec![],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
     "/ip4/0.0.0.0/tcp/3002".to_string(),
     params,
   )
   .unwrap();
 
+  // run nodes and gateway
+  // gateway dials nodes right away, so it's created only when nodes are listening
+
+  let _s0 = stack0.start();
+  let _s1 = stack1.start();
+  let _s2 = stack2.start();
+
   let mut gw = Gateway::new(
-    KeyRange(0),
     vec![
       "/ip4/127.0.0.1/tcp/3000".to_string(),
       "/ip4/127.0.0.1/tcp/3001".to_string(),
       "/ip4/127.0.0.1/tcp/3002".to_string(),
     ],
+    None,
   )
   .unwrap();
-
-  // run nodes and gateway
-
-  let _s0 = stack0.start();
-  let _s1 = stack1.start();
-  let _s2 = stack2.start();
+  let mut monitor = gw.monitor_subscribe();
 
   gw.start_in_background().await;
 
@@ -80,7 +86,9 @@ async fn basic() {
       let app_state_response = interface.request(AppRequest::GetState).await;
       println!("got app: {app_state_response:?}");
 
-      let AppResponse::State(app_state) = app_state_response;
+      let AppResponse::State(app_state) = app_state_response else {
+        unreachable!("GetState is answered with State");
+      };
       app_state == *offsets
     };
   let desired_state = CurrentOffsets {
@@ -102,6 +110,26 @@ async fn basic() {
   assert!(node0_correct);
   assert!(node1_correct);
   assert!(node2_correct);
+
+  // both transactions are committed in epochs and executed, summator multiplies a and b
+  let mut finished = Vec::new();
+  let collect_finished = async {
+    while finished.len() < 2 {
+      match monitor.recv().await {
+        Ok(MonitorEvent::TxUpdate { meta, result })
+          if meta.status == TxStatus::Finished && !finished.iter().any(|(id, _)| *id == meta.id) =>
+        {
+          finished.push((meta.id, result));
+        }
+        _ => {}
+      }
+    }
+  };
+  tokio::time::timeout(Duration::from_secs(10), collect_finished).await.expect("transactions should be executed");
+  finished.sort_by_key(|(id, _)| *id);
+  assert_eq!(vec![(UniqueU64BlobId(0), Some(Value::U64(8))), (UniqueU64BlobId(1), Some(Value::U64(8)))], finished);
+
+  assert!(epoch_store.latest().is_some(), "at least one epoch is committed");
 }
 
 #[cfg(test)]
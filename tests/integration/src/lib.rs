@@ -1,2 +1,3 @@
+mod allowlist;
 mod basic;
 mod request_missed_txs;
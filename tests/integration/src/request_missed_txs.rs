@@ -7,11 +7,12 @@ use common::{
   invoker_handler::InvokerInterface,
   range_key::{KeyOffset, KeyRange, UniqueU64BlobId},
 };
+use epoch_coordinator::memory::InMemoryEpochStore;
 use gateway::core::Gateway;
 use generated::maroon_assembler::{TestInfiniteSummatorQueueMessagePub, Value};
 use maroon::{
   app::{App, CurrentOffsets, Params, Request as AppRequest, Response as AppResponse},
-  stack,
+  stack::{self, EpochCoordinatorBackend},
 };
 use protocol::gm_request_response::Request;
 use protocol::meta_exchange::Response;
@@ -24,38 +25,42 @@ async fn request_missed_txs() {
 
   let params = Params::default().set_advertise_period(Duration::from_millis(500));
 
+  // all nodes share the same in-process epoch storage instead of etcd
+  let epoch_store = InMemoryEpochStore::new();
+
   // create nodes and gateway
 
   let (stack0, remote_control_0) = stack::MaroonStack::new(
     vec!["/ip4/127.0.0.1/tcp/3001".to_string(), "/ip4/127.0.0.1/tcp/3002".to_string()],
-    vec![],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
     "/ip4/0.0.0.0/tcp/3000".to_string(),
     params.clone(),
   )
   .unwrap();
   let (stack1, remote_control_1) = stack::MaroonStack::new(
     vec!["/dns4/localhost/tcp/3000".to_string(), "/dns4/localhost/tcp/3002".to_string()],
-    vec![],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
     "/ip4/0.0.0.0/tcp/3001".to_string(),
     params.clone(),
   )
   .unwrap();
   let (stack2, remote_control_2) = stack::MaroonStack::new(
     vec!["/ip4/127.0.0.1/tcp/3000".to_string(), "/ip4/127.0.0.1/tcp/3001".to_string()],
-    vec![],
+    EpochCoordinatorBackend::InMemory(epoch_store.clone()),
     "/ip4/0.0.0.0/tcp/3002".to_string(),
     params,
   )
   .unwrap();
 
-  let mut gw = Gateway::new(KeyRange(0), vec!["/ip4/127.0.0.1/tcp/3000".to_string()]).unwrap();
-
   // run nodes and gateway
+  // gateway dials nodes right away, so it's created only when nodes are listening
 
   let _s0 = stack0.start();
   let _s1 = stack1.start();
   let _s2 = stack2.start();
 
+  let mut gw = Gateway::new(vec!["/ip4/127.0.0.1/tcp/3000".to_string()], None).unwrap();
+
   gw.start_in_background().await;
 
   // wait until they are connected
@@ -72,7 +77,9 @@ async fn request_missed_txs() {
       let app_state_response = interface.request(AppRequest::GetState).await;
       println!("got app: {app_state_response:?}");
 
-      let AppResponse::State(app_state) = app_state_response;
+      let AppResponse::State(app_state) = app_state_response else {
+        unreachable!("GetState is answered with State");
+      };
       app_state == *offsets
     };
   let desired_state = CurrentOffsets {
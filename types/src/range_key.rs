@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 // TODO: KeyRange and KeyOffset shouldn't be u64 since their combination fits into u64
 //
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Display)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Display)]
 pub struct KeyRange(pub u64);
 
 #[derive(
@@ -0,0 +1,38 @@
+//! creates key files for `KEY_FILE` of nodes and gateways and prints their peer ids
+//!
+//! `cargo run -p util --bin keygen -- <key file>...` creates new keys, existing files aren't overwritten
+//! `cargo run -p util --bin keygen -- --peer-id <key file>...` prints peer ids of existing keys
+
+use common::identity;
+use libp2p::identity::Keypair;
+use std::path::Path;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+  let mut args: Vec<String> = std::env::args().skip(1).collect();
+  let only_print = args.first().is_some_and(|arg| arg == "--peer-id");
+  if only_print {
+    args.remove(0);
+  }
+  if args.is_empty() {
+    eprintln!("usage: keygen [--peer-id] <key file>...");
+    return ExitCode::FAILURE;
+  }
+
+  let mut code = ExitCode::SUCCESS;
+  for path in args {
+    let res = if only_print {
+      identity::read(Path::new(&path)).map(|keypair| keypair.public().to_peer_id())
+    } else {
+      identity::write(Path::new(&path), &Keypair::generate_ed25519())
+    };
+    match res {
+      Ok(peer_id) => println!("{path} {peer_id}"),
+      Err(e) => {
+        eprintln!("{path}: {e}");
+        code = ExitCode::FAILURE;
+      }
+    }
+  }
+  code
+}